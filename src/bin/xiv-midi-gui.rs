@@ -2,22 +2,97 @@ use crossbeam_channel::{unbounded, Receiver, Sender};
 use eframe::egui;
 use egui_taffy::{taffy, tui, TuiBuilderLogic};
 use midir::MidiInputConnection;
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use taffy::prelude::length;
 use xiv_midi::{
-    engine::MidiEngine,
-    keyboard::{EnigoKeyboardController, Key},
-    mapping::{create_ffxiv_default_mapping, Action, MappingConfig, NoteMapping},
-    midi::MidiEventType,
+    engine::{sweep_mapping, EngineOptions, MidiEngine, TimingPreset, ZoneMutes},
+    focus::{window_focus_check, GAME_WINDOW_TITLE},
+    keyboard::{EnigoKeyboardController, Key, KeyEvent, ModifierSide},
+    mapping::{
+        create_ffxiv_default_mapping, is_yaml_path, recoverable_autosave, remove_autosave, Action,
+        MappingConfig, NoteMapping, ZoneModifiers, MAPPING_EXTENSIONS,
+    },
+    midi::{MidiEventType, MidiMessage, OctaveConvention},
 };
 
+/// How often unsaved editor changes are written to the `.autosave` sidecar.
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Opens and closes the performance HUD, from either window.
+const HUD_TOGGLE_KEY: egui::Key = egui::Key::F2;
+
+/// How long a piano key glows after a note-off with release velocity 1; harder
+/// releases fade faster, and note-offs without a release velocity (0) don't glow.
+const RELEASE_FADE: Duration = Duration::from_millis(400);
+
+/// Releases every key the app is holding (see `XivMidiApp::panic`). Not Escape,
+/// which closes the HUD and dialogs.
+const PANIC_KEY: egui::Key = egui::Key::F9;
+
+/// How long the HUD has to stay put after a move or resize before it is saved.
+const HUD_SAVE_DELAY: Duration = Duration::from_millis(500);
+
+/// How many recent key presses/releases the "Keys sent" feed shows.
+const KEYS_SENT_SHOWN: usize = 16;
+
+/// How many recent notes the note history beside the piano lists.
+const NOTE_HISTORY_LEN: usize = 20;
+
+/// Width of the note history beside the piano.
+const NOTE_HISTORY_WIDTH: f32 = 190.0;
+
+/// How often the device list is checked with auto-reconnect on: for the connected
+/// device disappearing, and for a lost one coming back.
+const RECONNECT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Key of `SessionState` in eframe's storage.
+const SESSION_KEY: &str = "session";
+
 #[derive(Debug, Clone)]
 enum AppEvent {
     DeviceConnected(String),
     DeviceDisconnected,
-    MidiEvent { note: u8, velocity: u8, is_on: bool },
+    MidiEvent {
+        note: u8,
+        velocity: u8,
+        is_on: bool,
+    },
+    /// Message emitted by an `Action::Log` in the running mapping
+    EngineLog(String),
+    /// Raw incoming MIDI bytes, for the MIDI monitor
+    RawMidi(Vec<u8>),
+    /// A key the engine pressed or released
+    KeyEvent(KeyEvent),
+    /// Result of a background `mappings/` scan started by `scan_mapping_files`
+    MappingsScanned {
+        generation: u64,
+        options: Vec<MappingOption>,
+        log: Vec<String>,
+    },
+}
+
+/// One row of the MIDI monitor.
+#[derive(Debug, Clone)]
+struct MonitorEntry {
+    /// Time since the app started
+    time: Duration,
+    bytes: Vec<u8>,
+    /// Parsed interpretation, e.g. "NoteOn ch 0 C4 (60) vel 64"
+    parsed: String,
+}
+
+/// A note-on or note-off in the main tab's note history
+struct NoteHistoryEntry {
+    /// Time since the app started
+    time: Duration,
+    note: u8,
+    velocity: u8,
+    is_on: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -25,6 +100,92 @@ struct MappingOption {
     name: String,
     path: Option<PathBuf>,
     is_readonly: bool,
+    /// Channel and note range, e.g. "ch 0, C3–C6"
+    summary: String,
+}
+
+/// A named device + mapping + settings combination that can be connected in one click.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ConnectionProfile {
+    name: String,
+    device: String,
+    /// Name of the mapping as listed in the mapping picker
+    mapping: String,
+    #[serde(default)]
+    channel: Option<u8>,
+    #[serde(default)]
+    octave_transpose: bool,
+    #[serde(default)]
+    transpose_semitones: i8,
+}
+
+/// GUI settings persisted next to the executable as `settings.json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct AppSettings {
+    #[serde(default)]
+    profiles: Vec<ConnectionProfile>,
+    /// Octave numbering for displayed note names
+    #[serde(default)]
+    octave_convention: OctaveConvention,
+    /// Leave the built-in "Default FFXIV" out of the mapping lists
+    #[serde(default)]
+    hide_default_mapping: bool,
+    /// Built-in timing preset used when connecting (None = "default")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timing_preset: Option<String>,
+    /// Drop MIDI events while the game window isn't focused
+    #[serde(default)]
+    require_game_focus: bool,
+    /// Reconnect to a device that disappears (or fails to connect) once it is back
+    #[serde(default)]
+    auto_reconnect: bool,
+    #[serde(default)]
+    hud: HudSettings,
+}
+
+/// The performance HUD: a borderless window showing only the piano, e.g. to
+/// overlay on a stream capture.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct HudSettings {
+    always_on_top: bool,
+    /// Let the capture show through around the keys (needs a compositor)
+    transparent: bool,
+    /// Inner size of the window, in points
+    size: [f32; 2],
+    /// Outer position of the window (None = wherever the OS puts it)
+    position: Option<[f32; 2]>,
+}
+
+impl Default for HudSettings {
+    fn default() -> Self {
+        Self {
+            always_on_top: true,
+            transparent: false,
+            size: [640.0, 100.0],
+            position: None,
+        }
+    }
+}
+
+/// What was selected when the app last closed, kept in eframe's storage (the
+/// window size is kept there by eframe itself).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct SessionState {
+    device: Option<String>,
+    /// Name of the mapping as listed in the mapping picker
+    mapping: Option<String>,
+    tab: AppTab,
+}
+
+impl AppSettings {
+    fn timing(&self) -> TimingPreset {
+        self.timing_preset
+            .as_deref()
+            .and_then(TimingPreset::builtin)
+            .unwrap_or_default()
+    }
 }
 
 struct MappingEditor {
@@ -32,11 +193,16 @@ struct MappingEditor {
     selected_mapping_index: usize,
     current_mapping: MappingConfig,
     selected_note: Option<u8>,
+    /// Notes picked with Ctrl/Shift-click for bulk edits; empty for a single selection
+    selected_notes: BTreeSet<u8>,
+    bulk_modifiers: ZoneModifiers,
     rename_buffer: String,
     is_renaming: bool,
     is_modified: bool,
     new_mapping_name: String,
     show_new_mapping_dialog: bool,
+    save_as_name: String,
+    show_save_as_dialog: bool,
     // Action editor state
     show_action_dialog: bool,
     editing_action_index: Option<(ActionListType, usize)>, // (list type, index)
@@ -45,6 +211,18 @@ struct MappingEditor {
     show_unsaved_dialog: bool,
     pending_action: Option<PendingAction>,
     switch_to_main_requested: bool,
+    // Mirrored from the app settings
+    octave_convention: OctaveConvention,
+    hide_default_mapping: bool,
+    // Autosave
+    last_autosave: Instant,
+    recoverable_autosave: Option<PathBuf>,
+    // Play mode: the on-screen keyboard plays the mapping being edited
+    play_mode: bool,
+    play_engine: Option<MidiEngine>,
+    /// Keyboard range picked with the scroll/zoom buttons (None = fit the mapping)
+    keyboard_view: Option<RangeInclusive<u8>>,
+    played_note: Option<u8>,
 }
 
 #[derive(Debug, Clone)]
@@ -70,6 +248,20 @@ struct ActionEditor {
     shift: bool,
     ctrl: bool,
     alt: bool,
+    side: Option<ModifierSide>,
+    // For Log
+    log_message: String,
+    // For Repeat (inner actions edited as JSON so they can nest)
+    repeat_count: String,
+    repeat_actions: String,
+    // For Chord, in press order
+    chord_keys: Vec<Key>,
+    // For TypeText
+    text: String,
+    // For RepeatHeld (with `selected_key`)
+    interval_ms: String,
+    // For SwitchLayer
+    layer: String,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -78,25 +270,54 @@ enum ActionType {
     Release,
     Delay,
     SetModifiers,
+    Log,
+    Repeat,
+    ReleaseCurrent,
+    Chord,
+    TypeText,
+    Toggle,
+    RepeatHeld,
+    SwitchLayer,
 }
 
 struct XivMidiApp {
     // State
     devices: Vec<String>,
     selected_device: Option<String>,
+    midi_unavailable: bool,
     connection: Option<MidiInputConnection<()>>,
+    /// Name of the device `connection` is to
+    connected_device: Option<String>,
+    /// Device to reconnect to when it shows up again (with `auto_reconnect`)
+    reconnect_device: Option<String>,
+    /// When the device list was last checked for `auto_reconnect`
+    reconnect_polled_at: Instant,
 
     // Mapping
     available_mappings: Vec<MappingOption>,
     selected_mapping_index: usize,
     mapping: MappingConfig,
 
-    // Shared mapping reference for live engine updates
-    engine_mapping: Option<Arc<Mutex<MappingConfig>>>,
+    // Engine of the open connection, for live mapping updates
+    engine: Option<MidiEngine>,
 
     // Octave transpose toggle
     octave_transpose: bool,
 
+    // Semitone shift applied to incoming notes
+    transpose_semitones: i8,
+
+    // MIDI channel filter applied on connect (None = all channels)
+    channel: Option<u8>,
+
+    // Live zone mutes, kept across reconnects
+    mutes: ZoneMutes,
+
+    // Connection profiles
+    settings: AppSettings,
+    selected_profile: Option<usize>,
+    new_profile_name: String,
+
     // Editor
     editor: MappingEditor,
 
@@ -104,17 +325,41 @@ struct XivMidiApp {
     event_tx: Sender<AppEvent>,
     event_rx: Receiver<AppEvent>,
 
+    // Mapping scan: only the result of the latest scan is applied
+    scan_generation: u64,
+    scanning: bool,
+
     // UI State
     log_messages: Vec<String>,
+    midi_monitor: Vec<MonitorEntry>,
+    /// Recent notes from the device, oldest first
+    note_history: Vec<NoteHistoryEntry>,
+    started: Instant,
     active_notes: HashMap<u8, u8>,
+    /// Notes let go with a release velocity: when, and how long their glow lasts
+    released_notes: HashMap<u8, (Instant, Duration)>,
+    /// Keys the engine currently holds, in press order
+    held_keys: Vec<Key>,
+    /// Piano range picked with the scroll/zoom buttons (None = fit the mapping)
+    piano_view: Option<RangeInclusive<u8>>,
+    /// Most recent key presses/releases the engine sent, oldest first
+    keys_sent: Vec<KeyEvent>,
     current_tab: AppTab,
+    /// Mapping the last session had selected, picked once the first scan lists it
+    restored_mapping: Option<String>,
+    /// HUD settings as of when the HUD was opened (None = closed). The window is
+    /// built from these, so moving it doesn't feed back into its own position.
+    hud: Option<HudSettings>,
+    /// When the HUD was last moved or resized, until that is saved
+    hud_moved_at: Option<Instant>,
 
     // Status
     status: String,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
 enum AppTab {
+    #[default]
     Main,
     Editor,
 }
@@ -129,6 +374,14 @@ impl ActionEditor {
             shift: false,
             ctrl: false,
             alt: false,
+            side: None,
+            log_message: String::new(),
+            repeat_count: "2".to_string(),
+            repeat_actions: "[]".to_string(),
+            chord_keys: Vec::new(),
+            text: String::new(),
+            interval_ms: "100".to_string(),
+            layer: "1".to_string(),
         }
     }
 
@@ -140,6 +393,14 @@ impl ActionEditor {
         self.shift = false;
         self.ctrl = false;
         self.alt = false;
+        self.side = None;
+        self.log_message.clear();
+        self.repeat_count = "2".to_string();
+        self.repeat_actions = "[]".to_string();
+        self.chord_keys.clear();
+        self.text.clear();
+        self.interval_ms = "100".to_string();
+        self.layer = "1".to_string();
     }
 
     fn load_action(&mut self, action: &Action) {
@@ -156,15 +417,59 @@ impl ActionEditor {
                 self.action_type = ActionType::Delay;
                 self.delay_ms = ms.to_string();
             }
-            Action::SetModifiers { shift, ctrl, alt } => {
+            Action::SetModifiers {
+                shift,
+                ctrl,
+                alt,
+                side,
+            } => {
                 self.action_type = ActionType::SetModifiers;
                 self.shift = *shift;
                 self.ctrl = *ctrl;
                 self.alt = *alt;
+                self.side = *side;
+            }
+            Action::Log(message) => {
+                self.action_type = ActionType::Log;
+                self.log_message = message.clone();
+            }
+            Action::Repeat { count, actions } => {
+                self.action_type = ActionType::Repeat;
+                self.repeat_count = count.to_string();
+                self.repeat_actions =
+                    serde_json::to_string_pretty(actions).unwrap_or_else(|_| "[]".to_string());
+            }
+            Action::ReleaseCurrent => {
+                self.action_type = ActionType::ReleaseCurrent;
+            }
+            Action::PressChord(keys) => {
+                self.action_type = ActionType::Chord;
+                self.chord_keys = keys.clone();
+            }
+            Action::TypeText(text) => {
+                self.action_type = ActionType::TypeText;
+                self.text = text.clone();
+            }
+            Action::Toggle(key) => {
+                self.action_type = ActionType::Toggle;
+                self.selected_key = *key;
+            }
+            Action::RepeatHeld { key, interval_ms } => {
+                self.action_type = ActionType::RepeatHeld;
+                self.selected_key = *key;
+                self.interval_ms = interval_ms.to_string();
+            }
+            Action::SwitchLayer(layer) => {
+                self.action_type = ActionType::SwitchLayer;
+                self.layer = layer.to_string();
             }
         }
     }
 
+    fn parse_repeat_actions(&self) -> Option<Vec<Action>> {
+        serde_json::from_str(&self.repeat_actions).ok()
+    }
+
     fn build_action(&self) -> Option<Action> {
         match self.action_type {
             ActionType::Press => Some(Action::Press(self.selected_key)),
@@ -180,13 +485,39 @@ impl ActionEditor {
                 shift: self.shift,
                 ctrl: self.ctrl,
                 alt: self.alt,
+                side: self.side,
+            }),
+            ActionType::Log => Some(Action::Log(self.log_message.clone())),
+            ActionType::Repeat => Some(Action::Repeat {
+                count: self.repeat_count.parse().ok()?,
+                actions: self.parse_repeat_actions()?,
+            }),
+            ActionType::ReleaseCurrent => Some(Action::ReleaseCurrent),
+            ActionType::Chord => {
+                (!self.chord_keys.is_empty()).then(|| Action::PressChord(self.chord_keys.clone()))
+            }
+            ActionType::TypeText => {
+                (!self.text.is_empty()).then(|| Action::TypeText(self.text.clone()))
+            }
+            ActionType::Toggle => Some(Action::Toggle(self.selected_key)),
+            ActionType::RepeatHeld => Some(Action::RepeatHeld {
+                key: self.selected_key,
+                interval_ms: self.interval_ms.parse().ok()?,
             }),
+            ActionType::SwitchLayer => Some(Action::SwitchLayer(self.layer.parse().ok()?)),
         }
     }
 
     fn is_valid(&self) -> bool {
         match self.action_type {
             ActionType::Delay => self.delay_ms.parse::<u64>().is_ok(),
+            ActionType::Repeat => {
+                self.repeat_count.parse::<u32>().is_ok() && self.parse_repeat_actions().is_some()
+            }
+            ActionType::Chord => !self.chord_keys.is_empty(),
+            ActionType::TypeText => !self.text.is_empty(),
+            ActionType::RepeatHeld => self.interval_ms.parse::<u64>().is_ok(),
+            ActionType::SwitchLayer => self.layer.parse::<u8>().is_ok(),
             _ => true,
         }
     }
@@ -199,77 +530,34 @@ impl MappingEditor {
             selected_mapping_index: 0,
             current_mapping: create_ffxiv_default_mapping(),
             selected_note: None,
+            selected_notes: BTreeSet::new(),
+            bulk_modifiers: ZoneModifiers::NONE,
             rename_buffer: String::new(),
             is_renaming: false,
             is_modified: false,
             new_mapping_name: String::new(),
             show_new_mapping_dialog: false,
+            save_as_name: String::new(),
+            show_save_as_dialog: false,
             show_action_dialog: false,
             editing_action_index: None,
             action_editor: ActionEditor::new(),
             show_unsaved_dialog: false,
             pending_action: None,
             switch_to_main_requested: false,
+            octave_convention: OctaveConvention::default(),
+            hide_default_mapping: false,
+            last_autosave: Instant::now(),
+            recoverable_autosave: None,
+            play_mode: false,
+            play_engine: None,
+            played_note: None,
+            keyboard_view: None,
         }
     }
 
     fn scan_mappings(&mut self, log: &mut Vec<String>) {
-        self.available_mappings.clear();
-
-        // Add default mapping
-        self.available_mappings.push(MappingOption {
-            name: "Default FFXIV".to_string(),
-            path: None,
-            is_readonly: true,
-        });
-
-        if let Ok(exe_path) = std::env::current_exe() {
-            if let Some(exe_dir) = exe_path.parent() {
-                let mappings_dir = exe_dir.join("mappings");
-
-                if mappings_dir.exists() && mappings_dir.is_dir() {
-                    match std::fs::read_dir(&mappings_dir) {
-                        Ok(entries) => {
-                            let mut files: Vec<_> = entries
-                                .filter_map(|entry| entry.ok())
-                                .filter(|entry| {
-                                    entry
-                                        .path()
-                                        .extension()
-                                        .map(|ext| ext == "json")
-                                        .unwrap_or(false)
-                                })
-                                .collect();
-
-                            files.sort_by_key(|entry| entry.file_name());
-
-                            for entry in files {
-                                let path = entry.path();
-                                let name = path
-                                    .file_stem()
-                                    .and_then(|s| s.to_str())
-                                    .unwrap_or("Unknown")
-                                    .to_string();
-
-                                self.available_mappings.push(MappingOption {
-                                    name,
-                                    path: Some(path),
-                                    is_readonly: false,
-                                });
-                            }
-
-                            log.push(format!(
-                                "Found {} mapping file(s)",
-                                self.available_mappings.len() - 1
-                            ));
-                        }
-                        Err(e) => {
-                            log.push(format!("Error reading mappings directory: {}", e));
-                        }
-                    }
-                }
-            }
-        }
+        self.available_mappings = scan_mapping_options(!self.hide_default_mapping, log);
     }
 
     fn load_mapping(&mut self, index: usize, log: &mut Vec<String>) {
@@ -284,6 +572,9 @@ impl MappingEditor {
             match MappingConfig::from_file(path) {
                 Ok(m) => {
                     log.push(format!("Loaded '{}'", mapping_option.name));
+                    for warning in m.compatibility_warnings() {
+                        log.push(format!("Warning: {}", warning));
+                    }
                     m
                 }
                 Err(e) => {
@@ -293,12 +584,42 @@ impl MappingEditor {
             }
         } else {
             log.push("Loaded default mapping".to_string());
-            create_ffxiv_default_mapping()
+            default_mapping()
         };
 
         self.selected_mapping_index = index;
         self.is_modified = false;
         self.selected_note = None;
+        self.selected_notes.clear();
+        self.recoverable_autosave = self.current_path().and_then(|p| recoverable_autosave(&p));
+    }
+
+    fn current_path(&self) -> Option<PathBuf> {
+        self.available_mappings
+            .get(self.selected_mapping_index)
+            .and_then(|m| m.path.clone())
+    }
+
+    /// Write unsaved changes to the autosave sidecar every `AUTOSAVE_INTERVAL`.
+    fn autosave_if_due(&mut self, log: &mut Vec<String>) {
+        if !self.is_modified || self.last_autosave.elapsed() < AUTOSAVE_INTERVAL {
+            return;
+        }
+        self.last_autosave = Instant::now();
+        if let Some(path) = self.current_path()
+            && let Err(e) = self.current_mapping.write_autosave(&path)
+        {
+            log.push(format!("Error writing autosave: {}", e));
+        }
+    }
+
+    fn discard_autosave(&mut self, log: &mut Vec<String>) {
+        self.recoverable_autosave = None;
+        if let Some(path) = self.current_path()
+            && let Err(e) = remove_autosave(&path)
+        {
+            log.push(format!("Error removing autosave: {}", e));
+        }
     }
 
     fn duplicate_mapping(&mut self, index: usize, log: &mut Vec<String>) {
@@ -325,25 +646,25 @@ impl MappingEditor {
                 }
             }
         } else {
-            create_ffxiv_default_mapping()
+            default_mapping()
         };
 
-        if let Ok(exe_path) = std::env::current_exe() {
-            if let Some(exe_dir) = exe_path.parent() {
-                let mappings_dir = exe_dir.join("mappings");
-                if let Err(e) = std::fs::create_dir_all(&mappings_dir) {
-                    log.push(format!("Error creating directory: {}", e));
-                    return;
-                }
+        if let Ok(exe_path) = std::env::current_exe()
+            && let Some(exe_dir) = exe_path.parent()
+        {
+            let mappings_dir = exe_dir.join("mappings");
+            if let Err(e) = std::fs::create_dir_all(&mappings_dir) {
+                log.push(format!("Error creating directory: {}", e));
+                return;
+            }
 
-                let new_path = mappings_dir.join(format!("{}.json", new_name));
-                match mapping.to_file(&new_path) {
-                    Ok(_) => {
-                        log.push(format!("Duplicated to '{}'", new_name));
-                        self.scan_mappings(log);
-                    }
-                    Err(e) => log.push(format!("Error saving: {}", e)),
+            let new_path = mappings_dir.join(format!("{}.json", new_name));
+            match mapping.to_file(&new_path) {
+                Ok(_) => {
+                    log.push(format!("Duplicated to '{}'", new_name));
+                    self.scan_mappings(log);
                 }
+                Err(e) => log.push(format!("Error saving: {}", e)),
             }
         }
     }
@@ -364,6 +685,7 @@ impl MappingEditor {
         if let Some(ref path) = mapping.path {
             match std::fs::remove_file(path) {
                 Ok(_) => {
+                    let _ = remove_autosave(path);
                     log.push(format!("Deleted '{}'", mapping.name));
                     self.scan_mappings(log);
                     if self.selected_mapping_index >= self.available_mappings.len() {
@@ -399,17 +721,27 @@ impl MappingEditor {
             return;
         }
 
-        if let Some(ref old_path) = mapping.path {
-            if let Some(parent) = old_path.parent() {
-                let new_path = parent.join(format!("{}.json", new_name));
-                match std::fs::rename(old_path, &new_path) {
-                    Ok(_) => {
-                        log.push(format!("Renamed to '{}'", new_name));
-                        self.scan_mappings(log);
-                        self.is_renaming = false;
-                    }
-                    Err(e) => log.push(format!("Error renaming: {}", e)),
+        if let Some(ref old_path) = mapping.path
+            && let Some(parent) = old_path.parent()
+        {
+            if let Some(existing) = existing_mapping_file(parent, &new_name) {
+                log.push(format!("'{}' already exists", existing.display()));
+                return;
+            }
+            // Keep the file's format
+            let extension = old_path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .unwrap_or("json");
+            let new_path = parent.join(format!("{}.{}", new_name, extension));
+            match std::fs::rename(old_path, &new_path) {
+                Ok(_) => {
+                    let _ = remove_autosave(old_path);
+                    log.push(format!("Renamed to '{}'", new_name));
+                    self.scan_mappings(log);
+                    self.is_renaming = false;
                 }
+                Err(e) => log.push(format!("Error renaming: {}", e)),
             }
         }
     }
@@ -427,54 +759,190 @@ impl MappingEditor {
                 Ok(_) => {
                     self.is_modified = false;
                     log.push(format!("Saved '{}'", mapping.name));
+                    self.discard_autosave(log);
                 }
                 Err(e) => log.push(format!("Error saving: {}", e)),
             }
         }
     }
 
-    fn create_new(&mut self, name: String, log: &mut Vec<String>) {
+    /// Remove redundant `SetModifiers` actions from the mapping being edited.
+    fn clean_up(&mut self, log: &mut Vec<String>) {
+        let removed = self.current_mapping.normalize();
+        if removed > 0 {
+            self.is_modified = true;
+        }
+        log.push(format!(
+            "Clean up: removed {} redundant SetModifiers",
+            removed
+        ));
+    }
+
+    fn test_sweep(&mut self, log: &mut Vec<String>) {
+        for warning in self.current_mapping.validate() {
+            log.push(format!("Warning: {}", warning));
+        }
+        match sweep_mapping(&self.current_mapping, EngineOptions::default()) {
+            Ok(results) => {
+                let pressing = results.iter().filter(|r| r.presses_key()).count();
+                for result in &results {
+                    if result.presses_key() {
+                        log.push(format!("Sweep {}", result));
+                    } else {
+                        log.push(format!("Sweep {} (no key pressed)", result));
+                    }
+                }
+                log.push(format!(
+                    "Test sweep: {}/{} notes produced key presses",
+                    pressing,
+                    results.len()
+                ));
+            }
+            Err(e) => log.push(format!("Test sweep failed: {}", e)),
+        }
+    }
+
+    /// Path for a new `extension` mapping file called `name` in the exe-relative
+    /// `mappings/` directory, or `None` (with the reason logged) if the name is
+    /// empty or taken.
+    fn new_mapping_path(
+        &self,
+        name: &str,
+        extension: &str,
+        log: &mut Vec<String>,
+    ) -> Option<PathBuf> {
         if name.is_empty() {
             log.push("Name cannot be empty".to_string());
-            return;
+            return None;
         }
 
         if self.available_mappings.iter().any(|m| m.name == name) {
-            log.push("Name already exists".to_string());
+            log.push(format!("A mapping named '{}' already exists", name));
+            return None;
+        }
+
+        let exe_path = std::env::current_exe().ok()?;
+        let mappings_dir = exe_path.parent()?.join("mappings");
+        if let Err(e) = std::fs::create_dir_all(&mappings_dir) {
+            log.push(format!("Error creating directory: {}", e));
+            return None;
+        }
+
+        // A file the scan skipped (e.g. one that failed to parse) still can't be
+        // overwritten, and `name.json` and `name.yaml` would show up under one name
+        if let Some(existing) = existing_mapping_file(&mappings_dir, name) {
+            log.push(format!("'{}' already exists", existing.display()));
+            return None;
+        }
+        Some(mappings_dir.join(format!("{}.{}", name, extension)))
+    }
+
+    fn create_new(&mut self, name: String, log: &mut Vec<String>) {
+        let Some(path) = self.new_mapping_path(&name, "json", log) else {
+            return;
+        };
+
+        let mapping = MappingConfig::new();
+        match mapping.to_file(&path) {
+            Ok(_) => {
+                log.push(format!("Created '{}'", name));
+                self.scan_mappings(log);
+                self.show_new_mapping_dialog = false;
+                self.new_mapping_name.clear();
+            }
+            Err(e) => log.push(format!("Error creating: {}", e)),
+        }
+    }
+
+    /// Copy the mapping file at `source` into the `mappings/` directory (as
+    /// "<stem>_N" if the name is taken) and open it. A file that doesn't load is
+    /// reported and not copied.
+    fn import_mapping(&mut self, source: &Path, log: &mut Vec<String>) {
+        if let Err(e) = MappingConfig::from_file(source) {
+            log.push(format!("Error importing '{}': {}", source.display(), e));
             return;
         }
 
-        let mapping = MappingConfig {
-            channel: Some(0),
-            mappings: HashMap::new(),
-            octave_transpose: false,
+        let stem = source
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("imported");
+        let name = std::iter::once(stem.to_string())
+            .chain((2..).map(|n| format!("{}_{}", stem, n)))
+            .find(|name| !self.available_mappings.iter().any(|m| &m.name == name))
+            .unwrap_or_default();
+        let Some(path) = self.new_mapping_path(&name, mapping_extension(source), log) else {
+            return;
         };
 
-        if let Ok(exe_path) = std::env::current_exe() {
-            if let Some(exe_dir) = exe_path.parent() {
-                let mappings_dir = exe_dir.join("mappings");
-                if let Err(e) = std::fs::create_dir_all(&mappings_dir) {
-                    log.push(format!("Error creating directory: {}", e));
-                    return;
+        match std::fs::copy(source, &path) {
+            Ok(_) => {
+                log.push(format!("Imported '{}' as '{}'", source.display(), name));
+                self.scan_mappings(log);
+                if let Some(index) = self.available_mappings.iter().position(|m| m.name == name) {
+                    if self.is_modified {
+                        self.pending_action = Some(PendingAction::LoadMapping(index));
+                        self.show_unsaved_dialog = true;
+                    } else {
+                        self.load_mapping(index, log);
+                    }
                 }
+            }
+            Err(e) => log.push(format!("Error importing: {}", e)),
+        }
+    }
 
-                let path = mappings_dir.join(format!("{}.json", name));
-                match mapping.to_file(&path) {
-                    Ok(_) => {
-                        log.push(format!("Created '{}'", name));
-                        self.scan_mappings(log);
-                        self.show_new_mapping_dialog = false;
-                        self.new_mapping_name.clear();
-                    }
-                    Err(e) => log.push(format!("Error creating: {}", e)),
+    /// Write the mapping being edited to `path`, anywhere on disk. The editor keeps
+    /// editing the original file.
+    fn export_mapping(&self, path: &Path, log: &mut Vec<String>) {
+        match self.current_mapping.to_file(path) {
+            Ok(_) => log.push(format!("Exported to '{}'", path.display())),
+            Err(e) => log.push(format!("Error exporting: {}", e)),
+        }
+    }
+
+    /// First free "<name>_vN" for the mapping being edited, suggested by Save As.
+    fn next_version_name(&self) -> String {
+        let base = &self.available_mappings[self.selected_mapping_index].name;
+        (2..)
+            .map(|version| format!("{}_v{}", base, version))
+            .find(|name| !self.available_mappings.iter().any(|m| &m.name == name))
+            .unwrap_or_default()
+    }
+
+    /// Write the mapping being edited to a new file called `name` and switch to it,
+    /// leaving the original file as it was on disk.
+    fn save_as(&mut self, name: String, log: &mut Vec<String>) {
+        let name = name.trim().to_string();
+        let extension = self
+            .current_path()
+            .map_or("json", |p| mapping_extension(&p));
+        let Some(path) = self.new_mapping_path(&name, extension, log) else {
+            return;
+        };
+
+        match self.current_mapping.to_file(&path) {
+            Ok(_) => {
+                // The unsaved edits now live in the new file
+                self.discard_autosave(log);
+                log.push(format!("Saved as '{}'", name));
+                self.scan_mappings(log);
+                if let Some(index) = self.available_mappings.iter().position(|m| m.name == name) {
+                    self.selected_mapping_index = index;
                 }
+                self.is_modified = false;
+                self.show_save_as_dialog = false;
+                self.save_as_name.clear();
             }
+            Err(e) => log.push(format!("Error saving: {}", e)),
         }
     }
 
     fn draw(&mut self, ui: &mut egui::Ui, ctx: &egui::Context, log: &mut Vec<String>) {
         let mut action_queue: Vec<(&str, usize)> = Vec::new();
 
+        self.autosave_if_due(log);
+
         egui::SidePanel::left("mapping_list")
             .default_width(250.0)
             .resizable(true)
@@ -482,9 +950,19 @@ impl MappingEditor {
                 ui.heading("Mappings");
                 ui.separator();
 
-                if ui.button("+ New Mapping").clicked() {
-                    self.show_new_mapping_dialog = true;
-                }
+                ui.horizontal(|ui| {
+                    if ui.button("+ New Mapping").clicked() {
+                        self.show_new_mapping_dialog = true;
+                    }
+                    if ui
+                        .button("📂 Import…")
+                        .on_hover_text("Copy a mapping file from anywhere into mappings/")
+                        .clicked()
+                        && let Some(path) = mapping_file_dialog().pick_file()
+                    {
+                        self.import_mapping(&path, log);
+                    }
+                });
 
                 ui.separator();
 
@@ -498,6 +976,7 @@ impl MappingEditor {
                                 if ui.selectable_label(is_selected, &mapping.name).clicked() {
                                     action_queue.push(("load", index));
                                 }
+                                ui.weak(format!("({})", mapping.summary));
 
                                 if !mapping.is_readonly {
                                     if ui.small_button("📋").on_hover_text("Duplicate").clicked()
@@ -537,12 +1016,30 @@ impl MappingEditor {
 
             ui.separator();
 
-            ui.label("Select a MIDI note from the keyboard:");
-            self.draw_midi_keyboard(ui);
+            ui.horizontal(|ui| {
+                ui.label(if self.play_mode {
+                    "Click and hold keys to play the mapping:"
+                } else {
+                    "Select a MIDI note from the keyboard:"
+                });
+                if ui
+                    .checkbox(&mut self.play_mode, "🎹 Play")
+                    .on_hover_text(
+                        "Clicking a key plays it through this mapping, pressing its keys",
+                    )
+                    .changed()
+                    && !self.play_mode
+                {
+                    self.stop_playing();
+                }
+            });
+            self.draw_midi_keyboard(ui, log);
 
             ui.separator();
 
-            if let Some(note) = self.selected_note {
+            if self.selected_notes.len() > 1 {
+                self.draw_bulk_editor(ui, is_readonly, log);
+            } else if let Some(note) = self.selected_note {
                 self.draw_note_editor(ui, note, is_readonly, log);
             } else {
                 ui.label("Select a note from the keyboard above");
@@ -551,17 +1048,57 @@ impl MappingEditor {
             ui.separator();
 
             ui.horizontal(|ui| {
-                if !is_readonly {
-                    if ui
+                if !is_readonly
+                    && ui
                         .add_enabled(self.is_modified, egui::Button::new("💾 Save"))
                         .clicked()
-                    {
-                        self.save_current(log);
-                    }
+                {
+                    self.save_current(log);
+                }
+
+                if ui
+                    .button("💾 Save As…")
+                    .on_hover_text("Save this mapping to a new file and switch to it")
+                    .clicked()
+                {
+                    self.save_as_name = self.next_version_name();
+                    self.show_save_as_dialog = true;
+                }
 
+                if !is_readonly {
                     if self.is_modified {
                         ui.colored_label(egui::Color32::from_rgb(255, 165, 0), "* Modified");
                     }
+
+                    if ui
+                        .button("🧹 Clean Up")
+                        .on_hover_text("Remove SetModifiers actions that don't change anything")
+                        .clicked()
+                    {
+                        self.clean_up(log);
+                    }
+                }
+
+                if ui
+                    .button("💾 Export…")
+                    .on_hover_text("Save a copy of this mapping anywhere")
+                    .clicked()
+                    && let Some(path) = mapping_file_dialog()
+                        .set_file_name(format!(
+                            "{}.json",
+                            self.available_mappings[self.selected_mapping_index].name
+                        ))
+                        .save_file()
+                {
+                    self.export_mapping(&path, log);
+                }
+
+                if ui
+                    .button("▶ Test Sweep")
+                    .on_hover_text("Dry run every mapped note and log the key events")
+                    .clicked()
+                {
+                    self.test_sweep(log);
                 }
             });
         });
@@ -605,6 +1142,27 @@ impl MappingEditor {
                 });
         }
 
+        // Save as dialog
+        if self.show_save_as_dialog {
+            egui::Window::new("Save Mapping As")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.label("New mapping name:");
+                    ui.text_edit_singleline(&mut self.save_as_name);
+
+                    ui.horizontal(|ui| {
+                        if ui.button("Save").clicked() {
+                            self.save_as(self.save_as_name.clone(), log);
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.show_save_as_dialog = false;
+                            self.save_as_name.clear();
+                        }
+                    });
+                });
+        }
+
         // Rename dialog
         if self.is_renaming {
             egui::Window::new("Rename Mapping")
@@ -634,12 +1192,56 @@ impl MappingEditor {
             self.draw_unsaved_dialog(ctx, log);
         }
 
+        // Autosave recovery dialog
+        if self.recoverable_autosave.is_some() {
+            self.draw_recovery_dialog(ctx, log);
+        }
+
         // Action editor dialog
         if self.show_action_dialog {
             self.draw_action_dialog(ctx, log);
         }
     }
 
+    fn draw_recovery_dialog(&mut self, ctx: &egui::Context, log: &mut Vec<String>) {
+        let mut should_recover = false;
+        let mut should_discard = false;
+
+        egui::Window::new("Recover Unsaved Changes")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label("This mapping has autosaved changes newer than the saved file.");
+                ui.label("Do you want to recover them?");
+
+                ui.add_space(10.0);
+
+                ui.horizontal(|ui| {
+                    if ui.button("♻ Recover").clicked() {
+                        should_recover = true;
+                    }
+                    if ui.button("🗑 Discard").clicked() {
+                        should_discard = true;
+                    }
+                });
+            });
+
+        if should_recover && let Some(autosave) = self.recoverable_autosave.take() {
+            match MappingConfig::from_file(&autosave) {
+                Ok(mapping) => {
+                    self.current_mapping = mapping;
+                    self.is_modified = true;
+                    log.push("Recovered autosaved changes (not saved yet)".to_string());
+                }
+                Err(e) => log.push(format!("Error recovering autosave: {}", e)),
+            }
+        } else if should_discard {
+            self.discard_autosave(log);
+            log.push("Discarded autosaved changes".to_string());
+        }
+    }
+
     fn draw_unsaved_dialog(&mut self, ctx: &egui::Context, log: &mut Vec<String>) {
         let mut should_save = false;
         let mut should_discard = false;
@@ -682,6 +1284,7 @@ impl MappingEditor {
             }
         } else if should_discard {
             self.is_modified = false;
+            self.discard_autosave(log);
             if matches!(self.pending_action, Some(PendingAction::SwitchToMainTab)) {
                 self.switch_to_main_requested = true;
             } else {
@@ -738,12 +1341,67 @@ impl MappingEditor {
                         ActionType::Delay,
                         "Delay",
                     );
+                    ui.selectable_value(
+                        &mut self.action_editor.action_type,
+                        ActionType::Log,
+                        "Log",
+                    );
+                    ui.selectable_value(
+                        &mut self.action_editor.action_type,
+                        ActionType::Repeat,
+                        "Repeat",
+                    );
+                    ui.selectable_value(
+                        &mut self.action_editor.action_type,
+                        ActionType::ReleaseCurrent,
+                        "ReleaseCurrent",
+                    );
+                    ui.selectable_value(
+                        &mut self.action_editor.action_type,
+                        ActionType::Chord,
+                        "Chord",
+                    );
+                    ui.selectable_value(
+                        &mut self.action_editor.action_type,
+                        ActionType::TypeText,
+                        "Type Text",
+                    );
+                    ui.selectable_value(
+                        &mut self.action_editor.action_type,
+                        ActionType::Toggle,
+                        "Toggle",
+                    );
+                    ui.selectable_value(
+                        &mut self.action_editor.action_type,
+                        ActionType::RepeatHeld,
+                        "Repeat While Held",
+                    );
+                    ui.selectable_value(
+                        &mut self.action_editor.action_type,
+                        ActionType::SwitchLayer,
+                        "Switch Layer",
+                    );
                 });
 
                 ui.separator();
 
                 match self.action_editor.action_type {
-                    ActionType::Press | ActionType::Release => {
+                    ActionType::Press
+                    | ActionType::Release
+                    | ActionType::Toggle
+                    | ActionType::RepeatHeld => {
+                        if self.action_editor.action_type == ActionType::RepeatHeld {
+                            ui.horizontal(|ui| {
+                                ui.label("Tap every (milliseconds):");
+                                ui.text_edit_singleline(&mut self.action_editor.interval_ms);
+                            });
+                            if self.action_editor.interval_ms.parse::<u64>().is_err() {
+                                ui.colored_label(
+                                    egui::Color32::RED,
+                                    "⚠ Please enter a valid number",
+                                );
+                            }
+                        }
                         ui.label("Press a key:");
 
                         // Key capture area
@@ -759,8 +1417,20 @@ impl MappingEditor {
                             self.action_editor.capturing_key = true;
                         }
 
-                        // Capture key input
-                        if self.action_editor.capturing_key {
+                        egui::ComboBox::from_label("or pick from the list")
+                            .selected_text(format!("{:?}", self.action_editor.selected_key))
+                            .show_ui(ui, |ui| {
+                                for key in Key::all() {
+                                    ui.selectable_value(
+                                        &mut self.action_editor.selected_key,
+                                        *key,
+                                        format!("{:?}", key),
+                                    );
+                                }
+                            });
+
+                        // Capture key input
+                        if self.action_editor.capturing_key {
                             ui.colored_label(
                                 egui::Color32::from_rgb(255, 165, 0),
                                 "Waiting for key press...",
@@ -785,6 +1455,14 @@ impl MappingEditor {
                                 .color(egui::Color32::GRAY),
                         );
                     }
+                    ActionType::SwitchLayer => {
+                        ui.label("Layer (1 = the first in \"layers\", 0 = primary mappings):");
+                        ui.text_edit_singleline(&mut self.action_editor.layer);
+
+                        if self.action_editor.layer.parse::<u8>().is_err() {
+                            ui.colored_label(egui::Color32::RED, "⚠ Please enter a number (0-255)");
+                        }
+                    }
                     ActionType::Delay => {
                         ui.label("Delay (milliseconds):");
                         ui.text_edit_singleline(&mut self.action_editor.delay_ms);
@@ -798,6 +1476,143 @@ impl MappingEditor {
                         ui.checkbox(&mut self.action_editor.shift, "Shift");
                         ui.checkbox(&mut self.action_editor.ctrl, "Ctrl");
                         ui.checkbox(&mut self.action_editor.alt, "Alt");
+
+                        egui::ComboBox::from_label("Side")
+                            .selected_text(modifier_side_label(self.action_editor.side))
+                            .show_ui(ui, |ui| {
+                                for side in [
+                                    None,
+                                    Some(ModifierSide::Generic),
+                                    Some(ModifierSide::Left),
+                                    Some(ModifierSide::Right),
+                                ] {
+                                    ui.selectable_value(
+                                        &mut self.action_editor.side,
+                                        side,
+                                        modifier_side_label(side),
+                                    );
+                                }
+                            });
+                    }
+                    ActionType::Chord => {
+                        ui.label("Keys pressed together:");
+                        let mut removed = None;
+                        ui.horizontal_wrapped(|ui| {
+                            if self.action_editor.chord_keys.is_empty() {
+                                ui.weak("(none yet)");
+                            }
+                            for (i, key) in self.action_editor.chord_keys.iter().enumerate() {
+                                if ui
+                                    .button(format!("{:?} ✖", key))
+                                    .on_hover_text("Remove from the chord")
+                                    .clicked()
+                                {
+                                    removed = Some(i);
+                                }
+                            }
+                        });
+                        if let Some(i) = removed {
+                            self.action_editor.chord_keys.remove(i);
+                        }
+
+                        let mut added = None;
+                        egui::ComboBox::from_label("Add key")
+                            .selected_text("Pick a key…")
+                            .show_ui(ui, |ui| {
+                                for key in Key::all() {
+                                    if !self.action_editor.chord_keys.contains(key)
+                                        && ui.selectable_label(false, format!("{:?}", key)).clicked()
+                                    {
+                                        added = Some(*key);
+                                    }
+                                }
+                            });
+                        if let Some(key) = added {
+                            self.action_editor.chord_keys.push(key);
+                        }
+
+                        ui.label(
+                            egui::RichText::new(
+                                "All keys go down at once and are released together by the next note or the note-off",
+                            )
+                            .small()
+                            .italics()
+                            .color(egui::Color32::GRAY),
+                        );
+                    }
+                    ActionType::ReleaseCurrent => {
+                        ui.label(
+                            egui::RichText::new(
+                                "Releases the key of the note currently playing; modifiers stay held",
+                            )
+                            .small()
+                            .italics()
+                            .color(egui::Color32::GRAY),
+                        );
+                    }
+                    ActionType::TypeText => {
+                        ui.label("Text to type:");
+                        ui.add(
+                            egui::TextEdit::multiline(&mut self.action_editor.text)
+                                .hint_text("/p Hello!")
+                                .desired_rows(3),
+                        );
+                        ui.label(
+                            egui::RichText::new(
+                                "Typed as-is (open the chat box first, e.g. with an Enter press). \
+                                 Held modifiers are released before typing",
+                            )
+                            .small()
+                            .italics()
+                            .color(egui::Color32::GRAY),
+                        );
+                    }
+                    ActionType::Log => {
+                        ui.label("Log message:");
+                        ui.text_edit_singleline(&mut self.action_editor.log_message);
+                        ui.label(
+                            egui::RichText::new(
+                                "Written to the log when executed; no keys are pressed",
+                            )
+                            .small()
+                            .italics()
+                            .color(egui::Color32::GRAY),
+                        );
+                    }
+                    ActionType::Repeat => {
+                        ui.label("Repeat count:");
+                        ui.text_edit_singleline(&mut self.action_editor.repeat_count);
+                        if self.action_editor.repeat_count.parse::<u32>().is_err() {
+                            ui.colored_label(egui::Color32::RED, "⚠ Please enter a valid number");
+                        }
+
+                        ui.label("Actions to repeat (JSON list, may contain nested Repeats):");
+                        ui.add(
+                            egui::TextEdit::multiline(&mut self.action_editor.repeat_actions)
+                                .code_editor()
+                                .desired_rows(6),
+                        );
+                        match self.action_editor.parse_repeat_actions() {
+                            Some(actions) if actions.is_empty() => {
+                                ui.weak("(empty)");
+                            }
+                            Some(actions) => {
+                                for action in &actions {
+                                    ui.weak(action.to_string());
+                                }
+                            }
+                            None => {
+                                ui.colored_label(egui::Color32::RED, "⚠ Invalid action list");
+                            }
+                        }
+                        ui.label(
+                            egui::RichText::new(
+                                r#"e.g. [{"Press": "Q"}, {"Delay": 30}, {"Release": "Q"}]"#,
+                            )
+                            .small()
+                            .italics()
+                            .color(egui::Color32::GRAY),
+                        );
                     }
                 }
 
@@ -820,29 +1635,27 @@ impl MappingEditor {
                 });
             });
 
-        if should_save {
-            if let Some(action) = self.action_editor.build_action() {
-                if let (Some(note), Some((list_type, index))) =
-                    (self.selected_note, self.editing_action_index)
-                {
-                    let mapping = self.current_mapping.mappings.get_mut(&note).unwrap();
-                    let actions = match list_type {
-                        ActionListType::OnPress => &mut mapping.on_press,
-                        ActionListType::OnRelease => &mut mapping.on_release,
-                    };
+        if should_save
+            && let Some(action) = self.action_editor.build_action()
+            && let (Some(note), Some((list_type, index))) =
+                (self.selected_note, self.editing_action_index)
+        {
+            let mapping = self.current_mapping.mappings.get_mut(&note).unwrap();
+            let actions = match list_type {
+                ActionListType::OnPress => &mut mapping.on_press,
+                ActionListType::OnRelease => &mut mapping.on_release,
+            };
 
-                    if index < actions.len() {
-                        // Edit existing action
-                        actions[index] = action;
-                        log.push(format!("Updated action at index {}", index));
-                    } else {
-                        // Add new action
-                        actions.push(action);
-                        log.push("Added new action".to_string());
-                    }
-                    self.is_modified = true;
-                }
+            if index < actions.len() {
+                // Edit existing action
+                actions[index] = action;
+                log.push(format!("Updated action at index {}", index));
+            } else {
+                // Add new action
+                actions.push(action);
+                log.push("Added new action".to_string());
             }
+            self.is_modified = true;
         }
 
         if should_close {
@@ -853,185 +1666,70 @@ impl MappingEditor {
 
     fn capture_key_input(&self, ui: &egui::Ui) -> Option<Key> {
         ui.input(|i| {
-            // Check letter keys
-            for (egui_key, our_key) in [
-                (egui::Key::A, Key::A),
-                (egui::Key::B, Key::B),
-                (egui::Key::C, Key::C),
-                (egui::Key::D, Key::D),
-                (egui::Key::E, Key::E),
-                (egui::Key::F, Key::F),
-                (egui::Key::G, Key::G),
-                (egui::Key::H, Key::H),
-                (egui::Key::I, Key::I),
-                (egui::Key::J, Key::J),
-                (egui::Key::K, Key::K),
-                (egui::Key::L, Key::L),
-                (egui::Key::M, Key::M),
-                (egui::Key::N, Key::N),
-                (egui::Key::O, Key::O),
-                (egui::Key::P, Key::P),
-                (egui::Key::Q, Key::Q),
-                (egui::Key::R, Key::R),
-                (egui::Key::S, Key::S),
-                (egui::Key::T, Key::T),
-                (egui::Key::U, Key::U),
-                (egui::Key::V, Key::V),
-                (egui::Key::W, Key::W),
-                (egui::Key::X, Key::X),
-                (egui::Key::Y, Key::Y),
-                (egui::Key::Z, Key::Z),
-            ] {
-                if i.key_pressed(egui_key) {
-                    return Some(our_key);
-                }
-            }
-
-            // Check number keys
-            for (egui_key, our_key) in [
-                (egui::Key::Num0, Key::Num0),
-                (egui::Key::Num1, Key::Num1),
-                (egui::Key::Num2, Key::Num2),
-                (egui::Key::Num3, Key::Num3),
-                (egui::Key::Num4, Key::Num4),
-                (egui::Key::Num5, Key::Num5),
-                (egui::Key::Num6, Key::Num6),
-                (egui::Key::Num7, Key::Num7),
-                (egui::Key::Num8, Key::Num8),
-                (egui::Key::Num9, Key::Num9),
-            ] {
-                if i.key_pressed(egui_key) {
-                    return Some(our_key);
-                }
-            }
-
-            // Check function keys
-            for (egui_key, our_key) in [
-                (egui::Key::F1, Key::F1),
-                (egui::Key::F2, Key::F2),
-                (egui::Key::F3, Key::F3),
-                (egui::Key::F4, Key::F4),
-                (egui::Key::F5, Key::F5),
-                (egui::Key::F6, Key::F6),
-                (egui::Key::F7, Key::F7),
-                (egui::Key::F8, Key::F8),
-                (egui::Key::F9, Key::F9),
-                (egui::Key::F10, Key::F10),
-                (egui::Key::F11, Key::F11),
-                (egui::Key::F12, Key::F12),
-            ] {
-                if i.key_pressed(egui_key) {
-                    return Some(our_key);
-                }
-            }
-
-            // Check special keys
-            if i.key_pressed(egui::Key::Space) {
-                return Some(Key::Space);
-            }
-            if i.key_pressed(egui::Key::Enter) {
-                return Some(Key::Enter);
-            }
-            if i.key_pressed(egui::Key::Tab) {
-                return Some(Key::Tab);
-            }
-            if i.key_pressed(egui::Key::Backspace) {
-                return Some(Key::Backspace);
-            }
-            if i.key_pressed(egui::Key::ArrowUp) {
-                return Some(Key::Up);
-            }
-            if i.key_pressed(egui::Key::ArrowDown) {
-                return Some(Key::Down);
-            }
-            if i.key_pressed(egui::Key::ArrowLeft) {
-                return Some(Key::Left);
-            }
-            if i.key_pressed(egui::Key::ArrowRight) {
-                return Some(Key::Right);
-            }
-
-            None
+            Key::all()
+                .iter()
+                .copied()
+                // Escape cancels the capture instead
+                .filter(|key| *key != Key::Escape)
+                .find(|key| egui_key(*key).is_some_and(|k| i.key_pressed(k)))
         })
     }
 
-    fn draw_midi_keyboard(&mut self, ui: &mut egui::Ui) {
+    fn draw_midi_keyboard(&mut self, ui: &mut egui::Ui, log: &mut Vec<String>) {
+        let range = piano_range_controls(
+            ui,
+            &mut self.keyboard_view,
+            self.current_mapping.display_range(),
+            self.octave_convention,
+        );
         let (rect, response) = ui.allocate_exact_size(
             egui::vec2(ui.available_width(), 100.0),
             egui::Sense::click(),
         );
 
         let painter = ui.painter_at(rect);
+        let layout = PianoLayout::new(rect, range);
+        let note_at = |pos: egui::Pos2| layout.note_at(pos);
 
-        let start_note = 36;
-        let end_note = 96;
-
-        let mut white_notes = Vec::new();
-        for note in start_note..=end_note {
-            let note_in_octave = note % 12;
-            if !matches!(note_in_octave, 1 | 3 | 6 | 8 | 10) {
-                white_notes.push(note);
-            }
-        }
-
-        let num_white_keys = white_notes.len();
-        let white_key_width = rect.width() / num_white_keys as f32;
-        let white_key_height = rect.height();
-        let black_key_width = white_key_width * 0.7;
-        let black_key_height = white_key_height * 0.6;
-
-        if response.clicked() {
-            if let Some(pos) = response.interact_pointer_pos() {
-                let relative_pos = pos - rect.min;
-
-                let mut clicked_note = None;
-                for (i, &note) in white_notes.iter().enumerate() {
-                    let note_in_octave = note % 12;
-                    if !matches!(note_in_octave, 4 | 11) && i < num_white_keys - 1 {
-                        let black_note = note + 1;
-                        let x = (i as f32 + 1.0) * white_key_width - black_key_width / 2.0;
-
-                        if relative_pos.x >= x
-                            && relative_pos.x <= x + black_key_width
-                            && relative_pos.y <= black_key_height
-                        {
-                            clicked_note = Some(black_note);
-                            break;
-                        }
-                    }
-                }
-
-                if clicked_note.is_none() {
-                    let index = (relative_pos.x / white_key_width) as usize;
-                    if index < white_notes.len() {
-                        clicked_note = Some(white_notes[index]);
-                    }
+        if self.play_mode {
+            // Held key under the pointer; dragging across keys plays each in turn
+            let held = if response.is_pointer_button_down_on() {
+                response.interact_pointer_pos().and_then(note_at)
+            } else {
+                None
+            };
+            if held != self.played_note {
+                if let Some(note) = self.played_note.take() {
+                    self.play_note(note, false, log);
                 }
-
-                if let Some(note) = clicked_note {
-                    self.selected_note = Some(note);
+                if let Some(note) = held {
+                    self.play_note(note, true, log);
                 }
+                self.played_note = held;
             }
+        } else if response.clicked()
+            && let Some(note) = response.interact_pointer_pos().and_then(note_at)
+        {
+            let modifiers = ui.input(|i| i.modifiers);
+            self.select_note(note, modifiers.command, modifiers.shift);
         }
 
-        for (i, &note) in white_notes.iter().enumerate() {
-            let x = rect.min.x + i as f32 * white_key_width;
-            let is_selected = Some(note) == self.selected_note;
-            let has_mapping = self.current_mapping.mappings.contains_key(&note);
+        for (note, key_rect) in layout.white_keys() {
+            let is_selected = Some(note) == self.selected_note
+                || Some(note) == self.played_note
+                || self.selected_notes.contains(&note);
+            let mapping = self.current_mapping.mappings.get(&note);
 
             let color = if is_selected {
                 egui::Color32::from_rgb(100, 150, 255)
-            } else if has_mapping {
+            } else if mapping.is_some_and(|m| !m.enabled) {
+                egui::Color32::from_gray(190)
+            } else if mapping.is_some() {
                 egui::Color32::from_rgb(200, 255, 200)
             } else {
                 egui::Color32::WHITE
             };
 
-            let key_rect = egui::Rect::from_min_size(
-                egui::pos2(x, rect.min.y),
-                egui::vec2(white_key_width, white_key_height),
-            );
-
             painter.rect_filled(key_rect, 2.0, color);
             painter.rect_stroke(
                 key_rect,
@@ -1041,37 +1739,185 @@ impl MappingEditor {
             );
         }
 
-        for (i, &note) in white_notes.iter().enumerate() {
-            let note_in_octave = note % 12;
-            if !matches!(note_in_octave, 4 | 11) && i < num_white_keys - 1 {
-                let black_note = note + 1;
-                let x = rect.min.x + (i as f32 + 1.0) * white_key_width - black_key_width / 2.0;
+        for (black_note, key_rect) in layout.black_keys() {
+            let is_selected = Some(black_note) == self.selected_note
+                || Some(black_note) == self.played_note
+                || self.selected_notes.contains(&black_note);
+            let mapping = self.current_mapping.mappings.get(&black_note);
 
-                let is_selected = Some(black_note) == self.selected_note;
-                let has_mapping = self.current_mapping.mappings.contains_key(&black_note);
+            let color = if is_selected {
+                egui::Color32::from_rgb(50, 100, 200)
+            } else if mapping.is_some_and(|m| !m.enabled) {
+                egui::Color32::from_gray(90)
+            } else if mapping.is_some() {
+                egui::Color32::from_rgb(100, 200, 100)
+            } else {
+                egui::Color32::from_gray(40)
+            };
 
-                let color = if is_selected {
-                    egui::Color32::from_rgb(50, 100, 200)
-                } else if has_mapping {
-                    egui::Color32::from_rgb(100, 200, 100)
-                } else {
-                    egui::Color32::from_gray(40)
-                };
+            painter.rect_filled(key_rect, 1.0, color);
+            painter.rect_stroke(
+                key_rect,
+                1.0,
+                egui::Stroke::new(1.0, egui::Color32::BLACK),
+                egui::epaint::StrokeKind::Outside,
+            );
+        }
+    }
 
-                let key_rect = egui::Rect::from_min_size(
-                    egui::pos2(x, rect.min.y),
-                    egui::vec2(black_key_width, black_key_height),
-                );
+    /// Click on the editor keyboard: a plain click selects one note, `toggle`
+    /// (Ctrl/Cmd) adds or removes it, `extend` (Shift) selects the range from the
+    /// current note.
+    fn select_note(&mut self, note: u8, toggle: bool, extend: bool) {
+        match self.selected_note {
+            Some(anchor) if extend => {
+                self.selected_notes = (anchor.min(note)..=anchor.max(note)).collect();
+            }
+            Some(current) if toggle => {
+                if self.selected_notes.is_empty() {
+                    self.selected_notes.insert(current);
+                }
+                if !self.selected_notes.insert(note) {
+                    self.selected_notes.remove(&note);
+                }
+                self.selected_note = self
+                    .selected_notes
+                    .contains(&note)
+                    .then_some(note)
+                    .or_else(|| self.selected_notes.first().copied());
+            }
+            _ => {
+                self.selected_notes.clear();
+                self.selected_note = Some(note);
+            }
+        }
+    }
 
-                painter.rect_filled(key_rect, 1.0, color);
-                painter.rect_stroke(
-                    key_rect,
-                    1.0,
-                    egui::Stroke::new(1.0, egui::Color32::BLACK),
-                    egui::epaint::StrokeKind::Outside,
-                );
+    /// Edits applied to every note in `selected_notes` at once.
+    fn draw_bulk_editor(&mut self, ui: &mut egui::Ui, is_readonly: bool, log: &mut Vec<String>) {
+        let notes: Vec<u8> = self.selected_notes.iter().copied().collect();
+        let mapped = notes
+            .iter()
+            .filter(|n| self.current_mapping.mappings.contains_key(n))
+            .count();
+        ui.heading(format!(
+            "{} notes selected ({} mapped)",
+            notes.len(),
+            mapped
+        ));
+        ui.label("Ctrl-click to add or remove notes, Shift-click to select a range.");
+
+        ui.add_enabled_ui(!is_readonly, |ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.bulk_modifiers.shift, "Shift");
+                ui.checkbox(&mut self.bulk_modifiers.ctrl, "Ctrl");
+                ui.checkbox(&mut self.bulk_modifiers.alt, "Alt");
+                if ui.button("Set Modifiers").clicked() {
+                    for note in &notes {
+                        if let Some(mapping) = self.current_mapping.mappings.get_mut(note) {
+                            mapping.set_zone_modifiers(self.bulk_modifiers);
+                        }
+                    }
+                    self.is_modified = true;
+                    log.push(format!("Set modifiers on {} notes", mapped));
+                }
+            });
+
+            ui.horizontal(|ui| {
+                let source = self
+                    .selected_note
+                    .and_then(|n| self.current_mapping.mappings.get(&n).cloned());
+                if ui
+                    .add_enabled(source.is_some(), egui::Button::new("Copy Actions to All"))
+                    .on_hover_text("Give every selected note the last-clicked note's actions")
+                    .clicked()
+                    && let Some(source) = source
+                {
+                    for note in &notes {
+                        self.current_mapping.mappings.insert(*note, source.clone());
+                    }
+                    self.is_modified = true;
+                    log.push(format!("Copied actions to {} notes", notes.len()));
+                }
+
+                if ui.button("🗑 Delete Mappings").clicked() {
+                    for note in &notes {
+                        self.current_mapping.mappings.remove(note);
+                    }
+                    self.is_modified = true;
+                    log.push(format!("Deleted {} mappings", mapped));
+                }
+            });
+        });
+
+        if ui.button("Clear Selection").clicked() {
+            self.selected_notes.clear();
+        }
+    }
+
+    /// Pick up a changed default mapping. Only the read-only default entry is
+    /// touched, so edits to another mapping are kept.
+    fn reload_default(&mut self, log: &mut Vec<String>) {
+        for option in self.available_mappings.iter_mut() {
+            if option.path.is_none() {
+                *option = default_mapping_option();
+            }
+        }
+        let editing_default = self
+            .available_mappings
+            .get(self.selected_mapping_index)
+            .is_some_and(|m| m.path.is_none());
+        if editing_default {
+            self.current_mapping = default_mapping();
+            log.push("Reloaded default mapping".to_string());
+        }
+    }
+
+    /// Send a note from the on-screen keyboard through the play-mode engine, which
+    /// runs the mapping being edited (including unsaved changes).
+    fn play_note(&mut self, note: u8, is_on: bool, log: &mut Vec<String>) {
+        if self.play_engine.is_none() {
+            match EnigoKeyboardController::new_with_settings(self.current_mapping.keyboard) {
+                Ok(keyboard) => {
+                    self.play_engine =
+                        Some(MidiEngine::new(keyboard, self.current_mapping.clone()));
+                }
+                Err(e) => {
+                    log.push(format!("Play mode unavailable: {}", e));
+                    self.play_mode = false;
+                    return;
+                }
             }
         }
+        let (Some(engine), Ok(midi_note)) =
+            (&self.play_engine, xiv_midi::midi::MidiNote::new(note))
+        else {
+            return;
+        };
+
+        if is_on {
+            *engine.mapping().lock().unwrap() = self.current_mapping.clone();
+        }
+        engine.inject(MidiMessage {
+            event_type: if is_on {
+                MidiEventType::NoteOn
+            } else {
+                MidiEventType::NoteOff
+            },
+            channel: self.current_mapping.channel.unwrap_or(0),
+            note: midi_note,
+            velocity: if is_on { 100 } else { 0 },
+        });
+    }
+
+    /// Leave play mode, releasing anything the on-screen keyboard still holds.
+    fn stop_playing(&mut self) {
+        self.played_note = None;
+        if let Some(engine) = self.play_engine.take()
+            && let Err(e) = engine.stop()
+        {
+            tracing::error!("Error releasing play-mode keys: {}", e);
+        }
     }
 
     fn draw_note_editor(
@@ -1082,7 +1928,7 @@ impl MappingEditor {
         log: &mut Vec<String>,
     ) {
         let note_name = xiv_midi::midi::MidiNote::new(note)
-            .map(|n| n.full_name())
+            .map(|n| n.full_name_with(self.octave_convention))
             .unwrap_or_else(|_| note.to_string());
 
         ui.heading(format!("Note: {} (MIDI {})", note_name, note));
@@ -1093,17 +1939,39 @@ impl MappingEditor {
             ui.label("No mapping defined");
 
             if !is_readonly && ui.button("+ Add Mapping").clicked() {
-                self.current_mapping.mappings.insert(
-                    note,
-                    NoteMapping {
-                        on_press: vec![],
-                        on_release: vec![],
-                    },
-                );
+                self.current_mapping
+                    .mappings
+                    .insert(note, NoteMapping::default());
                 self.is_modified = true;
                 log.push(format!("Added mapping for note {}", note));
             }
         } else {
+            if let Some(mapping) = self.current_mapping.mappings.get_mut(&note) {
+                let mut enabled = mapping.enabled;
+                let toggle = ui
+                    .add_enabled(!is_readonly, egui::Checkbox::new(&mut enabled, "Enabled"))
+                    .on_hover_text("Disabled mappings keep their actions but are ignored");
+                if toggle.changed() {
+                    mapping.enabled = enabled;
+                    self.is_modified = true;
+                    let state = if enabled { "Enabled" } else { "Disabled" };
+                    log.push(format!("{} mapping for note {}", state, note));
+                }
+
+                let invert = ui
+                    .add_enabled(
+                        !is_readonly,
+                        egui::Checkbox::new(&mut mapping.invert, "Inverted"),
+                    )
+                    .on_hover_text(
+                        "Run On Press on note-off and On Release on note-on, \
+                         for foot switches that send note-off when pressed",
+                    );
+                if invert.changed() {
+                    self.is_modified = true;
+                }
+            }
+
             // Draw action lists
             egui::ScrollArea::vertical()
                 .max_height(400.0)
@@ -1173,7 +2041,7 @@ impl MappingEditor {
                         }
 
                         // Action display
-                        let action_text = format_action(action);
+                        let action_text = action.to_string();
                         ui.label(action_text);
 
                         ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
@@ -1198,12 +2066,10 @@ impl MappingEditor {
                 );
             }
 
-            if !is_readonly {
-                if ui.button("+ Add Action").clicked() {
-                    self.action_editor.reset();
-                    self.editing_action_index = Some((list_type, actions.len()));
-                    self.show_action_dialog = true;
-                }
+            if !is_readonly && ui.button("+ Add Action").clicked() {
+                self.action_editor.reset();
+                self.editing_action_index = Some((list_type, actions.len()));
+                self.show_action_dialog = true;
             }
         });
 
@@ -1242,57 +2108,237 @@ impl MappingEditor {
     }
 }
 
-fn format_action(action: &Action) -> String {
-    match action {
-        Action::Press(key) => format!("Press: {:?}", key),
-        Action::Release(key) => format!("Release: {:?}", key),
-        Action::Delay(ms) => format!("Delay: {}ms", ms),
-        Action::SetModifiers { shift, ctrl, alt } => {
-            let mut parts = Vec::new();
-            if *shift {
-                parts.push("Shift");
-            }
-            if *ctrl {
-                parts.push("Ctrl");
-            }
-            if *alt {
-                parts.push("Alt");
-            }
-            if parts.is_empty() {
-                "SetModifiers: None".to_string()
-            } else {
-                format!("SetModifiers: {}", parts.join(" + "))
+/// The egui key for one of ours, if egui reports it (modifier keys can't be captured).
+fn egui_key(key: Key) -> Option<egui::Key> {
+    // egui reports numpad keys as their main-keyboard equivalents (both Enter keys
+    // as `Enter`, numpad digits as digits), so a capture always yields the main
+    // key; numpad keys have to be picked from the list
+    if Key::numpad().contains(&key) {
+        return None;
+    }
+    // Other keys, punctuation included, share egui's names
+    let name = format!("{:?}", key);
+    // egui names the top-row digits "0".."9"
+    let name = name.strip_prefix("Num").unwrap_or(&name);
+    egui::Key::from_name(name)
+}
+
+/// Extension to give a copy of the mapping file at `path`: YAML files stay YAML,
+/// everything else is written as JSON.
+fn mapping_extension(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("yml") => "yml",
+        _ if is_yaml_path(path) => "yaml",
+        _ => "json",
+    }
+}
+
+/// The file in `dir` holding a mapping called `name`, under any mapping extension.
+fn existing_mapping_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    MAPPING_EXTENSIONS
+        .iter()
+        .map(|extension| dir.join(format!("{}.{}", name, extension)))
+        .find(|path| path.exists())
+}
+
+/// File dialog filtered to mapping files.
+fn mapping_file_dialog() -> rfd::FileDialog {
+    rfd::FileDialog::new()
+        .add_filter("Mapping", &MAPPING_EXTENSIONS)
+        .add_filter("All files", &["*"])
+}
+
+/// Scan the exe-relative `mappings/` directory, with the built-in default first.
+/// Without `include_default` the default is only listed if there are no files,
+/// so there is always something to select.
+fn scan_mapping_options(include_default: bool, log: &mut Vec<String>) -> Vec<MappingOption> {
+    let mut options = Vec::new();
+
+    if let Ok(exe_path) = std::env::current_exe()
+        && let Some(exe_dir) = exe_path.parent()
+    {
+        let mappings_dir = exe_dir.join("mappings");
+
+        if mappings_dir.exists() && mappings_dir.is_dir() {
+            match std::fs::read_dir(&mappings_dir) {
+                Ok(entries) => {
+                    let mut files: Vec<_> = entries
+                        .filter_map(|entry| entry.ok())
+                        .filter(|entry| {
+                            let path = entry.path();
+                            path.extension().is_some_and(|ext| ext == "json") || is_yaml_path(&path)
+                        })
+                        .collect();
+
+                    files.sort_by_key(|entry| entry.file_name());
+
+                    for entry in files {
+                        let path = entry.path();
+                        let name = path
+                            .file_stem()
+                            .and_then(|s| s.to_str())
+                            .unwrap_or("Unknown")
+                            .to_string();
+
+                        options.push(MappingOption {
+                            name,
+                            summary: mapping_summary(&path),
+                            path: Some(path),
+                            is_readonly: false,
+                        });
+                    }
+
+                    log.push(format!("Found {} mapping file(s)", options.len()));
+                }
+                Err(e) => {
+                    log.push(format!("Error reading mappings directory: {}", e));
+                }
             }
         }
     }
+
+    if include_default || options.is_empty() {
+        options.insert(0, default_mapping_option());
+    }
+
+    options
+}
+
+/// The built-in default mapping's entry in the mapping lists
+fn default_mapping_option() -> MappingOption {
+    MappingOption {
+        name: "Default FFXIV".to_string(),
+        path: None,
+        is_readonly: true,
+        summary: default_mapping().summary(),
+    }
+}
+
+/// The read-only default mapping: `default.json` next to the executable if present
+/// and valid, otherwise the built-in FFXIV layout.
+fn default_mapping() -> MappingConfig {
+    let override_path = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join("default.json")))
+        .filter(|path| path.exists());
+    if let Some(path) = override_path {
+        match MappingConfig::from_file(&path) {
+            Ok(mapping) => return mapping,
+            Err(e) => tracing::warn!("Ignoring invalid {}: {}", path.display(), e),
+        }
+    }
+    create_ffxiv_default_mapping()
+}
+
+fn modifier_side_label(side: Option<ModifierSide>) -> &'static str {
+    match side {
+        None => "Engine default",
+        Some(ModifierSide::Generic) => "Generic",
+        Some(ModifierSide::Left) => "Left",
+        Some(ModifierSide::Right) => "Right",
+    }
+}
+
+fn octave_convention_label(convention: OctaveConvention) -> &'static str {
+    match convention {
+        OctaveConvention::Yamaha => "Middle C = C3",
+        OctaveConvention::Scientific => "Middle C = C4",
+        OctaveConvention::MiddleC5 => "Middle C = C5",
+    }
+}
+
+fn settings_path() -> Option<PathBuf> {
+    let exe_path = std::env::current_exe().ok()?;
+    Some(exe_path.parent()?.join("settings.json"))
+}
+
+/// Load `settings.json`, falling back to defaults if it is missing or invalid.
+fn load_settings() -> AppSettings {
+    let Some(path) = settings_path() else {
+        return AppSettings::default();
+    };
+    match std::fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring invalid settings file {}: {}", path.display(), e);
+            AppSettings::default()
+        }),
+        Err(_) => AppSettings::default(),
+    }
+}
+
+fn save_settings(settings: &AppSettings) -> Result<(), String> {
+    let path = settings_path().ok_or("Cannot locate executable directory")?;
+    let content = serde_json::to_string_pretty(settings).map_err(|e| e.to_string())?;
+    std::fs::write(&path, content).map_err(|e| e.to_string())
+}
+
+/// Channel/range summary shown next to a mapping file, or "invalid" if it can't be parsed.
+fn mapping_summary(path: &Path) -> String {
+    match MappingConfig::from_file(path) {
+        Ok(mapping) => mapping.summary(),
+        Err(_) => "invalid".to_string(),
+    }
 }
 
 impl XivMidiApp {
-    fn new(_cc: &eframe::CreationContext<'_>) -> Self {
+    fn new(cc: &eframe::CreationContext<'_>) -> Self {
         let (event_tx, event_rx) = unbounded();
-        _cc.egui_ctx.all_styles_mut(|style| {
+        cc.egui_ctx.all_styles_mut(|style| {
             style.wrap_mode = Some(egui::TextWrapMode::Extend);
         });
+        let session: SessionState = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, SESSION_KEY))
+            .unwrap_or_default();
 
         let mut app = Self {
             devices: Vec::new(),
             selected_device: None,
+            midi_unavailable: false,
             connection: None,
-            available_mappings: Vec::new(),
+            connected_device: None,
+            reconnect_device: None,
+            reconnect_polled_at: Instant::now(),
+            available_mappings: vec![default_mapping_option()],
             selected_mapping_index: 0,
             mapping: create_ffxiv_default_mapping(),
-            engine_mapping: None,
+            engine: None,
             octave_transpose: false,
+            transpose_semitones: 0,
+            channel: None,
+            mutes: ZoneMutes::new(),
+            settings: load_settings(),
+            selected_profile: None,
+            new_profile_name: String::new(),
             editor: MappingEditor::new(),
             event_tx,
             event_rx,
+            scan_generation: 0,
+            scanning: false,
             log_messages: Vec::new(),
+            midi_monitor: Vec::new(),
+            note_history: Vec::new(),
+            started: Instant::now(),
             active_notes: HashMap::new(),
-            current_tab: AppTab::Main,
+            released_notes: HashMap::new(),
+            held_keys: Vec::new(),
+            piano_view: None,
+            keys_sent: Vec::new(),
+            current_tab: session.tab,
+            restored_mapping: session.mapping,
+            hud: None,
+            hud_moved_at: None,
             status: "Ready".to_string(),
         };
 
         app.refresh_devices();
+        if let Some(device) = session.device {
+            if app.devices.contains(&device) {
+                app.selected_device = Some(device);
+            } else {
+                app.log(format!("Last used device '{}' is not connected", device));
+            }
+        }
         app.scan_mapping_files();
         app
     }
@@ -1306,73 +2352,87 @@ impl XivMidiApp {
     }
 
     fn refresh_devices(&mut self) {
-        match MidiEngine::<EnigoKeyboardController>::list_devices() {
+        match MidiEngine::list_devices() {
             Ok(devices) => {
                 self.devices = devices;
+                self.midi_unavailable = false;
                 self.log(format!("Found {} MIDI device(s)", self.devices.len()));
             }
+            Err(xiv_midi::Error::NoMidiBackend) => {
+                self.devices.clear();
+                self.midi_unavailable = true;
+                self.log("No MIDI backend available on this system".to_string());
+            }
             Err(e) => {
                 self.log(format!("Error listing devices: {}", e));
             }
         }
     }
 
+    /// Rescan `mappings/` on a background thread; the list is replaced when
+    /// `AppEvent::MappingsScanned` arrives.
     fn scan_mapping_files(&mut self) {
-        self.available_mappings.clear();
+        // A scan still running (e.g. before "Hide default" was toggled) is superseded
+        self.scan_generation += 1;
+        self.scanning = true;
 
-        self.available_mappings.push(MappingOption {
-            name: "Default FFXIV".to_string(),
-            path: None,
-            is_readonly: true,
+        let generation = self.scan_generation;
+        let include_default = !self.settings.hide_default_mapping;
+        let event_tx = self.event_tx.clone();
+        std::thread::spawn(move || {
+            let mut log = Vec::new();
+            let options = scan_mapping_options(include_default, &mut log);
+            let _ = event_tx.send(AppEvent::MappingsScanned {
+                generation,
+                options,
+                log,
+            });
         });
+    }
 
-        if let Ok(exe_path) = std::env::current_exe() {
-            if let Some(exe_dir) = exe_path.parent() {
-                let mappings_dir = exe_dir.join("mappings");
-
-                if mappings_dir.exists() && mappings_dir.is_dir() {
-                    match std::fs::read_dir(&mappings_dir) {
-                        Ok(entries) => {
-                            let mut files: Vec<_> = entries
-                                .filter_map(|entry| entry.ok())
-                                .filter(|entry| {
-                                    entry
-                                        .path()
-                                        .extension()
-                                        .map(|ext| ext == "json")
-                                        .unwrap_or(false)
-                                })
-                                .collect();
-
-                            files.sort_by_key(|entry| entry.file_name());
-
-                            for entry in files {
-                                let path = entry.path();
-                                let name = path
-                                    .file_stem()
-                                    .and_then(|s| s.to_str())
-                                    .unwrap_or("Unknown")
-                                    .to_string();
-
-                                self.available_mappings.push(MappingOption {
-                                    name,
-                                    path: Some(path),
-                                    is_readonly: false,
-                                });
-                            }
+    fn apply_mapping_scan(&mut self, options: Vec<MappingOption>, log: Vec<String>) {
+        self.scanning = false;
+        let previous = self
+            .available_mappings
+            .get(self.selected_mapping_index)
+            .map(|m| m.name.clone());
 
-                            self.log(format!(
-                                "Found {} mapping file(s)",
-                                self.available_mappings.len() - 1
-                            ));
-                        }
-                        Err(e) => {
-                            self.log(format!("Error reading mappings directory: {}", e));
-                        }
-                    }
-                }
+        self.available_mappings = options;
+        for message in log {
+            self.log(message);
+        }
+
+        // Keep the same mapping selected if it is still listed; the first scan
+        // picks the one the last session used instead
+        let restored = self.restored_mapping.take();
+        let index = restored
+            .as_ref()
+            .or(previous.as_ref())
+            .and_then(|name| self.available_mappings.iter().position(|m| &m.name == name));
+        if let (Some(name), None) = (&restored, index) {
+            self.log(format!("Last used mapping '{}' is no longer listed", name));
+        }
+        self.selected_mapping_index = index.unwrap_or(0);
+        if index.is_none() || restored.is_some() {
+            self.load_selected_mapping();
+        }
+    }
+
+    /// Re-read the default mapping (built-in or `default.json`) into both mapping lists.
+    fn reload_default_mapping(&mut self) {
+        for option in self.available_mappings.iter_mut() {
+            if option.path.is_none() {
+                *option = default_mapping_option();
             }
         }
+        if self.available_mappings[self.selected_mapping_index]
+            .path
+            .is_none()
+        {
+            self.load_selected_mapping();
+        }
+        self.editor.reload_default(&mut self.log_messages);
+        self.log("Reloaded the default mapping".to_string());
     }
 
     fn load_selected_mapping(&mut self) {
@@ -1394,33 +2454,156 @@ impl XivMidiApp {
             }
         } else {
             self.log("Using default FFXIV mapping".to_string());
-            create_ffxiv_default_mapping()
+            default_mapping()
         };
+        self.channel = self.mapping.channel;
     }
 
-    fn connect_device(&mut self, device_name: String) {
-        self.log(format!("Connecting to '{}'...", device_name));
-
-        self.load_selected_mapping();
-
-        // Sync octave_transpose setting into the mapping before creating the engine
-        self.mapping.octave_transpose = self.octave_transpose;
+    fn persist_settings(&mut self) {
+        if let Err(e) = save_settings(&self.settings) {
+            self.log(format!("Error saving settings: {}", e));
+        }
+    }
 
-        let keyboard = match EnigoKeyboardController::new() {
-            Ok(k) => k,
-            Err(e) => {
-                self.log(format!("Error creating keyboard controller: {}", e));
-                return;
-            }
+    fn save_current_as_profile(&mut self, name: String) {
+        let Some(device) = self.selected_device.clone() else {
+            self.log("Select a device before saving a profile".to_string());
+            return;
+        };
+        let profile = ConnectionProfile {
+            name: name.clone(),
+            device,
+            mapping: self.available_mappings[self.selected_mapping_index]
+                .name
+                .clone(),
+            channel: self.channel,
+            octave_transpose: self.octave_transpose,
+            transpose_semitones: self.transpose_semitones,
         };
 
-        let engine = MidiEngine::new(keyboard, self.mapping.clone());
+        match self.settings.profiles.iter().position(|p| p.name == name) {
+            Some(index) => {
+                self.settings.profiles[index] = profile;
+                self.selected_profile = Some(index);
+            }
+            None => {
+                self.settings.profiles.push(profile);
+                self.selected_profile = Some(self.settings.profiles.len() - 1);
+            }
+        }
+        self.persist_settings();
+        self.log(format!("Saved profile '{}'", name));
+    }
 
-        // Keep a reference to the engine's shared mapping for live updates
-        let shared_mapping = engine.mapping();
+    fn delete_selected_profile(&mut self) {
+        if let Some(index) = self.selected_profile.take()
+            && index < self.settings.profiles.len()
+        {
+            let profile = self.settings.profiles.remove(index);
+            self.persist_settings();
+            self.log(format!("Deleted profile '{}'", profile.name));
+        }
+    }
+
+    /// Select the profile's mapping and settings, then connect if its device is present.
+    fn connect_profile(&mut self, index: usize) {
+        let Some(profile) = self.settings.profiles.get(index).cloned() else {
+            return;
+        };
+        self.log(format!("Applying profile '{}'", profile.name));
+
+        self.disconnect_device();
+
+        match self
+            .available_mappings
+            .iter()
+            .position(|m| m.name == profile.mapping)
+        {
+            Some(mapping_index) => self.selected_mapping_index = mapping_index,
+            None => {
+                self.log(format!(
+                    "Mapping '{}' not found, keeping current selection",
+                    profile.mapping
+                ));
+            }
+        }
+        self.load_selected_mapping();
+        self.channel = profile.channel;
+        self.octave_transpose = profile.octave_transpose;
+        self.transpose_semitones = profile.transpose_semitones;
+
+        self.refresh_devices();
+        if self.devices.contains(&profile.device) {
+            self.selected_device = Some(profile.device.clone());
+            self.connect_device(profile.device);
+        } else {
+            self.log(format!("Device '{}' not found", profile.device));
+            self.status = "Profile device not found".to_string();
+        }
+    }
+
+    fn connect_device(&mut self, device_name: String) {
+        self.log(format!("Connecting to '{}'...", device_name));
+
+        let channel = self.channel;
+        self.load_selected_mapping();
+
+        // Sync channel and transpose settings into the mapping before creating the engine
+        self.channel = channel;
+        self.mapping.channel = self.channel;
+        self.mapping.octave_transpose = self.octave_transpose;
+        self.mapping.transpose_semitones = self.transpose_semitones;
+
+        let keyboard = match EnigoKeyboardController::new_with_settings(self.mapping.keyboard) {
+            Ok(k) => k,
+            Err(e) => {
+                self.log(format!("Error creating keyboard controller: {}", e));
+                return;
+            }
+        };
+
+        for warning in self.mapping.validate() {
+            self.log(format!("Warning: {}", warning));
+        }
+
+        let mut engine = MidiEngine::new(keyboard, self.mapping.clone());
+        engine.set_mutes(self.mutes.clone());
+        if let Some(feedback) = &self.mapping.feedback
+            && let Err(e) = engine.open_feedback(&feedback.port)
+        {
+            self.log(format!(
+                "Error opening feedback output '{}': {}",
+                feedback.port, e
+            ));
+        }
+        engine.apply_timing(self.settings.timing());
+        if self.settings.require_game_focus {
+            engine.set_focus_check(window_focus_check(GAME_WINDOW_TITLE));
+        }
+
+        let log_tx = self.event_tx.clone();
+        engine.set_log_callback(move |message| {
+            let _ = log_tx.send(AppEvent::EngineLog(message.to_string()));
+        });
+
+        let raw_tx = self.event_tx.clone();
+        engine.set_raw_callback(move |bytes| {
+            let _ = raw_tx.send(AppEvent::RawMidi(bytes.to_vec()));
+        });
+
+        let key_tx = self.event_tx.clone();
+        engine.set_key_event_callback(move |event| {
+            let _ = key_tx.send(AppEvent::KeyEvent(event));
+        });
 
         let event_tx = self.event_tx.clone();
         match engine.connect_with_callback(&device_name, move |msg| {
+            if !matches!(
+                msg.event_type,
+                MidiEventType::NoteOn | MidiEventType::NoteOff
+            ) {
+                return;
+            }
             let _ = event_tx.send(AppEvent::MidiEvent {
                 note: msg.note.value(),
                 velocity: msg.velocity,
@@ -1429,7 +2612,9 @@ impl XivMidiApp {
         }) {
             Ok(conn) => {
                 self.connection = Some(conn);
-                self.engine_mapping = Some(shared_mapping);
+                self.connected_device = Some(device_name.clone());
+                self.reconnect_device = None;
+                self.engine = Some(engine);
                 self.status = format!("Connected to '{}'", device_name);
                 self.log(format!("Successfully connected to '{}'", device_name));
                 let _ = self
@@ -1439,14 +2624,60 @@ impl XivMidiApp {
             Err(e) => {
                 self.log(format!("Error connecting: {}", e));
                 self.status = "Connection failed".to_string();
+                if self.settings.auto_reconnect {
+                    self.wait_for_device(device_name);
+                }
             }
         }
     }
 
+    /// Retry connecting to `device_name` whenever `poll_reconnect` sees it listed.
+    fn wait_for_device(&mut self, device_name: String) {
+        self.status = format!("Waiting for '{}' to reconnect...", device_name);
+        self.reconnect_device = Some(device_name);
+    }
+
+    /// With auto-reconnect on, check the device list every `RECONNECT_POLL_INTERVAL`:
+    /// drop the connection if its device has gone, and reconnect (with the
+    /// selected mapping) once a lost device is back.
+    fn poll_reconnect(&mut self) {
+        if !self.settings.auto_reconnect
+            || (self.connected_device.is_none() && self.reconnect_device.is_none())
+            || self.reconnect_polled_at.elapsed() < RECONNECT_POLL_INTERVAL
+        {
+            return;
+        }
+        self.reconnect_polled_at = Instant::now();
+        let Ok(devices) = MidiEngine::list_devices() else {
+            return;
+        };
+
+        if let Some(device) = self.connected_device.clone()
+            && !devices.contains(&device)
+        {
+            self.log(format!("Device '{}' is gone", device));
+            self.disconnect_device();
+            self.wait_for_device(device);
+        } else if let Some(device) = self.reconnect_device.clone()
+            && devices.contains(&device)
+        {
+            self.devices = devices;
+            self.log(format!("Device '{}' is back, reconnecting", device));
+            self.connect_device(device);
+        }
+    }
+
     fn disconnect_device(&mut self) {
-        if self.connection.is_some() {
-            self.connection = None;
-            self.engine_mapping = None;
+        self.reconnect_device = None;
+        self.connected_device = None;
+        if let Some(connection) = self.connection.take() {
+            if let Some(engine) = self.engine.take()
+                && let Err(e) = engine.disconnect(connection)
+            {
+                tracing::error!("Error releasing keys on disconnect: {}", e);
+            }
+            self.held_keys.clear();
+            self.keys_sent.clear();
             self.status = "Disconnected".to_string();
             self.log("Disconnected from device".to_string());
             let _ = self.event_tx.send(AppEvent::DeviceDisconnected);
@@ -1467,10 +2698,63 @@ impl XivMidiApp {
                     velocity,
                     is_on,
                 } => {
+                    self.note_history.push(NoteHistoryEntry {
+                        time: self.started.elapsed(),
+                        note,
+                        velocity,
+                        is_on,
+                    });
+                    if self.note_history.len() > NOTE_HISTORY_LEN {
+                        self.note_history.remove(0);
+                    }
                     if is_on {
                         self.active_notes.insert(note, velocity);
+                        self.released_notes.remove(&note);
                     } else {
                         self.active_notes.remove(&note);
+                        if velocity > 0 {
+                            let fade = RELEASE_FADE.mul_f32(1.0 - velocity.min(127) as f32 / 128.0);
+                            self.released_notes.insert(note, (Instant::now(), fade));
+                        }
+                    }
+                }
+                AppEvent::EngineLog(message) => {
+                    self.log_messages.push(format!("[mapping] {}", message));
+                    if self.log_messages.len() > 100 {
+                        self.log_messages.remove(0);
+                    }
+                }
+                AppEvent::RawMidi(bytes) => {
+                    self.midi_monitor.push(MonitorEntry {
+                        time: self.started.elapsed(),
+                        parsed: describe_midi(&bytes, self.settings.octave_convention),
+                        bytes,
+                    });
+                    if self.midi_monitor.len() > 100 {
+                        self.midi_monitor.remove(0);
+                    }
+                }
+                AppEvent::KeyEvent(event) => {
+                    match event {
+                        KeyEvent::Press(key) => {
+                            if !self.held_keys.contains(&key) {
+                                self.held_keys.push(key);
+                            }
+                        }
+                        KeyEvent::Release(key) => self.held_keys.retain(|k| *k != key),
+                    }
+                    self.keys_sent.push(event);
+                    if self.keys_sent.len() > KEYS_SENT_SHOWN {
+                        self.keys_sent.remove(0);
+                    }
+                }
+                AppEvent::MappingsScanned {
+                    generation,
+                    options,
+                    log,
+                } => {
+                    if generation == self.scan_generation {
+                        self.apply_mapping_scan(options, log);
                     }
                 }
             }
@@ -1479,8 +2763,41 @@ impl XivMidiApp {
 }
 
 impl eframe::App for XivMidiApp {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        let session = SessionState {
+            device: self.selected_device.clone(),
+            mapping: self
+                .available_mappings
+                .get(self.selected_mapping_index)
+                .map(|m| m.name.clone()),
+            tab: self.current_tab,
+        };
+        eframe::set_value(storage, SESSION_KEY, &session);
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         self.process_events();
+        self.poll_reconnect();
+        if self.settings.auto_reconnect
+            && (self.connected_device.is_some() || self.reconnect_device.is_some())
+        {
+            ctx.request_repaint_after(RECONNECT_POLL_INTERVAL);
+        }
+        if self.scanning {
+            // Poll for the scan result even without input
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+
+        if ctx.input(|i| i.key_pressed(HUD_TOGGLE_KEY)) {
+            self.toggle_hud();
+        }
+        // Key capture may be recording the panic key itself as a mapped key
+        if !self.editor.action_editor.capturing_key && ctx.input(|i| i.key_pressed(PANIC_KEY)) {
+            self.panic();
+        }
+        if let Some(hud) = self.hud {
+            self.show_hud(ctx, hud);
+        }
 
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("XIV MIDI - FFXIV Performance Tool");
@@ -1506,7 +2823,8 @@ impl eframe::App for XivMidiApp {
                 {
                     self.current_tab = AppTab::Editor;
                     // Sync editor state when switching to editor tab
-                    self.editor.scan_mappings(&mut self.log_messages);
+                    self.editor.hide_default_mapping = self.settings.hide_default_mapping;
+                    self.editor.available_mappings = self.available_mappings.clone();
                     self.editor
                         .load_mapping(self.selected_mapping_index, &mut self.log_messages);
                 }
@@ -1517,7 +2835,11 @@ impl eframe::App for XivMidiApp {
             // Tab content
             match self.current_tab {
                 AppTab::Main => self.draw_main_tab(ui),
-                AppTab::Editor => self.editor.draw(ui, ctx, &mut self.log_messages),
+                AppTab::Editor => {
+                    self.editor.octave_convention = self.settings.octave_convention;
+                    self.editor.hide_default_mapping = self.settings.hide_default_mapping;
+                    self.editor.draw(ui, ctx, &mut self.log_messages)
+                }
             }
 
             // Handle tab switch request from editor
@@ -1529,10 +2851,216 @@ impl eframe::App for XivMidiApp {
 
         ctx.request_repaint();
     }
+
+    fn clear_color(&self, visuals: &egui::Visuals) -> [f32; 4] {
+        // Shared by every viewport; the main window's panel covers it anyway
+        if self.settings.hud.transparent {
+            [0.0; 4]
+        } else {
+            visuals.panel_fill.to_normalized_gamma_f32()
+        }
+    }
 }
 
 impl XivMidiApp {
+    /// Release every key held by the connection and the editor's play mode, e.g.
+    /// a modifier left stuck by a game hitch.
+    fn panic(&mut self) {
+        let errors: Vec<String> = self
+            .engine
+            .iter()
+            .chain(&self.editor.play_engine)
+            .filter_map(|engine| engine.panic().err())
+            .map(|e| format!("Error releasing keys: {}", e))
+            .collect();
+        for error in errors {
+            self.log(error);
+        }
+        self.log("Panic: released all keys".to_string());
+    }
+
+    fn toggle_hud(&mut self) {
+        if self.hud.take().is_none() {
+            self.hud = Some(self.settings.hud);
+        } else {
+            self.hud_moved_at = None;
+            self.persist_settings();
+        }
+    }
+
+    /// Show the HUD window built from `hud`. Drag it to move it; the toggle key or
+    /// Escape closes it and brings the full window back to the front.
+    fn show_hud(&mut self, ctx: &egui::Context, hud: HudSettings) {
+        let mut builder = egui::ViewportBuilder::default()
+            .with_title("XIV MIDI HUD")
+            .with_inner_size(hud.size)
+            .with_min_inner_size([200.0, 40.0])
+            .with_decorations(false)
+            .with_transparent(hud.transparent);
+        if hud.always_on_top {
+            builder = builder.with_always_on_top();
+        }
+        if let Some(position) = hud.position {
+            builder = builder.with_position(position);
+        }
+
+        let close = ctx.show_viewport_immediate(
+            egui::ViewportId::from_hash_of("hud"),
+            builder,
+            |ctx, class| self.draw_hud(ctx, class),
+        );
+        if close {
+            self.toggle_hud();
+            ctx.send_viewport_cmd_to(egui::ViewportId::ROOT, egui::ViewportCommand::Focus);
+        } else if self
+            .hud_moved_at
+            .is_some_and(|moved| moved.elapsed() >= HUD_SAVE_DELAY)
+        {
+            self.hud_moved_at = None;
+            self.persist_settings();
+        }
+    }
+
+    /// Contents of the HUD window. Returns whether it should close.
+    fn draw_hud(&mut self, ctx: &egui::Context, class: egui::ViewportClass) -> bool {
+        // Without multi-window support the HUD is shown as a window inside the app
+        if class == egui::ViewportClass::Embedded {
+            let mut open = true;
+            egui::Window::new("HUD")
+                .open(&mut open)
+                .show(ctx, |ui| self.draw_piano(ui, 100.0));
+            return !open;
+        }
+
+        let frame = if self.settings.hud.transparent {
+            egui::Frame::NONE
+        } else {
+            egui::Frame::central_panel(&ctx.style())
+        };
+        egui::CentralPanel::default().frame(frame).show(ctx, |ui| {
+            let height = ui.available_height();
+            self.draw_piano(ui, height);
+            let drag = ui.interact(ui.max_rect(), ui.id().with("hud_drag"), egui::Sense::drag());
+            if drag.drag_started() {
+                ctx.send_viewport_cmd(egui::ViewportCommand::StartDrag);
+            }
+        });
+
+        let (close, inner, outer) = ctx.input(|i| {
+            let viewport = i.viewport();
+            (
+                viewport.close_requested()
+                    || i.key_pressed(HUD_TOGGLE_KEY)
+                    || i.key_pressed(egui::Key::Escape),
+                viewport.inner_rect,
+                viewport.outer_rect,
+            )
+        });
+        let mut placed = self.settings.hud;
+        if let Some(inner) = inner {
+            placed.size = [inner.width(), inner.height()];
+        }
+        if let Some(outer) = outer {
+            placed.position = Some([outer.min.x, outer.min.y]);
+        }
+        if placed != self.settings.hud {
+            self.settings.hud = placed;
+            self.hud_moved_at = Some(Instant::now());
+        }
+        close
+    }
+
+    fn draw_profiles(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let selected_name = self
+                .selected_profile
+                .and_then(|i| self.settings.profiles.get(i))
+                .map(|p| p.name.as_str())
+                .unwrap_or("Select a profile...");
+
+            egui::ComboBox::from_label("Profile")
+                .selected_text(selected_name)
+                .show_ui(ui, |ui| {
+                    for (index, profile) in self.settings.profiles.iter().enumerate() {
+                        ui.selectable_value(&mut self.selected_profile, Some(index), &profile.name)
+                            .on_hover_text(format!("{} / {}", profile.device, profile.mapping));
+                    }
+                });
+
+            if ui
+                .add_enabled(
+                    self.selected_profile.is_some(),
+                    egui::Button::new("▶ Connect Profile"),
+                )
+                .clicked()
+                && let Some(index) = self.selected_profile
+            {
+                self.connect_profile(index);
+            }
+
+            if ui
+                .add_enabled(self.selected_profile.is_some(), egui::Button::new("🗑"))
+                .on_hover_text("Delete profile")
+                .clicked()
+            {
+                self.delete_selected_profile();
+            }
+
+            ui.separator();
+
+            ui.add(
+                egui::TextEdit::singleline(&mut self.new_profile_name)
+                    .hint_text("Profile name")
+                    .desired_width(120.0),
+            );
+            let name = self.new_profile_name.trim().to_string();
+            if ui
+                .add_enabled(
+                    !name.is_empty() && self.selected_device.is_some(),
+                    egui::Button::new("💾 Save Profile"),
+                )
+                .on_hover_text("Save the current device, mapping and settings as a profile")
+                .clicked()
+            {
+                self.save_current_as_profile(name);
+                self.new_profile_name.clear();
+            }
+        });
+    }
+
+    fn draw_zone_mutes(&mut self, ui: &mut egui::Ui) {
+        let zones = self.mapping.octave_zones();
+        if zones.is_empty() {
+            return;
+        }
+
+        let convention = self.settings.octave_convention;
+        let note_name = |note: u8| {
+            xiv_midi::midi::MidiNote::new(note)
+                .map(|n| n.full_name_with(convention))
+                .unwrap_or_else(|_| note.to_string())
+        };
+
+        ui.horizontal(|ui| {
+            ui.label("Mute zones:");
+            for zone in zones {
+                let mut muted = self.mutes.is_range_muted(&zone);
+                let label = format!("{}–{}", note_name(*zone.start()), note_name(*zone.end()));
+                if ui.checkbox(&mut muted, &label).changed() {
+                    self.mutes.set_muted(zone, muted);
+                    let state = if muted { "muted" } else { "unmuted" };
+                    self.log(format!("Zone {} {}", label, state));
+                }
+            }
+            if ui.small_button("Unmute all").clicked() {
+                self.mutes.clear();
+            }
+        });
+    }
+
     fn draw_main_tab(&mut self, ui: &mut egui::Ui) {
+        self.draw_profiles(ui);
+
         ui.horizontal(|ui| {
             if ui.button("🔄 Refresh Devices").clicked() {
                 self.refresh_devices();
@@ -1541,8 +3069,7 @@ impl XivMidiApp {
             egui::ComboBox::from_label("MIDI Device")
                 .selected_text(
                     self.selected_device
-                        .as_ref()
-                        .map(|s| s.as_str())
+                        .as_deref()
                         .unwrap_or("Select a device..."),
                 )
                 .show_ui(ui, |ui| {
@@ -1564,22 +3091,78 @@ impl XivMidiApp {
                         egui::Button::new("🔌 Connect"),
                     )
                     .clicked()
+                    && let Some(device) = self.selected_device.clone()
                 {
-                    if let Some(device) = self.selected_device.clone() {
-                        self.connect_device(device);
-                    }
+                    self.connect_device(device);
                 }
             } else {
                 if ui.button("⏸ Disconnect").clicked() {
                     self.disconnect_device();
                 }
             }
+            if self.reconnect_device.is_some() && ui.button("✖ Stop waiting").clicked() {
+                self.reconnect_device = None;
+                self.status = "Ready".to_string();
+            }
+
+            if ui
+                .checkbox(&mut self.settings.auto_reconnect, "Auto-reconnect")
+                .on_hover_text(
+                    "When the device disappears or fails to connect, reconnect to it as soon as \
+                     it is back, with the selected mapping",
+                )
+                .changed()
+            {
+                self.persist_settings();
+                if !self.settings.auto_reconnect {
+                    self.reconnect_device = None;
+                }
+            }
+
+            if ui
+                .button("🛑 Panic / Release All")
+                .on_hover_text("Release every held key and modifier (F9)")
+                .clicked()
+            {
+                self.panic();
+            }
         });
 
+        if self.midi_unavailable {
+            ui.colored_label(
+                egui::Color32::from_rgb(255, 165, 0),
+                "⚠ No MIDI backend available on this system. \
+                 Check that your MIDI drivers/services (e.g. ALSA on Linux) are installed \
+                 and running, then click Refresh Devices.",
+            );
+        }
+
         ui.horizontal(|ui| {
             if ui.button("🔄 Refresh Mappings").clicked() {
                 self.scan_mapping_files();
             }
+            if ui
+                .button("⟳ Reload Default")
+                .on_hover_text("Re-read the default mapping (or default.json next to the app)")
+                .clicked()
+            {
+                self.reload_default_mapping();
+            }
+            if self.scanning {
+                ui.spinner();
+                ui.label("Scanning…");
+            }
+
+            if ui
+                .checkbox(&mut self.settings.hide_default_mapping, "Hide default")
+                .on_hover_text(
+                    "Hide the built-in \"Default FFXIV\" mapping when mapping files exist",
+                )
+                .changed()
+            {
+                self.persist_settings();
+                self.scan_mapping_files();
+            }
 
             let prev_index = self.selected_mapping_index;
             let current_name = &self.available_mappings[self.selected_mapping_index].name;
@@ -1595,8 +3178,12 @@ impl XivMidiApp {
             // Auto-apply when selection changes
             if prev_index != self.selected_mapping_index {
                 self.load_selected_mapping();
-                if self.connection.is_some() {
-                    self.log("Mapping changed - disconnect and reconnect to apply".to_string());
+                if let Some(ref engine) = self.engine {
+                    self.mapping.channel = self.channel;
+                    self.mapping.octave_transpose = self.octave_transpose;
+                    self.mapping.transpose_semitones = self.transpose_semitones;
+                    engine.set_mapping(self.mapping.clone());
+                    self.log("Mapping applied to the connected device".to_string());
                 }
             }
         });
@@ -1612,6 +3199,59 @@ impl XivMidiApp {
             };
             ui.colored_label(status_color, &self.status);
 
+            if self.connection.is_some()
+                && let Some(ref engine) = self.engine
+            {
+                let stats = engine.stats();
+                let dropped_color = if stats.dropped > 0 {
+                    egui::Color32::RED
+                } else {
+                    egui::Color32::GRAY
+                };
+                if !self.mapping.layers.is_empty() {
+                    let layer = match engine.switched_layer() {
+                        0 => "primary".to_string(),
+                        layer => layer.to_string(),
+                    };
+                    ui.label(format!("Layer: {}", layer))
+                        .on_hover_text("Layer picked by the last Switch Layer action");
+                }
+                ui.colored_label(dropped_color, format!("dropped: {}", stats.dropped))
+                    .on_hover_text(format!(
+                        "Note-ons: {}\nNote-offs: {}\nDropped (queue full): {}\nParse errors: {}\n\
+                         Latency to keypress: {:.1} ms average, {:.1} ms max",
+                        stats.note_ons,
+                        stats.note_offs,
+                        stats.dropped,
+                        stats.parse_errors,
+                        stats.average_latency.as_secs_f64() * 1000.0,
+                        stats.max_latency.as_secs_f64() * 1000.0
+                    ));
+            }
+
+            ui.separator();
+
+            let prev_channel = self.channel;
+            egui::ComboBox::from_label("Channel")
+                .selected_text(match self.channel {
+                    Some(ch) => ch.to_string(),
+                    None => "All".to_string(),
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.channel, None, "All");
+                    for ch in 0..16u8 {
+                        ui.selectable_value(&mut self.channel, Some(ch), ch.to_string());
+                    }
+                });
+
+            // Live-update the engine mapping when the channel changes
+            if self.channel != prev_channel
+                && let Some(ref engine) = self.engine
+                && let Ok(mut m) = engine.mapping().lock()
+            {
+                m.channel = self.channel;
+            }
+
             ui.separator();
 
             let prev_octave_transpose = self.octave_transpose;
@@ -1620,10 +3260,10 @@ impl XivMidiApp {
 
             // Live-update the engine mapping when toggled
             if self.octave_transpose != prev_octave_transpose {
-                if let Some(ref engine_mapping) = self.engine_mapping {
-                    if let Ok(mut m) = engine_mapping.lock() {
-                        m.octave_transpose = self.octave_transpose;
-                    }
+                if let Some(ref engine) = self.engine
+                    && let Ok(mut m) = engine.mapping().lock()
+                {
+                    m.octave_transpose = self.octave_transpose;
                 }
                 let state = if self.octave_transpose {
                     "开启"
@@ -1632,15 +3272,180 @@ impl XivMidiApp {
                 };
                 self.log(format!("八度等效: {}", state));
             }
+
+            ui.separator();
+
+            let prev_transpose = self.transpose_semitones;
+            ui.label("Transpose");
+            ui.add(
+                egui::DragValue::new(&mut self.transpose_semitones)
+                    .range(-48..=48)
+                    .suffix(" st"),
+            )
+            .on_hover_text("Semitones added to every incoming note (drag or double-click)");
+
+            // Live-update the engine mapping when dragged
+            if self.transpose_semitones != prev_transpose
+                && let Some(ref engine) = self.engine
+                && let Ok(mut m) = engine.mapping().lock()
+            {
+                m.transpose_semitones = self.transpose_semitones;
+            }
+
+            ui.separator();
+
+            let prev_convention = self.settings.octave_convention;
+            egui::ComboBox::from_label("Note names")
+                .selected_text(octave_convention_label(self.settings.octave_convention))
+                .show_ui(ui, |ui| {
+                    for convention in OctaveConvention::ALL {
+                        ui.selectable_value(
+                            &mut self.settings.octave_convention,
+                            convention,
+                            octave_convention_label(convention),
+                        );
+                    }
+                })
+                .response
+                .on_hover_text(
+                    "Octave numbering for note names on the keyboards, in the editor and in the monitor (MIDI 60 is middle C; mapping files keep raw MIDI numbers)",
+                );
+            if self.settings.octave_convention != prev_convention {
+                self.persist_settings();
+            }
+
+            ui.separator();
+
+            let prev_timing = self.settings.timing_preset.clone();
+            let current = prev_timing.as_deref().unwrap_or("default");
+            egui::ComboBox::from_label("Timing")
+                .selected_text(current)
+                .show_ui(ui, |ui| {
+                    for (name, preset) in TimingPreset::BUILTIN {
+                        let label =
+                            ui.selectable_label(current == name, name)
+                                .on_hover_text(format!(
+                                    "note gap {} ms, modifier settle {} ms, tap hold {} ms",
+                                    preset.min_note_gap_ms,
+                                    preset.modifier_settle_ms,
+                                    preset.tap_hold_ms
+                                ));
+                        if label.clicked() {
+                            self.settings.timing_preset =
+                                (name != "default").then(|| name.to_string());
+                        }
+                    }
+                })
+                .response
+                .on_hover_text("Keypress timing preset, applied on the next connect");
+            if self.settings.timing_preset != prev_timing {
+                self.persist_settings();
+                self.log(format!(
+                    "Timing preset: {} (reconnect to apply)",
+                    self.settings.timing_preset.as_deref().unwrap_or("default")
+                ));
+            }
+
+            ui.separator();
+
+            if ui
+                .checkbox(&mut self.settings.require_game_focus, "Game focus only")
+                .on_hover_text(
+                    "Only send keys while the FFXIV window is focused, releasing held keys when it \
+                     loses focus (applied on the next connect)",
+                )
+                .changed()
+            {
+                self.persist_settings();
+                if self.connection.is_some() {
+                    self.log("Game focus setting changed - reconnect to apply".to_string());
+                }
+            }
         });
 
         ui.separator();
 
-        ui.heading("Active Notes");
-        self.draw_piano(ui);
+        self.draw_zone_mutes(ui);
+
+        ui.horizontal(|ui| {
+            ui.heading("Active Notes");
+            let label = if self.hud.is_some() {
+                "Close HUD (F2)"
+            } else {
+                "🖥 HUD (F2)"
+            };
+            if ui
+                .button(label)
+                .on_hover_text("Borderless window showing only the piano, e.g. for stream overlays")
+                .clicked()
+            {
+                self.toggle_hud();
+            }
+            let always_on_top = ui
+                .checkbox(&mut self.settings.hud.always_on_top, "On top")
+                .on_hover_text("Keep the HUD above other windows (applied when it opens)");
+            let transparent = ui
+                .checkbox(&mut self.settings.hud.transparent, "Transparent")
+                .on_hover_text("Transparent HUD background (applied when it opens)");
+            if always_on_top.changed() || transparent.changed() {
+                self.persist_settings();
+            }
+        });
+        piano_range_controls(
+            ui,
+            &mut self.piano_view,
+            self.mapping.display_range(),
+            self.settings.octave_convention,
+        );
+        ui.horizontal_top(|ui| {
+            let piano_width =
+                (ui.available_width() - NOTE_HISTORY_WIDTH - ui.spacing().item_spacing.x).max(0.0);
+            ui.allocate_ui(egui::vec2(piano_width, 100.0), |ui| {
+                self.draw_piano(ui, 100.0)
+            });
+            self.draw_note_history(ui);
+        });
 
         ui.separator();
 
+        ui.horizontal(|ui| {
+            ui.label("Keys held:");
+            if self.held_keys.is_empty() {
+                ui.label(
+                    egui::RichText::new("none")
+                        .italics()
+                        .color(egui::Color32::GRAY),
+                );
+            }
+            for key in &self.held_keys {
+                ui.label(egui::RichText::new(format!("{:?}", key)).strong());
+            }
+        });
+
+        ui.horizontal_wrapped(|ui| {
+            ui.label("Keys sent:").on_hover_text(
+                "Presses (+) and releases (-) the engine sent to the game, newest last",
+            );
+            if self.keys_sent.is_empty() {
+                ui.label(
+                    egui::RichText::new("none")
+                        .italics()
+                        .color(egui::Color32::GRAY),
+                );
+            }
+            for event in &self.keys_sent {
+                let color = match event {
+                    KeyEvent::Press(_) => egui::Color32::LIGHT_GREEN,
+                    KeyEvent::Release(_) => egui::Color32::GRAY,
+                };
+                ui.label(
+                    egui::RichText::new(event.to_string())
+                        .monospace()
+                        .color(color),
+                );
+            }
+        });
+
         ui.heading("Mapping & Live Actions");
         self.draw_mapping_info(ui);
 
@@ -1655,6 +3460,130 @@ impl XivMidiApp {
                     ui.label(msg);
                 }
             });
+
+        ui.separator();
+
+        self.draw_midi_monitor(ui);
+    }
+
+    /// The last `NOTE_HISTORY_LEN` notes with their time and velocity, newest first,
+    /// e.g. to check a controller's velocity curve.
+    fn draw_note_history(&self, ui: &mut egui::Ui) {
+        ui.allocate_ui(egui::vec2(NOTE_HISTORY_WIDTH, 100.0), |ui| {
+            ui.set_width(NOTE_HISTORY_WIDTH);
+            egui::ScrollArea::vertical()
+                .id_salt("note_history")
+                .max_height(100.0)
+                .auto_shrink([false, false])
+                .show(ui, |ui| {
+                    if self.note_history.is_empty() {
+                        ui.weak("No notes yet");
+                    }
+                    let convention = self.settings.octave_convention;
+                    for entry in self.note_history.iter().rev() {
+                        let name = xiv_midi::midi::MidiNote::new(entry.note)
+                            .map(|n| n.full_name_with(convention))
+                            .unwrap_or_else(|_| entry.note.to_string());
+                        let text = if entry.is_on {
+                            format!(
+                                "{:>8.3}s {:<4} vel {:>3}",
+                                entry.time.as_secs_f64(),
+                                name,
+                                entry.velocity
+                            )
+                        } else {
+                            format!("{:>8.3}s {:<4} off", entry.time.as_secs_f64(), name)
+                        };
+                        let color = if entry.is_on {
+                            ui.visuals().text_color()
+                        } else {
+                            egui::Color32::GRAY
+                        };
+                        ui.label(egui::RichText::new(text).monospace().small().color(color));
+                    }
+                });
+        });
+    }
+
+    fn draw_midi_monitor(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("MIDI Monitor")
+            .default_open(false)
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{} recent messages", self.midi_monitor.len()));
+                    if ui.button("Clear").clicked() {
+                        self.midi_monitor.clear();
+                    }
+                });
+
+                egui::ScrollArea::vertical()
+                    .id_salt("midi_monitor")
+                    .stick_to_bottom(true)
+                    .max_height(200.0)
+                    .show(ui, |ui| {
+                        egui::Grid::new("midi_monitor_grid")
+                            .striped(true)
+                            .show(ui, |ui| {
+                                for entry in &self.midi_monitor {
+                                    ui.monospace(format!("{:>9.3}s", entry.time.as_secs_f64()));
+                                    ui.monospace(format_bytes(&entry.bytes));
+                                    ui.label(&entry.parsed);
+                                    ui.end_row();
+                                }
+                            });
+                    });
+            });
+    }
+}
+
+/// Raw MIDI bytes as hex, e.g. "90 3C 40"
+fn format_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parsed interpretation of a raw MIDI message for the monitor
+fn describe_midi(bytes: &[u8], convention: OctaveConvention) -> String {
+    match MidiMessage::parse(bytes) {
+        Ok(MidiMessage {
+            event_type: MidiEventType::ControlChange { controller, value },
+            channel,
+            ..
+        }) => format!(
+            "Control Change ch {} controller {} value {}",
+            channel, controller, value
+        ),
+        Ok(MidiMessage {
+            event_type: MidiEventType::PitchBend(bend),
+            channel,
+            ..
+        }) => format!("Pitch Bend ch {} value {}", channel, bend),
+        Ok(msg) => format!(
+            "{:?} ch {} {} ({}) vel {}",
+            msg.event_type,
+            msg.channel,
+            msg.note.full_name_with(convention),
+            msg.note.value(),
+            msg.velocity
+        ),
+        Err(_) => {
+            let kind = match bytes.first().map(|status| status & 0xF0) {
+                Some(0xA0) => "Poly Aftertouch",
+                Some(0xC0) => "Program Change",
+                Some(0xD0) => "Channel Aftertouch",
+                Some(0xF0) => "System",
+                _ => "Unknown",
+            };
+            match bytes.first() {
+                Some(status) if status & 0xF0 != 0xF0 => {
+                    format!("{} ch {} (ignored)", kind, status & 0x0F)
+                }
+                _ => format!("{} (ignored)", kind),
+            }
+        }
     }
 }
 
@@ -1690,14 +3619,19 @@ impl XivMidiApp {
                                     Ok(n) => n,
                                     Err(_) => continue,
                                 };
-                                let note_name = midi_note.full_name();
+                                let convention = self.settings.octave_convention;
+                                let note_name = midi_note.full_name_with(convention);
 
                                 // Use octave-transposed lookup to match engine behavior
                                 let lookup_result = self.mapping.get_mapping_transposed(midi_note);
 
                                 if let Some((transposed_note, mapping)) = lookup_result {
                                     let label = if transposed_note.value() != *note_val {
-                                        format!("{} -> {}:", note_name, transposed_note.full_name())
+                                        format!(
+                                            "{} -> {}:",
+                                            note_name,
+                                            transposed_note.full_name_with(convention)
+                                        )
                                     } else {
                                         format!("{}:", note_name)
                                     };
@@ -1719,33 +3653,36 @@ impl XivMidiApp {
             });
     }
 
-    fn draw_piano(&self, ui: &mut egui::Ui) {
+    /// How much of a released note's glow is left (1.0 at the note-off, 0.0 once faded).
+    fn release_glow(&self, note: u8) -> f32 {
+        self.released_notes.get(&note).map_or(0.0, |(at, fade)| {
+            1.0 - (at.elapsed().as_secs_f32() / fade.as_secs_f32()).min(1.0)
+        })
+    }
+
+    fn draw_piano(&self, ui: &mut egui::Ui, height: f32) {
         let (rect, _) = ui.allocate_exact_size(
-            egui::vec2(ui.available_width(), 100.0),
+            egui::vec2(ui.available_width(), height),
             egui::Sense::hover(),
         );
 
         let painter = ui.painter_at(rect);
-
-        let start_note = 36;
-        let end_note = 96;
-
-        let mut white_notes = Vec::new();
-        for note in start_note..=end_note {
-            let note_in_octave = note % 12;
-            if !matches!(note_in_octave, 1 | 3 | 6 | 8 | 10) {
-                white_notes.push(note);
-            }
+        if self
+            .released_notes
+            .keys()
+            .any(|&note| self.release_glow(note) > 0.0)
+        {
+            ui.ctx().request_repaint();
         }
 
-        let num_white_keys = white_notes.len();
-        let white_key_width = rect.width() / num_white_keys as f32;
-        let white_key_height = rect.height();
-        let black_key_width = white_key_width * 0.7;
-        let black_key_height = white_key_height * 0.6;
+        let range = self
+            .piano_view
+            .clone()
+            .map(|view| *view.start()..=(*view.end()).min(127))
+            .unwrap_or_else(|| self.mapping.display_range());
+        let layout = PianoLayout::new(rect, range);
 
-        for (i, &note) in white_notes.iter().enumerate() {
-            let x = rect.min.x + i as f32 * white_key_width;
+        for (note, key_rect) in layout.white_keys() {
             let color = if let Some(&velocity) = self.active_notes.get(&note) {
                 let intensity = (velocity as f32 / 127.0).clamp(0.4, 1.0);
                 egui::Color32::from_rgb(
@@ -1754,14 +3691,12 @@ impl XivMidiApp {
                     (180.0 * (1.0 - intensity)) as u8,
                 )
             } else {
-                egui::Color32::WHITE
+                egui::Color32::WHITE.lerp_to_gamma(
+                    egui::Color32::from_rgb(180, 255, 180),
+                    self.release_glow(note),
+                )
             };
 
-            let key_rect = egui::Rect::from_min_size(
-                egui::pos2(x, rect.min.y),
-                egui::vec2(white_key_width, white_key_height),
-            );
-
             painter.rect_filled(key_rect, 2.0, color);
             painter.rect(
                 key_rect,
@@ -1770,37 +3705,203 @@ impl XivMidiApp {
                 egui::Stroke::new(1.0, egui::Color32::from_gray(180)),
                 egui::epaint::StrokeKind::Outside,
             );
+            if let Some(&velocity) = self.active_notes.get(&note) {
+                velocity_label(&painter, key_rect, velocity, egui::Color32::BLACK);
+            }
+        }
+
+        for (black_note, key_rect) in layout.black_keys() {
+            let color = if let Some(&velocity) = self.active_notes.get(&black_note) {
+                let intensity = (velocity as f32 / 127.0).clamp(0.4, 1.0);
+                egui::Color32::from_rgb(0, (100.0 + 155.0 * intensity) as u8, 0)
+            } else {
+                egui::Color32::from_gray(40).lerp_to_gamma(
+                    egui::Color32::from_rgb(0, 160, 0),
+                    self.release_glow(black_note),
+                )
+            };
+
+            painter.rect_filled(key_rect, 1.0, color);
+            painter.rect(
+                key_rect,
+                1.0,
+                egui::Color32::TRANSPARENT,
+                egui::Stroke::new(1.0, egui::Color32::BLACK),
+                egui::epaint::StrokeKind::Outside,
+            );
+            if let Some(&velocity) = self.active_notes.get(&black_note) {
+                velocity_label(&painter, key_rect, velocity, egui::Color32::BLACK);
+            }
         }
+    }
+}
 
-        for (i, &note) in white_notes.iter().enumerate() {
-            let note_in_octave = note % 12;
-            if !matches!(note_in_octave, 4 | 11) && i < num_white_keys - 1 {
-                let black_note = note + 1;
-                let x = rect.min.x + (i as f32 + 1.0) * white_key_width - black_key_width / 2.0;
+/// Where each key of a piano widget showing `range` inside `rect` goes. Shared by
+/// the main tab's piano and the editor keyboard.
+struct PianoLayout {
+    rect: egui::Rect,
+    white_notes: Vec<u8>,
+    white_key_width: f32,
+    black_key_size: egui::Vec2,
+}
 
-                let color = if let Some(&velocity) = self.active_notes.get(&black_note) {
-                    let _intensity = (velocity as f32 / 127.0).clamp(0.4, 1.0);
-                    egui::Color32::from_rgb(0, 255, 0)
-                } else {
-                    egui::Color32::from_gray(40)
-                };
+impl PianoLayout {
+    fn new(rect: egui::Rect, range: RangeInclusive<u8>) -> Self {
+        let white_notes: Vec<u8> = range
+            .filter(|note| !matches!(note % 12, 1 | 3 | 6 | 8 | 10))
+            .collect();
+        let white_key_width = rect.width() / white_notes.len().max(1) as f32;
+        Self {
+            rect,
+            white_notes,
+            white_key_width,
+            black_key_size: egui::vec2(white_key_width * 0.7, rect.height() * 0.6),
+        }
+    }
 
-                let key_rect = egui::Rect::from_min_size(
-                    egui::pos2(x, rect.min.y),
-                    egui::vec2(black_key_width, black_key_height),
-                );
+    /// White keys and their rectangles, left to right
+    fn white_keys(&self) -> impl Iterator<Item = (u8, egui::Rect)> + '_ {
+        self.white_notes.iter().enumerate().map(|(i, &note)| {
+            let min = egui::pos2(
+                self.rect.min.x + i as f32 * self.white_key_width,
+                self.rect.min.y,
+            );
+            let size = egui::vec2(self.white_key_width, self.rect.height());
+            (note, egui::Rect::from_min_size(min, size))
+        })
+    }
 
-                painter.rect_filled(key_rect, 1.0, color);
-                painter.rect(
-                    key_rect,
-                    1.0,
-                    egui::Color32::TRANSPARENT,
-                    egui::Stroke::new(1.0, egui::Color32::BLACK),
-                    egui::epaint::StrokeKind::Outside,
-                );
+    /// Black keys and their rectangles, which overlap the white keys' tops
+    fn black_keys(&self) -> impl Iterator<Item = (u8, egui::Rect)> + '_ {
+        let last = self.white_notes.len().saturating_sub(1);
+        self.white_notes
+            .iter()
+            .enumerate()
+            .filter(move |&(i, note)| !matches!(note % 12, 4 | 11) && i < last)
+            .map(|(i, &note)| {
+                let x = self.rect.min.x + (i as f32 + 1.0) * self.white_key_width
+                    - self.black_key_size.x / 2.0;
+                let min = egui::pos2(x, self.rect.min.y);
+                (
+                    note + 1,
+                    egui::Rect::from_min_size(min, self.black_key_size),
+                )
+            })
+    }
+
+    /// The note under `pos`; black keys win where they overlap white ones
+    fn note_at(&self, pos: egui::Pos2) -> Option<u8> {
+        self.black_keys()
+            .chain(self.white_keys())
+            .find(|(_, rect)| rect.contains(pos))
+            .map(|(note, _)| note)
+    }
+}
+
+/// Scroll and zoom buttons above a piano widget. `view` is the range picked with
+/// them, in whole octaves from a C (its end may pass 127 and is cut off when
+/// drawn); `None` follows `auto`, the mapping's display range. Returns the notes
+/// to draw.
+fn piano_range_controls(
+    ui: &mut egui::Ui,
+    view: &mut Option<RangeInclusive<u8>>,
+    auto: RangeInclusive<u8>,
+    convention: OctaveConvention,
+) -> RangeInclusive<u8> {
+    let (mut start, mut end) = match view {
+        Some(view) => (*view.start(), *view.end()),
+        None => (*auto.start(), auto.end().div_ceil(12) * 12),
+    };
+    let mut changed = false;
+    let mut reset = false;
+    ui.horizontal(|ui| {
+        if ui
+            .small_button("◀")
+            .on_hover_text("Scroll down an octave")
+            .clicked()
+            && start >= 12
+        {
+            start -= 12;
+            end -= 12;
+            changed = true;
+        }
+        if ui
+            .small_button("▶")
+            .on_hover_text("Scroll up an octave")
+            .clicked()
+            && end < 127
+        {
+            start += 12;
+            end += 12;
+            changed = true;
+        }
+        if ui
+            .small_button("➖")
+            .on_hover_text("Zoom in: show fewer octaves")
+            .clicked()
+            && end - start > 12
+        {
+            if end - start >= 36 {
+                start += 12;
+            }
+            end -= 12;
+            changed = true;
+        }
+        if ui
+            .small_button("➕")
+            .on_hover_text("Zoom out: show more octaves")
+            .clicked()
+            && (start > 0 || end < 127)
+        {
+            start = start.saturating_sub(12);
+            if end < 127 {
+                end += 12;
             }
+            changed = true;
         }
+        reset = ui
+            .add_enabled(view.is_some(), egui::Button::new("Fit").small())
+            .on_hover_text("Show the mapped notes again")
+            .clicked();
+
+        let name = |note: u8| {
+            xiv_midi::midi::MidiNote::new(note)
+                .map(|n| n.full_name_with(convention))
+                .unwrap_or_default()
+        };
+        ui.weak(format!("{} – {}", name(start), name(end.min(127))));
+    });
+
+    if reset {
+        *view = None;
+    } else if changed {
+        *view = Some(start..=end);
     }
+    match view {
+        Some(view) => *view.start()..=(*view.end()).min(127),
+        None => auto,
+    }
+}
+
+/// Draw `velocity` at the bottom of a held piano key, if the key is wide enough
+/// to fit it.
+fn velocity_label(
+    painter: &egui::Painter,
+    key_rect: egui::Rect,
+    velocity: u8,
+    color: egui::Color32,
+) {
+    let size = (key_rect.width() * 0.45).min(12.0);
+    if size < 6.0 {
+        return;
+    }
+    painter.text(
+        key_rect.center_bottom() - egui::vec2(0.0, 2.0),
+        egui::Align2::CENTER_BOTTOM,
+        velocity.to_string(),
+        egui::FontId::monospace(size),
+        color,
+    );
 }
 
 fn main() -> eframe::Result<()> {
@@ -1814,6 +3915,8 @@ fn main() -> eframe::Result<()> {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([800.0, 600.0])
             .with_min_inner_size([600.0, 400.0]),
+        // Reopen at the size and position the window was closed with
+        persist_window: true,
         ..Default::default()
     };
 
@@ -1,11 +1,23 @@
-use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use notify::{RecursiveMode, Watcher};
+use std::io::{BufRead, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use xiv_midi::{
-    engine::MidiEngine,
-    keyboard::EnigoKeyboardController,
-    mapping::{create_ffxiv_default_mapping, MappingConfig},
+    engine::{
+        sweep_mapping, EngineOptions, FoldedNoteOn, MidiEngine, ModifierOrder, TimingPreset,
+        UnmappedNoteLog,
+    },
+    focus::{foreground_window, window_focus_check, GAME_WINDOW_TITLE},
+    keyboard::{DryRunKeyboardController, EnigoKeyboardController, Key, ModifierSide},
+    mapping::{create_ffxiv_default_mapping, Action, MappingConfig, MappingLayout, ZoneModifiers},
+    midi::{smf_messages, smf_notes, MessageFilter, MidiEventType, MidiMessage, MidiNote},
 };
 
+/// How long `run --watch` waits after a change to the mapping file before reading it
+const WATCH_SETTLE: Duration = Duration::from_millis(100);
+
 #[derive(Parser)]
 #[command(name = "xiv-midi")]
 #[command(about = "Convert MIDI input to keyboard presses for FFXIV", long_about = None)]
@@ -19,15 +31,78 @@ enum Commands {
     /// List available MIDI devices
     List,
 
-    /// Run the MIDI to keyboard converter
-    Run {
-        /// MIDI device name to connect to
+    /// Print the notes and controllers a device sends, without pressing any keys
+    Monitor {
+        /// MIDI device name to connect to (or a unique part of it)
         #[arg(short, long)]
         device: String,
 
-        /// Path to custom mapping configuration file (JSON)
+        /// Also print the raw bytes of every incoming buffer, in hex
+        #[arg(long)]
+        raw: bool,
+    },
+
+    /// Run the MIDI to keyboard converter
+    Run(RunArgs),
+
+    /// Print the settings `run` would use with the same flags (mapping settings
+    /// after overrides, plus engine options and timing) as JSON
+    ConfigDump(RunArgs),
+
+    /// Dry run: play every mapped note and print the key events it produces
+    Test {
+        /// Mapping configuration file (JSON); the default FFXIV mapping if omitted
+        mapping: Option<PathBuf>,
+
+        /// Press the note key before its modifiers (default: modifiers first)
+        #[arg(long)]
+        key_first: bool,
+    },
+
+    /// Play a MIDI file through a mapping in real time, without a device, and
+    /// print the key events it produces
+    Simulate {
+        /// Standard MIDI file (.mid) to play
+        file: PathBuf,
+
+        /// Mapping configuration file (JSON); the default FFXIV mapping if omitted
         #[arg(short, long)]
         mapping: Option<PathBuf>,
+
+        /// Also send the keys to the focused window (by default nothing is pressed)
+        #[arg(long)]
+        send_keys: bool,
+    },
+
+    /// Print statistics for a mapping: notes, modifier zones and keys used
+    Describe {
+        /// Mapping configuration file (JSON); the default FFXIV mapping if omitted
+        mapping: Option<PathBuf>,
+
+        /// Keys the layout should use, e.g. "q w e 1 2"; reports any left unused
+        #[arg(long, value_name = "KEYS")]
+        keys: Option<String>,
+
+        /// Print statistics (text), or a note-to-key table as CSV or Markdown
+        #[arg(long, value_enum, default_value_t = DescribeFormat::Text)]
+        format: DescribeFormat,
+    },
+
+    /// Check a mapping for likely mistakes, e.g. notes that only press a modifier
+    /// or release a key they never press. Exits with status 1 if any are found.
+    Validate {
+        /// Mapping configuration file (JSON); the default FFXIV mapping if omitted
+        mapping: Option<PathBuf>,
+    },
+
+    /// Create a mapping with an empty entry for every note a MIDI file plays
+    Scaffold {
+        /// Standard MIDI file (.mid) of the song
+        midi_file: PathBuf,
+
+        /// Output path for the configuration file
+        #[arg(short, long, default_value = "mapping.json")]
+        output: PathBuf,
     },
 
     /// Generate default FFXIV mapping configuration file
@@ -35,9 +110,203 @@ enum Commands {
         /// Output path for the configuration file
         #[arg(short, long, default_value = "mapping.json")]
         output: PathBuf,
+
+        /// Build the layout step by step (start note, octaves, keys, modifiers)
+        #[arg(short, long)]
+        interactive: bool,
     },
 }
 
+/// Flags shared by `run` and `config-dump`.
+#[derive(Args)]
+struct RunArgs {
+    /// MIDI device name to connect to (or a unique part of it)
+    #[arg(short, long, required_unless_present = "device_index")]
+    device: Option<String>,
+
+    /// Connect to the device with this number in `xiv-midi list` instead of by
+    /// name (names can change between reboots, e.g. ALSA client numbers)
+    #[arg(long, conflicts_with = "device")]
+    device_index: Option<NonZeroUsize>,
+
+    /// Path to custom mapping configuration file (JSON)
+    #[arg(short, long)]
+    mapping: Option<PathBuf>,
+
+    /// Reload the mapping file whenever it changes (keeps the old one if the new
+    /// one doesn't load)
+    #[arg(long, requires = "mapping")]
+    watch: bool,
+
+    /// Only listen to this MIDI channel (0-15), overriding the mapping's `channel`
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=15))]
+    channel: Option<u8>,
+
+    /// Shift incoming notes by this many semitones before lookup (e.g. -12 for an
+    /// octave down), overriding the mapping's `transpose_semitones`
+    #[arg(long, value_name = "SEMITONES", allow_hyphen_values = true)]
+    transpose: Option<i8>,
+
+    /// Press the note key before its modifiers (default: modifiers first)
+    #[arg(long)]
+    key_first: bool,
+
+    /// Which Shift/Ctrl/Alt keys SetModifiers presses (actions can override per action)
+    #[arg(long, value_enum, default_value_t = Side::Generic)]
+    modifier_side: Side,
+
+    /// Let notes overlap instead of releasing the previous note on each note-on
+    #[arg(long)]
+    poly: bool,
+
+    /// With --poly, release the oldest held note beyond this many (voice stealing)
+    #[arg(long, requires = "poly")]
+    max_voices: Option<usize>,
+
+    /// How notes without a mapping are reported in the debug log
+    #[arg(long, value_enum, default_value_t = LogUnmapped::First)]
+    log_unmapped: LogUnmapped,
+
+    /// Forward processed notes to this MIDI output port (overrides the mapping's `thru_port`)
+    #[arg(long, value_name = "PORT")]
+    thru: Option<String>,
+
+    /// Keypress timing: a built-in preset (default, fast, safe, laggy-pc) or a JSON file
+    #[arg(long, value_name = "PRESET", value_parser = parse_timing, default_value = "default")]
+    timing: TimingPreset,
+
+    /// Release all Shift/Ctrl/Alt keys before every unmodified note, in case one got stuck
+    #[arg(long)]
+    paranoid_modifiers: bool,
+
+    /// Run each note's actions exactly as written, without auto-release or note gaps
+    #[arg(long)]
+    raw: bool,
+
+    /// Ignore a note-on that octave transpose folds onto the note already held,
+    /// instead of retriggering it
+    #[arg(long)]
+    ignore_folded: bool,
+
+    /// Release all Shift/Ctrl/Alt keys when connecting, before the first note
+    #[arg(long)]
+    reset_modifiers: bool,
+
+    /// Tap this key once when connecting (e.g. "LShift"), in case the game eats the first note
+    #[arg(long, value_name = "KEY", value_parser = parse_key_arg)]
+    warm_up_key: Option<Key>,
+
+    /// Experimental: when the device sends MIDI clock, scale the minimum note gap by
+    /// BPM / detected tempo (faster tempos get tighter gaps, limited to 0.5–2x)
+    #[arg(long, value_name = "BPM", value_parser = clap::value_parser!(u32).range(1..))]
+    tempo_gap: Option<u32>,
+
+    /// MIDI events that can wait to be processed before new ones are dropped (default
+    /// 64): raise it if dense chords lose notes, lower it to keep latency down
+    #[arg(long, value_name = "EVENTS")]
+    queue_capacity: Option<NonZeroUsize>,
+
+    /// Log each key press and release instead of sending it to the focused window
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Only send keys while the focused window's title or class contains PATTERN
+    /// (default "FINAL FANTASY XIV"); other events are dropped and held keys released
+    #[arg(long, value_name = "PATTERN", num_args = 0..=1, default_missing_value = GAME_WINDOW_TITLE)]
+    focus_window: Option<String>,
+}
+
+impl RunArgs {
+    fn engine_options(&self) -> EngineOptions {
+        EngineOptions {
+            modifier_order: if self.key_first {
+                ModifierOrder::KeyFirst
+            } else {
+                ModifierOrder::ModifiersFirst
+            },
+            unmapped_note_log: self.log_unmapped.into(),
+            modifier_side: self.modifier_side.into(),
+            polyphonic: self.poly,
+            max_voices: self.max_voices,
+            timing: self.timing,
+            paranoid_modifier_reset: self.paranoid_modifiers,
+            raw_execution: self.raw,
+            folded_note_on: if self.ignore_folded {
+                FoldedNoteOn::Ignore
+            } else {
+                FoldedNoteOn::Retrigger
+            },
+            reset_modifiers_on_connect: self.reset_modifiers,
+            warm_up_key: self.warm_up_key,
+            tempo_gap_reference_bpm: self.tempo_gap,
+            queue_capacity: self.queue_capacity.map(NonZeroUsize::get),
+        }
+    }
+
+    /// The mapping file (or the default mapping) with the flag overrides applied.
+    fn mapping(&self) -> xiv_midi::Result<MappingConfig> {
+        let mut mapping = match &self.mapping {
+            Some(path) => MappingConfig::from_file(path)?,
+            None => create_ffxiv_default_mapping(),
+        };
+        if let Some(channel) = self.channel {
+            mapping.channel = Some(channel);
+        }
+        if let Some(semitones) = self.transpose {
+            mapping.transpose_semitones = semitones;
+        }
+        if let Some(port) = &self.thru {
+            mapping.thru_port = Some(port.clone());
+        }
+        Ok(mapping)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DescribeFormat {
+    Text,
+    Csv,
+    Markdown,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Side {
+    /// Generic modifiers (usually the left keys)
+    Generic,
+    Left,
+    Right,
+}
+
+impl From<Side> for ModifierSide {
+    fn from(value: Side) -> Self {
+        match value {
+            Side::Generic => ModifierSide::Generic,
+            Side::Left => ModifierSide::Left,
+            Side::Right => ModifierSide::Right,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LogUnmapped {
+    /// Every unmapped note event
+    Always,
+    /// Only the first event for each unmapped note
+    First,
+    /// Never
+    Never,
+}
+
+impl From<LogUnmapped> for UnmappedNoteLog {
+    fn from(value: LogUnmapped) -> Self {
+        match value {
+            LogUnmapped::Always => UnmappedNoteLog::Always,
+            LogUnmapped::First => UnmappedNoteLog::FirstPerNote,
+            LogUnmapped::Never => UnmappedNoteLog::Never,
+        }
+    }
+}
+
 fn main() -> xiv_midi::Result<()> {
     // Initialize tracing
     tracing_subscriber::fmt()
@@ -52,21 +321,80 @@ fn main() -> xiv_midi::Result<()> {
         Commands::List => {
             list_devices()?;
         }
-        Commands::Run { device, mapping } => {
-            run(device, mapping)?;
+        Commands::Monitor { device, raw } => {
+            monitor(&device, raw)?;
+        }
+        Commands::Run(args) => {
+            run(args)?;
+        }
+        Commands::ConfigDump(args) => {
+            let mapping = args.mapping()?;
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&effective_config(&args, &mapping))?
+            );
+        }
+        Commands::Test { mapping, key_first } => {
+            let options = EngineOptions {
+                modifier_order: if key_first {
+                    ModifierOrder::KeyFirst
+                } else {
+                    ModifierOrder::ModifiersFirst
+                },
+                ..EngineOptions::default()
+            };
+            test_mapping(mapping, options)?;
+        }
+        Commands::Simulate {
+            file,
+            mapping,
+            send_keys,
+        } => {
+            simulate(&file, mapping, send_keys)?;
         }
-        Commands::GenerateConfig { output } => {
-            generate_config(output)?;
+        Commands::Describe {
+            mapping,
+            keys,
+            format,
+        } => {
+            describe_mapping(mapping, keys, format)?;
+        }
+        Commands::Validate { mapping } => {
+            validate_mapping(mapping)?;
+        }
+        Commands::Scaffold { midi_file, output } => {
+            scaffold_mapping(midi_file, output)?;
+        }
+        Commands::GenerateConfig {
+            output,
+            interactive,
+        } => {
+            generate_config(output, interactive)?;
         }
     }
 
     Ok(())
 }
 
+/// A built-in timing preset name, or the path of a preset saved as JSON.
+fn parse_timing(value: &str) -> Result<TimingPreset, String> {
+    if let Some(preset) = TimingPreset::builtin(value) {
+        return Ok(preset);
+    }
+    TimingPreset::from_file(std::path::Path::new(value)).map_err(|e| {
+        let names: Vec<&str> = TimingPreset::BUILTIN.iter().map(|(n, _)| *n).collect();
+        format!(
+            "not a built-in preset ({}) or a readable preset file: {}",
+            names.join(", "),
+            e
+        )
+    })
+}
+
 fn list_devices() -> xiv_midi::Result<()> {
     tracing::info!("Listing available MIDI devices...");
 
-    let devices = MidiEngine::<EnigoKeyboardController>::list_devices()?;
+    let devices = MidiEngine::list_devices()?;
 
     if devices.is_empty() {
         println!("No MIDI devices found.");
@@ -80,41 +408,498 @@ fn list_devices() -> xiv_midi::Result<()> {
     Ok(())
 }
 
-fn run(device_name: String, mapping_path: Option<PathBuf>) -> xiv_midi::Result<()> {
+fn run(args: RunArgs) -> xiv_midi::Result<()> {
     tracing::info!("Starting xiv-midi...");
 
     // Load or create mapping
-    let mapping = if let Some(path) = mapping_path {
-        tracing::info!("Loading mapping from: {}", path.display());
-        MappingConfig::from_file(&path)?
+    match &args.mapping {
+        Some(path) => tracing::info!("Loading mapping from: {}", path.display()),
+        None => tracing::info!("Using default FFXIV mapping"),
+    }
+    let mapping = args.mapping()?;
+
+    for warning in mapping.validate() {
+        tracing::warn!("{}", warning);
+    }
+
+    let thru_port = mapping.thru_port.clone();
+    let feedback_port = mapping.feedback.as_ref().map(|f| f.port.clone());
+
+    // Create the engine with a keyboard controller
+    let mut engine = if args.dry_run {
+        tracing::info!("Dry run: key events are logged, not sent");
+        MidiEngine::new(DryRunKeyboardController::logging(), mapping)
     } else {
-        tracing::info!("Using default FFXIV mapping");
-        create_ffxiv_default_mapping()
+        let keyboard = EnigoKeyboardController::new_with_settings(mapping.keyboard)?;
+        MidiEngine::new(keyboard, mapping)
     };
+    engine.set_options(args.engine_options());
 
-    // Create keyboard controller
-    let keyboard = EnigoKeyboardController::new()?;
+    if let Some(pattern) = &args.focus_window {
+        if foreground_window().is_none() {
+            tracing::warn!("Can't query the focused window; no keys will be sent");
+        }
+        tracing::info!("Only sending keys while '{}' is focused", pattern);
+        engine.set_focus_check(window_focus_check(pattern));
+    }
 
-    // Create engine
-    let engine = MidiEngine::new(keyboard, mapping);
+    if let Some(port) = &thru_port {
+        tracing::info!("Forwarding processed notes to MIDI output: {}", port);
+        engine.open_thru(port)?;
+    }
 
-    // Connect to device
-    tracing::info!("Connecting to device: {}", device_name);
-    let _connection = engine.connect(&device_name)?;
+    if let Some(port) = &feedback_port {
+        tracing::info!("Sending key feedback to MIDI output: {}", port);
+        engine.open_feedback(port)?;
+    }
 
-    println!("✓ Connected to '{}'", device_name);
+    // Connect to device
+    let _connection = match (&args.device, args.device_index) {
+        (_, Some(index)) => {
+            tracing::info!("Connecting to device #{}", index);
+            let connection = engine.connect_by_index(index.get() - 1)?;
+            println!("✓ Connected to device #{}", index);
+            connection
+        }
+        (Some(device), None) => {
+            tracing::info!("Connecting to device: {}", device);
+            let connection = engine.connect(device)?;
+            println!("✓ Connected to '{}'", device);
+            connection
+        }
+        (None, None) => unreachable!("clap requires --device or --device-index"),
+    };
     println!("Press Ctrl+C to exit...");
 
+    if args.watch
+        && let Some(path) = &args.mapping
+    {
+        return watch_mapping(&engine, &args, path);
+    }
+
     // Keep running until interrupted
     loop {
         std::thread::sleep(std::time::Duration::from_secs(1));
     }
 }
 
-fn generate_config(output: PathBuf) -> xiv_midi::Result<()> {
-    tracing::info!("Generating default mapping configuration...");
+/// Reload the mapping at `path` (with `args`' overrides) into `engine` each time
+/// the file changes, until interrupted. A file that fails to load is reported and
+/// the previous mapping kept.
+fn watch_mapping(engine: &MidiEngine, args: &RunArgs, path: &Path) -> xiv_midi::Result<()> {
+    let watch_error = |e: notify::Error| {
+        xiv_midi::Error::Mapping(format!("Can't watch {}: {}", path.display(), e))
+    };
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(watch_error)?;
+    // Watch the directory: editors often save by replacing the file, which would
+    // end a watch on the file itself
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .map_err(watch_error)?;
+    tracing::info!("Watching {} for changes", path.display());
+
+    let name = path.file_name();
+    while let Ok(event) = rx.recv() {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                tracing::warn!("File watch error: {}", e);
+                continue;
+            }
+        };
+        let changed = (event.kind.is_modify() || event.kind.is_create())
+            && event.paths.iter().any(|p| p.file_name() == name);
+        if !changed {
+            continue;
+        }
+        // A save usually comes as several events; let the editor finish writing
+        std::thread::sleep(WATCH_SETTLE);
+        while rx.try_recv().is_ok() {}
+
+        match args.mapping() {
+            Ok(mapping) => {
+                for warning in mapping.validate() {
+                    tracing::warn!("{}", warning);
+                }
+                engine.set_mapping(mapping);
+                println!("✓ Reloaded {}", path.display());
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Keeping the previous mapping, {} failed to load: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Connect to `device` and print each incoming message until interrupted. The
+/// engine gets an empty mapping and a dry-run keyboard, so nothing is pressed.
+fn monitor(device: &str, raw: bool) -> xiv_midi::Result<()> {
+    let mut mapping = MappingConfig::new();
+    mapping.channel = None;
+    mapping.listen = MessageFilter {
+        notes: true,
+        control_change: true,
+        pitch_bend: true,
+        channel_mode: true,
+        other: false,
+    };
+    let mut engine = MidiEngine::new(DryRunKeyboardController::new(), mapping);
+    if raw {
+        engine.set_raw_callback(|data| println!("raw  {}", hex_bytes(data)));
+    }
+
+    let _connection =
+        engine.connect_with_callback(device, |msg| println!("{}", monitor_line(&msg)))?;
+
+    println!("✓ Monitoring '{}'", device);
+    println!("Press Ctrl+C to exit...");
+
+    loop {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+}
+
+/// One line of `monitor` output for a parsed message (channels count from 0, as
+/// in `--channel`)
+fn monitor_line(msg: &MidiMessage) -> String {
+    match msg.event_type {
+        MidiEventType::NoteOn | MidiEventType::NoteOff => format!(
+            "{:<13} ch {:>2}  {:<4} ({:>3})  velocity {:>3}",
+            format!("{:?}", msg.event_type),
+            msg.channel,
+            msg.note.full_name(),
+            msg.note.value(),
+            msg.velocity
+        ),
+        MidiEventType::ControlChange { controller, value } => format!(
+            "{:<13} ch {:>2}  CC {:>3}      value {:>3}",
+            "ControlChange", msg.channel, controller, value
+        ),
+        MidiEventType::PitchBend(bend) => format!(
+            "{:<13} ch {:>2}  value {:>5}",
+            "PitchBend", msg.channel, bend
+        ),
+    }
+}
+
+/// `data` as space-separated uppercase hex bytes
+fn hex_bytes(data: &[u8]) -> String {
+    data.iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Settings `run` resolves from `args` and the overridden `mapping`, for `config-dump`.
+fn effective_config(args: &RunArgs, mapping: &MappingConfig) -> serde_json::Value {
+    let mut channel_tables: Vec<u8> = mapping.channel_mappings.keys().copied().collect();
+    channel_tables.sort_unstable();
+    serde_json::json!({
+        "device": args.device,
+        "device_index": args.device_index,
+        "mapping": args.mapping.as_ref().map(|path| path.display().to_string()),
+        "channel": mapping.channel,
+        "channel_tables": channel_tables,
+        "octave_transpose": mapping.octave_transpose,
+        "transpose_semitones": mapping.transpose_semitones,
+        "thru_port": mapping.thru_port,
+        "zero_velocity_note_on": mapping.zero_velocity_note_on,
+        "listen": mapping.message_filter(),
+        "delay_scale": mapping.delay_scale(),
+        "min_note_gap_ms": mapping.min_note_gap_ms,
+        "layer_triggers": mapping
+            .layers
+            .iter()
+            .map(|layer| serde_json::json!({
+                "trigger": layer.trigger,
+                "activation": layer.activation,
+            }))
+            .collect::<Vec<_>>(),
+        "cc_keys": mapping.cc_keys,
+        "sustain_pedal": mapping.sustain_pedal,
+        "feedback": mapping.feedback,
+        "keyboard": mapping.keyboard,
+        "focus_window": args.focus_window,
+        "dry_run": args.dry_run,
+        "engine": args.engine_options(),
+    })
+}
+
+fn test_mapping(mapping_path: Option<PathBuf>, options: EngineOptions) -> xiv_midi::Result<()> {
+    let mapping = match mapping_path {
+        Some(path) => MappingConfig::from_file(&path)?,
+        None => create_ffxiv_default_mapping(),
+    };
+
+    println!(
+        "Sweeping {} mapped notes (dry run)...",
+        mapping.mappings.len()
+    );
+    let results = sweep_mapping(&mapping, options)?;
+
+    let mut silent = 0;
+    for result in &results {
+        if result.presses_key() {
+            println!("  {}", result);
+        } else {
+            silent += 1;
+            println!("  {}  <- no key pressed", result);
+        }
+    }
+
+    println!(
+        "{}/{} notes produced key presses",
+        results.len() - silent,
+        results.len()
+    );
+
+    Ok(())
+}
+
+/// Feed the notes of the MIDI file at `path` to an engine with `mapping_path`'s
+/// mapping at the file's own pace, printing each key event with its time.
+fn simulate(path: &Path, mapping_path: Option<PathBuf>, send_keys: bool) -> xiv_midi::Result<()> {
+    let mapping = match mapping_path {
+        Some(path) => MappingConfig::from_file(&path)?,
+        None => create_ffxiv_default_mapping(),
+    };
+    let messages = smf_messages(&std::fs::read(path)?, mapping.zero_velocity_note_on)?;
+    let length = messages.last().map_or(Duration::ZERO, |(at, _)| *at);
+
+    let mut engine = if send_keys {
+        let keyboard = EnigoKeyboardController::new_with_settings(mapping.keyboard)?;
+        MidiEngine::new(keyboard, mapping)
+    } else {
+        MidiEngine::new(DryRunKeyboardController::new(), mapping)
+    };
+    let start = Instant::now();
+    engine.set_key_event_callback(move |event| {
+        println!("{:>9.3}s  {}", start.elapsed().as_secs_f64(), event);
+    });
+
+    println!(
+        "Playing {} messages from {} ({:.1}s)...",
+        messages.len(),
+        path.display(),
+        length.as_secs_f64()
+    );
+    for (at, message) in messages {
+        if let Some(wait) = (start + at).checked_duration_since(Instant::now()) {
+            std::thread::sleep(wait);
+        }
+        engine.inject(message);
+    }
+    engine.finish_injected()?;
+
+    let stats = engine.stats();
+    println!(
+        "✓ {} note-ons, {} note-offs, {} dropped; latency {:.1} ms average, {:.1} ms max",
+        stats.note_ons,
+        stats.note_offs,
+        stats.dropped,
+        stats.average_latency.as_secs_f64() * 1000.0,
+        stats.max_latency.as_secs_f64() * 1000.0
+    );
+    Ok(())
+}
+
+fn describe_mapping(
+    mapping_path: Option<PathBuf>,
+    keys: Option<String>,
+    format: DescribeFormat,
+) -> xiv_midi::Result<()> {
+    let target = match keys {
+        Some(text) => {
+            let mut target = Vec::new();
+            for name in text.split_whitespace() {
+                let key = parse_key(name)
+                    .ok_or_else(|| xiv_midi::Error::Mapping(format!("Unknown key '{}'", name)))?;
+                target.push(key);
+            }
+            Some(target)
+        }
+        None => None,
+    };
+
+    let mapping = match mapping_path {
+        Some(path) => MappingConfig::from_file(&path)?,
+        None => create_ffxiv_default_mapping(),
+    };
+    if format != DescribeFormat::Text {
+        print!("{}", mapping_table(&mapping, format));
+        return Ok(());
+    }
+    let stats = mapping.stats();
+
+    println!("Mapping: {}", mapping.summary());
+    match stats.note_range {
+        Some((min, max)) => println!(
+            "Notes mapped: {} ({} ({}) – {} ({}))",
+            stats.note_count,
+            min,
+            min.value(),
+            max,
+            max.value()
+        ),
+        None => println!("Notes mapped: 0"),
+    }
+    println!("Modifier zones:");
+    for (mods, count) in &stats.zones {
+        println!("  {:<16} {} notes", format_modifiers(*mods), count);
+    }
+    let used: Vec<String> = stats.keys_used.iter().map(|k| format_key(*k)).collect();
+    println!("Keys used ({}): {}", used.len(), used.join(" "));
+
+    if let Some(target) = target {
+        let unused = stats.unused_keys(&target);
+        if unused.is_empty() {
+            println!("All {} target keys are used", target.len());
+        } else {
+            let unused: Vec<String> = unused.iter().map(|k| format_key(*k)).collect();
+            println!(
+                "Unused target keys ({}): {}",
+                unused.len(),
+                unused.join(" ")
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_mapping(mapping_path: Option<PathBuf>) -> xiv_midi::Result<()> {
+    let mapping = match mapping_path {
+        Some(path) => MappingConfig::from_file(&path)?,
+        None => create_ffxiv_default_mapping(),
+    };
+    let warnings = mapping.validate();
+    if warnings.is_empty() {
+        println!("✓ No problems found ({})", mapping.summary());
+    } else {
+        for warning in &warnings {
+            println!("warning: {}", warning);
+        }
+        println!("{} warning(s)", warnings.len());
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Note name, MIDI number, modifiers and key for every mapped note, as CSV or a
+/// Markdown pipe table. Notes that press no key list their actions instead.
+fn mapping_table(mapping: &MappingConfig, format: DescribeFormat) -> String {
+    let header = ["Note", "MIDI", "Modifiers", "Key"];
+    let mut notes: Vec<&u8> = mapping.mappings.keys().collect();
+    notes.sort_unstable();
+
+    let rows = notes.into_iter().map(|&value| {
+        let note_mapping = &mapping.mappings[&value];
+        let mut mods = ZoneModifiers::NONE;
+        let mut key = None;
+        for action in &note_mapping.on_press {
+            match action {
+                Action::SetModifiers {
+                    shift, ctrl, alt, ..
+                } => {
+                    mods = ZoneModifiers {
+                        shift: *shift,
+                        ctrl: *ctrl,
+                        alt: *alt,
+                    };
+                }
+                Action::Press(k) if key.is_none() => key = Some(format_key(*k)),
+                Action::PressChord(keys) if key.is_none() && !keys.is_empty() => {
+                    let keys: Vec<String> = keys.iter().map(|k| format_key(*k)).collect();
+                    key = Some(keys.join(" + "));
+                }
+                _ => {}
+            }
+        }
+        let key = key.unwrap_or_else(|| {
+            let actions: Vec<String> = note_mapping
+                .on_press
+                .iter()
+                .map(Action::to_string)
+                .collect();
+            actions.join("; ")
+        });
+        let name = MidiNote::new(value).map_or_else(|_| String::new(), |n| n.full_name());
+        [name, value.to_string(), format_modifiers(mods), key]
+    });
+
+    let mut out = String::new();
+    match format {
+        DescribeFormat::Csv => {
+            out.push_str(&header.join(","));
+            out.push('\n');
+            for row in rows {
+                let cells: Vec<String> = row.iter().map(|c| csv_field(c)).collect();
+                out.push_str(&cells.join(","));
+                out.push('\n');
+            }
+        }
+        // Markdown (text output doesn't use the table)
+        _ => {
+            out.push_str(&format!("| {} |\n", header.join(" | ")));
+            out.push_str(&format!("|{}\n", "---|".repeat(header.len())));
+            for row in rows {
+                let cells: Vec<String> = row.iter().map(|c| c.replace('|', "\\|")).collect();
+                out.push_str(&format!("| {} |\n", cells.join(" | ")));
+            }
+        }
+    }
+    out
+}
+
+/// Quote a CSV field if it contains a separator, quote or newline.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn scaffold_mapping(midi_file: PathBuf, output: PathBuf) -> xiv_midi::Result<()> {
+    let notes = smf_notes(&std::fs::read(&midi_file)?)?;
+    let mapping = MappingConfig::scaffold(notes);
+    mapping.to_file(&output)?;
+
+    println!(
+        "✓ {} notes from {} saved to: {}",
+        mapping.mappings.len(),
+        midi_file.display(),
+        output.display()
+    );
+    if let Some((min, max)) = mapping.note_range() {
+        println!(
+            "Range: {} – {}; fill in each note's on_press/on_release",
+            min, max
+        );
+    }
+
+    Ok(())
+}
 
-    let mapping = create_ffxiv_default_mapping();
+fn generate_config(output: PathBuf, interactive: bool) -> xiv_midi::Result<()> {
+    let mapping = if interactive {
+        let stdin = std::io::stdin();
+        let layout = prompt_layout(&mut stdin.lock(), &mut std::io::stdout())?;
+        layout.build()
+    } else {
+        tracing::info!("Generating default mapping configuration...");
+        create_ffxiv_default_mapping()
+    };
     mapping.to_file(&output)?;
 
     println!("✓ Configuration saved to: {}", output.display());
@@ -122,3 +907,341 @@ fn generate_config(output: PathBuf) -> xiv_midi::Result<()> {
 
     Ok(())
 }
+
+/// Ask a question, returning `default` for an empty answer or end of input.
+fn ask<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    question: &str,
+    default: &str,
+) -> std::io::Result<String> {
+    write!(output, "{} [{}]: ", question, default)?;
+    output.flush()?;
+
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    let answer = line.trim();
+    Ok(if answer.is_empty() {
+        default.to_string()
+    } else {
+        answer.to_string()
+    })
+}
+
+/// Ask until `parse` accepts the answer.
+fn ask_parsed<R: BufRead, W: Write, T>(
+    input: &mut R,
+    output: &mut W,
+    question: &str,
+    default: &str,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> std::io::Result<T> {
+    loop {
+        let answer = ask(input, output, question, default)?;
+        match parse(&answer) {
+            Ok(value) => return Ok(value),
+            Err(e) => writeln!(output, "  {}", e)?,
+        }
+    }
+}
+
+/// Parse a key name like "Q", "q", "2", "Num2" or "F1".
+fn parse_key(name: &str) -> Option<Key> {
+    let name = if name.len() == 1 && name.chars().all(|c| c.is_ascii_digit()) {
+        format!("Num{}", name)
+    } else if name.len() == 1 {
+        name.to_ascii_uppercase()
+    } else {
+        name.to_string()
+    };
+    serde_json::from_value(serde_json::Value::String(name)).ok()
+}
+
+/// `parse_key` for clap arguments.
+fn parse_key_arg(name: &str) -> Result<Key, String> {
+    parse_key(name).ok_or_else(|| format!("unknown key '{}'", name))
+}
+
+fn format_key(key: Key) -> String {
+    let name = format!("{:?}", key);
+    match name.strip_prefix("Num") {
+        Some(digit) => digit.to_string(),
+        None => name,
+    }
+}
+
+/// Parse "none", "shift", "ctrl+alt", ...
+fn parse_modifiers(text: &str) -> Result<ZoneModifiers, String> {
+    let mut mods = ZoneModifiers::NONE;
+    for part in text.split(['+', ' ']).filter(|p| !p.is_empty()) {
+        match part.to_ascii_lowercase().as_str() {
+            "none" => {}
+            "shift" => mods.shift = true,
+            "ctrl" | "control" => mods.ctrl = true,
+            "alt" => mods.alt = true,
+            other => return Err(format!("Unknown modifier '{}'", other)),
+        }
+    }
+    Ok(mods)
+}
+
+fn format_modifiers(mods: ZoneModifiers) -> String {
+    let mut parts = Vec::new();
+    if mods.shift {
+        parts.push("shift");
+    }
+    if mods.ctrl {
+        parts.push("ctrl");
+    }
+    if mods.alt {
+        parts.push("alt");
+    }
+    if parts.is_empty() {
+        "none".to_string()
+    } else {
+        parts.join("+")
+    }
+}
+
+/// Guided prompts for building an octave-zoned mapping layout.
+fn prompt_layout<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+) -> xiv_midi::Result<MappingLayout> {
+    let defaults = MappingLayout::ffxiv_default();
+
+    writeln!(
+        output,
+        "Build a mapping layout (press Enter to accept defaults)"
+    )?;
+
+    let start_note = ask_parsed(
+        input,
+        output,
+        "Lowest MIDI note",
+        &defaults.start_note.to_string(),
+        |s| match s.parse::<u8>() {
+            Ok(n) if n <= 127 => Ok(n),
+            _ => Err("Enter a MIDI note number between 0 and 127".to_string()),
+        },
+    )?;
+
+    let zone_count = ask_parsed(
+        input,
+        output,
+        "Number of octave zones",
+        &defaults.zones.len().to_string(),
+        |s| match s.parse::<usize>() {
+            Ok(n) if (1..=10).contains(&n) => Ok(n),
+            _ => Err("Enter a number between 1 and 10".to_string()),
+        },
+    )?;
+
+    let default_keys: Vec<String> = defaults.keys.iter().map(|k| format_key(*k)).collect();
+    let keys = ask_parsed(
+        input,
+        output,
+        "Keys per octave, lowest note first",
+        &default_keys.join(" "),
+        |s| {
+            s.split_whitespace()
+                .map(|name| parse_key(name).ok_or_else(|| format!("Unknown key '{}'", name)))
+                .collect::<Result<Vec<_>, _>>()
+        },
+    )?;
+
+    let mut zones = Vec::with_capacity(zone_count);
+    for zone in 0..zone_count {
+        let default = if zone_count == defaults.zones.len() {
+            defaults.zones[zone]
+        } else {
+            ZoneModifiers::NONE
+        };
+        let mods = ask_parsed(
+            input,
+            output,
+            &format!(
+                "Modifiers for zone {} (none/shift/ctrl/alt, e.g. ctrl+shift)",
+                zone + 1
+            ),
+            &format_modifiers(default),
+            parse_modifiers,
+        )?;
+        zones.push(mods);
+    }
+
+    Ok(MappingLayout {
+        start_note,
+        keys,
+        zones,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scripted(input: &str) -> MappingLayout {
+        let mut output = Vec::new();
+        prompt_layout(&mut input.as_bytes(), &mut output).unwrap()
+    }
+
+    #[test]
+    fn test_mapping_table() {
+        let mapping = create_ffxiv_default_mapping();
+
+        let csv = mapping_table(&mapping, DescribeFormat::Csv);
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("Note,MIDI,Modifiers,Key"));
+        assert_eq!(lines.next(), Some("C3,48,ctrl,Q"));
+        assert!(csv.contains("\nC#5,73,shift,2\n"));
+        assert_eq!(csv.lines().count(), 1 + mapping.mappings.len());
+
+        let markdown = mapping_table(&mapping, DescribeFormat::Markdown);
+        let mut lines = markdown.lines();
+        assert_eq!(lines.next(), Some("| Note | MIDI | Modifiers | Key |"));
+        assert_eq!(lines.next(), Some("|---|---|---|---|"));
+        assert_eq!(lines.next(), Some("| C3 | 48 | ctrl | Q |"));
+    }
+
+    #[test]
+    fn test_config_dump_reflects_overrides() {
+        let cli = Cli::try_parse_from([
+            "xiv-midi",
+            "config-dump",
+            "--device",
+            "Piano",
+            "--channel",
+            "3",
+            "--transpose",
+            "-12",
+            "--thru",
+            "Loopback",
+            "--timing",
+            "fast",
+            "--poly",
+            "--queue-capacity",
+            "256",
+            "--dry-run",
+        ])
+        .unwrap();
+        let Commands::ConfigDump(args) = cli.command else {
+            panic!("expected config-dump");
+        };
+
+        let config = effective_config(&args, &args.mapping().unwrap());
+        assert_eq!(config["device"], "Piano");
+        assert_eq!(config["channel"], 3);
+        assert_eq!(config["transpose_semitones"], -12);
+        assert_eq!(config["thru_port"], "Loopback");
+        assert_eq!(config["engine"]["polyphonic"], true);
+        assert_eq!(config["engine"]["queue_capacity"], 256);
+        assert_eq!(config["dry_run"], true);
+        assert_eq!(
+            config["engine"]["timing"],
+            serde_json::to_value(TimingPreset::builtin("fast").unwrap()).unwrap()
+        );
+
+        // Without the flag, the mapping's own channel is used
+        let cli = Cli::try_parse_from(["xiv-midi", "config-dump", "--device", "Piano"]).unwrap();
+        let Commands::ConfigDump(args) = cli.command else {
+            panic!("expected config-dump");
+        };
+        let config = effective_config(&args, &args.mapping().unwrap());
+        assert_eq!(
+            config["channel"],
+            serde_json::to_value(create_ffxiv_default_mapping().channel).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_watch_requires_mapping() {
+        assert!(Cli::try_parse_from(["xiv-midi", "run", "--device", "Piano", "--watch"]).is_err());
+        let cli = Cli::try_parse_from([
+            "xiv-midi",
+            "run",
+            "--device",
+            "Piano",
+            "--mapping",
+            "my.json",
+            "--watch",
+        ])
+        .unwrap();
+        let Commands::Run(args) = cli.command else {
+            panic!("expected run");
+        };
+        assert!(args.watch);
+    }
+
+    #[test]
+    fn test_device_index_replaces_device() {
+        let cli = Cli::try_parse_from(["xiv-midi", "run", "--device-index", "2"]).unwrap();
+        let Commands::Run(args) = cli.command else {
+            panic!("expected run");
+        };
+        assert_eq!(args.device, None);
+        assert_eq!(args.device_index, NonZeroUsize::new(2));
+
+        // One of the two is required, and they can't be combined
+        assert!(Cli::try_parse_from(["xiv-midi", "run"]).is_err());
+        assert!(
+            Cli::try_parse_from(["xiv-midi", "run", "-d", "Piano", "--device-index", "1"]).is_err()
+        );
+        // Numbers start at 1, like `xiv-midi list`
+        assert!(Cli::try_parse_from(["xiv-midi", "run", "--device-index", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_prompt_defaults_match_ffxiv() {
+        assert_eq!(scripted("\n\n\n\n\n\n"), MappingLayout::ffxiv_default());
+        // End of input also falls back to defaults
+        assert_eq!(scripted(""), MappingLayout::ffxiv_default());
+    }
+
+    #[test]
+    fn test_prompt_custom_layout() {
+        // Invalid answers are asked again
+        let layout = scripted("200\n60\n2\nq 1 F1 Space\nbogus\nnone\nctrl+shift\n");
+        assert_eq!(layout.start_note, 60);
+        assert_eq!(layout.keys, vec![Key::Q, Key::Num1, Key::F1, Key::Space]);
+        assert_eq!(
+            layout.zones,
+            vec![
+                ZoneModifiers::NONE,
+                ZoneModifiers {
+                    shift: true,
+                    ctrl: true,
+                    alt: false,
+                }
+            ]
+        );
+
+        let mapping = layout.build();
+        assert_eq!(mapping.mappings.len(), 8);
+        assert!(mapping.mappings.contains_key(&75));
+    }
+
+    #[test]
+    fn test_monitor_line() {
+        let note_on = MidiMessage {
+            event_type: MidiEventType::NoteOn,
+            channel: 9,
+            note: MidiNote::new(61).unwrap(),
+            velocity: 100,
+        };
+        assert_eq!(
+            monitor_line(&note_on),
+            "NoteOn        ch  9  C#4  ( 61)  velocity 100"
+        );
+        let pedal = MidiMessage::control_change(9, 64, 127);
+        assert_eq!(
+            monitor_line(&pedal),
+            "ControlChange ch  9  CC  64      value 127"
+        );
+        let bend = MidiMessage::parse(&[0xE9, 0x00, 0x40]).unwrap();
+        assert_eq!(monitor_line(&bend), "PitchBend     ch  9  value     0");
+        let bend = MidiMessage::parse(&[0xE9, 0x00, 0x00]).unwrap();
+        assert_eq!(monitor_line(&bend), "PitchBend     ch  9  value -8192");
+        assert_eq!(hex_bytes(&[0x90, 0x3D, 0x64]), "90 3D 64");
+    }
+}
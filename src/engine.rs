@@ -1,24 +1,449 @@
-use crate::error::{Error, Result};
-use crate::keyboard::{Key, KeyboardController};
-use crate::mapping::{Action, MappingConfig};
-use crate::midi::{MidiEventType, MidiMessage};
+use crate::error::{no_backend, Error, Result};
+use crate::keyboard::{DryRunKeyboardController, Key, KeyEvent, KeyboardController, ModifierSide};
+use crate::mapping::{
+    Action, BendDirection, LayerActivation, LayerTrigger, MappingConfig, NoteMapping,
+    MIN_TREMOLO_INTERVAL_MS, SUSTAIN_CONTROLLER,
+};
+use crate::midi::{
+    MidiDecoder, MidiEventType, MidiMessage, MidiNote, MidiSink, ALL_NOTES_OFF, ALL_SOUND_OFF,
+};
 use crossbeam_channel::{self as channel};
-use midir::{Ignore, MidiInput, MidiInputConnection, MidiInputPort};
+use midir::{
+    Ignore, MidiInput, MidiInputConnection, MidiInputPort, MidiOutput, MidiOutputConnection,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
-/// Default minimum gap between consecutive note-on keypresses.
-/// FF14 needs a small window to distinguish two keypresses.
-const DEFAULT_MIN_NOTE_GAP: Duration = Duration::from_millis(3);
+/// Upper bound on `Action::Repeat` counts, so a typo can't lock up the keyboard.
+const MAX_REPEAT_COUNT: u32 = 1000;
 
-/// Delay after changing modifier keys to let them register.
-const MODIFIER_SETTLE_DELAY: Duration = Duration::from_millis(3);
+/// Upper bound on how deeply `Action::Repeat`s can nest.
+const MAX_REPEAT_DEPTH: usize = 4;
+
+/// Bounds on the factor MIDI clock tempo scales `min_note_gap` by.
+pub const TEMPO_GAP_SCALE_RANGE: RangeInclusive<f64> = 0.5..=2.0;
+
+/// Events the processing thread's queue holds when `EngineOptions::queue_capacity`
+/// isn't set.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 64;
+
+/// MIDI clock pulses per quarter note.
+const CLOCK_PPQN: f64 = 24.0;
+
+/// A clock gap longer than this means the clock stopped; the tempo is forgotten.
+const CLOCK_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Order in which a note's modifiers and key are pressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum ModifierOrder {
+    /// Set modifiers, let them settle, then press the key (what FFXIV expects)
+    #[default]
+    ModifiersFirst,
+    /// Press the key, then set modifiers (for games that want it the other way)
+    KeyFirst,
+}
+
+/// How notes without a mapping are reported in the debug log.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum UnmappedNoteLog {
+    /// Log every unmapped note event
+    Always,
+    /// Log each unmapped note only the first time it is seen on a connection
+    #[default]
+    FirstPerNote,
+    /// Never log unmapped notes
+    Never,
+}
+
+/// What a note-on does when octave transposition folds it onto the mapped note
+/// that another incoming note is already holding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum FoldedNoteOn {
+    /// Release and press the key again, like repeating the held note
+    #[default]
+    Retrigger,
+    /// Leave the held key alone; the folded note's note-off is ignored too
+    Ignore,
+}
+
+/// Keypress timing values, saved and shared as a preset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimingPreset {
+    /// Minimum gap between consecutive note-on keypresses.
+    /// FF14 needs a small window to distinguish two keypresses.
+    pub min_note_gap_ms: u64,
+    /// Delay after changing modifier keys to let them register
+    pub modifier_settle_ms: u64,
+    /// Minimum time a note's key stays down before a note-off releases it
+    pub tap_hold_ms: u64,
+}
+
+impl TimingPreset {
+    /// Built-in presets by name, starting with the default.
+    pub const BUILTIN: [(&'static str, TimingPreset); 4] = [
+        ("default", TimingPreset::new(3, 3, 0)),
+        ("fast", TimingPreset::new(1, 1, 0)),
+        ("safe", TimingPreset::new(10, 8, 15)),
+        ("laggy-pc", TimingPreset::new(25, 20, 40)),
+    ];
+
+    pub const fn new(min_note_gap_ms: u64, modifier_settle_ms: u64, tap_hold_ms: u64) -> Self {
+        Self {
+            min_note_gap_ms,
+            modifier_settle_ms,
+            tap_hold_ms,
+        }
+    }
+
+    /// Look up a built-in preset by name.
+    pub fn builtin(name: &str) -> Option<Self> {
+        Self::BUILTIN
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, preset)| *preset)
+    }
+
+    /// Load from JSON file
+    pub fn from_file(path: &std::path::Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Save to JSON file
+    pub fn to_file(&self, path: &std::path::Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+
+    pub fn min_note_gap(&self) -> Duration {
+        Duration::from_millis(self.min_note_gap_ms)
+    }
+
+    pub fn modifier_settle(&self) -> Duration {
+        Duration::from_millis(self.modifier_settle_ms)
+    }
+
+    pub fn tap_hold(&self) -> Duration {
+        Duration::from_millis(self.tap_hold_ms)
+    }
+}
+
+impl Default for TimingPreset {
+    fn default() -> Self {
+        Self::BUILTIN[0].1
+    }
+}
+
+/// Tunable scheduler behaviour. Applies to connections made after it is set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct EngineOptions {
+    pub modifier_order: ModifierOrder,
+    pub unmapped_note_log: UnmappedNoteLog,
+    /// Left/right modifier keys used by `SetModifiers` actions that don't pick one
+    pub modifier_side: ModifierSide,
+    /// Let notes overlap instead of releasing the previous note on each note-on
+    pub polyphonic: bool,
+    /// In polyphonic mode, release the oldest held note when a new one would exceed
+    /// this many (voice stealing). `None` = unlimited.
+    pub max_voices: Option<usize>,
+    pub timing: TimingPreset,
+    /// Before an unmodified note, release every Shift/Ctrl/Alt key (both sides) even if
+    /// the scheduler thinks they are up, in case the game missed a release
+    pub paranoid_modifier_reset: bool,
+    /// Run `on_press`/`on_release` exactly as authored, without the auto-release,
+    /// minimum gap and modifier tracking of the smart scheduler
+    pub raw_execution: bool,
+    pub folded_note_on: FoldedNoteOn,
+    /// On connect, release every Shift/Ctrl/Alt key (both sides) before the first
+    /// note, in case one is physically held
+    pub reset_modifiers_on_connect: bool,
+    /// Key tapped once on connect, before the first note, to prime the game's
+    /// input handling (the first note is sometimes eaten otherwise)
+    pub warm_up_key: Option<Key>,
+    /// Experimental: while the input sends MIDI clock, scale `min_note_gap` by this
+    /// tempo over the detected one (faster = tighter), within `TEMPO_GAP_SCALE_RANGE`
+    pub tempo_gap_reference_bpm: Option<u32>,
+    /// Events that can wait for the processing thread before new ones are dropped
+    /// (`None` = `DEFAULT_QUEUE_CAPACITY`, at least 1). A larger queue rides out
+    /// bursts like dense chords without losing notes, but a backlog then plays
+    /// late; a smaller one keeps latency low and drops notes sooner.
+    pub queue_capacity: Option<usize>,
+}
+
+/// Callback receiving messages emitted by `Action::Log`.
+pub type LogCallback = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Callback receiving every raw incoming MIDI message, before parsing.
+pub type RawMidiCallback = Arc<dyn Fn(&[u8]) + Send + Sync>;
+
+/// Callback receiving every key press/release the scheduler sends, after it is sent.
+pub type KeyEventCallback = Arc<dyn Fn(KeyEvent) + Send + Sync>;
+
+/// Decides whether keys may be sent right now, e.g. `focus::window_focus_check`.
+pub type FocusCheck = Arc<dyn Fn() -> bool + Send + Sync>;
+
+/// Shared MIDI thru output that processed notes are forwarded to.
+pub type ThruSink = Arc<Mutex<dyn MidiSink>>;
+
+/// Snapshot of the engine's event counters (see `MidiEngine::stats`). Counts
+/// cover every connection since the engine was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EngineStats {
+    /// Note-ons handled by the scheduler
+    pub note_ons: u64,
+    /// Note-offs handled by the scheduler
+    pub note_offs: u64,
+    /// Messages dropped because the processing thread's queue was full
+    pub dropped: u64,
+    /// Incoming data that couldn't be parsed as a MIDI message
+    pub parse_errors: u64,
+    /// Time from a message reaching the MIDI backend to the first key it pressed,
+    /// averaged over roughly the last `LATENCY_SMOOTHING` keypresses
+    pub average_latency: Duration,
+    /// Longest time from a message reaching the MIDI backend to its first key
+    pub max_latency: Duration,
+}
+
+/// Keypresses the rolling `EngineStats::average_latency` mostly reflects: each new
+/// sample moves the average by 1/`LATENCY_SMOOTHING` of the difference.
+pub const LATENCY_SMOOTHING: u64 = 16;
+
+/// Counters behind `EngineStats`, updated from the MIDI callback and the
+/// processing threads.
+#[derive(Default)]
+struct StatCounters {
+    note_ons: AtomicU64,
+    note_offs: AtomicU64,
+    dropped: AtomicU64,
+    parse_errors: AtomicU64,
+    /// Whether `average_latency_us` holds a sample yet
+    latency_sampled: AtomicBool,
+    average_latency_us: AtomicU64,
+    max_latency_us: AtomicU64,
+}
+
+impl StatCounters {
+    fn snapshot(&self) -> EngineStats {
+        EngineStats {
+            note_ons: self.note_ons.load(Ordering::Relaxed),
+            note_offs: self.note_offs.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            parse_errors: self.parse_errors.load(Ordering::Relaxed),
+            average_latency: Duration::from_micros(self.average_latency_us.load(Ordering::Relaxed)),
+            max_latency: Duration::from_micros(self.max_latency_us.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn record_latency(&self, latency: Duration) {
+        let sample = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.max_latency_us.fetch_max(sample, Ordering::Relaxed);
+        let first = !self.latency_sampled.swap(true, Ordering::Relaxed);
+        let _ =
+            self.average_latency_us
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |average| {
+                    Some(if first {
+                        sample
+                    } else {
+                        average - average / LATENCY_SMOOTHING + sample / LATENCY_SMOOTHING
+                    })
+                });
+    }
+
+    fn count_processed(&self, event_type: MidiEventType) {
+        let counter = match event_type {
+            MidiEventType::NoteOn => &self.note_ons,
+            MidiEventType::NoteOff => &self.note_offs,
+            _ => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
 
 /// MIDI engine that processes MIDI events and triggers keyboard actions
-pub struct MidiEngine<K: KeyboardController> {
-    keyboard: Arc<Mutex<K>>,
+pub struct MidiEngine {
+    keyboard: Arc<Mutex<Box<dyn KeyboardController>>>,
     mapping: Arc<Mutex<MappingConfig>>,
+    /// Wakes the processing threads to move held notes over to a replaced mapping
+    mapping_changed: Signal,
+    /// Wakes the processing threads to forget the keys `panic` released
+    reset: Signal,
+    log_callback: Option<LogCallback>,
+    raw_callback: Option<RawMidiCallback>,
+    key_event_callback: Option<KeyEventCallback>,
+    focus_check: Option<FocusCheck>,
+    options: EngineOptions,
+    thru: Option<ThruSink>,
+    feedback: Option<ThruSink>,
+    mutes: ZoneMutes,
+    tempo: ClockTempo,
+    stats: Arc<StatCounters>,
+    /// Layer the processing threads' `Action::SwitchLayer`s picked
+    switched_layer: Arc<AtomicU8>,
+    /// Queue of the latest connection's processing thread, for `inject`
+    injector: Mutex<Option<channel::Sender<MidiEvent>>>,
+    /// Dropping the sender tells every processing thread to exit (see `stop`)
+    shutdown: Mutex<(channel::Sender<()>, channel::Receiver<()>)>,
+    /// Processing threads (and their key event reporters) that `stop` waits for
+    threads: Mutex<Vec<thread::JoinHandle<()>>>,
+}
+
+/// Tempo estimated from incoming MIDI clock (0xF8) pulses. Clones share the estimate.
+#[derive(Clone, Default)]
+pub struct ClockTempo {
+    state: Arc<Mutex<ClockState>>,
+}
+
+#[derive(Default)]
+struct ClockState {
+    last_pulse: Option<Instant>,
+    /// Smoothed time between pulses
+    interval: Option<Duration>,
+}
+
+impl ClockTempo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a clock pulse received at `at`.
+    pub fn pulse(&self, at: Instant) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(last) = state.last_pulse {
+            let interval = at.saturating_duration_since(last);
+            state.interval = if interval > CLOCK_TIMEOUT {
+                None
+            } else {
+                // Smooth out USB/driver jitter between pulses
+                Some(match state.interval {
+                    Some(average) => average.mul_f64(0.8) + interval.mul_f64(0.2),
+                    None => interval,
+                })
+            };
+        }
+        state.last_pulse = Some(at);
+    }
+
+    /// Record a pulse at `at` for each clock byte in a raw MIDI buffer. Real-time
+    /// bytes can't be data bytes, so this holds even mid-message.
+    pub fn pulses_in(&self, data: &[u8], at: Instant) {
+        for _ in data.iter().filter(|&&byte| byte == 0xF8) {
+            self.pulse(at);
+        }
+    }
+
+    /// Detected tempo in BPM, or `None` if no clock has been seen recently at `now`.
+    pub fn bpm(&self, now: Instant) -> Option<f64> {
+        let state = self.state.lock().unwrap();
+        let last = state.last_pulse?;
+        if now.saturating_duration_since(last) > CLOCK_TIMEOUT {
+            return None;
+        }
+        let interval = state.interval?.as_secs_f64();
+        (interval > 0.0).then(|| 60.0 / (interval * CLOCK_PPQN))
+    }
+}
+
+/// Wake-up sent to every processing thread running when it is raised. Each
+/// thread subscribes when it starts; raising it with no thread running does
+/// nothing. Clones share the subscribers.
+#[derive(Clone, Default)]
+struct Signal {
+    subscribers: Arc<Mutex<Vec<channel::Sender<()>>>>,
+}
+
+impl Signal {
+    /// A receiver woken by every later `raise`. Raises while one is still
+    /// pending collapse into it.
+    fn subscribe(&self) -> channel::Receiver<()> {
+        let (tx, rx) = channel::bounded(1);
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Wake every subscriber, forgetting those whose thread has exited.
+    fn raise(&self) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| !matches!(tx.try_send(()), Err(channel::TrySendError::Disconnected(_))));
+    }
+}
+
+/// Runtime-toggleable note ranges whose events are dropped, e.g. to silence one
+/// zone of the controller during a duet. Clones share the same set.
+#[derive(Clone, Default)]
+pub struct ZoneMutes {
+    ranges: Arc<Mutex<Vec<RangeInclusive<u8>>>>,
+    /// Wakes the processing threads so held notes in a newly-muted range are released
+    changed: Signal,
+}
+
+impl ZoneMutes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mute or unmute a note range (matched against incoming, untransposed notes).
+    pub fn set_muted(&self, range: RangeInclusive<u8>, muted: bool) {
+        let mut ranges = self.ranges.lock().unwrap();
+        let existing = ranges.iter().position(|r| *r == range);
+        match (existing, muted) {
+            (None, true) => ranges.push(range),
+            (Some(index), false) => {
+                ranges.remove(index);
+            }
+            _ => return,
+        }
+        self.changed.raise();
+    }
+
+    /// Whether exactly this range is currently muted.
+    pub fn is_range_muted(&self, range: &RangeInclusive<u8>) -> bool {
+        self.ranges.lock().unwrap().contains(range)
+    }
+
+    /// Whether `note` falls in any muted range.
+    pub fn is_muted(&self, note: u8) -> bool {
+        self.ranges
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|r| r.contains(&note))
+    }
+
+    /// Unmute everything.
+    pub fn clear(&self) {
+        let mut ranges = self.ranges.lock().unwrap();
+        if !ranges.is_empty() {
+            ranges.clear();
+            self.changed.raise();
+        }
+    }
+}
+
+/// Time source for the scheduler, so timing logic can be tested deterministically.
+trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+/// Wall-clock time backed by `Instant` and `thread::sleep`.
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
 }
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
@@ -26,360 +451,4071 @@ struct ModifierState {
     shift: bool,
     ctrl: bool,
     alt: bool,
+    side: ModifierSide,
 }
 
 /// Tracks the currently playing note so we can auto-release before the next one.
 struct NoteScheduler {
     /// The key currently held down (if any)
     current_key: Option<Key>,
+    /// The rest of an `Action::PressChord` held together with `current_key`
+    current_chord: Vec<Key>,
+    /// Incoming (untransposed) note that pressed `current_key`
+    current_note: Option<u8>,
+    /// Mapped note `current_note` resolved to
+    current_mapped_note: Option<u8>,
+    /// `MappingConfig::delay_scale` of the mapping being played
+    delay_scale: f32,
+    /// `MappingConfig::min_note_gap_ms` of the mapping being played
+    mapping_note_gap: Option<Duration>,
+    /// Incoming notes dropped by `FoldedNoteOn::Ignore`, whose note-off is dropped too
+    ignored_folds: HashSet<u8>,
+    /// Keys held down by `Action::Toggle`, separate from the note keys
+    toggled: HashSet<Key>,
+    /// Keys held in polyphonic mode, oldest first
+    voices: VecDeque<Key>,
     /// Modifier state currently applied
     current_modifiers: ModifierState,
+    /// Note whose `on_press` set modifiers without pressing a key, and the state
+    /// it set; its note-off clears them if nothing has changed them since
+    modifier_note: Option<(u8, ModifierState)>,
     /// When the last note-on keypress was sent
     last_note_time: Instant,
-    /// Minimum gap between consecutive note-on events
-    min_note_gap: Duration,
+    /// Active tremolo re-tapping the current key
+    tremolo: Option<Tremolo>,
+    /// Keys re-tapped by `Action::RepeatHeld`, by the incoming note holding them
+    repeaters: HashMap<u8, Tremolo>,
+    /// Per-note position in `NoteMapping::cycle`, keyed by mapped note
+    cycles: HashMap<u8, CycleState>,
+    /// Unmapped notes already reported, for `UnmappedNoteLog::FirstPerNote`
+    logged_unmapped: HashSet<u8>,
+    /// Layers whose trigger is held, by index in `MappingConfig::layers`
+    layers_down: HashSet<usize>,
+    /// Active layers: held `Hold` layers and switched-on `Toggle` layers
+    active_layers: HashSet<usize>,
+    /// Layer picked by `Action::SwitchLayer` (1 = the first, 0 = none), shared
+    /// with the engine so it can be displayed
+    switched_layer: Arc<AtomicU8>,
+    /// Layer each held note was pressed in, so it releases from the same table
+    note_layers: HashMap<u8, usize>,
+    /// Keys held by `MappingConfig::cc_keys`, by controller number
+    cc_held: HashMap<u8, Key>,
+    /// Direction of the `MappingConfig::pitch_bend` trigger that fired and hasn't
+    /// re-armed yet
+    bend_fired: Option<BendDirection>,
+    /// Keys the fired pitch bend actions left pressed, released when it re-arms
+    bend_held: Vec<Key>,
+    /// Whether the sustain pedal is down (with `MappingConfig::sustain_pedal`)
+    sustain: bool,
+    /// Note-off of the current key held back by the sustain pedal: its release
+    /// actions and key, run when the pedal lifts
+    sustained_release: Option<(Vec<Action>, Key)>,
+    /// Notes and controllers (at 64 or more) held on the device, in the order they
+    /// went down, whatever the mapping made of them
+    held_input: Vec<MidiMessage>,
+    clock: Arc<dyn Clock>,
+    /// Extra destination for `Action::Log` messages
+    log_callback: Option<LogCallback>,
+    /// Events are dropped while this returns false
+    focus_check: Option<FocusCheck>,
+    /// Whether the focus check failed last time (held keys were already released)
+    focus_lost: bool,
+    options: EngineOptions,
+    /// MIDI thru output for processed notes
+    thru: Option<ThruSink>,
+    /// Output for `MappingConfig::feedback` messages
+    feedback: Option<ThruSink>,
+    /// Held keys with feedback sent, and the message that turns it off again
+    lit: HashMap<Key, [u8; 3]>,
+    mutes: ZoneMutes,
+    /// Input tempo for `EngineOptions::tempo_gap_reference_bpm`
+    tempo: ClockTempo,
+}
+
+/// Position of a note in its action cycle.
+struct CycleState {
+    step: usize,
+    last_press: Instant,
+}
+
+/// A key being re-tapped at a fixed interval while its note is held.
+struct Tremolo {
+    key: Key,
+    interval: Duration,
+    next_tap: Instant,
 }
 
 impl NoteScheduler {
     fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    fn with_clock(clock: Arc<dyn Clock>) -> Self {
         Self {
             current_key: None,
+            current_chord: Vec::new(),
+            current_note: None,
+            current_mapped_note: None,
+            delay_scale: 1.0,
+            mapping_note_gap: None,
+            ignored_folds: HashSet::new(),
+            toggled: HashSet::new(),
+            voices: VecDeque::new(),
             current_modifiers: ModifierState::default(),
-            last_note_time: Instant::now() - Duration::from_secs(1), // far in the past
-            min_note_gap: DEFAULT_MIN_NOTE_GAP,
-        }
-    }
-
-    /// Release the currently playing note (if any).
-    fn release_current<K: KeyboardController>(&mut self, kb: &mut K) -> Result<()> {
-        if let Some(key) = self.current_key.take() {
-            kb.release(key)?;
+            modifier_note: None,
+            last_note_time: clock.now() - Duration::from_secs(1), // far in the past
+            tremolo: None,
+            repeaters: HashMap::new(),
+            cycles: HashMap::new(),
+            logged_unmapped: HashSet::new(),
+            layers_down: HashSet::new(),
+            active_layers: HashSet::new(),
+            switched_layer: Arc::default(),
+            note_layers: HashMap::new(),
+            cc_held: HashMap::new(),
+            bend_fired: None,
+            bend_held: Vec::new(),
+            sustain: false,
+            sustained_release: None,
+            held_input: Vec::new(),
+            clock,
+            log_callback: None,
+            focus_check: None,
+            focus_lost: false,
+            options: EngineOptions::default(),
+            thru: None,
+            feedback: None,
+            lit: HashMap::new(),
+            mutes: ZoneMutes::default(),
+            tempo: ClockTempo::default(),
         }
-        Ok(())
     }
 
-    /// Ensure the minimum gap since the last note-on has elapsed.
-    fn wait_min_gap(&self) {
-        let elapsed = self.last_note_time.elapsed();
-        if elapsed < self.min_note_gap {
-            thread::sleep(self.min_note_gap - elapsed);
-        }
+    /// Handle one incoming MIDI message against the mapping.
+    fn handle_message<K: KeyboardController>(
+        &mut self,
+        msg: &MidiMessage,
+        mapping: &MappingConfig,
+        kb: &mut K,
+    ) -> Result<()> {
+        let result = self.process_message(msg, mapping, kb);
+        self.sync_feedback();
+        result
     }
 
-    /// Set modifier keys to the desired state, only sending changes.
-    fn set_modifiers<K: KeyboardController>(
+    fn process_message<K: KeyboardController>(
         &mut self,
-        desired: ModifierState,
+        msg: &MidiMessage,
+        mapping: &MappingConfig,
         kb: &mut K,
     ) -> Result<()> {
-        let cur = self.current_modifiers;
-        let mut changed = false;
-
-        if desired.shift != cur.shift {
-            if desired.shift {
-                kb.press(Key::Shift)?;
-            } else {
-                kb.release(Key::Shift)?;
-            }
-            changed = true;
+        if !mapping.listens_to(msg.channel) {
+            return Ok(());
         }
-
-        if desired.ctrl != cur.ctrl {
-            if desired.ctrl {
-                kb.press(Key::Control)?;
-            } else {
-                kb.release(Key::Control)?;
-            }
-            changed = true;
+        self.track_input(msg);
+        if !self.check_focus(kb)? {
+            return Ok(());
         }
+        self.delay_scale = mapping.delay_scale();
+        self.mapping_note_gap = mapping.min_note_gap();
 
-        if desired.alt != cur.alt {
-            if desired.alt {
-                kb.press(Key::Alt)?;
-            } else {
-                kb.release(Key::Alt)?;
+        // Layer triggers and other controllers don't play notes
+        if self.update_layer(msg, mapping) {
+            return Ok(());
+        }
+        match msg.event_type {
+            MidiEventType::PitchBend(bend) => return self.update_pitch_bend(bend, mapping, kb),
+            MidiEventType::ControlChange { controller, value } => {
+                // Sent instead of note-offs by some controllers and sequencers on stop
+                if matches!(controller, ALL_SOUND_OFF | ALL_NOTES_OFF) {
+                    tracing::debug!("All notes off (CC {}): releasing held keys", controller);
+                    return self.release_held(kb);
+                }
+                if mapping.sustain_pedal && controller == SUSTAIN_CONTROLLER {
+                    self.update_sustain(value >= 64, kb)?;
+                }
+                return self.update_cc_key(controller, value, mapping, kb);
             }
-            changed = true;
+            MidiEventType::NoteOn | MidiEventType::NoteOff => {}
         }
 
-        self.current_modifiers = desired;
-
-        // Only sleep if modifiers actually changed and at least one is active
-        if changed && (desired.shift || desired.ctrl || desired.alt) {
-            thread::sleep(MODIFIER_SETTLE_DELAY);
+        if self.mutes.is_muted(msg.note.value()) {
+            return Ok(());
         }
 
-        Ok(())
-    }
+        // Release a note from the same table it was pressed in, even if the layers changed since
+        let layer = if msg.event_type == MidiEventType::NoteOn {
+            let switched = usize::from(self.switched_layer.load(Ordering::Relaxed));
+            let layer = mapping.resolve_layer(msg.note, |i| {
+                self.active_layers.contains(&i) || switched == i + 1
+            });
+            match layer {
+                Some(i) => self.note_layers.insert(msg.note.value(), i),
+                None => self.note_layers.remove(&msg.note.value()),
+            };
+            layer
+        } else {
+            self.note_layers.remove(&msg.note.value())
+        };
 
-    /// Play a new note: auto-release previous, enforce gap, set modifiers, press key.
-    fn play_note<K: KeyboardController>(&mut self, actions: &[Action], kb: &mut K) -> Result<()> {
-        // Pre-scan: extract the target modifier state and key from the action list
-        // so we can do the smart release-before-press logic.
-        let mut target_mods: Option<ModifierState> = None;
-        let mut target_key: Option<Key> = None;
+        let transposed = mapping.get_mapping_layered(msg.note, msg.channel, layer);
+        self.forward_thru(msg, transposed.map_or(msg.note, |(n, _)| n));
 
-        for action in actions {
-            match action {
-                Action::SetModifiers { shift, ctrl, alt } => {
-                    target_mods = Some(ModifierState {
-                        shift: *shift,
-                        ctrl: *ctrl,
-                        alt: *alt,
-                    });
+        let (note, note_mapping) = match transposed {
+            Some((transposed_note, m)) => (transposed_note.value(), m),
+            None => {
+                self.log_unmapped(msg.note);
+                return Ok(());
+            }
+        };
+        if !note_mapping.enabled {
+            return Ok(());
+        }
+        let event_type = match msg.event_type {
+            MidiEventType::NoteOn if note_mapping.invert => MidiEventType::NoteOff,
+            MidiEventType::NoteOff if note_mapping.invert => MidiEventType::NoteOn,
+            event_type => event_type,
+        };
+
+        if self.options.raw_execution {
+            let actions = match event_type {
+                MidiEventType::NoteOn => {
+                    self.stop_repeaters(msg.note.value());
+                    self.advance_cycle(note, note_mapping)
                 }
-                Action::Press(key) => {
-                    target_key = Some(*key);
+                MidiEventType::NoteOff => {
+                    self.repeaters.remove(&msg.note.value());
+                    note_mapping.release_actions(msg.velocity)
                 }
-                _ => {}
+                MidiEventType::ControlChange { .. } | MidiEventType::PitchBend(_) => {
+                    return Ok(());
+                }
+            };
+            self.execute_actions_raw(actions, kb)?;
+            if event_type == MidiEventType::NoteOn {
+                self.start_repeaters(msg.note.value(), actions);
             }
+            return Ok(());
         }
 
-        // If this is a note-on (has a Press action), do the smart scheduling
-        if let Some(key) = target_key {
-            // 1. Release the previous note first
-            self.release_current(kb)?;
-
-            // 2. Enforce minimum gap between note-on events
-            self.wait_min_gap();
+        match event_type {
+            MidiEventType::NoteOn => {
+                let folded = self
+                    .current_note
+                    .is_some_and(|held| held != msg.note.value())
+                    && self.current_mapped_note == Some(note);
+                if folded && self.options.folded_note_on == FoldedNoteOn::Ignore {
+                    self.ignored_folds.insert(msg.note.value());
+                    return Ok(());
+                }
+                self.stop_repeaters(msg.note.value());
+                if self.note_on(note, note_mapping, msg.velocity, kb)? {
+                    self.current_note = Some(msg.note.value());
+                    self.current_mapped_note = Some(note);
+                    if let (Some(key), Some(feedback)) = (self.current_key, &mapping.feedback) {
+                        self.light(key, feedback.messages(msg.note.value()));
+                    }
+                } else if let Some(mods) =
+                    self.target_modifiers(self.current_press_actions(note, note_mapping))
+                {
+                    self.modifier_note = Some((msg.note.value(), mods));
+                }
+                self.start_repeaters(
+                    msg.note.value(),
+                    self.current_press_actions(note, note_mapping),
+                );
+                Ok(())
+            }
+            MidiEventType::NoteOff => {
+                if self.ignored_folds.remove(&msg.note.value()) {
+                    return Ok(());
+                }
+                self.repeaters.remove(&msg.note.value());
+                tracing::debug!(
+                    "Note-off {} with release velocity {}",
+                    msg.note,
+                    msg.velocity
+                );
+                // Figure out which key this note maps to for smart release
+                let pressed = self.current_press_actions(note, note_mapping);
+                let released_key = pressed.iter().find_map(|a| match a {
+                    Action::Press(k) => Some(*k),
+                    Action::PressChord(keys) => keys.first().copied(),
+                    _ => None,
+                });
+                let actions = note_mapping.release_actions(msg.velocity);
+                self.handle_note_off(actions, released_key, kb)?;
+                self.release_modifier_note(msg.note.value(), kb)
+            }
+            MidiEventType::ControlChange { .. } | MidiEventType::PitchBend(_) => Ok(()),
+        }
+    }
 
-            // 3. Set modifiers
-            if let Some(mods) = target_mods {
-                self.set_modifiers(mods, kb)?;
+    /// Track the layer triggers. A `Hold` layer is active while its trigger is
+    /// held; a `Toggle` layer switches on or off when its trigger goes down (a
+    /// controller rising to 64 or more). Returns whether `msg` was a trigger.
+    fn update_layer(&mut self, msg: &MidiMessage, mapping: &MappingConfig) -> bool {
+        let note = msg.note.value();
+        let mut matched = false;
+        for (i, layer) in mapping.layers.iter().enumerate() {
+            let down = match (layer.trigger, msg.event_type) {
+                (LayerTrigger::Note(n), MidiEventType::NoteOn) if n == note => true,
+                (LayerTrigger::Note(n), MidiEventType::NoteOff) if n == note => false,
+                (
+                    LayerTrigger::ControlChange(c),
+                    MidiEventType::ControlChange { controller, value },
+                ) if c == controller => value >= 64,
+                _ => continue,
+            };
+            matched = true;
+            let pressed = if down {
+                self.layers_down.insert(i)
+            } else {
+                self.layers_down.remove(&i);
+                false
+            };
+            let active = match layer.activation {
+                LayerActivation::Hold => down,
+                LayerActivation::Toggle if pressed => !self.active_layers.contains(&i),
+                LayerActivation::Toggle => continue,
+            };
+            if active {
+                self.active_layers.insert(i);
+            } else {
+                self.active_layers.remove(&i);
             }
+        }
+        matched
+    }
 
-            // 4. Press the new key
+    /// Press or release the key a controller holds via `MappingConfig::cc_keys`.
+    fn update_cc_key<K: KeyboardController>(
+        &mut self,
+        controller: u8,
+        value: u8,
+        mapping: &MappingConfig,
+        kb: &mut K,
+    ) -> Result<()> {
+        let Some(&key) = mapping.cc_keys.get(&controller) else {
+            return Ok(());
+        };
+        let on = value >= 64;
+        if on && !self.cc_held.contains_key(&controller) {
             kb.press(key)?;
-            self.current_key = Some(key);
-            self.last_note_time = Instant::now();
-        } else {
-            // This is a note-off or other action sequence — execute normally
-            self.execute_actions_raw(actions, kb)?;
+            self.cc_held.insert(controller, key);
+        } else if !on && let Some(held) = self.cc_held.remove(&controller) {
+            kb.release(held)?;
         }
-
         Ok(())
     }
 
-    /// Handle a note-off event.
-    fn handle_note_off<K: KeyboardController>(
+    /// Run the `MappingConfig::pitch_bend` actions once when the wheel passes a
+    /// threshold, and re-arm (releasing what they left pressed) when it comes back.
+    fn update_pitch_bend<K: KeyboardController>(
         &mut self,
-        actions: &[Action],
-        released_key: Option<Key>,
+        value: i16,
+        mapping: &MappingConfig,
         kb: &mut K,
     ) -> Result<()> {
-        // Only process the release if this note is still the current one.
-        // If a newer note has already replaced it, skip the release to avoid
-        // cutting off the new note.
-        if let Some(rk) = released_key {
-            if self.current_key == Some(rk) {
-                self.execute_actions_raw(actions, kb)?;
-                self.current_key = None;
-            }
-            // else: a different note is playing now, ignore this release
-        } else {
-            // No specific key identified, just run the actions
+        let Some(bend) = &mapping.pitch_bend else {
+            return Ok(());
+        };
+        if let Some(direction) = self.bend_fired
+            && bend.rearms(direction, value)
+        {
+            self.bend_fired = None;
+            self.release_bend(kb)?;
+        }
+        if self.bend_fired.is_none()
+            && let Some(direction) = bend.triggered(value)
+        {
+            self.bend_fired = Some(direction);
+            let actions = bend.actions(direction);
             self.execute_actions_raw(actions, kb)?;
+            self.bend_held = keys_left_pressed(actions);
         }
-
         Ok(())
     }
 
-    /// Execute actions without the smart scheduling (raw passthrough).
-    fn execute_actions_raw<K: KeyboardController>(
-        &mut self,
-        actions: &[Action],
-        kb: &mut K,
-    ) -> Result<()> {
-        for action in actions {
-            match action {
-                Action::Press(key) => {
-                    kb.press(*key)?;
-                }
-                Action::Release(key) => {
-                    kb.release(*key)?;
-                }
-                Action::Delay(ms) => {
-                    thread::sleep(Duration::from_millis(*ms));
-                }
-                Action::SetModifiers { shift, ctrl, alt } => {
-                    let desired = ModifierState {
-                        shift: *shift,
-                        ctrl: *ctrl,
-                        alt: *alt,
-                    };
-                    self.set_modifiers(desired, kb)?;
-                }
-            }
+    /// Release the keys the last pitch bend trigger left pressed.
+    fn release_bend<K: KeyboardController>(&mut self, kb: &mut K) -> Result<()> {
+        for key in std::mem::take(&mut self.bend_held) {
+            kb.release(key)?;
         }
         Ok(())
     }
-}
 
-/// Internal event sent through the channel from the MIDI callback to the processing thread.
-struct MidiEvent {
-    message: MidiMessage,
-}
+    /// Track the sustain pedal, running the held-back note-off when it lifts.
+    fn update_sustain<K: KeyboardController>(&mut self, down: bool, kb: &mut K) -> Result<()> {
+        self.sustain = down;
+        if !down && let Some((actions, key)) = self.sustained_release.take() {
+            self.handle_note_off(&actions, Some(key), kb)?;
+        }
+        Ok(())
+    }
 
-impl<K: KeyboardController + 'static> MidiEngine<K> {
-    pub fn new(keyboard: K, mapping: MappingConfig) -> Self {
-        Self {
-            keyboard: Arc::new(Mutex::new(keyboard)),
-            mapping: Arc::new(Mutex::new(mapping)),
+    /// Report a note with no mapping, according to `options.unmapped_note_log`.
+    fn log_unmapped(&mut self, note: MidiNote) {
+        let should_log = match self.options.unmapped_note_log {
+            UnmappedNoteLog::Always => true,
+            UnmappedNoteLog::FirstPerNote => self.logged_unmapped.insert(note.value()),
+            UnmappedNoteLog::Never => false,
+        };
+        if should_log {
+            tracing::debug!("No mapping for note {}", note);
         }
     }
 
-    /// Get a shared reference to the mapping config.
-    /// This can be used to modify settings (like octave_transpose) at runtime.
-    pub fn mapping(&self) -> Arc<Mutex<MappingConfig>> {
-        Arc::clone(&self.mapping)
+    /// Send the message to the thru output (if any) with its note replaced by `note`.
+    fn forward_thru(&self, msg: &MidiMessage, note: MidiNote) {
+        let Some(thru) = &self.thru else {
+            return;
+        };
+        let forwarded = MidiMessage {
+            note,
+            ..msg.clone()
+        };
+        if let Err(e) = thru.lock().unwrap().send(&forwarded.to_bytes()) {
+            tracing::warn!("MIDI thru send failed: {}", e);
+        }
     }
 
-    /// List available MIDI input devices
-    pub fn list_devices() -> Result<Vec<String>> {
-        let midi_in = MidiInput::new("xiv-midi-probe")?;
-        let mut devices = Vec::new();
+    /// Send the "on" feedback message for a note that now holds `key`.
+    fn light(&mut self, key: Key, (on, off): ([u8; 3], [u8; 3])) {
+        if self.feedback.is_none() {
+            return;
+        }
+        // The key may still be lit for another note that pressed it
+        if let Some(previous) = self.lit.insert(key, off)
+            && previous != off
+        {
+            self.send_feedback(&previous);
+        }
+        self.send_feedback(&on);
+    }
 
-        for port in midi_in.ports() {
-            if let Ok(name) = midi_in.port_name(&port) {
-                devices.push(name);
-            }
+    /// Send the "off" feedback message for every lit key that is no longer held.
+    fn sync_feedback(&mut self) {
+        if self.lit.is_empty() {
+            return;
         }
+        let held: HashSet<Key> = self
+            .current_key
+            .iter()
+            .chain(&self.voices)
+            .copied()
+            .collect();
+        let released: Vec<[u8; 3]> = self
+            .lit
+            .extract_if(|key, _| !held.contains(key))
+            .map(|(_, off)| off)
+            .collect();
+        for off in released {
+            self.send_feedback(&off);
+        }
+    }
 
-        Ok(devices)
+    fn send_feedback(&self, message: &[u8]) {
+        if let Some(feedback) = &self.feedback
+            && let Err(e) = feedback.lock().unwrap().send(message)
+        {
+            tracing::warn!("MIDI feedback send failed: {}", e);
+        }
     }
 
-    /// Connect to a MIDI device by name
-    pub fn connect(&self, device_name: &str) -> Result<MidiInputConnection<()>> {
-        self.connect_with_callback(device_name, |_| {})
+    /// Play a note-on, starting a tremolo if the mapping asks for one at this velocity.
+    /// Returns whether a new key is now held for this note.
+    fn note_on<K: KeyboardController>(
+        &mut self,
+        note: u8,
+        note_mapping: &NoteMapping,
+        velocity: u8,
+        kb: &mut K,
+    ) -> Result<bool> {
+        let actions = self.advance_cycle(note, note_mapping);
+        let pressed = self.play_note(actions, kb)?;
+
+        if let (Some(key), Some(tremolo)) = (self.current_key, &note_mapping.velocity_tremolo)
+            && let Some(interval) = tremolo.interval(velocity)
+        {
+            self.tremolo = Some(Tremolo {
+                key,
+                interval,
+                next_tap: self.last_note_time + interval,
+            });
+        }
+
+        Ok(pressed)
     }
 
-    /// Connect to a MIDI device by name with a callback for MIDI events
-    pub fn connect_with_callback<F>(
-        &self,
-        device_name: &str,
-        callback: F,
-    ) -> Result<MidiInputConnection<()>>
-    where
-        F: Fn(MidiMessage) + Send + 'static,
-    {
-        let mut midi_in = MidiInput::new("xiv-midi")?;
-        midi_in.ignore(Ignore::None);
+    /// Pick the press actions for a note-on, stepping its cycle if it has one.
+    /// A cycle restarts from the first step after `cycle_reset_ms` without a press.
+    fn advance_cycle<'a>(&mut self, note: u8, note_mapping: &'a NoteMapping) -> &'a [Action] {
+        if note_mapping.cycle.is_empty() {
+            return &note_mapping.on_press;
+        }
+
+        let now = self.clock.now();
+        let timeout = note_mapping.cycle_reset_ms.map(Duration::from_millis);
+        let step = match self.cycles.get(&note) {
+            Some(state) if timeout.is_none_or(|t| now.duration_since(state.last_press) < t) => {
+                (state.step + 1) % note_mapping.cycle.len()
+            }
+            _ => 0,
+        };
+        self.cycles.insert(
+            note,
+            CycleState {
+                step,
+                last_press: now,
+            },
+        );
+
+        &note_mapping.cycle[step]
+    }
 
-        // Find the port with matching name
-        let port = midi_in
-            .ports()
+    /// The press actions most recently run for a note (its current cycle step, if any).
+    fn current_press_actions<'a>(&self, note: u8, note_mapping: &'a NoteMapping) -> &'a [Action] {
+        match self.cycles.get(&note) {
+            Some(state) if state.step < note_mapping.cycle.len() => &note_mapping.cycle[state.step],
+            _ => &note_mapping.on_press,
+        }
+    }
+
+    /// When `tick` next needs to run, if any timer is pending.
+    fn next_deadline(&self) -> Option<Instant> {
+        let repeaters = self.repeaters.values().map(|r| r.next_tap);
+        self.tremolo
+            .as_ref()
+            .map(|t| t.next_tap)
             .into_iter()
-            .find(|p| {
-                midi_in
-                    .port_name(p)
-                    .map(|name| name == device_name)
-                    .unwrap_or(false)
-            })
-            .ok_or_else(|| Error::Mapping(format!("Device '{}' not found", device_name)))?;
+            .chain(repeaters)
+            .min()
+    }
 
-        self.connect_port_with_callback(port, callback)
+    /// Run timers that have come due.
+    fn tick<K: KeyboardController>(&mut self, kb: &mut K) -> Result<()> {
+        self.tick_tremolo(kb)?;
+        self.tick_repeaters(kb)
     }
 
-    /// Connect to a MIDI device by port
-    pub fn connect_port(&self, port: MidiInputPort) -> Result<MidiInputConnection<()>> {
-        self.connect_port_with_callback(port, |_| {})
+    /// Re-tap the tremolo key if it is due.
+    fn tick_tremolo<K: KeyboardController>(&mut self, kb: &mut K) -> Result<()> {
+        let Some(tremolo) = self.tremolo.as_ref() else {
+            return Ok(());
+        };
+        if self.clock.now() < tremolo.next_tap {
+            return Ok(());
+        }
+
+        let key = tremolo.key;
+        let interval = tremolo.interval;
+        if !self.check_focus(kb)? {
+            return Ok(());
+        }
+
+        kb.release(key)?;
+        self.wait_min_gap();
+        kb.press(key)?;
+        self.last_note_time = self.clock.now();
+
+        if let Some(tremolo) = self.tremolo.as_mut() {
+            tremolo.next_tap = self.last_note_time + interval;
+        }
+
+        Ok(())
     }
 
-    /// Connect to a MIDI device by port with a callback for MIDI events
-    pub fn connect_port_with_callback<F>(
+    /// Tap the keys of the `Action::RepeatHeld` repeaters that are due.
+    fn tick_repeaters<K: KeyboardController>(&mut self, kb: &mut K) -> Result<()> {
+        let now = self.clock.now();
+        let mut due: Vec<u8> = self
+            .repeaters
+            .iter()
+            .filter(|(_, repeater)| repeater.next_tap <= now)
+            .map(|(note, _)| *note)
+            .collect();
+        if due.is_empty() || !self.check_focus(kb)? {
+            return Ok(());
+        }
+        due.sort_unstable();
+        for note in due {
+            if let Some(repeater) = self.repeaters.get_mut(&note) {
+                repeater.next_tap = now + repeater.interval;
+                let key = repeater.key;
+                kb.tap(key, self.options.timing.tap_hold())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Before a note-on of `note`: stop the repeater a previous press of it left
+    /// running, and in monophonic mode every other note's too.
+    fn stop_repeaters(&mut self, note: u8) {
+        if self.options.polyphonic {
+            self.repeaters.remove(&note);
+        } else {
+            self.repeaters.clear();
+        }
+    }
+
+    /// After `note`'s press `actions` ran (tapping once): keep re-tapping the key
+    /// of their `Action::RepeatHeld`, if any, until its note-off.
+    fn start_repeaters(&mut self, note: u8, actions: &[Action]) {
+        let repeat = actions.iter().rev().find_map(|action| match action {
+            Action::RepeatHeld { key, interval_ms } => Some((*key, *interval_ms)),
+            _ => None,
+        });
+        if let Some((key, interval_ms)) = repeat {
+            let interval = Duration::from_millis(interval_ms.max(MIN_TREMOLO_INTERVAL_MS));
+            self.repeaters.insert(
+                note,
+                Tremolo {
+                    key,
+                    interval,
+                    next_tap: self.clock.now() + interval,
+                },
+            );
+        }
+    }
+
+    /// Release the current note if it has been muted since it was pressed.
+    fn release_if_muted<K: KeyboardController>(&mut self, kb: &mut K) -> Result<()> {
+        if self
+            .current_note
+            .is_some_and(|note| self.mutes.is_muted(note))
+        {
+            self.release_current(kb)?;
+            self.sync_feedback();
+        }
+        Ok(())
+    }
+
+    /// Record whether `msg` puts a note or controller down or lifts it.
+    fn track_input(&mut self, msg: &MidiMessage) {
+        // The note or controller `msg` is about
+        let input = |msg: &MidiMessage| match msg.event_type {
+            MidiEventType::NoteOn | MidiEventType::NoteOff => Some((false, msg.note.value())),
+            MidiEventType::ControlChange { controller, .. } => Some((true, controller)),
+            MidiEventType::PitchBend(_) => None,
+        };
+        let Some(this) = input(msg) else {
+            return;
+        };
+        self.held_input.retain(|held| input(held) != Some(this));
+        let down = match msg.event_type {
+            MidiEventType::NoteOn => true,
+            MidiEventType::ControlChange { value, .. } => value >= 64,
+            MidiEventType::NoteOff | MidiEventType::PitchBend(_) => false,
+        };
+        if down {
+            self.held_input.push(msg.clone());
+        }
+    }
+
+    /// Switch to a replaced mapping: release every key pressed under the old one,
+    /// then replay what is still held on the device so it presses its new keys.
+    /// Layer state is rebuilt from the replayed triggers, so toggled layers reset.
+    fn reload<K: KeyboardController>(&mut self, mapping: &MappingConfig, kb: &mut K) -> Result<()> {
+        self.release_held(kb)?;
+        self.sustain = false;
+        self.ignored_folds.clear();
+        self.layers_down.clear();
+        self.active_layers.clear();
+        self.switched_layer.store(0, Ordering::Relaxed);
+        self.note_layers.clear();
+        for msg in std::mem::take(&mut self.held_input) {
+            self.handle_message(&msg, mapping, kb)?;
+        }
+        Ok(())
+    }
+
+    /// Release every key on the keyboard and forget what the scheduler was holding,
+    /// without running any `on_release` actions. Notes still held on the device
+    /// play nothing until they are pressed again.
+    fn reset_keys<K: KeyboardController>(&mut self, kb: &mut K) -> Result<()> {
+        self.tremolo = None;
+        self.sustained_release = None;
+        self.current_key = None;
+        self.current_chord.clear();
+        self.current_note = None;
+        self.current_mapped_note = None;
+        self.voices.clear();
+        self.cc_held.clear();
+        self.bend_fired = None;
+        self.bend_held.clear();
+        self.toggled.clear();
+        self.repeaters.clear();
+        self.note_layers.clear();
+        self.current_modifiers = ModifierState::default();
+        self.modifier_note = None;
+        self.sync_feedback();
+        kb.release_all()
+    }
+
+    /// Whether keys may be sent. When the focus check starts failing, every held key
+    /// is released once so nothing stays down in whatever window took focus.
+    fn check_focus<K: KeyboardController>(&mut self, kb: &mut K) -> Result<bool> {
+        let focused = match &self.focus_check {
+            Some(check) => check(),
+            None => return Ok(true),
+        };
+        if focused {
+            self.focus_lost = false;
+        } else if !self.focus_lost {
+            self.focus_lost = true;
+            tracing::warn!("Game window not focused, dropping MIDI events");
+            self.release_held(kb)?;
+        }
+        Ok(focused)
+    }
+
+    /// Release every key the scheduler is holding: notes, controller keys and modifiers.
+    fn release_held<K: KeyboardController>(&mut self, kb: &mut K) -> Result<()> {
+        self.release_current(kb)?;
+        for key in self.voices.drain(..) {
+            kb.release(key)?;
+        }
+        for (_, key) in self.cc_held.drain() {
+            kb.release(key)?;
+        }
+        self.bend_fired = None;
+        self.release_bend(kb)?;
+        self.release_toggled(kb)?;
+        self.modifier_note = None;
+        self.repeaters.clear();
+        self.sync_feedback();
+        self.set_modifiers(ModifierState::default(), kb)
+    }
+
+    /// Release the keys `Action::Toggle` left down.
+    fn release_toggled<K: KeyboardController>(&mut self, kb: &mut K) -> Result<()> {
+        for key in std::mem::take(&mut self.toggled) {
+            kb.release(key)?;
+        }
+        Ok(())
+    }
+
+    /// Release the currently playing note (if any).
+    fn release_current<K: KeyboardController>(&mut self, kb: &mut K) -> Result<()> {
+        self.tremolo = None;
+        self.sustained_release = None;
+        self.current_note = None;
+        if let Some(key) = self.current_key.take() {
+            self.voices.retain(|k| *k != key);
+            kb.release(key)?;
+        }
+        self.release_chord(kb)
+    }
+
+    /// Release the rest of the current chord (whatever `on_release` didn't already).
+    fn release_chord<K: KeyboardController>(&mut self, kb: &mut K) -> Result<()> {
+        for key in std::mem::take(&mut self.current_chord) {
+            kb.release(key)?;
+        }
+        Ok(())
+    }
+
+    /// Emit a mapping-authored log message.
+    fn log(&self, message: &str) {
+        tracing::info!("Mapping log: {}", message);
+        if let Some(callback) = &self.log_callback {
+            callback(message);
+        }
+    }
+
+    /// Minimum gap between note-ons: the mapping's, else the timing preset's, scaled
+    /// by the MIDI clock tempo if enabled.
+    fn min_note_gap(&self) -> Duration {
+        let gap = self
+            .mapping_note_gap
+            .unwrap_or_else(|| self.options.timing.min_note_gap());
+        let Some(reference) = self.options.tempo_gap_reference_bpm else {
+            return gap;
+        };
+        match self.tempo.bpm(self.clock.now()) {
+            Some(bpm) => {
+                let (min, max) = TEMPO_GAP_SCALE_RANGE.into_inner();
+                gap.mul_f64((f64::from(reference) / bpm).clamp(min, max))
+            }
+            None => gap,
+        }
+    }
+
+    /// Ensure the minimum gap since the last note-on has elapsed.
+    fn wait_min_gap(&self) {
+        let elapsed = self.clock.now().duration_since(self.last_note_time);
+        let gap = self.min_note_gap();
+        if elapsed < gap {
+            self.clock.sleep(gap - elapsed);
+        }
+    }
+
+    /// Keep the last pressed key down for at least the tap hold time.
+    fn wait_tap_hold(&self) {
+        let held = self.clock.now().duration_since(self.last_note_time);
+        let hold = self.options.timing.tap_hold();
+        if held < hold {
+            self.clock.sleep(hold - held);
+        }
+    }
+
+    /// Modifier state for a `SetModifiers` action, falling back to the engine-wide side.
+    fn modifier_state(
         &self,
-        port: MidiInputPort,
-        callback: F,
-    ) -> Result<MidiInputConnection<()>>
-    where
-        F: Fn(MidiMessage) + Send + 'static,
-    {
-        let midi_in = MidiInput::new("xiv-midi")?;
+        shift: bool,
+        ctrl: bool,
+        alt: bool,
+        side: Option<ModifierSide>,
+    ) -> ModifierState {
+        ModifierState {
+            shift,
+            ctrl,
+            alt,
+            side: side.unwrap_or(self.options.modifier_side),
+        }
+    }
 
-        let keyboard = Arc::clone(&self.keyboard);
-        let mapping = Arc::clone(&self.mapping);
+    /// The modifier state the last `SetModifiers` in `actions` asks for, if any.
+    fn target_modifiers(&self, actions: &[Action]) -> Option<ModifierState> {
+        actions.iter().rev().find_map(|action| match action {
+            Action::SetModifiers {
+                shift,
+                ctrl,
+                alt,
+                side,
+            } => Some(self.modifier_state(*shift, *ctrl, *alt, *side)),
+            _ => None,
+        })
+    }
 
-        // Create a bounded channel — small buffer to avoid latency buildup.
-        // If the processing thread can't keep up, we'd rather drop old events
-        // than accumulate latency.
-        let (tx, rx) = channel::bounded::<MidiEvent>(64);
+    /// After the note-off of a note that only set modifiers: clear them, unless a
+    /// key is held with them or something else has set them since (including its
+    /// own `on_release`).
+    fn release_modifier_note<K: KeyboardController>(&mut self, note: u8, kb: &mut K) -> Result<()> {
+        let Some((_, mods)) = self.modifier_note.take_if(|(n, _)| *n == note) else {
+            return Ok(());
+        };
+        if self.current_key.is_none() && self.current_modifiers == mods {
+            self.set_modifiers(ModifierState::default(), kb)?;
+        }
+        Ok(())
+    }
 
-        // Spawn the processing thread with the NoteScheduler
-        thread::spawn(move || {
-            let mut scheduler = NoteScheduler::new();
+    /// Set modifier keys to the desired state, only sending changes.
+    fn set_modifiers<K: KeyboardController>(
+        &mut self,
+        desired: ModifierState,
+        kb: &mut K,
+    ) -> Result<()> {
+        let cur = self.current_modifiers;
+        let mut changed = false;
+
+        // Compare physical keys so switching sides swaps a held modifier
+        let modifiers = [
+            (
+                cur.shift.then(|| cur.side.shift()),
+                desired.shift.then(|| desired.side.shift()),
+            ),
+            (
+                cur.ctrl.then(|| cur.side.control()),
+                desired.ctrl.then(|| desired.side.control()),
+            ),
+            (
+                cur.alt.then(|| cur.side.alt()),
+                desired.alt.then(|| desired.side.alt()),
+            ),
+        ];
+        for (held, target) in modifiers {
+            if held != target {
+                if let Some(key) = held {
+                    kb.release(key)?;
+                }
+                if let Some(key) = target {
+                    kb.press(key)?;
+                }
+                changed = true;
+            }
+        }
 
-            while let Ok(event) = rx.recv() {
-                let msg = event.message;
+        self.current_modifiers = desired;
 
-                // Look up mapping
-                let mapping_guard = mapping.lock().unwrap();
-                if let Some(channel) = mapping_guard.channel {
-                    if msg.channel != channel {
-                        continue;
-                    }
+        // Only sleep if modifiers actually changed and at least one is active
+        if changed && (desired.shift || desired.ctrl || desired.alt) {
+            self.clock.sleep(self.options.timing.modifier_settle());
+        }
+
+        Ok(())
+    }
+
+    /// Run once when a processing thread starts, before its first event.
+    fn start<K: KeyboardController>(&mut self, kb: &mut K) -> Result<()> {
+        if let Some(key) = self.options.warm_up_key {
+            kb.tap(key, self.options.timing.tap_hold())?;
+        }
+        if self.options.reset_modifiers_on_connect {
+            self.force_release_modifiers(kb)?;
+        }
+        Ok(())
+    }
+
+    /// Run when a processing thread exits: release keys held by controllers and
+    /// toggles, and turn off feedback.
+    fn stop<K: KeyboardController>(&mut self, kb: &mut K) -> Result<()> {
+        for (_, off) in std::mem::take(&mut self.lit) {
+            self.send_feedback(&off);
+        }
+        for (_, key) in self.cc_held.drain() {
+            kb.release(key)?;
+        }
+        self.release_toggled(kb)?;
+        self.repeaters.clear();
+        self.release_bend(kb)
+    }
+
+    /// Release every managed modifier key regardless of the tracked state.
+    fn force_release_modifiers<K: KeyboardController>(&mut self, kb: &mut K) -> Result<()> {
+        for side in [
+            ModifierSide::Generic,
+            ModifierSide::Left,
+            ModifierSide::Right,
+        ] {
+            kb.force_release(side.shift())?;
+            kb.force_release(side.control())?;
+            kb.force_release(side.alt())?;
+        }
+        self.current_modifiers = ModifierState::default();
+        self.clock.sleep(self.options.timing.modifier_settle());
+        Ok(())
+    }
+
+    /// Play a new note: auto-release previous, enforce gap, set modifiers, press key.
+    /// Returns whether a key was pressed (as opposed to running the actions raw).
+    fn play_note<K: KeyboardController>(&mut self, actions: &[Action], kb: &mut K) -> Result<bool> {
+        // Pre-scan: extract the target modifier state and key from the action list
+        // so we can do the smart release-before-press logic.
+        let mut target_mods: Option<ModifierState> = None;
+        let mut target_key: Option<Key> = None;
+        let mut chord: &[Key] = &[];
+
+        for action in actions {
+            match action {
+                Action::SetModifiers {
+                    shift,
+                    ctrl,
+                    alt,
+                    side,
+                } => {
+                    target_mods = Some(self.modifier_state(*shift, *ctrl, *alt, *side));
+                }
+                Action::Press(key) => {
+                    target_key = Some(*key);
+                    chord = &[];
                 }
+                Action::PressChord(keys) if !keys.is_empty() => {
+                    target_key = Some(keys[0]);
+                    chord = &keys[1..];
+                }
+                _ => {}
+            }
+        }
+
+        // If this is a note-on (has a Press action), do the smart scheduling
+        if let Some(key) = target_key {
+            // 1. Release the previous note first (or, when polyphonic, make room for a voice)
+            if self.options.polyphonic {
+                self.make_room_for_voice(key, kb)?;
+            } else {
+                self.release_current(kb)?;
+            }
+
+            // 2. Enforce minimum gap between note-on events
+            self.wait_min_gap();
+
+            let unmodified = target_mods.is_none_or(|m| !(m.shift || m.ctrl || m.alt));
+            if self.options.paranoid_modifier_reset && unmodified {
+                self.force_release_modifiers(kb)?;
+            }
 
-                let note_mapping = match mapping_guard.get_mapping_transposed(msg.note) {
-                    Some((_transposed_note, m)) => m.clone(),
-                    None => {
-                        tracing::debug!("No mapping for note {}", msg.note);
-                        continue;
+            // 3-4. Set modifiers and press the new key (and the rest of its chord),
+            // in the configured order
+            let press = |kb: &mut K| -> Result<()> {
+                for key in std::iter::once(&key).chain(chord) {
+                    kb.press(*key)?;
+                }
+                Ok(())
+            };
+            match self.options.modifier_order {
+                ModifierOrder::ModifiersFirst => {
+                    if let Some(mods) = target_mods {
+                        self.set_modifiers(mods, kb)?;
                     }
-                };
-                drop(mapping_guard);
-
-                let mut kb = keyboard.lock().unwrap();
-
-                let result = match msg.event_type {
-                    MidiEventType::NoteOn => scheduler.play_note(&note_mapping.on_press, &mut *kb),
-                    MidiEventType::NoteOff => {
-                        // Figure out which key this note maps to for smart release
-                        let released_key = note_mapping.on_press.iter().find_map(|a| {
-                            if let Action::Press(k) = a {
-                                Some(*k)
-                            } else {
-                                None
-                            }
-                        });
-                        scheduler.handle_note_off(&note_mapping.on_release, released_key, &mut *kb)
+                    press(kb)?;
+                }
+                ModifierOrder::KeyFirst => {
+                    press(kb)?;
+                    if let Some(mods) = target_mods {
+                        self.set_modifiers(mods, kb)?;
                     }
-                };
-
-                if let Err(e) = result {
-                    tracing::error!("Error handling MIDI event: {}", e);
                 }
             }
+            self.current_key = Some(key);
+            self.current_chord = chord.to_vec();
+            self.last_note_time = self.clock.now();
+            if self.options.polyphonic {
+                self.voices.push_back(key);
+            }
 
-            tracing::info!("MIDI processing thread exiting");
-        });
-
-        // Connect midir — the callback just forwards events through the channel
-        let connection = midi_in.connect(
-            &port,
-            "xiv-midi-input",
-            move |_timestamp, data, _| match MidiMessage::parse(data) {
-                Ok(msg) => {
-                    callback(msg.clone());
-
-                    // Non-blocking send: if the channel is full, drop the event
-                    // to avoid latency buildup
-                    if let Err(e) = tx.try_send(MidiEvent { message: msg }) {
-                        tracing::warn!("MIDI event dropped (channel full): {}", e);
+            // 5. Emit any log markers and first repeat taps (the smart path doesn't
+            // replay the sequence)
+            for action in actions {
+                match action {
+                    Action::Log(message) => self.log(message),
+                    Action::RepeatHeld { key, .. } => {
+                        kb.tap(*key, self.options.timing.tap_hold())?
                     }
+                    Action::SwitchLayer(layer) => self.switch_layer(*layer),
+                    _ => {}
                 }
-                Err(e) => {
-                    tracing::error!("Error parsing MIDI message: {}", e);
+            }
+        } else {
+            // This is a note-off or other action sequence — execute normally
+            self.execute_actions_raw(actions, kb)?;
+        }
+
+        Ok(target_key.is_some())
+    }
+
+    /// Before pressing `key` polyphonically: retrigger it if already held, and steal
+    /// the oldest voices while at `max_voices`.
+    fn make_room_for_voice<K: KeyboardController>(&mut self, key: Key, kb: &mut K) -> Result<()> {
+        if let Some(pos) = self.voices.iter().position(|k| *k == key) {
+            self.release_voice(pos, kb)?;
+        }
+        if let Some(max) = self.options.max_voices {
+            while !self.voices.is_empty() && self.voices.len() >= max.max(1) {
+                self.release_voice(0, kb)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Release the held voice at `index` in `voices`.
+    fn release_voice<K: KeyboardController>(&mut self, index: usize, kb: &mut K) -> Result<()> {
+        let Some(key) = self.voices.remove(index) else {
+            return Ok(());
+        };
+        if self.current_key == Some(key) {
+            self.current_key = None;
+            self.current_note = None;
+            self.tremolo = None;
+            self.release_chord(kb)?;
+        }
+        kb.release(key)
+    }
+
+    /// Handle a note-off event.
+    fn handle_note_off<K: KeyboardController>(
+        &mut self,
+        actions: &[Action],
+        released_key: Option<Key>,
+        kb: &mut K,
+    ) -> Result<()> {
+        // Polyphonic: release the voice if it is still held (it may have been stolen)
+        if self.options.polyphonic
+            && let Some(rk) = released_key
+        {
+            if let Some(pos) = self.voices.iter().position(|k| *k == rk) {
+                self.voices.remove(pos);
+                let mut chord = Vec::new();
+                if self.current_key == Some(rk) {
+                    self.current_key = None;
+                    self.current_note = None;
+                    self.tremolo = None;
+                    chord = std::mem::take(&mut self.current_chord);
                 }
-            },
-            (),
-        )?;
+                self.wait_tap_hold();
+                self.execute_actions_raw(actions, kb)?;
+                Self::release_pure_modifier(rk, actions, kb)?;
+                Self::release_chord_keys(rk, chord, actions, kb)?;
+            }
+            return Ok(());
+        }
 
-        tracing::info!("Connected to MIDI device");
-        Ok(connection)
+        // Only process the release if this note is still the current one.
+        // If a newer note has already replaced it, skip the release to avoid
+        // cutting off the new note.
+        if let Some(rk) = released_key {
+            if self.current_key == Some(rk) && self.sustain {
+                // Held until the pedal lifts or another note replaces it
+                self.sustained_release = Some((actions.to_vec(), rk));
+            } else if self.current_key == Some(rk) {
+                self.tremolo = None;
+                self.wait_tap_hold();
+                let chord = std::mem::take(&mut self.current_chord);
+                self.execute_actions_raw(actions, kb)?;
+                Self::release_pure_modifier(rk, actions, kb)?;
+                Self::release_chord_keys(rk, chord, actions, kb)?;
+                self.current_key = None;
+                self.current_note = None;
+            }
+            // else: a different note is playing now, ignore this release
+        } else {
+            // No specific key identified, just run the actions
+            self.execute_actions_raw(actions, kb)?;
+        }
+
+        Ok(())
     }
 
-    /// Release all keys
-    pub fn release_all(&self) -> Result<()> {
-        self.keyboard.lock().unwrap().release_all()
+    /// A note whose `on_press` only presses a modifier holds that modifier as its
+    /// key; release it on note-off even if `on_release` forgot to.
+    fn release_pure_modifier<K: KeyboardController>(
+        key: Key,
+        on_release: &[Action],
+        kb: &mut K,
+    ) -> Result<()> {
+        let released = on_release
+            .iter()
+            .any(|a| matches!(a, Action::Release(k) if *k == key));
+        if key.is_modifier() && !released {
+            kb.release(key)?;
+        }
+        Ok(())
+    }
+
+    /// A chord (`key` plus the rest in `chord`) is released as a whole on note-off;
+    /// release the keys `on_release` didn't.
+    fn release_chord_keys<K: KeyboardController>(
+        key: Key,
+        chord: Vec<Key>,
+        on_release: &[Action],
+        kb: &mut K,
+    ) -> Result<()> {
+        if chord.is_empty() {
+            return Ok(());
+        }
+        for key in std::iter::once(key).chain(chord) {
+            if !on_release
+                .iter()
+                .any(|a| matches!(a, Action::Release(k) if *k == key))
+            {
+                kb.release(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Execute actions without the smart scheduling (raw passthrough).
+    fn execute_actions_raw<K: KeyboardController>(
+        &mut self,
+        actions: &[Action],
+        kb: &mut K,
+    ) -> Result<()> {
+        self.execute_actions_nested(actions, kb, 0)
+    }
+
+    /// Execute actions raw, `depth` levels inside `Action::Repeat`s.
+    fn execute_actions_nested<K: KeyboardController>(
+        &mut self,
+        actions: &[Action],
+        kb: &mut K,
+        depth: usize,
+    ) -> Result<()> {
+        for action in actions {
+            match action {
+                Action::Press(key) => {
+                    kb.press(*key)?;
+                }
+                Action::PressChord(keys) => {
+                    for key in keys {
+                        kb.press(*key)?;
+                    }
+                }
+                Action::Release(key) => {
+                    kb.release(*key)?;
+                }
+                Action::Delay(ms) => {
+                    let delay = Duration::from_millis(*ms).mul_f64(f64::from(self.delay_scale));
+                    self.clock.sleep(delay);
+                }
+                Action::SetModifiers {
+                    shift,
+                    ctrl,
+                    alt,
+                    side,
+                } => {
+                    let desired = self.modifier_state(*shift, *ctrl, *alt, *side);
+                    self.set_modifiers(desired, kb)?;
+                }
+                Action::Log(message) => {
+                    self.log(message);
+                }
+                Action::Repeat { count, actions } => {
+                    if *count > MAX_REPEAT_COUNT {
+                        return Err(Error::Mapping(format!(
+                            "Repeat count {} exceeds the maximum of {}",
+                            count, MAX_REPEAT_COUNT
+                        )));
+                    }
+                    if depth >= MAX_REPEAT_DEPTH {
+                        return Err(Error::Mapping(format!(
+                            "Repeat nested deeper than {} levels",
+                            MAX_REPEAT_DEPTH
+                        )));
+                    }
+                    for _ in 0..*count {
+                        self.execute_actions_nested(actions, kb, depth + 1)?;
+                    }
+                }
+                Action::ReleaseCurrent => {
+                    self.release_current(kb)?;
+                }
+                Action::TypeText(text) => {
+                    // Held modifiers would change what gets typed; the held key is
+                    // left alone and stays tracked in `current_key`
+                    self.set_modifiers(ModifierState::default(), kb)?;
+                    kb.type_text(text)?;
+                }
+                Action::RepeatHeld { key, .. } => {
+                    kb.tap(*key, self.options.timing.tap_hold())?;
+                }
+                Action::Toggle(key) => {
+                    if self.toggled.remove(key) {
+                        kb.release(*key)?;
+                    } else {
+                        kb.press(*key)?;
+                        self.toggled.insert(*key);
+                    }
+                }
+                Action::SwitchLayer(layer) => {
+                    self.switch_layer(*layer);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Make `layer` (1 = the first in `MappingConfig::layers`) the switched layer,
+    /// or switch back to the primary mappings with 0. Notes already held still
+    /// release from the table they were pressed in.
+    fn switch_layer(&mut self, layer: u8) {
+        tracing::debug!("Switching to layer {}", layer);
+        self.switched_layer.store(layer, Ordering::Relaxed);
+    }
+}
+
+/// Keyboard wrapper used by the processing thread: sends keys to the real backend,
+/// then queues each one for the key event callback (if any).
+struct ReportingKeyboard<'a> {
+    inner: &'a mut dyn KeyboardController,
+    events: Option<&'a channel::Sender<KeyEvent>>,
+    /// When the first key was pressed through this wrapper, for latency stats
+    first_press: Option<Instant>,
+}
+
+impl<'a> ReportingKeyboard<'a> {
+    fn new(
+        inner: &'a mut dyn KeyboardController,
+        events: Option<&'a channel::Sender<KeyEvent>>,
+    ) -> Self {
+        Self {
+            inner,
+            events,
+            first_press: None,
+        }
+    }
+
+    fn report(&mut self, event: KeyEvent) {
+        if let KeyEvent::Press(_) = event {
+            self.first_press.get_or_insert_with(Instant::now);
+        }
+        if let Some(events) = self.events {
+            let _ = events.send(event);
+        }
+    }
+}
+
+impl KeyboardController for ReportingKeyboard<'_> {
+    fn press(&mut self, key: Key) -> Result<()> {
+        self.inner.press(key)?;
+        self.report(KeyEvent::Press(key));
+        Ok(())
+    }
+
+    fn release(&mut self, key: Key) -> Result<()> {
+        self.inner.release(key)?;
+        self.report(KeyEvent::Release(key));
+        Ok(())
+    }
+
+    fn release_all(&mut self) -> Result<()> {
+        self.inner.release_all()
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<()> {
+        self.inner.type_text(text)
+    }
+
+    fn tap(&mut self, key: Key, hold: Duration) -> Result<()> {
+        self.inner.tap(key, hold)?;
+        self.report(KeyEvent::Press(key));
+        self.report(KeyEvent::Release(key));
+        Ok(())
+    }
+
+    fn force_release(&mut self, key: Key) -> Result<()> {
+        self.inner.force_release(key)?;
+        self.report(KeyEvent::Release(key));
+        Ok(())
+    }
+}
+
+/// Internal event sent through the channel from the MIDI callback to the processing thread.
+struct MidiEvent {
+    message: MidiMessage,
+    /// When the message reached the MIDI backend, for latency stats
+    received: Instant,
+}
+
+/// Converts midir's per-connection microsecond timestamps to `Instant`s.
+#[derive(Default)]
+struct TimestampClock {
+    /// A timestamp and the moment it was seen
+    origin: Option<(u64, Instant)>,
+}
+
+impl TimestampClock {
+    /// When the message stamped `timestamp` arrived, given that it is `now`.
+    fn received_at(&mut self, timestamp: u64, now: Instant) -> Instant {
+        if let Some((origin_timestamp, origin)) = self.origin
+            && let Some(elapsed) = timestamp.checked_sub(origin_timestamp)
+        {
+            let at = origin + Duration::from_micros(elapsed);
+            if at <= now {
+                return at;
+            }
+        }
+        // First message, or the backend's clock went backwards or ran ahead of ours
+        self.origin = Some((timestamp, now));
+        now
+    }
+}
+
+/// Open the platform MIDI input API as `client_name`. This only fails when the
+/// API isn't there at all (e.g. no ALSA sequencer on a headless machine).
+fn open_input(client_name: &str) -> Result<MidiInput> {
+    MidiInput::new(client_name).map_err(no_backend)
+}
+
+/// Connect to the MIDI output port named `port_name` as `client_name`.
+/// On Unix a virtual port with that name is created if no such port exists.
+fn open_output(client_name: &str, port_name: &str) -> Result<MidiOutputConnection> {
+    let midi_out = MidiOutput::new(client_name)?;
+
+    let port = midi_out.ports().into_iter().find(|p| {
+        midi_out
+            .port_name(p)
+            .map(|name| name == port_name)
+            .unwrap_or(false)
+    });
+
+    match port {
+        Some(port) => midi_out.connect(&port, client_name),
+        #[cfg(unix)]
+        None => {
+            use midir::os::unix::VirtualOutput;
+            midi_out.create_virtual(port_name)
+        }
+        #[cfg(not(unix))]
+        None => {
+            return Err(Error::MidiOutput(format!(
+                "Output port '{}' not found",
+                port_name
+            )));
+        }
+    }
+    .map_err(|e| Error::MidiOutput(e.to_string()))
+}
+
+/// Hand `event` to the processing thread without blocking. If the queue is full
+/// the event is dropped (and counted) to avoid latency buildup; if the thread has
+/// stopped it is ignored.
+fn enqueue(tx: &channel::Sender<MidiEvent>, event: MidiEvent, stats: &StatCounters) {
+    match tx.try_send(event) {
+        Ok(()) => {}
+        Err(channel::TrySendError::Full(_)) => {
+            stats.dropped.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!("MIDI event dropped (channel full)");
+        }
+        Err(channel::TrySendError::Disconnected(_)) => {
+            tracing::trace!("MIDI event ignored: the processing thread has stopped");
+        }
+    }
+}
+
+/// Parse a raw MIDI buffer from the device and queue the messages the decoder
+/// accepts for the processing thread, passing each to `callback` first.
+fn queue_messages<F: Fn(MidiMessage)>(
+    data: &[u8],
+    decoder: &mut MidiDecoder,
+    callback: &F,
+    tx: &channel::Sender<MidiEvent>,
+    stats: &StatCounters,
+    received: Instant,
+) {
+    // A buffer may batch several messages (e.g. a note-off and the next
+    // note-on); they are queued in order so the scheduler sees a clean handoff
+    for parsed in decoder.decode(data) {
+        match parsed {
+            Ok(msg) => {
+                callback(msg.clone());
+                enqueue(
+                    tx,
+                    MidiEvent {
+                        message: msg,
+                        received,
+                    },
+                    stats,
+                );
+            }
+            Err(e) => {
+                stats.parse_errors.fetch_add(1, Ordering::Relaxed);
+                tracing::error!("Error parsing MIDI message: {}", e);
+            }
+        }
+    }
+}
+
+/// Why the processing thread woke up.
+enum Wake {
+    Event(MidiEvent),
+    MutesChanged,
+    MappingChanged,
+    Reset,
+    Timer,
+}
+
+impl MidiEngine {
+    pub fn new<K: KeyboardController + 'static>(keyboard: K, mapping: MappingConfig) -> Self {
+        Self {
+            keyboard: Arc::new(Mutex::new(Box::new(keyboard))),
+            mapping: Arc::new(Mutex::new(mapping)),
+            mapping_changed: Signal::default(),
+            reset: Signal::default(),
+            log_callback: None,
+            raw_callback: None,
+            key_event_callback: None,
+            focus_check: None,
+            options: EngineOptions::default(),
+            thru: None,
+            feedback: None,
+            mutes: ZoneMutes::default(),
+            tempo: ClockTempo::default(),
+            stats: Arc::default(),
+            switched_layer: Arc::default(),
+            injector: Mutex::new(None),
+            shutdown: Mutex::new(channel::bounded(0)),
+            threads: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Replace the keyboard backend, releasing all keys on the old one first.
+    /// Running connections share the engine's keyboard, so this applies to them
+    /// immediately without reconnecting MIDI.
+    pub fn set_keyboard<K: KeyboardController + 'static>(&self, keyboard: K) -> Result<()> {
+        let mut current = self.keyboard.lock().unwrap();
+        current.release_all()?;
+        *current = Box::new(keyboard);
+        Ok(())
+    }
+
+    /// Handle to the engine's live note mutes.
+    pub fn mutes(&self) -> ZoneMutes {
+        self.mutes.clone()
+    }
+
+    /// Use an existing mute set (e.g. to keep mutes across reconnects).
+    /// Takes effect for connections made after this call.
+    pub fn set_mutes(&mut self, mutes: ZoneMutes) {
+        self.mutes = mutes;
+    }
+
+    /// Current scheduler options.
+    pub fn options(&self) -> EngineOptions {
+        self.options
+    }
+
+    /// Set scheduler options for connections made after this call.
+    pub fn set_options(&mut self, options: EngineOptions) {
+        self.options = options;
+    }
+
+    /// Use `preset`'s timing for connections made after this call.
+    pub fn apply_timing(&mut self, preset: TimingPreset) {
+        self.options.timing = preset;
+    }
+
+    /// Forward `Action::Log` messages to `callback` (in addition to tracing).
+    /// Takes effect for connections made after this call.
+    pub fn set_log_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        self.log_callback = Some(Arc::new(callback));
+    }
+
+    /// Pass every raw incoming MIDI message to `callback`, including ones that fail to
+    /// parse (e.g. for a MIDI monitor). Takes effect for connections made after this call.
+    pub fn set_raw_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(&[u8]) + Send + Sync + 'static,
+    {
+        self.raw_callback = Some(Arc::new(callback));
+    }
+
+    /// Pass every key press/release the scheduler sends to `callback`, e.g. to drive
+    /// an on-screen keyboard. The callback runs on its own thread, after the key is
+    /// sent. Takes effect for connections made after this call.
+    pub fn set_key_event_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(KeyEvent) + Send + Sync + 'static,
+    {
+        self.key_event_callback = Some(Arc::new(callback));
+    }
+
+    /// Only send keys while `check` returns true (e.g. `focus::window_focus_check`).
+    /// Events arriving while it fails are dropped, and held keys are released when it
+    /// starts failing. Takes effect for connections made after this call.
+    pub fn set_focus_check<F>(&mut self, check: F)
+    where
+        F: Fn() -> bool + Send + Sync + 'static,
+    {
+        self.focus_check = Some(Arc::new(check));
+    }
+
+    /// Handle to the tempo detected from the connected device's MIDI clock
+    /// (tracked while `EngineOptions::tempo_gap_reference_bpm` is set).
+    pub fn clock_tempo(&self) -> ClockTempo {
+        self.tempo.clone()
+    }
+
+    /// Counts of the notes handled and the events lost so far, e.g. to show
+    /// whether the machine keeps up with the input.
+    pub fn stats(&self) -> EngineStats {
+        self.stats.snapshot()
+    }
+
+    /// Layer the last `Action::SwitchLayer` picked (1 = the first in
+    /// `MappingConfig::layers`), or 0 while the primary mappings are in use.
+    pub fn switched_layer(&self) -> u8 {
+        self.switched_layer.load(Ordering::Relaxed)
+    }
+
+    /// Get a shared reference to the mapping config.
+    /// This can be used to modify settings (like octave_transpose) at runtime.
+    pub fn mapping(&self) -> Arc<Mutex<MappingConfig>> {
+        Arc::clone(&self.mapping)
+    }
+
+    /// Override the timing preset's minimum note gap for the live mapping (see
+    /// `MappingConfig::min_note_gap_ms`); `None` goes back to the preset. Applies
+    /// from the next message.
+    pub fn set_min_note_gap(&self, gap_ms: Option<u64>) {
+        self.mapping.lock().unwrap().min_note_gap_ms = gap_ms;
+    }
+
+    /// Swap in a new mapping on a running connection (hot reload, profile switch).
+    /// Keys pressed under the old mapping are released, then notes still held on
+    /// the device are pressed again under the new one. Settings read when
+    /// connecting (e.g. `listen`, `feedback.port`) need a reconnect.
+    pub fn set_mapping(&self, mapping: MappingConfig) {
+        *self.mapping.lock().unwrap() = mapping;
+        self.mapping_changed.raise();
+    }
+
+    /// Forward processed notes (after channel filtering and octave transposition) to `sink`.
+    /// Takes effect for connections made after this call.
+    pub fn set_thru<S: MidiSink + 'static>(&mut self, sink: S) {
+        self.thru = Some(Arc::new(Mutex::new(sink)));
+    }
+
+    /// Open the MIDI output port named `port_name` as the thru output.
+    /// On Unix a virtual port with that name is created if no such port exists.
+    pub fn open_thru(&mut self, port_name: &str) -> Result<()> {
+        let connection = open_output("xiv-midi-thru", port_name)?;
+        self.set_thru(connection);
+        Ok(())
+    }
+
+    /// Send `MappingConfig::feedback` messages (e.g. pad LEDs) to `sink`.
+    /// Takes effect for connections made after this call.
+    pub fn set_feedback<S: MidiSink + 'static>(&mut self, sink: S) {
+        self.feedback = Some(Arc::new(Mutex::new(sink)));
+    }
+
+    /// Open the MIDI output port named `port_name` (usually the controller's own
+    /// output) for feedback messages, like `open_thru`.
+    pub fn open_feedback(&mut self, port_name: &str) -> Result<()> {
+        let connection = open_output("xiv-midi-feedback", port_name)?;
+        self.set_feedback(connection);
+        Ok(())
+    }
+
+    /// List available MIDI input devices
+    pub fn list_devices() -> Result<Vec<String>> {
+        let midi_in = open_input("xiv-midi-probe")?;
+        Ok(named_ports(&midi_in)
+            .into_iter()
+            .map(|(_, name)| name)
+            .collect())
+    }
+
+    /// Connect to a MIDI device by name
+    pub fn connect(&self, device_name: &str) -> Result<MidiInputConnection<()>> {
+        self.connect_with_callback(device_name, |_| {})
+    }
+
+    /// Connect to the `index`th device (counting from 0) in the order
+    /// `list_devices` returns them, for setups where the names change between
+    /// reboots (e.g. ALSA client numbers)
+    pub fn connect_by_index(&self, index: usize) -> Result<MidiInputConnection<()>> {
+        let midi_in = open_input("xiv-midi")?;
+        let mut ports = named_ports(&midi_in);
+        if index >= ports.len() {
+            return Err(Error::Mapping(format!(
+                "Device index {} is out of range ({} devices found)",
+                index,
+                ports.len()
+            )));
+        }
+        let (port, name) = ports.swap_remove(index);
+        tracing::info!("Device {} is '{}'", index, name);
+        self.connect_port(port)
+    }
+
+    /// Connect to a MIDI device by name with a callback for MIDI events. If no
+    /// device has exactly that name, a device whose name contains it (ignoring
+    /// case) is used, as long as only one does.
+    pub fn connect_with_callback<F>(
+        &self,
+        device_name: &str,
+        callback: F,
+    ) -> Result<MidiInputConnection<()>>
+    where
+        F: Fn(MidiMessage) + Send + 'static,
+    {
+        let mut midi_in = open_input("xiv-midi")?;
+        midi_in.ignore(Ignore::None);
+
+        let mut ports = named_ports(&midi_in);
+        let names: Vec<&str> = ports.iter().map(|(_, name)| name.as_str()).collect();
+        let index = find_device(&names, device_name)?;
+        let (port, name) = ports.swap_remove(index);
+        if name != device_name {
+            tracing::info!("Using device '{}' for '{}'", name, device_name);
+        }
+
+        self.connect_port_with_callback(port, callback)
+    }
+
+    /// Connect to a MIDI device by port
+    pub fn connect_port(&self, port: MidiInputPort) -> Result<MidiInputConnection<()>> {
+        self.connect_port_with_callback(port, |_| {})
+    }
+
+    /// Connect to a MIDI device by port with a callback for MIDI events
+    pub fn connect_port_with_callback<F>(
+        &self,
+        port: MidiInputPort,
+        callback: F,
+    ) -> Result<MidiInputConnection<()>>
+    where
+        F: Fn(MidiMessage) + Send + 'static,
+    {
+        let midi_in = open_input("xiv-midi")?;
+
+        let mut decoder = {
+            let mapping = self.mapping.lock().unwrap();
+            MidiDecoder::new(mapping.zero_velocity_note_on, mapping.message_filter())
+        };
+        let raw_callback = self.raw_callback.clone();
+        let tempo = self
+            .options
+            .tempo_gap_reference_bpm
+            .map(|_| self.tempo.clone());
+        let stats = Arc::clone(&self.stats);
+        let mut timestamps = TimestampClock::default();
+        let tx = self.spawn_scheduler();
+        let inject_tx = tx.clone();
+
+        // Connect midir — the callback just forwards events through the channel
+        let connection = midi_in.connect(
+            &port,
+            "xiv-midi-input",
+            move |timestamp, data, _| {
+                let received = timestamps.received_at(timestamp, Instant::now());
+                if let Some(raw) = &raw_callback {
+                    raw(data);
+                }
+                if let Some(tempo) = &tempo {
+                    tempo.pulses_in(data, Instant::now());
+                }
+
+                queue_messages(data, &mut decoder, &callback, &tx, &stats, received);
+            },
+            (),
+        )?;
+        *self.injector.lock().unwrap() = Some(inject_tx);
+
+        tracing::info!("Connected to MIDI device");
+        Ok(connection)
+    }
+
+    /// Feed `message` to the scheduler as if it came from the MIDI device, e.g. from
+    /// an on-screen keyboard. Without a connection, a device-less processing thread
+    /// is started for injected messages.
+    pub fn inject(&self, message: MidiMessage) {
+        let mut injector = self.injector.lock().unwrap();
+        let tx = injector.get_or_insert_with(|| self.spawn_scheduler());
+        let received = Instant::now();
+        enqueue(tx, MidiEvent { message, received }, &self.stats);
+    }
+
+    /// Wait until every message passed to `inject` so far is handled, then `stop`.
+    /// Meant for an engine without a device connection, e.g. one playing a file:
+    /// a connection's processing thread only ends once it is closed.
+    pub fn finish_injected(&self) -> Result<()> {
+        // Closing the queue lets the thread finish what was queued, then exit
+        self.injector.lock().unwrap().take();
+        let threads = std::mem::take(&mut *self.threads.lock().unwrap());
+        for handle in threads {
+            if handle.join().is_err() {
+                tracing::error!("MIDI processing thread panicked");
+            }
+        }
+        self.stop()
+    }
+
+    /// Start a processing thread with its own `NoteScheduler`, returning the queue
+    /// that feeds it. The thread exits once every sender is dropped.
+    fn spawn_scheduler(&self) -> channel::Sender<MidiEvent> {
+        let keyboard = Arc::clone(&self.keyboard);
+        let mapping = Arc::clone(&self.mapping);
+        let log_callback = self.log_callback.clone();
+        let focus_check = self.focus_check.clone();
+        let options = self.options;
+        let thru = self.thru.clone();
+        let feedback = self.feedback.clone();
+        let tempo = self.tempo.clone();
+        let stats = Arc::clone(&self.stats);
+        let switched_layer = Arc::clone(&self.switched_layer);
+        let mutes = self.mutes.clone();
+        // Subscribed here rather than in the thread so nothing raised after this
+        // returns is missed
+        let mutes_changed = self.mutes.changed.subscribe();
+        let mapping_changed = self.mapping_changed.subscribe();
+        let reset = self.reset.subscribe();
+        let shutdown = self.shutdown.lock().unwrap().1.clone();
+
+        // Key events are handed to a separate thread so the callback never delays
+        // the next keypress
+        let key_events = self.key_event_callback.clone().map(|callback| {
+            let (events_tx, events_rx) = channel::unbounded::<KeyEvent>();
+            // Joined by `stop` too, so every event is reported by the time it returns
+            let handle = thread::spawn(move || {
+                for event in events_rx {
+                    callback(event);
+                }
+            });
+            self.threads.lock().unwrap().push(handle);
+            events_tx
+        });
+
+        // Create a bounded channel — small buffer to avoid latency buildup.
+        // If the processing thread can't keep up, we'd rather drop old events
+        // than accumulate latency.
+        let capacity = options.queue_capacity.unwrap_or(DEFAULT_QUEUE_CAPACITY);
+        let (tx, rx) = channel::bounded::<MidiEvent>(capacity.max(1));
+
+        // Spawn the processing thread with the NoteScheduler
+        let handle = thread::spawn(move || {
+            let mut scheduler = NoteScheduler::new();
+            scheduler.log_callback = log_callback;
+            scheduler.focus_check = focus_check;
+            scheduler.options = options;
+            scheduler.thru = thru;
+            scheduler.feedback = feedback;
+            scheduler.tempo = tempo;
+            scheduler.mutes = mutes;
+            scheduler.switched_layer = switched_layer;
+
+            let events = key_events.as_ref();
+            let mut kb = keyboard.lock().unwrap();
+            if let Err(e) = scheduler.start(&mut ReportingKeyboard::new(&mut **kb, events)) {
+                tracing::error!("Error preparing the keyboard on connect: {}", e);
+            }
+            drop(kb);
+
+            loop {
+                // Wake up for new events, mute changes, and pending timers (e.g. tremolo
+                // taps) even without new events
+                let timer = scheduler
+                    .next_deadline()
+                    .map_or_else(channel::never, channel::at);
+                let wake = channel::select! {
+                    recv(rx) -> event => match event {
+                        Ok(event) => Wake::Event(event),
+                        Err(_) => break,
+                    },
+                    recv(mutes_changed) -> _ => Wake::MutesChanged,
+                    recv(mapping_changed) -> _ => Wake::MappingChanged,
+                    recv(reset) -> _ => Wake::Reset,
+                    recv(timer) -> _ => Wake::Timer,
+                    // Nothing is ever sent; this fires when `stop` drops the sender
+                    recv(shutdown) -> _ => break,
+                };
+
+                let result = match wake {
+                    Wake::Event(event) => {
+                        stats.count_processed(event.message.event_type);
+                        let mapping_guard = mapping.lock().unwrap();
+                        let mut kb = keyboard.lock().unwrap();
+                        let mut kb = ReportingKeyboard::new(&mut **kb, events);
+                        let result =
+                            scheduler.handle_message(&event.message, &mapping_guard, &mut kb);
+                        if let Some(pressed) = kb.first_press {
+                            stats.record_latency(pressed.saturating_duration_since(event.received));
+                        }
+                        result
+                    }
+                    Wake::MutesChanged => {
+                        let mut kb = keyboard.lock().unwrap();
+                        scheduler.release_if_muted(&mut ReportingKeyboard::new(&mut **kb, events))
+                    }
+                    Wake::MappingChanged => {
+                        let mapping_guard = mapping.lock().unwrap();
+                        let mut kb = keyboard.lock().unwrap();
+                        let mut kb = ReportingKeyboard::new(&mut **kb, events);
+                        scheduler.reload(&mapping_guard, &mut kb)
+                    }
+                    Wake::Reset => {
+                        let mut kb = keyboard.lock().unwrap();
+                        scheduler.reset_keys(&mut ReportingKeyboard::new(&mut **kb, events))
+                    }
+                    Wake::Timer => {
+                        let mut kb = keyboard.lock().unwrap();
+                        scheduler.tick(&mut ReportingKeyboard::new(&mut **kb, events))
+                    }
+                };
+
+                if let Err(e) = result {
+                    tracing::error!("Error handling MIDI event: {}", e);
+                }
+            }
+
+            let mut kb = keyboard.lock().unwrap();
+            if let Err(e) = scheduler.stop(&mut ReportingKeyboard::new(&mut **kb, events)) {
+                tracing::error!("Error releasing controller keys: {}", e);
+            }
+            tracing::info!("MIDI processing thread exiting");
+        });
+        self.threads.lock().unwrap().push(handle);
+
+        tx
+    }
+
+    /// Close `connection` and `stop` the engine.
+    pub fn disconnect(&self, connection: MidiInputConnection<()>) -> Result<()> {
+        connection.close();
+        self.stop()
+    }
+
+    /// Stop every processing thread this engine started (connections and `inject`),
+    /// wait for them to exit, then release all keys so no modifier stays held.
+    /// Events from a connection that is still open are dropped afterwards; close it
+    /// or use `disconnect`. A later `connect` or `inject` starts a new thread.
+    pub fn stop(&self) -> Result<()> {
+        // Replacing the pair drops the sender the running threads listen on
+        *self.shutdown.lock().unwrap() = channel::bounded(0);
+        self.injector.lock().unwrap().take();
+        let threads = std::mem::take(&mut *self.threads.lock().unwrap());
+        for handle in threads {
+            if handle.join().is_err() {
+                tracing::error!("MIDI processing thread panicked");
+            }
+        }
+        self.keyboard.lock().unwrap().release_all()
+    }
+
+    /// Release all keys (see `panic`).
+    pub fn release_all(&self) -> Result<()> {
+        self.panic()
+    }
+
+    /// Release every key the keyboard backend holds, e.g. a modifier stuck after a
+    /// game hitch. Running processing threads also forget the note and modifiers
+    /// they were holding, so the next note presses its keys from scratch instead
+    /// of assuming they are still down.
+    pub fn panic(&self) -> Result<()> {
+        tracing::warn!("Panic: releasing all keys");
+        let result = self.keyboard.lock().unwrap().release_all();
+        self.reset.raise();
+        result
+    }
+}
+
+/// Key events produced by one mapped note in a `sweep_mapping` dry run.
+#[derive(Debug, Clone)]
+pub struct NoteSweep {
+    pub note: MidiNote,
+    pub on_press: Vec<KeyEvent>,
+    pub on_release: Vec<KeyEvent>,
+}
+
+impl NoteSweep {
+    /// Whether the note-on pressed at least one key
+    pub fn presses_key(&self) -> bool {
+        self.on_press
+            .iter()
+            .any(|e| matches!(e, KeyEvent::Press(_)))
+    }
+}
+
+impl std::fmt::Display for NoteSweep {
+    /// `C4 (60): +Q | -Q` - note-on events, then note-off events
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}):", self.note.full_name(), self.note.value())?;
+        for event in &self.on_press {
+            write!(f, " {}", event)?;
+        }
+        write!(f, " |")?;
+        for event in &self.on_release {
+            write!(f, " {}", event)?;
+        }
+        Ok(())
+    }
+}
+
+/// Play every mapped note in ascending order (note-on, then note-off) through the
+/// scheduler against a dry-run keyboard, recording the key events each produces.
+pub fn sweep_mapping(mapping: &MappingConfig, options: EngineOptions) -> Result<Vec<NoteSweep>> {
+    let mut scheduler = NoteScheduler::new();
+    scheduler.options = options;
+    let mut kb = DryRunKeyboardController::new();
+
+    let mut notes: Vec<u8> = mapping.mappings.keys().copied().collect();
+    notes.sort_unstable();
+
+    let mut results = Vec::with_capacity(notes.len());
+    for value in notes {
+        let note = MidiNote::new(value)?;
+        let mut message = MidiMessage {
+            event_type: MidiEventType::NoteOn,
+            channel: mapping.channel.unwrap_or(0),
+            note,
+            velocity: 100,
+        };
+
+        scheduler.handle_message(&message, mapping, &mut kb)?;
+        let on_press = kb.take_events();
+
+        message.event_type = MidiEventType::NoteOff;
+        message.velocity = 0;
+        scheduler.handle_message(&message, mapping, &mut kb)?;
+        let on_release = kb.take_events();
+
+        results.push(NoteSweep {
+            note,
+            on_press,
+            on_release,
+        });
+    }
+
+    Ok(results)
+}
+
+/// Keys `actions` press and don't release again, in press order
+fn keys_left_pressed(actions: &[Action]) -> Vec<Key> {
+    let mut pressed = Vec::new();
+    for action in actions {
+        match action {
+            Action::Press(key) => pressed.push(*key),
+            Action::PressChord(keys) => pressed.extend(keys),
+            Action::Release(key) => pressed.retain(|k| k != key),
+            _ => {}
+        }
+    }
+    pressed
+}
+
+/// Index of the device `query` names in `names`: the exact match if there is one,
+/// otherwise the only name containing `query`, ignoring case
+fn find_device(names: &[&str], query: &str) -> Result<usize> {
+    if let Some(index) = names.iter().position(|name| *name == query) {
+        return Ok(index);
+    }
+    let query_lower = query.to_lowercase();
+    let matches: Vec<usize> = (0..names.len())
+        .filter(|&i| names[i].to_lowercase().contains(&query_lower))
+        .collect();
+    match matches[..] {
+        [index] => Ok(index),
+        [] => Err(Error::Mapping(format!("Device '{}' not found", query))),
+        _ => Err(Error::Mapping(format!(
+            "Device '{}' matches several devices: {}",
+            query,
+            matches
+                .iter()
+                .map(|&i| format!("'{}'", names[i]))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+/// Input ports whose names can be read, in the order the backend lists them
+fn named_ports(midi_in: &MidiInput) -> Vec<(MidiInputPort, String)> {
+    midi_in
+        .ports()
+        .into_iter()
+        .filter_map(|port| {
+            let name = midi_in.port_name(&port).ok()?;
+            Some((port, name))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::mock::{KeyEvent, MockKeyboard, RecordingEnigo};
+    use crate::keyboard::{EnigoKeyboardController, KeyboardSettings};
+    use crate::mapping::{
+        create_ffxiv_default_mapping, Feedback, MappingLayer, NoteMapping, PitchBendMapping,
+        ReleaseLayer, VelocityTremolo,
+    };
+    use crate::midi::{MessageFilter, MidiNote, PITCH_BEND_CENTER};
+
+    /// Deterministic clock: `sleep` advances time instantly.
+    struct MockClock {
+        now: Mutex<Instant>,
+    }
+
+    impl MockClock {
+        fn new() -> Arc<Self> {
+            Arc::new(Self {
+                now: Mutex::new(Instant::now()),
+            })
+        }
+
+        fn advance(&self, duration: Duration) {
+            *self.now.lock().unwrap() += duration;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            *self.now.lock().unwrap()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.advance(duration);
+        }
+    }
+
+    fn note_on(note: u8, velocity: u8) -> MidiMessage {
+        MidiMessage {
+            event_type: MidiEventType::NoteOn,
+            channel: 0,
+            note: MidiNote::new(note).unwrap(),
+            velocity,
+        }
+    }
+
+    fn note_off(note: u8) -> MidiMessage {
+        MidiMessage {
+            event_type: MidiEventType::NoteOff,
+            channel: 0,
+            note: MidiNote::new(note).unwrap(),
+            velocity: 0,
+        }
+    }
+
+    /// Hold a tremolo note for `hold` at `velocity`, ticking every millisecond.
+    fn tremolo_presses(velocity: u8, hold: Duration) -> usize {
+        let mut mapping = MappingConfig::new();
+        mapping.add_mapping(
+            MidiNote::new(60).unwrap(),
+            NoteMapping {
+                on_press: vec![Action::Press(Key::Q)],
+                on_release: vec![Action::Release(Key::Q)],
+                velocity_tremolo: Some(VelocityTremolo {
+                    threshold: 64,
+                    slowest_ms: 200,
+                    fastest_ms: 50,
+                }),
+                ..Default::default()
+            },
+        );
+
+        let clock = MockClock::new();
+        let mut scheduler = NoteScheduler::with_clock(clock.clone());
+        let mut kb = MockKeyboard::new();
+
+        let start = clock.now();
+        scheduler
+            .handle_message(&note_on(60, velocity), &mapping, &mut kb)
+            .unwrap();
+        while clock.now() - start < hold {
+            clock.advance(Duration::from_millis(1));
+            scheduler.tick(&mut kb).unwrap();
+        }
+        scheduler
+            .handle_message(&note_off(60), &mapping, &mut kb)
+            .unwrap();
+
+        // Released on note-off and no further taps afterwards
+        assert!(kb.pressed.is_empty());
+        assert_eq!(scheduler.next_deadline(), None);
+        kb.press_count(Key::Q)
+    }
+
+    #[test]
+    fn test_log_action_emits_without_key_events() {
+        let mut mapping = MappingConfig::new();
+        mapping.add_mapping(
+            MidiNote::new(60).unwrap(),
+            NoteMapping {
+                on_press: vec![Action::Log("combo start".to_string())],
+                ..Default::default()
+            },
+        );
+
+        let logged = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&logged);
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        scheduler.log_callback = Some(Arc::new(move |m: &str| {
+            sink.lock().unwrap().push(m.to_string())
+        }));
+        let mut kb = MockKeyboard::new();
+
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+
+        assert_eq!(*logged.lock().unwrap(), vec!["combo start".to_string()]);
+        assert!(kb.events.is_empty());
+    }
+
+    fn shifted_note_events(order: ModifierOrder) -> Vec<KeyEvent> {
+        let mapping = create_ffxiv_default_mapping();
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        scheduler.options.modifier_order = order;
+        let mut kb = MockKeyboard::new();
+
+        // C5: Shift + Q
+        scheduler
+            .handle_message(&note_on(72, 100), &mapping, &mut kb)
+            .unwrap();
+        kb.events
+    }
+
+    #[test]
+    fn test_modifier_order() {
+        assert_eq!(
+            shifted_note_events(ModifierOrder::ModifiersFirst),
+            vec![KeyEvent::Press(Key::Shift), KeyEvent::Press(Key::Q)]
+        );
+        assert_eq!(
+            shifted_note_events(ModifierOrder::KeyFirst),
+            vec![KeyEvent::Press(Key::Q), KeyEvent::Press(Key::Shift)]
+        );
+    }
+
+    fn cycle_mapping(reset_ms: Option<u64>) -> MappingConfig {
+        let mut mapping = MappingConfig::new();
+        mapping.add_mapping(
+            MidiNote::new(60).unwrap(),
+            NoteMapping {
+                on_release: vec![Action::Release(Key::A), Action::Release(Key::B)],
+                cycle: vec![vec![Action::Press(Key::A)], vec![Action::Press(Key::B)]],
+                cycle_reset_ms: reset_ms,
+                ..Default::default()
+            },
+        );
+        mapping
+    }
+
+    #[test]
+    fn test_cycle_steps_through_actions() {
+        let mapping = cycle_mapping(None);
+        let clock = MockClock::new();
+        let mut scheduler = NoteScheduler::with_clock(clock.clone());
+        let mut kb = MockKeyboard::new();
+
+        for _ in 0..3 {
+            scheduler
+                .handle_message(&note_on(60, 100), &mapping, &mut kb)
+                .unwrap();
+            clock.advance(Duration::from_millis(50));
+            scheduler
+                .handle_message(&note_off(60), &mapping, &mut kb)
+                .unwrap();
+        }
+
+        let presses: Vec<_> = kb
+            .events
+            .iter()
+            .filter(|e| matches!(e, KeyEvent::Press(_)))
+            .copied()
+            .collect();
+        assert_eq!(
+            presses,
+            vec![
+                KeyEvent::Press(Key::A),
+                KeyEvent::Press(Key::B),
+                KeyEvent::Press(Key::A)
+            ]
+        );
+        assert!(kb.pressed.is_empty());
+    }
+
+    #[test]
+    fn test_cycle_resets_after_timeout() {
+        let mapping = cycle_mapping(Some(500));
+        let clock = MockClock::new();
+        let mut scheduler = NoteScheduler::with_clock(clock.clone());
+        let mut kb = MockKeyboard::new();
+
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+        scheduler
+            .handle_message(&note_off(60), &mapping, &mut kb)
+            .unwrap();
+        clock.advance(Duration::from_secs(1));
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+
+        assert_eq!(kb.press_count(Key::A), 2);
+        assert_eq!(kb.press_count(Key::B), 0);
+    }
+
+    #[test]
+    fn test_velocity_tremolo_tap_counts() {
+        let hold = Duration::from_millis(500);
+
+        // Soft note: single sustained press
+        assert_eq!(tremolo_presses(40, hold), 1);
+        // Threshold velocity: 200ms interval, first press + 2 taps
+        assert_eq!(tremolo_presses(64, hold), 3);
+        // Full velocity: 50ms interval, first press + 10 taps
+        assert_eq!(tremolo_presses(127, hold), 11);
+    }
+
+    /// Thru output that records every message it is sent.
+    #[derive(Clone, Default)]
+    struct MockSink {
+        sent: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl MidiSink for MockSink {
+        fn send(&mut self, message: &[u8]) -> Result<()> {
+            self.sent.lock().unwrap().push(message.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_thru_forwards_transposed_notes() {
+        // Default mapping covers C3–C6 (48–84); C7 (96) transposes down to C6
+        let mut mapping = create_ffxiv_default_mapping();
+        mapping.octave_transpose = true;
+
+        let sink = MockSink::default();
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        scheduler.thru = Some(Arc::new(Mutex::new(sink.clone())));
+        let mut kb = MockKeyboard::new();
+
+        for msg in [note_on(96, 100), note_off(96), note_on(60, 90)] {
+            scheduler.handle_message(&msg, &mapping, &mut kb).unwrap();
+        }
+        // Filtered out by the channel setting, so not forwarded
+        let mut other_channel = note_on(60, 90);
+        other_channel.channel = 3;
+        scheduler
+            .handle_message(&other_channel, &mapping, &mut kb)
+            .unwrap();
+
+        assert_eq!(
+            *sink.sent.lock().unwrap(),
+            vec![vec![0x90, 84, 100], vec![0x80, 84, 0], vec![0x90, 60, 90]]
+        );
+    }
+
+    /// Writer collecting formatted tracing output for assertions.
+    #[derive(Clone, Default)]
+    struct CaptureWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CaptureWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Play `notes` (all unmapped) and count the "No mapping" debug lines logged.
+    fn unmapped_log_lines(setting: UnmappedNoteLog, notes: &[u8]) -> usize {
+        let mapping = create_ffxiv_default_mapping();
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        scheduler.options.unmapped_note_log = setting;
+        let mut kb = MockKeyboard::new();
+
+        let writer = CaptureWriter::default();
+        let make_writer = writer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(move || make_writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            for &note in notes {
+                scheduler
+                    .handle_message(&note_on(note, 100), &mapping, &mut kb)
+                    .unwrap();
+                scheduler
+                    .handle_message(&note_off(note), &mapping, &mut kb)
+                    .unwrap();
+            }
+        });
+
+        assert!(kb.events.is_empty());
+        let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+        output.matches("No mapping for note").count()
+    }
+
+    #[test]
+    fn test_unmapped_note_logging() {
+        // Default mapping covers 48–84, so 21 and 108 are unmapped
+        let notes = [21, 21, 108, 21, 108];
+        assert_eq!(unmapped_log_lines(UnmappedNoteLog::FirstPerNote, &notes), 2);
+        assert_eq!(unmapped_log_lines(UnmappedNoteLog::Never, &notes), 0);
+        // Note-on and note-off are each logged
+        assert_eq!(unmapped_log_lines(UnmappedNoteLog::Always, &notes), 10);
+    }
+
+    #[test]
+    fn test_muted_zone_drops_notes() {
+        let mapping = create_ffxiv_default_mapping();
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        // Mute the middle octave (C4–B4)
+        scheduler.mutes.set_muted(60..=71, true);
+        for msg in [
+            note_on(60, 100),
+            note_off(60),
+            note_on(71, 100),
+            note_off(71),
+        ] {
+            scheduler.handle_message(&msg, &mapping, &mut kb).unwrap();
+        }
+        assert!(kb.events.is_empty());
+
+        // Other zones still play; muting the held note's zone releases it
+        scheduler
+            .handle_message(&note_on(48, 100), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(kb.press_count(Key::Q), 1);
+        scheduler.mutes.set_muted(48..=59, true);
+        scheduler.release_if_muted(&mut kb).unwrap();
+        assert!(!kb.pressed.contains(&Key::Q));
+
+        // Unmuting lets the zone play again
+        scheduler.mutes.set_muted(60..=71, false);
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(kb.press_count(Key::Q), 2);
+    }
+
+    fn tap_q(count: u32) -> Action {
+        Action::Repeat {
+            count,
+            actions: vec![Action::Press(Key::Q), Action::Release(Key::Q)],
+        }
+    }
+
+    #[test]
+    fn test_repeat_action() {
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        scheduler.execute_actions_raw(&[tap_q(3)], &mut kb).unwrap();
+        assert_eq!(kb.press_count(Key::Q), 3);
+        assert!(kb.pressed.is_empty());
+
+        // Nested repeats multiply
+        let nested = Action::Repeat {
+            count: 2,
+            actions: vec![tap_q(2), Action::Delay(10)],
+        };
+        kb.events.clear();
+        scheduler.execute_actions_raw(&[nested], &mut kb).unwrap();
+        assert_eq!(kb.press_count(Key::Q), 4);
+
+        // A zero count is a no-op
+        kb.events.clear();
+        scheduler.execute_actions_raw(&[tap_q(0)], &mut kb).unwrap();
+        assert!(kb.events.is_empty());
+    }
+
+    #[test]
+    fn test_repeat_guards() {
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        assert!(scheduler
+            .execute_actions_raw(&[tap_q(MAX_REPEAT_COUNT + 1)], &mut kb)
+            .is_err());
+
+        let mut deep = tap_q(1);
+        for _ in 0..MAX_REPEAT_DEPTH {
+            deep = Action::Repeat {
+                count: 1,
+                actions: vec![deep],
+            };
+        }
+        assert!(scheduler.execute_actions_raw(&[deep], &mut kb).is_err());
+        assert!(kb.events.is_empty());
+    }
+
+    #[test]
+    fn test_modifier_side() {
+        let mapping = create_ffxiv_default_mapping();
+
+        // Engine-wide right-side modifiers: C5 (Shift + Q) presses RShift
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        scheduler.options.modifier_side = ModifierSide::Right;
+        let mut kb = MockKeyboard::new();
+        scheduler
+            .handle_message(&note_on(72, 100), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(
+            kb.events,
+            vec![KeyEvent::Press(Key::RShift), KeyEvent::Press(Key::Q)]
+        );
+
+        // A per-action side overrides it, swapping the held modifier
+        let left_shift = [Action::SetModifiers {
+            shift: true,
+            ctrl: false,
+            alt: false,
+            side: Some(ModifierSide::Left),
+        }];
+        kb.events.clear();
+        scheduler.execute_actions_raw(&left_shift, &mut kb).unwrap();
+        assert_eq!(
+            kb.events,
+            vec![KeyEvent::Release(Key::RShift), KeyEvent::Press(Key::LShift)]
+        );
+    }
+
+    #[test]
+    fn test_voice_stealing() {
+        let mapping = create_ffxiv_default_mapping();
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        scheduler.options.polyphonic = true;
+        scheduler.options.max_voices = Some(2);
+        let mut kb = MockKeyboard::new();
+
+        // C4, D4, E4 (Q, W, E) held together: the third steals the first
+        for note in [60, 62, 64] {
+            scheduler
+                .handle_message(&note_on(note, 100), &mapping, &mut kb)
+                .unwrap();
+        }
+        assert_eq!(
+            kb.events,
+            vec![
+                KeyEvent::Press(Key::Q),
+                KeyEvent::Press(Key::W),
+                KeyEvent::Release(Key::Q),
+                KeyEvent::Press(Key::E),
+            ]
+        );
+        assert_eq!(kb.pressed, HashSet::from([Key::W, Key::E]));
+
+        // The stolen note's own note-off is a no-op; the others release normally
+        kb.events.clear();
+        for note in [60, 62, 64] {
+            scheduler
+                .handle_message(&note_off(note), &mapping, &mut kb)
+                .unwrap();
+        }
+        assert_eq!(
+            kb.events,
+            vec![KeyEvent::Release(Key::W), KeyEvent::Release(Key::E)]
+        );
+    }
+
+    #[test]
+    fn test_sweep_default_mapping() {
+        let mapping = create_ffxiv_default_mapping();
+        let results = sweep_mapping(&mapping, EngineOptions::default()).unwrap();
+
+        // Three octaves plus the top C: 48..=84
+        assert_eq!(mapping.mappings.len(), 37);
+        assert_eq!(results.len(), mapping.mappings.len());
+        assert!(results.iter().all(NoteSweep::presses_key));
+        assert_eq!(results[0].note.value(), 48);
+        // C5: Shift + Q, released again on note-off
+        let c5 = results.iter().find(|r| r.note.value() == 72).unwrap();
+        assert_eq!(
+            c5.on_press,
+            vec![KeyEvent::Press(Key::Shift), KeyEvent::Press(Key::Q)]
+        );
+        assert_eq!(
+            c5.on_release,
+            vec![KeyEvent::Release(Key::Q), KeyEvent::Release(Key::Shift)]
+        );
+        assert_eq!(c5.to_string(), "C5 (72): +Shift +Q | -Q -Shift");
+    }
+
+    #[test]
+    fn test_timing_preset_round_trip() {
+        for (name, preset) in TimingPreset::BUILTIN {
+            let json = serde_json::to_string(&preset).unwrap();
+            let loaded: TimingPreset = serde_json::from_str(&json).unwrap();
+            assert_eq!(loaded, preset, "{}", name);
+            assert_eq!(TimingPreset::builtin(name), Some(preset));
+        }
+        assert_eq!(TimingPreset::builtin("nope"), None);
+        assert_eq!(TimingPreset::default(), TimingPreset::new(3, 3, 0));
+    }
+
+    #[test]
+    fn test_apply_timing_preset() {
+        let laggy = TimingPreset::builtin("laggy-pc").unwrap();
+        let mut engine = MidiEngine::new(MockKeyboard::new(), create_ffxiv_default_mapping());
+        engine.apply_timing(laggy);
+        assert_eq!(engine.options().timing, laggy);
+
+        // The scheduler honours the gap and the tap hold
+        let mapping = create_ffxiv_default_mapping();
+        let clock = MockClock::new();
+        let mut scheduler = NoteScheduler::with_clock(clock.clone());
+        scheduler.options = engine.options();
+        let mut kb = MockKeyboard::new();
+
+        let start = clock.now();
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+        scheduler
+            .handle_message(&note_off(60), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(clock.now() - start, laggy.tap_hold());
+
+        // The hold already covered the note gap, so the next note goes out at once...
+        let start = clock.now();
+        scheduler
+            .handle_message(&note_on(62, 100), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(clock.now(), start);
+
+        // ...but one straight after it waits out the gap
+        scheduler
+            .handle_message(&note_on(64, 100), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(clock.now() - start, laggy.min_note_gap());
+    }
+
+    #[test]
+    fn test_release_current_keeps_modifiers() {
+        let mut mapping = create_ffxiv_default_mapping();
+        mapping.add_mapping(
+            MidiNote::new(40).unwrap(),
+            NoteMapping {
+                on_press: vec![Action::ReleaseCurrent],
+                ..Default::default()
+            },
+        );
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        // C5: Shift + Q held
+        scheduler
+            .handle_message(&note_on(72, 100), &mapping, &mut kb)
+            .unwrap();
+        kb.events.clear();
+
+        scheduler
+            .handle_message(&note_on(40, 100), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(kb.events, vec![KeyEvent::Release(Key::Q)]);
+        assert!(kb.pressed.contains(&Key::Shift));
+        assert_eq!(scheduler.current_key, None);
+
+        // The cut note's own release and the stale note-off do nothing further
+        scheduler
+            .handle_message(&note_off(40), &mapping, &mut kb)
+            .unwrap();
+        scheduler
+            .handle_message(&note_off(72), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(kb.events, vec![KeyEvent::Release(Key::Q)]);
+    }
+
+    #[test]
+    fn test_paranoid_modifier_reset() {
+        let mapping = create_ffxiv_default_mapping();
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        // Off by default: an unmodified note just presses its key
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(kb.events, vec![KeyEvent::Press(Key::Q)]);
+
+        // Paranoid: every modifier is force-released first, though none is tracked as held
+        scheduler.options.paranoid_modifier_reset = true;
+        kb.events.clear();
+        scheduler
+            .handle_message(&note_on(62, 100), &mapping, &mut kb)
+            .unwrap();
+        let mut expected = vec![KeyEvent::Release(Key::Q)];
+        for side in [
+            ModifierSide::Generic,
+            ModifierSide::Left,
+            ModifierSide::Right,
+        ] {
+            for key in [side.shift(), side.control(), side.alt()] {
+                expected.push(KeyEvent::Release(key));
+            }
+        }
+        expected.push(KeyEvent::Press(Key::W));
+        assert_eq!(kb.events, expected);
+
+        // Modified notes set their modifiers as usual
+        kb.events.clear();
+        scheduler
+            .handle_message(&note_on(72, 100), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(
+            kb.events,
+            vec![
+                KeyEvent::Release(Key::W),
+                KeyEvent::Press(Key::Shift),
+                KeyEvent::Press(Key::Q)
+            ]
+        );
+    }
+
+    /// Key-ups for every modifier, as `force_release_modifiers` sends them
+    fn modifier_releases() -> Vec<(enigo::Key, enigo::Direction)> {
+        let mut releases = Vec::new();
+        for side in [
+            ModifierSide::Generic,
+            ModifierSide::Left,
+            ModifierSide::Right,
+        ] {
+            for key in [side.shift(), side.control(), side.alt()] {
+                releases.push((key.to_enigo_key().unwrap(), enigo::Direction::Release));
+            }
+        }
+        releases
+    }
+
+    #[test]
+    fn test_paranoid_modifier_reset_reaches_enigo_backend() {
+        let mapping = create_ffxiv_default_mapping();
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        scheduler.options.paranoid_modifier_reset = true;
+        let backend = RecordingEnigo::default();
+        let mut kb =
+            EnigoKeyboardController::with_backend(backend.clone(), KeyboardSettings::default());
+
+        // None of the modifiers were pressed by the controller, yet each key-up is sent
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+        let mut expected = modifier_releases();
+        expected.push((Key::Q.to_enigo_key().unwrap(), enigo::Direction::Press));
+        assert_eq!(backend.sent(), expected);
+    }
+
+    fn control_change(controller: u8, value: u8) -> MidiMessage {
+        MidiMessage::control_change(0, controller, value)
+    }
+
+    fn layered_mapping(trigger: LayerTrigger) -> MappingConfig {
+        let mut mapping = create_ffxiv_default_mapping();
+        mapping.layers = vec![MappingLayer {
+            trigger,
+            activation: LayerActivation::Hold,
+            mappings: HashMap::from([(60, tap(Key::E))]),
+        }];
+        mapping
+    }
+
+    fn tap(key: Key) -> NoteMapping {
+        NoteMapping {
+            on_press: vec![Action::Press(key)],
+            on_release: vec![Action::Release(key)],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_layer_held_by_pedal() {
+        let mapping = layered_mapping(LayerTrigger::ControlChange(64));
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+        let mut play = |msg: MidiMessage, kb: &mut MockKeyboard| {
+            scheduler.handle_message(&msg, &mapping, kb).unwrap();
+        };
+
+        // Without the pedal: primary mapping
+        play(note_on(60, 100), &mut kb);
+        play(note_off(60), &mut kb);
+        // Pedal down: the layer's mapping; unmapped layer notes fall through
+        play(control_change(64, 127), &mut kb);
+        play(note_on(60, 100), &mut kb);
+        play(note_on(62, 100), &mut kb);
+        // Pedal up while C4 is held: it still releases the layer's key
+        play(note_on(60, 100), &mut kb);
+        play(control_change(64, 0), &mut kb);
+        play(note_off(60), &mut kb);
+        play(note_on(60, 100), &mut kb);
+
+        assert_eq!(
+            kb.events,
+            vec![
+                KeyEvent::Press(Key::Q),
+                KeyEvent::Release(Key::Q),
+                KeyEvent::Press(Key::E),
+                KeyEvent::Release(Key::E),
+                KeyEvent::Press(Key::W),
+                KeyEvent::Release(Key::W),
+                KeyEvent::Press(Key::E),
+                KeyEvent::Release(Key::E),
+                KeyEvent::Press(Key::Q),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_switch_layer_persists_across_notes() {
+        let mut mapping = layered_mapping(LayerTrigger::Switch);
+        for (note, layer) in [(36, 1), (37, 0)] {
+            mapping.mappings.insert(
+                note,
+                NoteMapping {
+                    on_press: vec![Action::SwitchLayer(layer)],
+                    ..Default::default()
+                },
+            );
+        }
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+        let mut play = |msg: MidiMessage, kb: &mut MockKeyboard| {
+            scheduler.handle_message(&msg, &mapping, kb).unwrap();
+        };
+
+        play(note_on(60, 100), &mut kb);
+        play(note_off(60), &mut kb);
+        // The pad switches once; later notes keep using the layer
+        play(note_on(36, 100), &mut kb);
+        play(note_off(36), &mut kb);
+        play(note_on(60, 100), &mut kb);
+        play(note_off(60), &mut kb);
+        play(note_on(62, 100), &mut kb);
+        play(note_off(62), &mut kb);
+        play(note_on(60, 100), &mut kb);
+        // Back to the primary mappings; the held note still releases its layer key
+        play(note_on(37, 100), &mut kb);
+        play(note_off(60), &mut kb);
+        play(note_on(60, 100), &mut kb);
+
+        assert_eq!(
+            kb.events,
+            vec![
+                KeyEvent::Press(Key::Q),
+                KeyEvent::Release(Key::Q),
+                KeyEvent::Press(Key::E),
+                KeyEvent::Release(Key::E),
+                KeyEvent::Press(Key::W),
+                KeyEvent::Release(Key::W),
+                KeyEvent::Press(Key::E),
+                KeyEvent::Release(Key::E),
+                KeyEvent::Press(Key::Q),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_layer_held_by_note() {
+        let mapping = layered_mapping(LayerTrigger::Note(36));
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        for msg in [
+            note_on(36, 100),
+            note_on(60, 100),
+            note_off(60),
+            note_off(36),
+        ] {
+            scheduler.handle_message(&msg, &mapping, &mut kb).unwrap();
+        }
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+
+        // The trigger note itself presses nothing
+        assert_eq!(
+            kb.events,
+            vec![
+                KeyEvent::Press(Key::E),
+                KeyEvent::Release(Key::E),
+                KeyEvent::Press(Key::Q),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stacked_layers() {
+        // Layer 0 held by the pedal maps C4 and D4; layer 1 above it, toggled by C2,
+        // overrides only C4
+        let mut mapping = layered_mapping(LayerTrigger::ControlChange(64));
+        mapping.layers[0].mappings.insert(62, tap(Key::R));
+        mapping.layers.push(MappingLayer {
+            trigger: LayerTrigger::Note(36),
+            activation: LayerActivation::Toggle,
+            mappings: HashMap::from([(60, tap(Key::T))]),
+        });
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+        let tap_note = |note: u8| [note_on(note, 100), note_off(note)];
+
+        let messages = [
+            tap_note(60).to_vec(), // base
+            vec![control_change(64, 127)],
+            tap_note(60).to_vec(), // layer 0
+            tap_note(36).to_vec(), // layer 1 on; the trigger plays nothing
+            tap_note(60).to_vec(), // layer 1 wins
+            tap_note(62).to_vec(), // falls through to layer 0
+            vec![control_change(64, 0)],
+            tap_note(62).to_vec(), // base
+            tap_note(60).to_vec(), // layer 1 stays on without the pedal
+            tap_note(36).to_vec(), // layer 1 off
+            tap_note(60).to_vec(), // base
+        ];
+        for msg in messages.concat() {
+            scheduler.handle_message(&msg, &mapping, &mut kb).unwrap();
+        }
+
+        let taps: Vec<Key> = kb
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                KeyEvent::Press(key) => Some(*key),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            taps,
+            vec![Key::Q, Key::E, Key::T, Key::R, Key::W, Key::T, Key::Q]
+        );
+    }
+
+    #[test]
+    fn test_reload_moves_held_notes() {
+        let old = create_ffxiv_default_mapping();
+        let mut new = create_ffxiv_default_mapping();
+        new.mappings.insert(60, tap(Key::E));
+        new.mappings.remove(&62);
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        // D4 was tapped and let go; C4 is still held when the mapping changes
+        for msg in [note_on(62, 100), note_off(62), note_on(60, 100)] {
+            scheduler.handle_message(&msg, &old, &mut kb).unwrap();
+        }
+        kb.events.clear();
+        scheduler.reload(&new, &mut kb).unwrap();
+        scheduler
+            .handle_message(&note_off(60), &new, &mut kb)
+            .unwrap();
+
+        assert_eq!(
+            kb.events,
+            vec![
+                KeyEvent::Release(Key::Q),
+                KeyEvent::Press(Key::E),
+                KeyEvent::Release(Key::E),
+            ]
+        );
+
+        // Nothing held: a reload presses nothing
+        kb.events.clear();
+        scheduler.reload(&old, &mut kb).unwrap();
+        assert!(kb.events.is_empty(), "{:?}", kb.events);
+    }
+
+    #[test]
+    fn test_reset_keys_forgets_held_state() {
+        let mapping = create_ffxiv_default_mapping();
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        // C3 holds Ctrl+Q when everything is released from under it
+        scheduler
+            .handle_message(&note_on(48, 100), &mapping, &mut kb)
+            .unwrap();
+        scheduler.reset_keys(&mut kb).unwrap();
+        assert!(kb.pressed.is_empty());
+        assert_eq!(scheduler.current_key, None);
+        assert_eq!(scheduler.current_modifiers, ModifierState::default());
+
+        // The next note presses Ctrl again instead of assuming it is still down
+        kb.events.clear();
+        for msg in [note_off(48), note_on(48, 100)] {
+            scheduler.handle_message(&msg, &mapping, &mut kb).unwrap();
+        }
+        assert_eq!(kb.press_count(Key::Control), 1);
+        assert_eq!(kb.press_count(Key::Q), 1);
+    }
+
+    #[test]
+    fn test_signal_reaches_every_thread() {
+        let signal = Signal::default();
+        // Raised with nothing running: not delivered to threads started later
+        signal.raise();
+        let first = signal.subscribe();
+        let second = signal.subscribe();
+        assert!(first.try_recv().is_err());
+
+        signal.raise();
+        signal.raise();
+        for rx in [&first, &second] {
+            assert!(rx.try_recv().is_ok());
+            // Repeated raises collapse into one wake-up
+            assert!(rx.try_recv().is_err());
+        }
+
+        // Exited threads are forgotten
+        drop(second);
+        signal.raise();
+        assert_eq!(signal.subscribers.lock().unwrap().len(), 1);
+        assert!(first.try_recv().is_ok());
+    }
+
+    #[test]
+    fn test_release_velocity_layers() {
+        let mut mapping = create_ffxiv_default_mapping();
+        let c4 = mapping.mappings.get_mut(&60).unwrap();
+        c4.release_layers.push(ReleaseLayer {
+            min_velocity: 100,
+            on_release: vec![
+                Action::Release(Key::Q),
+                Action::Press(Key::Escape),
+                Action::Release(Key::Escape),
+            ],
+        });
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        // A soft release runs the plain on_release, a hard one the layer
+        for (release_velocity, cancels) in [(40, 0), (120, 1)] {
+            kb.events.clear();
+            let release = MidiMessage {
+                velocity: release_velocity,
+                ..note_off(60)
+            };
+            for msg in [note_on(60, 100), release] {
+                scheduler.handle_message(&msg, &mapping, &mut kb).unwrap();
+            }
+            assert_eq!(kb.press_count(Key::Escape), cancels);
+            assert!(kb.pressed.is_empty(), "{:?}", kb.pressed);
+        }
+    }
+
+    #[test]
+    fn test_channel_mappings() {
+        let mut mapping = create_ffxiv_default_mapping();
+        mapping.channel = Some(0);
+        mapping
+            .channel_mappings
+            .insert(9, HashMap::from([(60, tap(Key::E))]));
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+        let on_channel = |channel, msg: MidiMessage| MidiMessage { channel, ..msg };
+
+        // Channel 9 plays its own C4 and falls back to the main table for D4;
+        // channel 3 has no table and isn't the listened channel
+        for msg in [
+            note_on(60, 100),
+            note_off(60),
+            on_channel(9, note_on(60, 100)),
+            on_channel(9, note_off(60)),
+            on_channel(9, note_on(62, 100)),
+            on_channel(9, note_off(62)),
+            on_channel(3, note_on(60, 100)),
+        ] {
+            scheduler.handle_message(&msg, &mapping, &mut kb).unwrap();
+        }
+
+        assert_eq!(
+            kb.events,
+            vec![
+                KeyEvent::Press(Key::Q),
+                KeyEvent::Release(Key::Q),
+                KeyEvent::Press(Key::E),
+                KeyEvent::Release(Key::E),
+                KeyEvent::Press(Key::W),
+                KeyEvent::Release(Key::W),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_type_text_keeps_held_note() {
+        let mut mapping = create_ffxiv_default_mapping();
+        mapping.mappings.insert(
+            61,
+            NoteMapping {
+                on_press: vec![Action::TypeText("/p Hello!".to_string())],
+                ..Default::default()
+            },
+        );
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        // Shift+Q is held when the text note comes in
+        scheduler
+            .handle_message(&note_on(72, 100), &mapping, &mut kb)
+            .unwrap();
+        scheduler
+            .handle_message(&note_on(61, 100), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(kb.typed, vec!["/p Hello!".to_string()]);
+        // Shift is let go so the text is typed as written; Q stays held
+        assert_eq!(scheduler.current_key, Some(Key::Q));
+        assert!(kb.pressed.contains(&Key::Q));
+        assert!(!kb.pressed.contains(&Key::Shift));
+
+        scheduler
+            .handle_message(&note_off(61), &mapping, &mut kb)
+            .unwrap();
+        assert!(kb.pressed.contains(&Key::Q));
+        scheduler
+            .handle_message(&note_off(72), &mapping, &mut kb)
+            .unwrap();
+        assert!(kb.pressed.is_empty());
+        assert_eq!(scheduler.current_key, None);
+    }
+
+    #[test]
+    fn test_find_device() {
+        let names = ["MPK mini 3 Port 0", "Midi Through Port-0", "MPK"];
+
+        // An exact name wins over substring matches
+        assert_eq!(find_device(&names, "MPK").unwrap(), 2);
+        // Otherwise a unique case-insensitive substring
+        assert_eq!(find_device(&names, "mini 3").unwrap(), 0);
+        assert_eq!(find_device(&names, "through").unwrap(), 1);
+
+        let err = find_device(&names, "Port").unwrap_err().to_string();
+        assert!(err.contains("'MPK mini 3 Port 0'") && err.contains("'Midi Through Port-0'"));
+        assert!(find_device(&names, "Keystation").is_err());
+    }
+
+    #[test]
+    fn test_pitch_bend_fires_once_until_centered() {
+        let mut mapping = create_ffxiv_default_mapping();
+        mapping.pitch_bend = Some(PitchBendMapping {
+            up: vec![Action::Press(Key::Space)],
+            down: vec![Action::Press(Key::E), Action::Release(Key::E)],
+            ..Default::default()
+        });
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+        let bend = |value: u16| {
+            MidiMessage::parse(&[0xE0, (value & 0x7F) as u8, (value >> 7) as u8]).unwrap()
+        };
+
+        // Pushing further up while past the threshold doesn't fire again
+        for value in [9000, 13000, 16383, 12500] {
+            scheduler
+                .handle_message(&bend(value), &mapping, &mut kb)
+                .unwrap();
+        }
+        assert_eq!(kb.events, vec![KeyEvent::Press(Key::Space)]);
+        assert!(kb.pressed.contains(&Key::Space));
+
+        // Back at center the held key is released and the trigger re-arms
+        scheduler
+            .handle_message(&bend(PITCH_BEND_CENTER), &mapping, &mut kb)
+            .unwrap();
+        assert!(kb.pressed.is_empty());
+        scheduler
+            .handle_message(&bend(0), &mapping, &mut kb)
+            .unwrap();
+        scheduler
+            .handle_message(&bend(2000), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(
+            kb.events,
+            vec![
+                KeyEvent::Press(Key::Space),
+                KeyEvent::Release(Key::Space),
+                KeyEvent::Press(Key::E),
+                KeyEvent::Release(Key::E)
+            ]
+        );
+
+        // Notes still play while the wheel is bent
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+        assert!(kb.pressed.contains(&Key::Q));
+    }
+
+    #[test]
+    fn test_toggle_holds_until_next_note_on() {
+        let mut mapping = MappingConfig::new();
+        mapping.add_mapping(
+            MidiNote::new(60).unwrap(),
+            NoteMapping {
+                on_press: vec![Action::Toggle(Key::Q)],
+                on_release: vec![],
+                ..Default::default()
+            },
+        );
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+        scheduler
+            .handle_message(&note_off(60), &mapping, &mut kb)
+            .unwrap();
+        // Still held after the note-off
+        assert_eq!(kb.events, vec![KeyEvent::Press(Key::Q)]);
+
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(
+            kb.events,
+            vec![KeyEvent::Press(Key::Q), KeyEvent::Release(Key::Q)]
+        );
+        assert!(kb.pressed.is_empty());
+
+        // Releasing everything forgets the toggle, so the next note-on presses again
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+        scheduler.reset_keys(&mut kb).unwrap();
+        assert!(kb.pressed.is_empty());
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(kb.events.last(), Some(&KeyEvent::Press(Key::Q)));
+
+        // Stopping the processing thread releases it
+        scheduler.stop(&mut kb).unwrap();
+        assert!(kb.pressed.is_empty());
+    }
+
+    #[test]
+    fn test_modifier_only_note_releases_on_note_off() {
+        let mut mapping = create_ffxiv_default_mapping();
+        let shift = Action::SetModifiers {
+            shift: true,
+            ctrl: false,
+            alt: false,
+            side: None,
+        };
+        mapping.add_mapping(
+            MidiNote::new(36).unwrap(),
+            NoteMapping {
+                on_press: vec![shift.clone()],
+                on_release: vec![Action::Log("shift off".to_string())],
+                ..Default::default()
+            },
+        );
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let logged = Arc::new(Mutex::new(Vec::new()));
+        let sink = logged.clone();
+        scheduler.log_callback = Some(Arc::new(move |message: &str| {
+            sink.lock().unwrap().push(message.to_string())
+        }));
+        let mut kb = MockKeyboard::new();
+
+        scheduler
+            .handle_message(&note_on(36, 100), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(kb.events, vec![KeyEvent::Press(Key::Shift)]);
+        scheduler
+            .handle_message(&note_off(36), &mapping, &mut kb)
+            .unwrap();
+        // The release actions ran and the modifier was let go
+        assert_eq!(*logged.lock().unwrap(), vec!["shift off"]);
+        assert!(kb.pressed.is_empty());
+
+        // A note played meanwhile keeps its own modifiers
+        scheduler
+            .handle_message(&note_on(36, 100), &mapping, &mut kb)
+            .unwrap();
+        scheduler
+            .handle_message(&note_on(48, 100), &mapping, &mut kb)
+            .unwrap();
+        scheduler
+            .handle_message(&note_off(36), &mapping, &mut kb)
+            .unwrap();
+        assert!(kb.pressed.contains(&Key::Control));
+        assert!(kb.pressed.contains(&Key::Q));
+    }
+
+    #[test]
+    fn test_repeat_held_taps_until_note_off() {
+        let mut mapping = create_ffxiv_default_mapping();
+        mapping.add_mapping(
+            MidiNote::new(61).unwrap(),
+            NoteMapping {
+                on_press: vec![Action::RepeatHeld {
+                    key: Key::E,
+                    interval_ms: 100,
+                }],
+                on_release: vec![],
+                ..Default::default()
+            },
+        );
+        let clock = MockClock::new();
+        let mut scheduler = NoteScheduler::with_clock(clock.clone());
+        let mut kb = MockKeyboard::new();
+        let run_for = |scheduler: &mut NoteScheduler, kb: &mut MockKeyboard, ms: u64| {
+            for _ in 0..ms {
+                clock.advance(Duration::from_millis(1));
+                scheduler.tick(kb).unwrap();
+            }
+        };
+        let e_taps = |kb: &MockKeyboard| kb.taps.iter().filter(|(k, _)| *k == Key::E).count();
+
+        // Tapped on the note-on, then at 100ms and 200ms
+        scheduler
+            .handle_message(&note_on(61, 100), &mapping, &mut kb)
+            .unwrap();
+        run_for(&mut scheduler, &mut kb, 250);
+        assert_eq!(e_taps(&kb), 3);
+
+        scheduler
+            .handle_message(&note_off(61), &mapping, &mut kb)
+            .unwrap();
+        run_for(&mut scheduler, &mut kb, 300);
+        assert_eq!(e_taps(&kb), 3);
+        assert_eq!(scheduler.next_deadline(), None);
+
+        // A new note stops the repeat before it plays
+        scheduler
+            .handle_message(&note_on(61, 100), &mapping, &mut kb)
+            .unwrap();
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+        run_for(&mut scheduler, &mut kb, 300);
+        assert_eq!(e_taps(&kb), 4);
+        assert!(kb.pressed.contains(&Key::Q));
+
+        // So does releasing everything
+        scheduler
+            .handle_message(&note_on(61, 100), &mapping, &mut kb)
+            .unwrap();
+        scheduler.reset_keys(&mut kb).unwrap();
+        run_for(&mut scheduler, &mut kb, 300);
+        assert_eq!(e_taps(&kb), 5);
+    }
+
+    #[test]
+    fn test_all_notes_off_releases_held_note() {
+        let mapping = create_ffxiv_default_mapping();
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        scheduler
+            .handle_message(&note_on(72, 100), &mapping, &mut kb)
+            .unwrap();
+        assert!(kb.pressed.contains(&Key::Q));
+
+        // The controller stops with CC 123 instead of a note-off
+        scheduler
+            .handle_message(&control_change(ALL_NOTES_OFF, 0), &mapping, &mut kb)
+            .unwrap();
+        assert!(kb.pressed.is_empty());
+        assert_eq!(kb.events.last(), Some(&KeyEvent::Release(Key::Shift)));
+
+        // The next note starts from scratch
+        kb.events.clear();
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(kb.events, vec![KeyEvent::Press(Key::Q)]);
+
+        scheduler
+            .handle_message(&control_change(ALL_SOUND_OFF, 0), &mapping, &mut kb)
+            .unwrap();
+        assert!(kb.pressed.is_empty());
+    }
+
+    #[test]
+    fn test_sustain_pedal_holds_last_note() {
+        let mut mapping = create_ffxiv_default_mapping();
+        mapping.sustain_pedal = true;
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        // A lone note stays down after its note-off until the pedal lifts
+        for msg in [note_on(60, 100), control_change(64, 127), note_off(60)] {
+            scheduler.handle_message(&msg, &mapping, &mut kb).unwrap();
+        }
+        assert_eq!(kb.events, vec![KeyEvent::Press(Key::Q)]);
+        scheduler
+            .handle_message(&control_change(64, 0), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(kb.events.last(), Some(&KeyEvent::Release(Key::Q)));
+
+        // A new note still replaces the sustained one
+        kb.events.clear();
+        for msg in [
+            control_change(64, 127),
+            note_on(60, 100),
+            note_off(60),
+            note_on(62, 100),
+            note_off(62),
+        ] {
+            scheduler.handle_message(&msg, &mapping, &mut kb).unwrap();
+        }
+        assert_eq!(
+            kb.events,
+            vec![
+                KeyEvent::Press(Key::Q),
+                KeyEvent::Release(Key::Q),
+                KeyEvent::Press(Key::W),
+            ]
+        );
+        scheduler
+            .handle_message(&control_change(64, 0), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(kb.events.last(), Some(&KeyEvent::Release(Key::W)));
+    }
+
+    #[test]
+    fn test_chord_pressed_and_released_together() {
+        let mut mapping = create_ffxiv_default_mapping();
+        mapping.mappings.insert(
+            60,
+            NoteMapping {
+                on_press: vec![Action::PressChord(vec![Key::A, Key::B, Key::C])],
+                on_release: vec![Action::Release(Key::A)],
+                ..Default::default()
+            },
+        );
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        // Replaced by the next note, then released by its own note-off
+        for msg in [
+            note_on(60, 100),
+            note_on(62, 100),
+            note_off(62),
+            note_off(60),
+            note_on(60, 100),
+            note_off(60),
+        ] {
+            scheduler.handle_message(&msg, &mapping, &mut kb).unwrap();
+        }
+
+        assert_eq!(
+            kb.events,
+            vec![
+                KeyEvent::Press(Key::A),
+                KeyEvent::Press(Key::B),
+                KeyEvent::Press(Key::C),
+                KeyEvent::Release(Key::A),
+                KeyEvent::Release(Key::B),
+                KeyEvent::Release(Key::C),
+                KeyEvent::Press(Key::W),
+                KeyEvent::Release(Key::W),
+                KeyEvent::Press(Key::A),
+                KeyEvent::Press(Key::B),
+                KeyEvent::Press(Key::C),
+                KeyEvent::Release(Key::A),
+                KeyEvent::Release(Key::B),
+                KeyEvent::Release(Key::C),
+            ]
+        );
+        assert!(scheduler.current_chord.is_empty());
+    }
+
+    #[test]
+    fn test_swap_keyboard_mid_session() {
+        let mapping = create_ffxiv_default_mapping();
+        let first = Arc::new(Mutex::new(MockKeyboard::new()));
+        let second = Arc::new(Mutex::new(MockKeyboard::new()));
+        let engine = MidiEngine::new(first.clone(), mapping.clone());
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+
+        // Drive the engine's shared keyboard like a connection's processing thread does
+        let mut play = |msg: MidiMessage| {
+            let mut kb = engine.keyboard.lock().unwrap();
+            scheduler.handle_message(&msg, &mapping, &mut *kb).unwrap();
+        };
+
+        play(note_on(60, 100));
+        engine.set_keyboard(second.clone()).unwrap();
+        play(note_on(62, 100));
+
+        // The old backend had its held key released; the new one takes over
+        assert_eq!(
+            first.lock().unwrap().events,
+            vec![KeyEvent::Press(Key::Q), KeyEvent::Release(Key::Q)]
+        );
+        let second = second.lock().unwrap();
+        assert_eq!(second.press_count(Key::W), 1);
+        assert!(second.pressed.contains(&Key::W));
+    }
+
+    #[test]
+    fn test_disabled_mapping_is_ignored() {
+        let mut mapping = create_ffxiv_default_mapping();
+        mapping.mappings.get_mut(&72).unwrap().enabled = false;
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        for msg in [note_on(72, 100), note_off(72)] {
+            scheduler.handle_message(&msg, &mapping, &mut kb).unwrap();
+        }
+        assert!(kb.events.is_empty());
+
+        // Files written before the flag existed load as enabled
+        let loaded: NoteMapping =
+            serde_json::from_str(r#"{"on_press": [], "on_release": []}"#).unwrap();
+        assert!(loaded.enabled);
+    }
+
+    #[test]
+    fn test_off_then_on_in_one_buffer() {
+        let mapping = create_ffxiv_default_mapping();
+        let clock = MockClock::new();
+        let mut scheduler = NoteScheduler::with_clock(clock.clone());
+        let mut kb = MockKeyboard::new();
+
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+        clock.advance(Duration::from_millis(100));
+
+        // One buffer: C4 off, D4 on
+        let start = clock.now();
+        for msg in MidiMessage::parse_all(&[0x80, 60, 0, 0x90, 62, 100], Default::default()) {
+            scheduler
+                .handle_message(&msg.unwrap(), &mapping, &mut kb)
+                .unwrap();
+        }
+
+        // Q released exactly once, W pressed straight away
+        assert_eq!(
+            kb.events,
+            vec![
+                KeyEvent::Press(Key::Q),
+                KeyEvent::Release(Key::Q),
+                KeyEvent::Press(Key::W)
+            ]
+        );
+        assert_eq!(clock.now(), start);
+        assert_eq!(scheduler.current_key, Some(Key::W));
+    }
+
+    #[test]
+    fn test_pure_modifier_note_released_on_note_off() {
+        let mut mapping = create_ffxiv_default_mapping();
+        mapping.mappings.insert(
+            60,
+            NoteMapping {
+                on_press: vec![Action::Press(Key::Shift)],
+                on_release: vec![],
+                ..Default::default()
+            },
+        );
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+        scheduler
+            .handle_message(&note_off(60), &mapping, &mut kb)
+            .unwrap();
+
+        assert_eq!(
+            kb.events,
+            vec![KeyEvent::Press(Key::Shift), KeyEvent::Release(Key::Shift)]
+        );
+        assert!(kb.pressed.is_empty());
+        assert_eq!(scheduler.current_key, None);
+    }
+
+    #[test]
+    fn test_raw_execution() {
+        let mapping = create_ffxiv_default_mapping();
+        let events = |raw_execution: bool| {
+            let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+            scheduler.options.raw_execution = raw_execution;
+            let mut kb = MockKeyboard::new();
+            for msg in [
+                note_on(60, 100),
+                note_on(62, 100),
+                note_off(60),
+                note_off(62),
+            ] {
+                scheduler.handle_message(&msg, &mapping, &mut kb).unwrap();
+            }
+            kb.events
+        };
+
+        // Smart: C4 is released before D4, and its late note-off is ignored
+        assert_eq!(
+            events(false),
+            vec![
+                KeyEvent::Press(Key::Q),
+                KeyEvent::Release(Key::Q),
+                KeyEvent::Press(Key::W),
+                KeyEvent::Release(Key::W),
+            ]
+        );
+        // Raw: exactly the authored actions, in event order
+        assert_eq!(
+            events(true),
+            vec![
+                KeyEvent::Press(Key::Q),
+                KeyEvent::Press(Key::W),
+                KeyEvent::Release(Key::Q),
+                KeyEvent::Release(Key::W),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_folded_note_on() {
+        let mut mapping = MappingConfig::new();
+        mapping.octave_transpose = true;
+        mapping.mappings.insert(
+            60,
+            NoteMapping {
+                on_press: vec![Action::Press(Key::Q)],
+                on_release: vec![Action::Release(Key::Q)],
+                ..Default::default()
+            },
+        );
+        let events = |folded_note_on: FoldedNoteOn| {
+            let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+            scheduler.options.folded_note_on = folded_note_on;
+            let mut kb = MockKeyboard::new();
+            // C5 folds down onto C4 while C4 is held
+            for msg in [
+                note_on(60, 100),
+                note_on(72, 100),
+                note_off(72),
+                note_off(60),
+            ] {
+                scheduler.handle_message(&msg, &mapping, &mut kb).unwrap();
+            }
+            kb.events
+        };
+
+        assert_eq!(
+            events(FoldedNoteOn::Retrigger),
+            vec![
+                KeyEvent::Press(Key::Q),
+                KeyEvent::Release(Key::Q),
+                KeyEvent::Press(Key::Q),
+                KeyEvent::Release(Key::Q),
+            ]
+        );
+        // Ignored: Q stays held until C4 itself is released
+        assert_eq!(
+            events(FoldedNoteOn::Ignore),
+            vec![KeyEvent::Press(Key::Q), KeyEvent::Release(Key::Q)]
+        );
+    }
+
+    #[test]
+    fn test_inject_without_device() {
+        let kb = Arc::new(Mutex::new(MockKeyboard::new()));
+        let engine = MidiEngine::new(kb.clone(), create_ffxiv_default_mapping());
+        let wait_for = |done: &dyn Fn(&MockKeyboard) -> bool| {
+            let deadline = Instant::now() + Duration::from_secs(2);
+            while !done(&kb.lock().unwrap()) {
+                assert!(Instant::now() < deadline, "injected event not processed");
+                thread::sleep(Duration::from_millis(5));
+            }
+        };
+
+        engine.inject(note_on(60, 100));
+        wait_for(&|kb| kb.pressed.contains(&Key::Q));
+        engine.inject(note_off(60));
+        wait_for(&|kb| kb.pressed.is_empty());
+
+        assert_eq!(
+            kb.lock().unwrap().events,
+            vec![KeyEvent::Press(Key::Q), KeyEvent::Release(Key::Q)]
+        );
+    }
+
+    #[test]
+    fn test_stop_joins_processing_thread() {
+        let kb = Arc::new(Mutex::new(MockKeyboard::new()));
+        let engine = MidiEngine::new(kb.clone(), create_ffxiv_default_mapping());
+
+        // Ctrl+Q is held when the engine stops
+        engine.inject(note_on(48, 100));
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !kb.lock().unwrap().pressed.contains(&Key::Q) {
+            assert!(Instant::now() < deadline, "injected event not processed");
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        engine.stop().unwrap();
+        assert!(engine.threads.lock().unwrap().is_empty());
+        assert!(kb.lock().unwrap().pressed.is_empty());
+
+        // The engine can be used again afterwards
+        engine.inject(note_on(60, 100));
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !kb.lock().unwrap().pressed.contains(&Key::Q) {
+            assert!(Instant::now() < deadline, "injected event not processed");
+            thread::sleep(Duration::from_millis(5));
+        }
+        engine.stop().unwrap();
+        assert!(kb.lock().unwrap().pressed.is_empty());
+    }
+
+    #[test]
+    fn test_reset_modifiers_on_connect() {
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        scheduler.start(&mut kb).unwrap();
+        assert!(kb.events.is_empty());
+
+        scheduler.options.reset_modifiers_on_connect = true;
+        scheduler.start(&mut kb).unwrap();
+        let mut expected = Vec::new();
+        for side in [
+            ModifierSide::Generic,
+            ModifierSide::Left,
+            ModifierSide::Right,
+        ] {
+            for key in [side.shift(), side.control(), side.alt()] {
+                expected.push(KeyEvent::Release(key));
+            }
+        }
+        assert_eq!(kb.events, expected);
+    }
+
+    #[test]
+    fn test_reset_modifiers_on_connect_reaches_enigo_backend() {
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        scheduler.options.reset_modifiers_on_connect = true;
+        let backend = RecordingEnigo::default();
+        let mut kb =
+            EnigoKeyboardController::with_backend(backend.clone(), KeyboardSettings::default());
+
+        scheduler.start(&mut kb).unwrap();
+        assert_eq!(backend.sent(), modifier_releases());
+    }
+
+    #[test]
+    fn test_delay_scale() {
+        let mut mapping = MappingConfig::new();
+        mapping.mappings.insert(
+            60,
+            NoteMapping {
+                on_press: vec![Action::Delay(40)],
+                ..Default::default()
+            },
+        );
+        let elapsed = |mapping: &MappingConfig| {
+            let clock = MockClock::new();
+            let mut scheduler = NoteScheduler::with_clock(clock.clone());
+            let start = clock.now();
+            scheduler
+                .handle_message(&note_on(60, 100), mapping, &mut MockKeyboard::new())
+                .unwrap();
+            clock.now() - start
+        };
+
+        assert_eq!(elapsed(&mapping), Duration::from_millis(40));
+        mapping.delay_scale = 2.0;
+        assert_eq!(elapsed(&mapping), Duration::from_millis(80));
+        // Out-of-range scales are clamped
+        mapping.delay_scale = 1000.0;
+        assert_eq!(elapsed(&mapping), Duration::from_millis(400));
+    }
+
+    #[test]
+    fn test_cc_key_threshold() {
+        let mut mapping = create_ffxiv_default_mapping();
+        mapping.cc_keys.insert(64, Key::LShift);
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        // Rising through 64 presses once; moving while on doesn't re-press
+        for value in [10, 63, 64, 100, 127] {
+            scheduler
+                .handle_message(&control_change(64, value), &mapping, &mut kb)
+                .unwrap();
+        }
+        assert_eq!(kb.events, vec![KeyEvent::Press(Key::LShift)]);
+
+        // Falling below 64 releases once; other controllers are ignored
+        for value in [80, 63, 0] {
+            scheduler
+                .handle_message(&control_change(64, value), &mapping, &mut kb)
+                .unwrap();
+        }
+        scheduler
+            .handle_message(&control_change(1, 127), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(
+            kb.events,
+            vec![KeyEvent::Press(Key::LShift), KeyEvent::Release(Key::LShift)]
+        );
+
+        // A key still held when the connection ends is released
+        scheduler
+            .handle_message(&control_change(64, 127), &mapping, &mut kb)
+            .unwrap();
+        scheduler.stop(&mut kb).unwrap();
+        assert!(kb.pressed.is_empty());
+    }
+
+    #[test]
+    fn test_warm_up_tap_on_connect() {
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        scheduler.options.warm_up_key = Some(Key::LShift);
+        let mut kb = MockKeyboard::new();
+
+        scheduler.start(&mut kb).unwrap();
+
+        assert_eq!(kb.taps, vec![(Key::LShift, Duration::ZERO)]);
+        assert_eq!(
+            kb.events,
+            vec![KeyEvent::Press(Key::LShift), KeyEvent::Release(Key::LShift)]
+        );
+    }
+
+    #[test]
+    fn test_inverted_note() {
+        let mut mapping = create_ffxiv_default_mapping();
+        mapping.mappings.get_mut(&60).unwrap().invert = true;
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let mut kb = MockKeyboard::new();
+
+        // Pressing a normally-closed switch sends note-off; letting go sends note-on
+        scheduler
+            .handle_message(&note_off(60), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(kb.events, vec![KeyEvent::Press(Key::Q)]);
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(
+            kb.events,
+            vec![KeyEvent::Press(Key::Q), KeyEvent::Release(Key::Q)]
+        );
+    }
+
+    #[test]
+    fn test_key_event_callback() {
+        let mut engine = MidiEngine::new(MockKeyboard::new(), create_ffxiv_default_mapping());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        engine.set_key_event_callback(move |event| sink.lock().unwrap().push(event));
+
+        engine.inject(note_on(72, 100));
+        engine.inject(note_off(72));
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while seen.lock().unwrap().len() < 4 {
+            assert!(Instant::now() < deadline, "key events not reported");
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                KeyEvent::Press(Key::Shift),
+                KeyEvent::Press(Key::Q),
+                KeyEvent::Release(Key::Q),
+                KeyEvent::Release(Key::Shift),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unfocused_events_dropped() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let mapping = create_ffxiv_default_mapping();
+        let focused = Arc::new(AtomicBool::new(true));
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        let check = focused.clone();
+        scheduler.focus_check = Some(Arc::new(move || check.load(Ordering::SeqCst)));
+        let mut kb = MockKeyboard::new();
+
+        // Losing focus releases the held note and its modifier
+        scheduler
+            .handle_message(&note_on(72, 100), &mapping, &mut kb)
+            .unwrap();
+        assert!(kb.pressed.contains(&Key::Q));
+        focused.store(false, Ordering::SeqCst);
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+        assert!(kb.pressed.is_empty());
+
+        // Further events are dropped until focus returns
+        let sent = kb.events.len();
+        scheduler
+            .handle_message(&note_off(60), &mapping, &mut kb)
+            .unwrap();
+        scheduler
+            .handle_message(&note_on(62, 100), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(kb.events.len(), sent);
+
+        focused.store(true, Ordering::SeqCst);
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut kb)
+            .unwrap();
+        assert_eq!(kb.events.last(), Some(&KeyEvent::Press(Key::Q)));
+    }
+
+    #[test]
+    fn test_notes_only_filter_drops_cc() {
+        let (tx, rx) = channel::bounded(8);
+        let seen = Mutex::new(Vec::new());
+        let callback = |msg: MidiMessage| seen.lock().unwrap().push(msg.event_type);
+
+        // CC, note-on, channel aftertouch, note-off
+        let data = [0xB0, 1, 127, 0x90, 60, 100, 0xD0, 40, 0x80, 60, 0];
+        let mapping = create_ffxiv_default_mapping();
+        let mut decoder = MidiDecoder::new(Default::default(), mapping.message_filter());
+        let stats = StatCounters::default();
+        queue_messages(&data, &mut decoder, &callback, &tx, &stats, Instant::now());
+
+        let queued: Vec<_> = rx.try_iter().map(|e| e.message.event_type).collect();
+        assert_eq!(queued, vec![MidiEventType::NoteOn, MidiEventType::NoteOff]);
+        assert_eq!(*seen.lock().unwrap(), queued);
+
+        // A mapping that uses a controller still receives it
+        let mut mapping = create_ffxiv_default_mapping();
+        mapping.cc_keys.insert(64, Key::LShift);
+        let mut decoder = MidiDecoder::new(Default::default(), mapping.message_filter());
+        queue_messages(&data, &mut decoder, &|_| {}, &tx, &stats, Instant::now());
+        assert_eq!(rx.try_iter().count(), 3);
+        assert_eq!(stats.snapshot(), EngineStats::default());
+    }
+
+    #[test]
+    fn test_full_queue_counts_dropped_events() {
+        let (tx, rx) = channel::bounded(1);
+        let stats = StatCounters::default();
+        let mut decoder = MidiDecoder::new(Default::default(), MessageFilter::NOTES_ONLY);
+
+        // Two notes into a queue of one, then a stray data byte
+        let data = [0x90, 60, 100, 0x90, 62, 100];
+        queue_messages(&data, &mut decoder, &|_| {}, &tx, &stats, Instant::now());
+        let mut fresh = MidiDecoder::new(Default::default(), MessageFilter::NOTES_ONLY);
+        queue_messages(&[0x40], &mut fresh, &|_| {}, &tx, &stats, Instant::now());
+
+        assert_eq!(rx.try_iter().count(), 1);
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.dropped, 1);
+        assert_eq!(snapshot.parse_errors, 1);
+
+        // Once the processing thread is gone, events aren't counted as dropped
+        drop(rx);
+        queue_messages(&data, &mut decoder, &|_| {}, &tx, &stats, Instant::now());
+        assert_eq!(stats.snapshot().dropped, 1);
+
+        stats.count_processed(MidiEventType::NoteOn);
+        stats.count_processed(MidiEventType::NoteOff);
+        stats.count_processed(MidiEventType::ControlChange {
+            controller: 64,
+            value: 127,
+        });
+        assert_eq!(
+            (stats.snapshot().note_ons, stats.snapshot().note_offs),
+            (1, 1)
+        );
+    }
+
+    #[test]
+    fn test_latency_stats() {
+        let stats = StatCounters::default();
+        stats.record_latency(Duration::from_millis(2));
+        assert_eq!(stats.snapshot().average_latency, Duration::from_millis(2));
+
+        // One slow keypress only nudges the average, but sets the max
+        stats.record_latency(Duration::from_millis(18));
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.average_latency, Duration::from_millis(3));
+        assert_eq!(snapshot.max_latency, Duration::from_millis(18));
+
+        stats.record_latency(Duration::from_millis(1));
+        assert_eq!(stats.snapshot().max_latency, Duration::from_millis(18));
+    }
+
+    #[test]
+    fn test_timestamp_clock_maps_backend_time() {
+        let mut clock = TimestampClock::default();
+        let start = Instant::now();
+        assert_eq!(clock.received_at(5_000, start), start);
+
+        // Queued in the backend for 3ms before the callback ran
+        let now = start + Duration::from_millis(10);
+        assert_eq!(
+            clock.received_at(12_000, now),
+            start + Duration::from_millis(7)
+        );
+
+        // A timestamp ahead of the callback, or one that went backwards, starts over
+        assert_eq!(clock.received_at(30_000, now), now);
+        assert_eq!(clock.received_at(1_000, now), now);
+    }
+
+    #[test]
+    fn test_queue_capacity_bounds_pending_events() {
+        let mut engine = MidiEngine::new(MockKeyboard::new(), create_ffxiv_default_mapping());
+        // Clamped up to one queued event
+        engine.set_options(EngineOptions {
+            queue_capacity: Some(0),
+            ..EngineOptions::default()
+        });
+
+        // Holding the keyboard stalls the processing thread before it reads anything
+        let kb = engine.keyboard.lock().unwrap();
+        for note in [60, 62, 64] {
+            engine.inject(note_on(note, 100));
+        }
+        drop(kb);
+        engine.finish_injected().unwrap();
+
+        assert_eq!(engine.stats().dropped, 2);
+        assert_eq!(engine.stats().note_ons, 1);
+    }
+
+    #[test]
+    fn test_feedback_follows_held_key() {
+        let mut mapping = create_ffxiv_default_mapping();
+        mapping.feedback = Some(Feedback {
+            port: "pads".to_string(),
+            channel: 1,
+            on_velocity: 5,
+            off_velocity: 0,
+            notes: HashMap::from([(60, 36)]),
+        });
+        let sink = MockSink::default();
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        scheduler.feedback = Some(Arc::new(Mutex::new(sink.clone())));
+        let mut kb = MockKeyboard::new();
+
+        // Pressing lights the pad's LED (through the note override), releasing clears it
+        for msg in [note_on(60, 100), note_off(60)] {
+            scheduler.handle_message(&msg, &mapping, &mut kb).unwrap();
+        }
+        assert_eq!(
+            *sink.sent.lock().unwrap(),
+            vec![vec![0x91, 36, 5], vec![0x91, 36, 0]]
+        );
+
+        // A new note auto-releasing the previous one moves the light
+        sink.sent.lock().unwrap().clear();
+        for msg in [note_on(62, 100), note_on(64, 100)] {
+            scheduler.handle_message(&msg, &mapping, &mut kb).unwrap();
+        }
+        assert_eq!(
+            *sink.sent.lock().unwrap(),
+            vec![vec![0x91, 62, 5], vec![0x91, 64, 5], vec![0x91, 62, 0]]
+        );
+    }
+
+    #[test]
+    fn test_clock_tempo_scales_note_gap() {
+        let clock = MockClock::new();
+        let mut scheduler = NoteScheduler::with_clock(clock.clone());
+        scheduler.options.timing = TimingPreset::new(10, 3, 0);
+        scheduler.options.tempo_gap_reference_bpm = Some(120);
+
+        // No clock yet: the preset's gap
+        assert_eq!(scheduler.min_note_gap(), Duration::from_millis(10));
+
+        // 240 BPM = 24 pulses per 250ms quarter note
+        let pulses = |bpm: f64, count: u32| {
+            let interval = Duration::from_secs_f64(60.0 / (bpm * 24.0));
+            for _ in 0..count {
+                clock.advance(interval);
+                scheduler.tempo.pulse(clock.now());
+            }
+        };
+        pulses(240.0, 48);
+        let bpm = scheduler.tempo.bpm(clock.now()).unwrap();
+        assert!((bpm - 240.0).abs() < 0.5, "{}", bpm);
+        let gap = scheduler.min_note_gap().as_secs_f64() * 1000.0;
+        assert!((gap - 5.0).abs() < 0.05, "{}", gap);
+
+        // Very slow clock: the gap only grows to the clamp
+        pulses(30.0, 96);
+        assert_eq!(scheduler.min_note_gap(), Duration::from_millis(20));
+
+        // The clock stopping falls back to the preset's gap
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(scheduler.tempo.bpm(clock.now()), None);
+        assert_eq!(scheduler.min_note_gap(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_mapping_min_note_gap() {
+        let mut scheduler = NoteScheduler::with_clock(MockClock::new());
+        scheduler.options.timing = TimingPreset::new(3, 3, 0);
+        let mut mapping = create_ffxiv_default_mapping();
+        mapping.min_note_gap_ms = Some(10);
+
+        scheduler
+            .handle_message(&note_on(60, 100), &mapping, &mut MockKeyboard::new())
+            .unwrap();
+        assert_eq!(scheduler.min_note_gap(), Duration::from_millis(10));
+
+        // Without an override, the preset's gap
+        mapping.min_note_gap_ms = None;
+        scheduler
+            .handle_message(&note_off(60), &mapping, &mut MockKeyboard::new())
+            .unwrap();
+        assert_eq!(scheduler.min_note_gap(), Duration::from_millis(3));
+
+        // The engine setter edits the live mapping
+        let engine = MidiEngine::new(MockKeyboard::new(), mapping);
+        engine.set_min_note_gap(Some(8));
+        assert_eq!(engine.mapping().lock().unwrap().min_note_gap_ms, Some(8));
     }
 }
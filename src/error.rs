@@ -5,15 +5,27 @@ pub enum Error {
     #[error("MIDI error: {0}")]
     Midi(#[from] midir::InitError),
 
+    #[error("No MIDI backend available on this system")]
+    NoMidiBackend,
+
     #[error("MIDI connection error: {0}")]
     MidiConnection(#[from] midir::ConnectError<midir::MidiInput>),
 
+    #[error("MIDI output error: {0}")]
+    MidiOutput(String),
+
     #[error("Invalid MIDI message: {0}")]
     InvalidMidiMessage(String),
 
+    #[error("MIDI file error: {0}")]
+    MidiFile(#[from] midly::Error),
+
     #[error("Keyboard error: {0}")]
     Keyboard(String),
 
+    #[error("Key {0:?} can't be sent by the keyboard backend on this platform")]
+    UnsupportedKey(crate::keyboard::Key),
+
     #[error("Mapping error: {0}")]
     Mapping(String),
 
@@ -22,6 +34,42 @@ pub enum Error {
 
     #[error("JSON error: {0}")]
     Json(#[from] serde_json::Error),
+
+    #[cfg(feature = "yaml")]
+    #[error("YAML error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+/// Error for a failed `MidiInput::new`. `midir::InitError` carries no detail, and
+/// opening an input only fails when the platform has no MIDI API at all.
+pub(crate) fn no_backend(_: midir::InitError) -> Error {
+    Error::NoMidiBackend
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_midi_errors() {
+        // Only opening MIDI input reports a missing backend; other init errors
+        // (e.g. opening an output) keep their own variant
+        let err = Error::from(midir::InitError);
+        assert!(matches!(err, Error::Midi(_)));
+
+        let err = no_backend(midir::InitError);
+        assert!(matches!(err, Error::NoMidiBackend));
+        assert_eq!(err.to_string(), "No MIDI backend available on this system");
+    }
+
+    #[test]
+    fn test_unsupported_key_message() {
+        let err = Error::UnsupportedKey(crate::keyboard::Key::RAlt);
+        assert_eq!(
+            err.to_string(),
+            "Key RAlt can't be sent by the keyboard backend on this platform"
+        );
+    }
+}
@@ -0,0 +1,150 @@
+//! Foreground window queries, so keys are only sent while the game has focus.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Title FFXIV's window uses on every platform.
+pub const GAME_WINDOW_TITLE: &str = "FINAL FANTASY XIV";
+
+/// How long a focus query result is reused. Querying on every note is cheap on
+/// Windows/X11 but spawns a process on macOS.
+const FOCUS_CACHE: Duration = Duration::from_millis(200);
+
+/// The window that currently has keyboard focus.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ForegroundWindow {
+    pub title: String,
+    /// Window class (Windows, X11) or application name (macOS)
+    pub class: String,
+}
+
+impl ForegroundWindow {
+    /// Whether the title or class contains `pattern`, ignoring case.
+    pub fn matches(&self, pattern: &str) -> bool {
+        let pattern = pattern.to_lowercase();
+        self.title.to_lowercase().contains(&pattern) || self.class.to_lowercase().contains(&pattern)
+    }
+}
+
+/// Focus check for `MidiEngine::set_focus_check`: true while the foreground window
+/// matches `pattern` (see `ForegroundWindow::matches`). If the foreground window
+/// can't be queried, the game counts as unfocused.
+pub fn window_focus_check(pattern: &str) -> impl Fn() -> bool + Send + Sync + 'static {
+    let pattern = pattern.to_string();
+    let last: Mutex<Option<(Instant, bool)>> = Mutex::new(None);
+    move || {
+        let mut last = last.lock().unwrap();
+        if let Some((at, focused)) = *last
+            && at.elapsed() < FOCUS_CACHE
+        {
+            return focused;
+        }
+        let focused = foreground_window().is_some_and(|w| w.matches(&pattern));
+        *last = Some((Instant::now(), focused));
+        focused
+    }
+}
+
+/// The window that currently has keyboard focus, if it can be determined.
+#[cfg(target_os = "windows")]
+pub fn foreground_window() -> Option<ForegroundWindow> {
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        GetClassNameW, GetForegroundWindow, GetWindowTextW,
+    };
+
+    let mut buffer = [0u16; 256];
+    // SAFETY: the buffer length passed matches the buffer, and the window handle
+    // is only used for these queries (they fail gracefully if it has closed)
+    unsafe {
+        let hwnd = GetForegroundWindow();
+        if hwnd.is_null() {
+            return None;
+        }
+        let len = GetWindowTextW(hwnd, buffer.as_mut_ptr(), buffer.len() as i32);
+        let title = String::from_utf16_lossy(&buffer[..len.max(0) as usize]);
+        let len = GetClassNameW(hwnd, buffer.as_mut_ptr(), buffer.len() as i32);
+        let class = String::from_utf16_lossy(&buffer[..len.max(0) as usize]);
+        Some(ForegroundWindow { title, class })
+    }
+}
+
+/// The window that currently has keyboard focus, if it can be determined.
+/// Only the frontmost application's name is available without accessibility
+/// permissions, so it is reported as both title and class.
+#[cfg(target_os = "macos")]
+pub fn foreground_window() -> Option<ForegroundWindow> {
+    let output = std::process::Command::new("osascript")
+        .args([
+            "-e",
+            "tell application \"System Events\" to get name of first application process whose frontmost is true",
+        ])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Some(ForegroundWindow {
+        title: name.clone(),
+        class: name,
+    })
+}
+
+/// The window that currently has keyboard focus, if it can be determined.
+/// Uses the EWMH `_NET_ACTIVE_WINDOW` hint, so it needs an X11 (or XWayland)
+/// window manager.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn foreground_window() -> Option<ForegroundWindow> {
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{AtomEnum, ConnectionExt};
+
+    let (conn, screen) = x11rb::connect(None).ok()?;
+    let root = conn.setup().roots.get(screen)?.root;
+    let atom = |name: &[u8]| -> Option<u32> {
+        Some(conn.intern_atom(false, name).ok()?.reply().ok()?.atom)
+    };
+    let property = |window: u32, property: u32, type_: u32| {
+        conn.get_property(false, window, property, type_, 0, 1024)
+            .ok()?
+            .reply()
+            .ok()
+    };
+
+    let active = property(root, atom(b"_NET_ACTIVE_WINDOW")?, AtomEnum::WINDOW.into())?;
+    let window = active.value32()?.next().filter(|w| *w != 0)?;
+
+    let utf8 = atom(b"UTF8_STRING")?;
+    let title = property(window, atom(b"_NET_WM_NAME")?, utf8)
+        .filter(|reply| !reply.value.is_empty())
+        .or_else(|| property(window, AtomEnum::WM_NAME.into(), AtomEnum::ANY.into()))
+        .map(|reply| String::from_utf8_lossy(&reply.value).into_owned())
+        .unwrap_or_default();
+    // WM_CLASS is "instance\0class\0"
+    let class = property(window, AtomEnum::WM_CLASS.into(), AtomEnum::STRING.into())
+        .map(|reply| {
+            String::from_utf8_lossy(&reply.value)
+                .replace('\0', " ")
+                .trim()
+                .to_string()
+        })
+        .unwrap_or_default();
+
+    Some(ForegroundWindow { title, class })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_foreground_window_matches() {
+        let window = ForegroundWindow {
+            title: "FINAL FANTASY XIV".to_string(),
+            class: "FFXIVGAME".to_string(),
+        };
+        assert!(window.matches(GAME_WINDOW_TITLE));
+        assert!(window.matches("final fantasy"));
+        assert!(window.matches("ffxivgame"));
+        assert!(!window.matches("Discord"));
+    }
+}
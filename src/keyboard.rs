@@ -3,6 +3,55 @@ use enigo::{
     Direction, Enigo, Key as EnigoKey, Keyboard as EnigoKeyboard, Settings,
 };
 use std::collections::HashMap;
+use std::time::Duration;
+
+/// X11 keysym for the right Alt key, which enigo has no named variant for on Linux.
+#[cfg(all(unix, not(target_os = "macos")))]
+const XK_ALT_R: u32 = 0xffea;
+
+/// X11 keysym for the numpad Enter key (enigo's `Return` is the main Enter key).
+#[cfg(all(unix, not(target_os = "macos")))]
+const XK_KP_ENTER: u32 = 0xff8d;
+
+/// macOS virtual keycode for the numpad Enter key (`kVK_ANSI_KeypadEnter`).
+#[cfg(target_os = "macos")]
+const KVK_KEYPAD_ENTER: u32 = 0x4c;
+
+/// Which physical modifier keys `SetModifiers` presses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum ModifierSide {
+    /// Generic Shift/Control/Alt (let the OS pick, usually the left key)
+    #[default]
+    Generic,
+    Left,
+    Right,
+}
+
+impl ModifierSide {
+    pub fn shift(self) -> Key {
+        match self {
+            ModifierSide::Generic => Key::Shift,
+            ModifierSide::Left => Key::LShift,
+            ModifierSide::Right => Key::RShift,
+        }
+    }
+
+    pub fn control(self) -> Key {
+        match self {
+            ModifierSide::Generic => Key::Control,
+            ModifierSide::Left => Key::LControl,
+            ModifierSide::Right => Key::RControl,
+        }
+    }
+
+    pub fn alt(self) -> Key {
+        match self {
+            ModifierSide::Generic => Key::Alt,
+            ModifierSide::Left => Key::LAlt,
+            ModifierSide::Right => Key::RAlt,
+        }
+    }
+}
 
 /// Keyboard key representation
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
@@ -25,9 +74,20 @@ pub enum Key {
     Alt,
     Meta,
 
+    // Side-specific modifier keys
+    LShift,
+    RShift,
+    LControl,
+    RControl,
+    LAlt,
+    RAlt,
+
     // Special keys
     Space,
+    /// The main Enter/Return key
     Enter,
+    /// The Enter key on the numpad, which some games bind separately from Return
+    NumpadEnter,
     Escape,
     Tab,
     Backspace,
@@ -37,11 +97,158 @@ pub enum Key {
     Down,
     Left,
     Right,
+
+    // Punctuation keys (named after their US-layout symbols, as egui does)
+    /// `-`
+    Minus,
+    /// `=`
+    Equals,
+    /// `[`
+    OpenBracket,
+    /// `]`
+    CloseBracket,
+    /// `;`
+    Semicolon,
+    /// `'`
+    Quote,
+    /// `,`
+    Comma,
+    /// `.`
+    Period,
+    /// `/`
+    Slash,
+    /// `` ` ``
+    Backtick,
+    /// `\`
+    Backslash,
+
+    // Numpad keys (numpad Enter is with the special keys)
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4,
+    Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    NumpadAdd,
+    NumpadSubtract,
+    NumpadMultiply,
+    NumpadDivide,
+    NumpadDecimal,
 }
 
 impl Key {
-    fn to_enigo_key(self) -> EnigoKey {
-        match self {
+    const ALL: [Key; 94] = [
+        // Letter keys
+        Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L, Key::M,
+        Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+
+        // Number keys
+        Key::Num0, Key::Num1, Key::Num2, Key::Num3, Key::Num4,
+        Key::Num5, Key::Num6, Key::Num7, Key::Num8, Key::Num9,
+
+        // Function keys
+        Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6,
+        Key::F7, Key::F8, Key::F9, Key::F10, Key::F11, Key::F12,
+
+        // Modifier keys
+        Key::Shift, Key::Control, Key::Alt, Key::Meta,
+
+        // Side-specific modifier keys
+        Key::LShift, Key::RShift, Key::LControl, Key::RControl, Key::LAlt, Key::RAlt,
+
+        // Special keys
+        Key::Space, Key::Enter, Key::NumpadEnter, Key::Escape, Key::Tab, Key::Backspace,
+
+        // Arrow keys
+        Key::Up, Key::Down, Key::Left, Key::Right,
+
+        // Punctuation keys
+        Key::Minus, Key::Equals, Key::OpenBracket, Key::CloseBracket, Key::Semicolon, Key::Quote,
+        Key::Comma, Key::Period, Key::Slash, Key::Backtick, Key::Backslash,
+
+        // Numpad keys
+        Key::Numpad0, Key::Numpad1, Key::Numpad2, Key::Numpad3, Key::Numpad4,
+        Key::Numpad5, Key::Numpad6, Key::Numpad7, Key::Numpad8, Key::Numpad9,
+        Key::NumpadAdd, Key::NumpadSubtract, Key::NumpadMultiply, Key::NumpadDivide,
+        Key::NumpadDecimal,
+    ];
+
+    const LETTERS: [Key; 26] = [
+        Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L, Key::M,
+        Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    ];
+
+    const NUMBERS: [Key; 10] = [
+        Key::Num0, Key::Num1, Key::Num2, Key::Num3, Key::Num4,
+        Key::Num5, Key::Num6, Key::Num7, Key::Num8, Key::Num9,
+    ];
+
+    const FUNCTION_KEYS: [Key; 12] = [
+        Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6,
+        Key::F7, Key::F8, Key::F9, Key::F10, Key::F11, Key::F12,
+    ];
+
+    const PUNCTUATION: [Key; 11] = [
+        Key::Minus, Key::Equals, Key::OpenBracket, Key::CloseBracket, Key::Semicolon, Key::Quote,
+        Key::Comma, Key::Period, Key::Slash, Key::Backtick, Key::Backslash,
+    ];
+
+    const NUMPAD: [Key; 16] = [
+        Key::Numpad0, Key::Numpad1, Key::Numpad2, Key::Numpad3, Key::Numpad4,
+        Key::Numpad5, Key::Numpad6, Key::Numpad7, Key::Numpad8, Key::Numpad9,
+        Key::NumpadAdd, Key::NumpadSubtract, Key::NumpadMultiply, Key::NumpadDivide,
+        Key::NumpadDecimal, Key::NumpadEnter,
+    ];
+
+    const MODIFIERS: [Key; 10] = [
+        Key::Shift, Key::Control, Key::Alt, Key::Meta,
+        Key::LShift, Key::RShift, Key::LControl, Key::RControl, Key::LAlt, Key::RAlt,
+    ];
+
+    /// Every key, in declaration order
+    pub fn all() -> &'static [Key] {
+        &Self::ALL
+    }
+
+    /// Letter keys A-Z
+    pub fn letters() -> &'static [Key] {
+        &Self::LETTERS
+    }
+
+    /// Top-row number keys 0-9
+    pub fn numbers() -> &'static [Key] {
+        &Self::NUMBERS
+    }
+
+    /// Function keys F1-F12
+    pub fn function_keys() -> &'static [Key] {
+        &Self::FUNCTION_KEYS
+    }
+
+    /// Punctuation keys `-`, `=`, `[`, `]`, `;`, `'`, `,`, `.`, `/`, `` ` `` and `\`
+    pub fn punctuation() -> &'static [Key] {
+        &Self::PUNCTUATION
+    }
+
+    /// Numpad keys: digits, operators, decimal point and Enter
+    pub fn numpad() -> &'static [Key] {
+        &Self::NUMPAD
+    }
+
+    /// Shift, Control, Alt and Meta, plus the left/right variants
+    pub fn modifiers() -> &'static [Key] {
+        &Self::MODIFIERS
+    }
+
+    /// Whether this is one of `Key::modifiers()`
+    pub fn is_modifier(self) -> bool {
+        Self::MODIFIERS.contains(&self)
+    }
+
+    /// Whether the keyboard backend can send this key on the current platform
+    pub fn is_supported(self) -> bool {
+        self.to_enigo_key().is_some()
+    }
+
+    /// The enigo key to send, or `None` if this platform has no equivalent
+    pub(crate) fn to_enigo_key(self) -> Option<EnigoKey> {
+        Some(match self {
             Key::A => EnigoKey::Unicode('a'),
             Key::B => EnigoKey::Unicode('b'),
             Key::C => EnigoKey::Unicode('c'),
@@ -94,8 +301,38 @@ impl Key {
             Key::Control => EnigoKey::Control,
             Key::Alt => EnigoKey::Alt,
             Key::Meta => EnigoKey::Meta,
+            // Left variants: the generic keys already send the left key where
+            // enigo has no dedicated variant
+            #[cfg(any(target_os = "windows", all(unix, not(target_os = "macos"))))]
+            Key::LShift => EnigoKey::LShift,
+            #[cfg(target_os = "macos")]
+            Key::LShift => EnigoKey::Shift,
+            #[cfg(target_os = "windows")]
+            Key::LControl => EnigoKey::LControl,
+            #[cfg(not(target_os = "windows"))]
+            Key::LControl => EnigoKey::Control,
+            #[cfg(target_os = "windows")]
+            Key::LAlt => EnigoKey::LMenu,
+            #[cfg(not(target_os = "windows"))]
+            Key::LAlt => EnigoKey::Alt,
+            Key::RShift => EnigoKey::RShift,
+            Key::RControl => EnigoKey::RControl,
+            #[cfg(target_os = "windows")]
+            Key::RAlt => EnigoKey::RMenu,
+            #[cfg(target_os = "macos")]
+            Key::RAlt => EnigoKey::ROption,
+            #[cfg(all(unix, not(target_os = "macos")))]
+            Key::RAlt => EnigoKey::Other(XK_ALT_R),
             Key::Space => EnigoKey::Space,
             Key::Enter => EnigoKey::Return,
+            #[cfg(all(unix, not(target_os = "macos")))]
+            Key::NumpadEnter => EnigoKey::Other(XK_KP_ENTER),
+            #[cfg(target_os = "macos")]
+            Key::NumpadEnter => EnigoKey::Other(KVK_KEYPAD_ENTER),
+            // enigo can't set the extended-key flag that tells numpad Enter
+            // apart from Return on Windows, so refuse rather than send Return
+            #[cfg(target_os = "windows")]
+            Key::NumpadEnter => return None,
             Key::Escape => EnigoKey::Escape,
             Key::Tab => EnigoKey::Tab,
             Key::Backspace => EnigoKey::Backspace,
@@ -103,7 +340,33 @@ impl Key {
             Key::Down => EnigoKey::DownArrow,
             Key::Left => EnigoKey::LeftArrow,
             Key::Right => EnigoKey::RightArrow,
-        }
+            Key::Minus => EnigoKey::Unicode('-'),
+            Key::Equals => EnigoKey::Unicode('='),
+            Key::OpenBracket => EnigoKey::Unicode('['),
+            Key::CloseBracket => EnigoKey::Unicode(']'),
+            Key::Semicolon => EnigoKey::Unicode(';'),
+            Key::Quote => EnigoKey::Unicode('\''),
+            Key::Comma => EnigoKey::Unicode(','),
+            Key::Period => EnigoKey::Unicode('.'),
+            Key::Slash => EnigoKey::Unicode('/'),
+            Key::Backtick => EnigoKey::Unicode('`'),
+            Key::Backslash => EnigoKey::Unicode('\\'),
+            Key::Numpad0 => EnigoKey::Numpad0,
+            Key::Numpad1 => EnigoKey::Numpad1,
+            Key::Numpad2 => EnigoKey::Numpad2,
+            Key::Numpad3 => EnigoKey::Numpad3,
+            Key::Numpad4 => EnigoKey::Numpad4,
+            Key::Numpad5 => EnigoKey::Numpad5,
+            Key::Numpad6 => EnigoKey::Numpad6,
+            Key::Numpad7 => EnigoKey::Numpad7,
+            Key::Numpad8 => EnigoKey::Numpad8,
+            Key::Numpad9 => EnigoKey::Numpad9,
+            Key::NumpadAdd => EnigoKey::Add,
+            Key::NumpadSubtract => EnigoKey::Subtract,
+            Key::NumpadMultiply => EnigoKey::Multiply,
+            Key::NumpadDivide => EnigoKey::Divide,
+            Key::NumpadDecimal => EnigoKey::Decimal,
+        })
     }
 }
 
@@ -112,27 +375,252 @@ pub trait KeyboardController: Send {
     fn press(&mut self, key: Key) -> Result<()>;
     fn release(&mut self, key: Key) -> Result<()>;
     fn release_all(&mut self) -> Result<()>;
+    /// Type `text` as characters (e.g. a chat message), independent of held keys
+    fn type_text(&mut self, text: &str) -> Result<()>;
+
+    /// Press `key`, hold it for `hold`, then release it.
+    /// Backends with a more precise timer can override this.
+    fn tap(&mut self, key: Key, hold: Duration) -> Result<()> {
+        self.press(key)?;
+        std::thread::sleep(hold);
+        self.release(key)
+    }
+
+    /// Release `key` even if this controller didn't press it, e.g. a modifier stuck
+    /// in the OS or held on the physical keyboard. Backends whose `release` skips
+    /// keys they don't track as held must override this.
+    fn force_release(&mut self, key: Key) -> Result<()> {
+        self.release(key)
+    }
 }
 
-/// Enigo-based keyboard controller
-pub struct EnigoKeyboardController {
-    enigo: Enigo,
+impl<K: KeyboardController + ?Sized> KeyboardController for Box<K> {
+    fn press(&mut self, key: Key) -> Result<()> {
+        (**self).press(key)
+    }
+
+    fn release(&mut self, key: Key) -> Result<()> {
+        (**self).release(key)
+    }
+
+    fn release_all(&mut self) -> Result<()> {
+        (**self).release_all()
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<()> {
+        (**self).type_text(text)
+    }
+
+    fn tap(&mut self, key: Key, hold: Duration) -> Result<()> {
+        (**self).tap(key, hold)
+    }
+
+    fn force_release(&mut self, key: Key) -> Result<()> {
+        (**self).force_release(key)
+    }
+}
+
+/// A key press or release, as recorded by `DryRunKeyboardController`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEvent {
+    Press(Key),
+    Release(Key),
+}
+
+impl std::fmt::Display for KeyEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyEvent::Press(key) => write!(f, "+{:?}", key),
+            KeyEvent::Release(key) => write!(f, "-{:?}", key),
+        }
+    }
+}
+
+/// Keyboard controller that records events instead of sending them
+#[derive(Debug, Default)]
+pub struct DryRunKeyboardController {
+    /// Every press/release, in order
+    pub events: Vec<KeyEvent>,
+    /// Every `type_text`, in order
+    pub typed: Vec<String>,
+    pressed: Vec<Key>,
+    /// Log events instead of recording them (see `logging`)
+    log: bool,
+}
+
+impl DryRunKeyboardController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A controller that logs each event (at info level) instead of recording it,
+    /// for trying out a mapping on the device without typing into the focused
+    /// window. Taps still wait out their hold, so the timing matches a real run.
+    pub fn logging() -> Self {
+        Self {
+            log: true,
+            ..Self::default()
+        }
+    }
+
+    /// Take the events recorded so far
+    pub fn take_events(&mut self) -> Vec<KeyEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Keys pressed and not released yet, in the order they went down
+    pub fn pressed(&self) -> &[Key] {
+        &self.pressed
+    }
+
+    fn record(&mut self, event: KeyEvent) {
+        if !self.log {
+            self.events.push(event);
+            return;
+        }
+        match event {
+            KeyEvent::Press(key) => tracing::info!("Dry run: press {:?}", key),
+            KeyEvent::Release(key) => tracing::info!("Dry run: release {:?}", key),
+        }
+    }
+}
+
+impl KeyboardController for DryRunKeyboardController {
+    fn press(&mut self, key: Key) -> Result<()> {
+        self.record(KeyEvent::Press(key));
+        if !self.pressed.contains(&key) {
+            self.pressed.push(key);
+        }
+        Ok(())
+    }
+
+    fn release(&mut self, key: Key) -> Result<()> {
+        self.record(KeyEvent::Release(key));
+        self.pressed.retain(|k| *k != key);
+        Ok(())
+    }
+
+    fn release_all(&mut self) -> Result<()> {
+        for key in std::mem::take(&mut self.pressed) {
+            self.record(KeyEvent::Release(key));
+        }
+        Ok(())
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<()> {
+        if self.log {
+            tracing::info!("Dry run: type {:?}", text);
+        } else {
+            self.typed.push(text.to_string());
+        }
+        Ok(())
+    }
+
+    /// Records the press and release without waiting, unless logging
+    fn tap(&mut self, key: Key, hold: Duration) -> Result<()> {
+        self.press(key)?;
+        if self.log {
+            std::thread::sleep(hold);
+        }
+        self.release(key)
+    }
+}
+
+/// Per-machine tuning for `EnigoKeyboardController`, stored with the mapping
+/// (`MappingConfig::keyboard`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct KeyboardSettings {
+    /// Wait this long after each key press before the next keyboard operation
+    /// (milliseconds), for when the game misses presses that are too short
+    pub key_hold_ms: u64,
+    /// Release keys still held when the controller is dropped
+    pub release_keys_when_dropped: bool,
+}
+
+impl Default for KeyboardSettings {
+    fn default() -> Self {
+        Self {
+            key_hold_ms: 0,
+            release_keys_when_dropped: true,
+        }
+    }
+}
+
+/// Enigo-based keyboard controller. Keys still held when it is dropped are
+/// released (unless `KeyboardSettings::release_keys_when_dropped` is off), so a
+/// panic or a dropped engine can't leave one stuck in the game.
+pub struct EnigoKeyboardController<E: EnigoKeyboard = Enigo> {
+    enigo: E,
+    settings: KeyboardSettings,
     pressed_keys: HashMap<Key, bool>,
+    /// Maps keys to enigo keys; tests swap it to mark a key unsupported
+    lookup: fn(Key) -> Option<EnigoKey>,
 }
 
 impl EnigoKeyboardController {
     pub fn new() -> Result<Self> {
-        let enigo = Enigo::new(&Settings::default())
-            .map_err(|e| Error::Keyboard(format!("Failed to initialize Enigo: {:?}", e)))?;
+        Self::new_with_settings(KeyboardSettings::default())
+    }
 
-        Ok(Self {
+    pub fn new_with_settings(settings: KeyboardSettings) -> Result<Self> {
+        let enigo = Enigo::new(&Settings {
+            release_keys_when_dropped: settings.release_keys_when_dropped,
+            ..Settings::default()
+        })
+        .map_err(|e| Error::Keyboard(format!("Failed to initialize Enigo: {:?}", e)))?;
+
+        Ok(Self::with_backend(enigo, settings))
+    }
+}
+
+impl<E: EnigoKeyboard> EnigoKeyboardController<E> {
+    /// Controller sending keys through `enigo` (a recording backend in tests)
+    pub(crate) fn with_backend(enigo: E, settings: KeyboardSettings) -> Self {
+        Self {
             enigo,
+            settings,
             pressed_keys: HashMap::new(),
-        })
+            lookup: Key::to_enigo_key,
+        }
+    }
+
+    /// `key` as an enigo key, or a descriptive error if this platform can't send it.
+    fn enigo_key(&self, key: Key) -> Result<EnigoKey> {
+        (self.lookup)(key).ok_or(Error::UnsupportedKey(key))
+    }
+
+    fn release_pressed(&mut self) -> Result<()> {
+        tracing::debug!("Releasing all keys");
+
+        for (key, pressed) in &self.pressed_keys {
+            if *pressed {
+                let enigo_key = self.enigo_key(*key)?;
+                self.enigo
+                    .key(enigo_key, Direction::Release)
+                    .map_err(|e| {
+                        Error::Keyboard(format!("Failed to release key {:?}: {:?}", key, e))
+                    })?;
+            }
+        }
+
+        self.pressed_keys.clear();
+        Ok(())
     }
 }
 
-impl KeyboardController for EnigoKeyboardController {
+impl<E: EnigoKeyboard> Drop for EnigoKeyboardController<E> {
+    fn drop(&mut self) {
+        if !self.settings.release_keys_when_dropped {
+            return;
+        }
+        if let Err(e) = self.release_pressed() {
+            tracing::error!("Error releasing keys on shutdown: {}", e);
+        }
+    }
+}
+
+impl<E: EnigoKeyboard + Send> KeyboardController for EnigoKeyboardController<E> {
     fn press(&mut self, key: Key) -> Result<()> {
         // Check if key is already pressed
         if self.pressed_keys.get(&key).copied().unwrap_or(false) {
@@ -141,11 +629,15 @@ impl KeyboardController for EnigoKeyboardController {
 
         tracing::debug!("Pressing key: {:?}", key);
 
+        let enigo_key = self.enigo_key(key)?;
         self.enigo
-            .key(key.to_enigo_key(), Direction::Press)
+            .key(enigo_key, Direction::Press)
             .map_err(|e| Error::Keyboard(format!("Failed to press key {:?}: {:?}", key, e)))?;
 
         self.pressed_keys.insert(key, true);
+        if self.settings.key_hold_ms > 0 {
+            std::thread::sleep(Duration::from_millis(self.settings.key_hold_ms));
+        }
         Ok(())
     }
 
@@ -157,8 +649,22 @@ impl KeyboardController for EnigoKeyboardController {
 
         tracing::debug!("Releasing key: {:?}", key);
 
+        let enigo_key = self.enigo_key(key)?;
         self.enigo
-            .key(key.to_enigo_key(), Direction::Release)
+            .key(enigo_key, Direction::Release)
+            .map_err(|e| Error::Keyboard(format!("Failed to release key {:?}: {:?}", key, e)))?;
+
+        self.pressed_keys.insert(key, false);
+        Ok(())
+    }
+
+    /// Sends the key-up whether or not this controller pressed the key
+    fn force_release(&mut self, key: Key) -> Result<()> {
+        tracing::debug!("Force-releasing key: {:?}", key);
+
+        let enigo_key = self.enigo_key(key)?;
+        self.enigo
+            .key(enigo_key, Direction::Release)
             .map_err(|e| Error::Keyboard(format!("Failed to release key {:?}: {:?}", key, e)))?;
 
         self.pressed_keys.insert(key, false);
@@ -166,19 +672,420 @@ impl KeyboardController for EnigoKeyboardController {
     }
 
     fn release_all(&mut self) -> Result<()> {
-        tracing::debug!("Releasing all keys");
+        self.release_pressed()
+    }
 
-        for (key, pressed) in &self.pressed_keys {
-            if *pressed {
-                self.enigo
-                    .key(key.to_enigo_key(), Direction::Release)
-                    .map_err(|e| {
-                        Error::Keyboard(format!("Failed to release key {:?}: {:?}", key, e))
-                    })?;
+    fn type_text(&mut self, text: &str) -> Result<()> {
+        tracing::debug!("Typing text: {:?}", text);
+
+        self.enigo
+            .text(text)
+            .map_err(|e| Error::Keyboard(format!("Failed to type text {:?}: {:?}", text, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::{self, Deserializer, Visitor};
+
+    /// Deserializer that records the variant names serde derived for an enum.
+    struct VariantProbe<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> Deserializer<'de> for VariantProbe<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(
+            self,
+            _: V,
+        ) -> std::result::Result<V::Value, Self::Error> {
+            Err(de::Error::custom("not an enum"))
+        }
+
+        fn deserialize_enum<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            variants: &'static [&'static str],
+            _visitor: V,
+        ) -> std::result::Result<V::Value, Self::Error> {
+            *self.0 = variants;
+            Err(de::Error::custom("probe"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map struct identifier ignored_any
+        }
+    }
+
+    #[test]
+    fn test_all_keys_matches_variants() {
+        let mut variants: &'static [&'static str] = &[];
+        let _ = <Key as serde::Deserialize>::deserialize(VariantProbe(&mut variants));
+
+        assert_eq!(Key::all().len(), variants.len());
+        let names: Vec<String> = Key::all().iter().map(|k| format!("{:?}", k)).collect();
+        assert_eq!(names, variants);
+
+        for category in [
+            Key::letters(),
+            Key::numbers(),
+            Key::function_keys(),
+            Key::punctuation(),
+            Key::numpad(),
+            Key::modifiers(),
+        ] {
+            assert!(category.iter().all(|k| Key::all().contains(k)));
+        }
+        assert_eq!(Key::letters().last(), Some(&Key::Z));
+        assert_eq!(Key::numbers().last(), Some(&Key::Num9));
+        assert_eq!(Key::function_keys().last(), Some(&Key::F12));
+        assert_eq!(Key::punctuation().last(), Some(&Key::Backslash));
+        assert!(Key::numpad().contains(&Key::NumpadEnter));
+    }
+
+    #[test]
+    fn test_modifier_side_keys() {
+        assert_eq!(ModifierSide::Generic.shift(), Key::Shift);
+        assert_eq!(ModifierSide::Left.control(), Key::LControl);
+        assert_eq!(ModifierSide::Right.alt(), Key::RAlt);
+
+        assert_eq!(ModifierSide::Right.shift().to_enigo_key(), Some(EnigoKey::RShift));
+        assert_eq!(ModifierSide::Right.control().to_enigo_key(), Some(EnigoKey::RControl));
+        assert_eq!(ModifierSide::Generic.shift().to_enigo_key(), Some(EnigoKey::Shift));
+    }
+
+    /// Controller relying on the default `tap`, recording when each event happened.
+    #[derive(Default)]
+    struct TimedKeyboard {
+        events: Vec<(mock::KeyEvent, std::time::Instant)>,
+    }
+
+    impl KeyboardController for TimedKeyboard {
+        fn press(&mut self, key: Key) -> Result<()> {
+            let now = std::time::Instant::now();
+            self.events.push((mock::KeyEvent::Press(key), now));
+            Ok(())
+        }
+
+        fn release(&mut self, key: Key) -> Result<()> {
+            let now = std::time::Instant::now();
+            self.events.push((mock::KeyEvent::Release(key), now));
+            Ok(())
+        }
+
+        fn release_all(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn type_text(&mut self, _text: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tap_presses_holds_and_releases() {
+        let hold = Duration::from_millis(20);
+
+        let mut kb = TimedKeyboard::default();
+        kb.tap(Key::Q, hold).unwrap();
+        let [(first, pressed_at), (second, released_at)] = kb.events[..] else {
+            panic!("expected exactly two events, got {}", kb.events.len());
+        };
+        assert_eq!(first, mock::KeyEvent::Press(Key::Q));
+        assert_eq!(second, mock::KeyEvent::Release(Key::Q));
+        assert!(released_at - pressed_at >= hold);
+
+        let mut kb = mock::MockKeyboard::new();
+        kb.tap(Key::E, hold).unwrap();
+        assert_eq!(
+            kb.events,
+            vec![mock::KeyEvent::Press(Key::E), mock::KeyEvent::Release(Key::E)]
+        );
+        assert_eq!(kb.taps, vec![(Key::E, hold)]);
+        assert!(kb.pressed.is_empty());
+    }
+
+    #[test]
+    fn test_logging_dry_run_tracks_held_keys() {
+        let mut kb = DryRunKeyboardController::logging();
+        kb.press(Key::Shift).unwrap();
+        kb.press(Key::Q).unwrap();
+        kb.press(Key::Q).unwrap();
+        assert_eq!(kb.pressed(), [Key::Shift, Key::Q]);
+
+        kb.release(Key::Q).unwrap();
+        kb.tap(Key::E, Duration::ZERO).unwrap();
+        assert_eq!(kb.pressed(), [Key::Shift]);
+
+        kb.release_all().unwrap();
+        assert!(kb.pressed().is_empty());
+        // Logged, not recorded
+        assert!(kb.events.is_empty());
+    }
+
+    #[test]
+    fn test_all_keys_supported() {
+        // Every key has an enigo equivalent on the platforms we build for,
+        // except numpad Enter on Windows
+        let kb = EnigoKeyboardController::with_backend(
+            mock::RecordingEnigo::default(),
+            KeyboardSettings::default(),
+        );
+        for key in Key::all() {
+            if cfg!(target_os = "windows") && *key == Key::NumpadEnter {
+                assert!(matches!(
+                    kb.enigo_key(*key),
+                    Err(Error::UnsupportedKey(Key::NumpadEnter))
+                ));
+                continue;
             }
+            assert!(key.is_supported(), "{:?}", key);
+            assert!(kb.enigo_key(*key).is_ok());
         }
+    }
 
-        self.pressed_keys.clear();
-        Ok(())
+    #[test]
+    fn test_enigo_unsupported_key_error() {
+        let backend = mock::RecordingEnigo::default();
+        let mut kb =
+            EnigoKeyboardController::with_backend(backend.clone(), KeyboardSettings::default());
+        // Stand in for a platform without F12
+        kb.lookup = |key| if key == Key::F12 { None } else { key.to_enigo_key() };
+
+        let err = kb.press(Key::F12).unwrap_err();
+        assert!(matches!(err, Error::UnsupportedKey(Key::F12)));
+        assert_eq!(
+            err.to_string(),
+            "Key F12 can't be sent by the keyboard backend on this platform"
+        );
+        let err = kb.force_release(Key::F12).unwrap_err();
+        assert!(matches!(err, Error::UnsupportedKey(Key::F12)));
+        assert!(backend.sent().is_empty());
+
+        // Other keys still go through, and the failed key isn't tracked as held
+        kb.press(Key::Q).unwrap();
+        kb.release_all().unwrap();
+        assert_eq!(
+            backend.sent(),
+            vec![
+                (EnigoKey::Unicode('q'), Direction::Press),
+                (EnigoKey::Unicode('q'), Direction::Release),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_enter_keys() {
+        for key in [Key::Enter, Key::NumpadEnter] {
+            let json = serde_json::to_string(&key).unwrap();
+            assert_eq!(serde_json::from_str::<Key>(&json).unwrap(), key);
+        }
+        assert_eq!(serde_json::to_string(&Key::NumpadEnter).unwrap(), "\"NumpadEnter\"");
+
+        assert_eq!(Key::Enter.to_enigo_key(), Some(EnigoKey::Return));
+        assert_ne!(Key::NumpadEnter.to_enigo_key(), Some(EnigoKey::Return));
+    }
+
+    #[test]
+    fn test_punctuation_keys() {
+        let json = serde_json::to_string(&Key::OpenBracket).unwrap();
+        assert_eq!(json, "\"OpenBracket\"");
+        for key in Key::punctuation() {
+            let json = serde_json::to_string(key).unwrap();
+            assert_eq!(serde_json::from_str::<Key>(&json).unwrap(), *key);
+        }
+
+        assert_eq!(Key::Minus.to_enigo_key(), Some(EnigoKey::Unicode('-')));
+        assert_eq!(Key::Backslash.to_enigo_key(), Some(EnigoKey::Unicode('\\')));
+        assert_eq!(Key::Quote.to_enigo_key(), Some(EnigoKey::Unicode('\'')));
+    }
+
+    #[test]
+    fn test_numpad_keys() {
+        // Numpad digits are separate keys from the top-row digits
+        assert_eq!(Key::Numpad1.to_enigo_key(), Some(EnigoKey::Numpad1));
+        assert_ne!(Key::Numpad1.to_enigo_key(), Key::Num1.to_enigo_key());
+        assert_eq!(Key::NumpadAdd.to_enigo_key(), Some(EnigoKey::Add));
+        assert_eq!(Key::NumpadDecimal.to_enigo_key(), Some(EnigoKey::Decimal));
+
+        assert_eq!(serde_json::to_string(&Key::Numpad7).unwrap(), "\"Numpad7\"");
+        assert_eq!(serde_json::from_str::<Key>("\"NumpadDivide\"").unwrap(), Key::NumpadDivide);
+    }
+
+    #[test]
+    fn test_enigo_force_release_sends_untracked_keys() {
+        let backend = mock::RecordingEnigo::default();
+        let mut kb =
+            EnigoKeyboardController::with_backend(backend.clone(), KeyboardSettings::default());
+        // `release` skips a key this controller never pressed; `force_release` doesn't
+        kb.release(Key::Shift).unwrap();
+        assert!(backend.sent().is_empty());
+        kb.force_release(Key::Shift).unwrap();
+        assert_eq!(backend.sent(), vec![(EnigoKey::Shift, Direction::Release)]);
+
+        // A force-released key isn't released again on drop
+        kb.press(Key::Control).unwrap();
+        kb.force_release(Key::Control).unwrap();
+        drop(kb);
+        assert_eq!(backend.sent().len(), 3);
+    }
+
+    #[test]
+    fn test_enigo_controller_releases_on_drop() {
+        let backend = mock::RecordingEnigo::default();
+        let mut kb =
+            EnigoKeyboardController::with_backend(backend.clone(), KeyboardSettings::default());
+        kb.press(Key::Shift).unwrap();
+        kb.press(Key::Q).unwrap();
+        kb.press(Key::E).unwrap();
+        kb.release(Key::E).unwrap();
+        drop(kb);
+
+        let keys = backend.keys.lock().unwrap();
+        let released: Vec<_> = keys[4..].iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys.len(), 6);
+        assert!(keys[4..].iter().all(|(_, direction)| *direction == Direction::Release));
+        assert!(released.contains(&EnigoKey::Shift));
+        assert!(released.contains(&EnigoKey::Unicode('q')));
+    }
+
+    #[test]
+    fn test_keyboard_settings() {
+        let settings = KeyboardSettings {
+            key_hold_ms: 20,
+            release_keys_when_dropped: false,
+        };
+        let backend = mock::RecordingEnigo::default();
+        let mut kb = EnigoKeyboardController::with_backend(backend.clone(), settings);
+        let start = std::time::Instant::now();
+        kb.press(Key::Q).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        drop(kb);
+        assert_eq!(backend.keys.lock().unwrap().len(), 1);
+
+        // Missing fields keep their defaults
+        let settings: KeyboardSettings = serde_json::from_str(r#"{"key_hold_ms": 15}"#).unwrap();
+        assert_eq!(settings.key_hold_ms, 15);
+        assert!(settings.release_keys_when_dropped);
+    }
+}
+
+/// Recording keyboard controller for unit tests.
+#[cfg(test)]
+pub(crate) mod mock {
+    pub(crate) use super::KeyEvent;
+    use super::{Direction, EnigoKey, EnigoKeyboard, Key, KeyboardController};
+    use crate::error::Result;
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// Enigo backend recording key events instead of sending them. Clones share
+    /// the recording.
+    #[derive(Default, Clone)]
+    pub(crate) struct RecordingEnigo {
+        pub(crate) keys: Arc<Mutex<Vec<(EnigoKey, Direction)>>>,
+    }
+
+    impl RecordingEnigo {
+        /// Key events sent so far, oldest first
+        pub(crate) fn sent(&self) -> Vec<(EnigoKey, Direction)> {
+            self.keys.lock().unwrap().clone()
+        }
+    }
+
+    impl EnigoKeyboard for RecordingEnigo {
+        fn fast_text(&mut self, _text: &str) -> enigo::InputResult<Option<()>> {
+            Ok(Some(()))
+        }
+
+        fn key(&mut self, key: EnigoKey, direction: Direction) -> enigo::InputResult<()> {
+            self.keys.lock().unwrap().push((key, direction));
+            Ok(())
+        }
+
+        fn raw(&mut self, _keycode: u16, _direction: Direction) -> enigo::InputResult<()> {
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    pub(crate) struct MockKeyboard {
+        pub(crate) events: Vec<KeyEvent>,
+        pub(crate) pressed: HashSet<Key>,
+        /// Hold duration requested by each `tap`, in order
+        pub(crate) taps: Vec<(Key, Duration)>,
+        /// Text passed to each `type_text`, in order
+        pub(crate) typed: Vec<String>,
+    }
+
+    impl MockKeyboard {
+        pub(crate) fn new() -> Self {
+            Self::default()
+        }
+
+        /// Number of times `key` was pressed.
+        pub(crate) fn press_count(&self, key: Key) -> usize {
+            self.events
+                .iter()
+                .filter(|e| **e == KeyEvent::Press(key))
+                .count()
+        }
+    }
+
+    impl KeyboardController for MockKeyboard {
+        fn press(&mut self, key: Key) -> Result<()> {
+            self.events.push(KeyEvent::Press(key));
+            self.pressed.insert(key);
+            Ok(())
+        }
+
+        fn release(&mut self, key: Key) -> Result<()> {
+            self.events.push(KeyEvent::Release(key));
+            self.pressed.remove(&key);
+            Ok(())
+        }
+
+        /// Records the hold instead of sleeping so tests stay instant.
+        fn tap(&mut self, key: Key, hold: Duration) -> Result<()> {
+            self.press(key)?;
+            self.taps.push((key, hold));
+            self.release(key)
+        }
+
+        fn release_all(&mut self) -> Result<()> {
+            let mut keys: Vec<Key> = self.pressed.drain().collect();
+            keys.sort_by_key(|k| format!("{:?}", k));
+            for key in keys {
+                self.events.push(KeyEvent::Release(key));
+            }
+            Ok(())
+        }
+
+        fn type_text(&mut self, text: &str) -> Result<()> {
+            self.typed.push(text.to_string());
+            Ok(())
+        }
+    }
+
+    /// Lets a test keep inspecting a mock after handing it to an engine.
+    impl KeyboardController for Arc<Mutex<MockKeyboard>> {
+        fn press(&mut self, key: Key) -> Result<()> {
+            self.lock().unwrap().press(key)
+        }
+
+        fn release(&mut self, key: Key) -> Result<()> {
+            self.lock().unwrap().release(key)
+        }
+
+        fn release_all(&mut self) -> Result<()> {
+            self.lock().unwrap().release_all()
+        }
+
+        fn type_text(&mut self, text: &str) -> Result<()> {
+            self.lock().unwrap().type_text(text)
+        }
     }
 }
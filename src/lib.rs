@@ -1,4 +1,5 @@
 pub mod error;
+pub mod focus;
 pub mod keyboard;
 pub mod mapping;
 pub mod midi;
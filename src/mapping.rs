@@ -1,7 +1,10 @@
-use crate::keyboard::Key;
-use crate::midi::MidiNote;
+use crate::keyboard::{Key, KeyboardSettings, ModifierSide};
+use crate::midi::{MessageFilter, MidiNote, ZeroVelocityNoteOn, PITCH_BEND_CENTER};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 /// Action to perform when a MIDI event occurs
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -13,7 +16,92 @@ pub enum Action {
     /// Wait for a duration
     Delay(u64), // milliseconds
     /// Set modifiers for the following actions
-    SetModifiers { shift: bool, ctrl: bool, alt: bool },
+    SetModifiers {
+        shift: bool,
+        ctrl: bool,
+        alt: bool,
+        /// Left/right modifier keys to use, overriding the engine-wide setting
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        side: Option<ModifierSide>,
+    },
+    /// Write a marker to the log (debugging aid, doesn't touch the keyboard)
+    Log(String),
+    /// Run `actions` `count` times in a row
+    Repeat { count: u32, actions: Vec<Action> },
+    /// Release the key of the note currently playing, leaving modifiers held
+    /// (e.g. on a dedicated "cut" note)
+    ReleaseCurrent,
+    /// Press several keys at once (e.g. a macro bound to a key combo). They are
+    /// held together and released together by the next note or the note-off.
+    PressChord(Vec<Key>),
+    /// Type a whole string, e.g. a chat message or slash command. Held modifiers
+    /// are released first; a held note key stays down.
+    TypeText(String),
+    /// Press a key if it isn't toggled on, release it if it is: the key stays
+    /// down between runs, e.g. a sustained note held until the note plays again
+    Toggle(Key),
+    /// Tap `key` now, then again every `interval_ms` (at least
+    /// `MIN_TREMOLO_INTERVAL_MS`) until the note's note-off, e.g. for a trill.
+    /// Only repeats from `on_press` (or a `cycle` step); a new note stops it.
+    RepeatHeld { key: Key, interval_ms: u64 },
+    /// Switch to layer n of `MappingConfig::layers` (1 = the first), or back to the
+    /// primary mappings with 0. It stays active across notes until the next switch,
+    /// e.g. a pad that moves the melody notes to another hotbar.
+    SwitchLayer(u8),
+}
+
+impl std::fmt::Display for Action {
+    /// Short description for lists and tables, e.g. "Press: Q", "SetModifiers: Shift + Ctrl"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::Press(key) => write!(f, "Press: {:?}", key),
+            Action::Release(key) => write!(f, "Release: {:?}", key),
+            Action::Delay(ms) => write!(f, "Delay: {}ms", ms),
+            Action::SetModifiers {
+                shift,
+                ctrl,
+                alt,
+                side,
+            } => {
+                let mut parts = Vec::new();
+                if *shift {
+                    parts.push("Shift");
+                }
+                if *ctrl {
+                    parts.push("Ctrl");
+                }
+                if *alt {
+                    parts.push("Alt");
+                }
+                if parts.is_empty() {
+                    write!(f, "SetModifiers: None")?;
+                } else {
+                    write!(f, "SetModifiers: {}", parts.join(" + "))?;
+                }
+                match side {
+                    Some(side) => write!(f, " ({:?})", side),
+                    None => Ok(()),
+                }
+            }
+            Action::Log(message) => write!(f, "Log: {}", message),
+            Action::Repeat { count, actions } => {
+                let inner: Vec<String> = actions.iter().map(Action::to_string).collect();
+                write!(f, "Repeat ×{}: [{}]", count, inner.join(", "))
+            }
+            Action::ReleaseCurrent => write!(f, "Release current note"),
+            Action::PressChord(keys) => {
+                let keys: Vec<String> = keys.iter().map(|key| format!("{:?}", key)).collect();
+                write!(f, "Chord: {}", keys.join(" + "))
+            }
+            Action::TypeText(text) => write!(f, "Type text: {:?}", text),
+            Action::Toggle(key) => write!(f, "Toggle: {:?}", key),
+            Action::RepeatHeld { key, interval_ms } => {
+                write!(f, "Repeat while held: {:?} every {}ms", key, interval_ms)
+            }
+            Action::SwitchLayer(0) => write!(f, "Switch to primary mappings"),
+            Action::SwitchLayer(layer) => write!(f, "Switch to layer {}", layer),
+        }
+    }
 }
 
 /// Mapping from a MIDI note to keyboard actions
@@ -23,6 +111,26 @@ pub struct NoteMapping {
     pub on_press: Vec<Action>,
     /// Actions to perform when note is released
     pub on_release: Vec<Action>,
+    /// Release actions picked by note-off (release) velocity, replacing `on_release`
+    /// (see `release_actions`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub release_layers: Vec<ReleaseLayer>,
+    /// Re-tap the pressed key while held, faster for harder hits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub velocity_tremolo: Option<VelocityTremolo>,
+    /// Rotating press actions: each note-on runs the next list instead of `on_press`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cycle: Vec<Vec<Action>>,
+    /// Restart `cycle` from the first step after this long without a press (milliseconds)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cycle_reset_ms: Option<u64>,
+    /// Disabled mappings are kept but ignored by the engine
+    #[serde(default = "enabled_by_default", skip_serializing_if = "is_enabled")]
+    pub enabled: bool,
+    /// Run `on_press` on note-off and `on_release` on note-on, for "normally
+    /// closed" gate controls (e.g. foot switches) that send note-off when pressed
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub invert: bool,
 }
 
 impl Default for NoteMapping {
@@ -30,99 +138,1084 @@ impl Default for NoteMapping {
         Self {
             on_press: Vec::new(),
             on_release: Vec::new(),
+            release_layers: Vec::new(),
+            velocity_tremolo: None,
+            cycle: Vec::new(),
+            cycle_reset_ms: None,
+            enabled: true,
+            invert: false,
         }
     }
 }
 
+impl NoteMapping {
+    /// Call `f` with every key a `Press`, `Release` or `PressChord` in this mapping
+    /// names, including inside cycles and repeats
+    pub fn for_each_key(&self, f: &mut impl FnMut(Key)) {
+        self.for_each_action(&mut |action| match action {
+            Action::Press(key)
+            | Action::Release(key)
+            | Action::Toggle(key)
+            | Action::RepeatHeld { key, .. } => f(*key),
+            Action::PressChord(keys) => keys.iter().for_each(|key| f(*key)),
+            _ => {}
+        });
+    }
+
+    /// Call `f` with every action in this mapping, including those inside cycles
+    /// and repeats (after the `Repeat` itself)
+    pub fn for_each_action(&self, f: &mut impl FnMut(&Action)) {
+        fn visit(actions: &[Action], f: &mut impl FnMut(&Action)) {
+            for action in actions {
+                f(action);
+                if let Action::Repeat { actions, .. } = action {
+                    visit(actions, f);
+                }
+            }
+        }
+        visit(&self.on_press, f);
+        visit(&self.on_release, f);
+        for layer in &self.release_layers {
+            visit(&layer.on_release, f);
+        }
+        for step in &self.cycle {
+            visit(step, f);
+        }
+    }
+
+    /// Actions to run on a note-off with release `velocity`: the release layer
+    /// with the highest `min_velocity` the velocity reaches, else `on_release`.
+    pub fn release_actions(&self, velocity: u8) -> &[Action] {
+        self.release_layers
+            .iter()
+            .filter(|layer| velocity >= layer.min_velocity)
+            .max_by_key(|layer| layer.min_velocity)
+            .map_or(&self.on_release, |layer| &layer.on_release)
+    }
+
+    /// Drop `SetModifiers` actions that can't change the resulting modifier state.
+    /// Returns how many were removed.
+    pub fn normalize(&mut self) -> usize {
+        let mut removed = normalize_actions(&mut self.on_press);
+        removed += normalize_actions(&mut self.on_release);
+        for layer in &mut self.release_layers {
+            removed += normalize_actions(&mut layer.on_release);
+        }
+        for step in &mut self.cycle {
+            removed += normalize_actions(step);
+        }
+        removed
+    }
+
+    /// Likely mistakes in this note's actions, for `MappingConfig::validate`
+    fn problems(&self) -> Vec<String> {
+        fn visit(actions: &[Action], f: &mut impl FnMut(&Action)) {
+            for action in actions {
+                match action {
+                    Action::Repeat { actions, .. } => visit(actions, f),
+                    action => f(action),
+                }
+            }
+        }
+
+        let mut problems = Vec::new();
+        let mut unsupported = Vec::new();
+        self.for_each_key(&mut |key| {
+            if !key.is_supported() && !unsupported.contains(&key) {
+                unsupported.push(key);
+            }
+        });
+        for key in unsupported {
+            problems.push(format!("key {:?} can't be sent on this platform", key));
+        }
+
+        let mut pressed = Vec::new();
+        let mut sets_modifiers = false;
+        let mut types_text = false;
+        visit(&self.on_press, &mut |action| match action {
+            Action::Press(key) => pressed.push(*key),
+            Action::PressChord(keys) => pressed.extend(keys),
+            Action::SetModifiers { .. } => sets_modifiers = true,
+            Action::TypeText(_) => types_text = true,
+            _ => {}
+        });
+        if !pressed.is_empty() && pressed.iter().all(|k| k.is_modifier()) {
+            problems.push(format!(
+                "on_press only presses modifier {:?}; it is held until the note's note-off",
+                pressed[0]
+            ));
+        }
+        if sets_modifiers && pressed.is_empty() && self.cycle.is_empty() {
+            problems.push("on_press sets modifiers but presses no key".to_string());
+        }
+        if types_text && !pressed.is_empty() {
+            problems
+                .push("on_press types text and presses a key; only the key is pressed".to_string());
+        }
+
+        // Keys pressed twice in one list without a release in between
+        let mut doubled = Vec::new();
+        for actions in std::iter::once(&self.on_press).chain(&self.cycle) {
+            let mut held = HashSet::new();
+            visit(actions, &mut |action| {
+                let keys = match action {
+                    Action::Press(key) => std::slice::from_ref(key),
+                    Action::PressChord(keys) => keys.as_slice(),
+                    Action::Release(key) => {
+                        held.remove(key);
+                        return;
+                    }
+                    _ => return,
+                };
+                for key in keys {
+                    if !held.insert(*key) && !doubled.contains(key) {
+                        doubled.push(*key);
+                    }
+                }
+            });
+        }
+        for key in doubled {
+            problems.push(format!("presses {:?} again without releasing it", key));
+        }
+
+        // Releases of keys no press list ever presses
+        for step in &self.cycle {
+            visit(step, &mut |action| match action {
+                Action::Press(key) => pressed.push(*key),
+                Action::PressChord(keys) => pressed.extend(keys),
+                _ => {}
+            });
+        }
+        let mut stray = Vec::new();
+        let release_lists = std::iter::once(&self.on_press)
+            .chain(&self.cycle)
+            .chain(std::iter::once(&self.on_release))
+            .chain(self.release_layers.iter().map(|layer| &layer.on_release));
+        for actions in release_lists {
+            visit(actions, &mut |action| {
+                if let Action::Release(key) = action
+                    && !pressed.contains(key)
+                    && !stray.contains(key)
+                {
+                    stray.push(*key);
+                }
+            });
+        }
+        for key in stray {
+            problems.push(format!("releases {:?}, which it never presses", key));
+        }
+        problems
+    }
+
+    /// Replace the note's top-level `SetModifiers` with `mods`, laid out like
+    /// `MappingLayout::build`: set before the press, cleared after the release.
+    pub fn set_zone_modifiers(&mut self, mods: ZoneModifiers) {
+        let is_set_modifiers = |a: &Action| matches!(a, Action::SetModifiers { .. });
+        self.on_press.retain(|a| !is_set_modifiers(a));
+        let releases = std::iter::once(&mut self.on_release)
+            .chain(self.release_layers.iter_mut().map(|l| &mut l.on_release));
+        for on_release in releases {
+            on_release.retain(|a| !is_set_modifiers(a));
+            if !mods.is_none() {
+                on_release.push(Action::SetModifiers {
+                    shift: false,
+                    ctrl: false,
+                    alt: false,
+                    side: None,
+                });
+            }
+        }
+        if mods.is_none() {
+            return;
+        }
+        self.on_press.insert(
+            0,
+            Action::SetModifiers {
+                shift: mods.shift,
+                ctrl: mods.ctrl,
+                alt: mods.alt,
+                side: None,
+            },
+        );
+    }
+}
+
+/// A likely mistake found by `MappingConfig::validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationWarning {
+    /// Table the note is in, e.g. "layer 1" or "channel 9"; `None` for `mappings`
+    pub table: Option<String>,
+    /// Mapped note number; `None` for warnings about the whole file
+    pub note: Option<u8>,
+    pub message: String,
+}
+
+impl ValidationWarning {
+    fn file(message: String) -> Self {
+        Self {
+            table: None,
+            note: None,
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(table) = &self.table {
+            write!(f, "{} ", table)?;
+        }
+        match self.note.map(|value| (value, MidiNote::new(value))) {
+            Some((value, Ok(note))) => write!(f, "note {} ({}): ", note, value)?,
+            Some((value, Err(_))) => write!(f, "note {}: ", value)?,
+            None => {}
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// `on_release` used instead of the note's own for note-offs released at
+/// `min_velocity` or harder, e.g. a hard release that presses a cancel key.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReleaseLayer {
+    /// Lowest release velocity that picks this layer (1-127)
+    pub min_velocity: u8,
+    pub on_release: Vec<Action>,
+}
+
+/// Remove a `SetModifiers` directly followed by another (only the last one counts)
+/// and one that repeats the state the previous `SetModifiers` already set. The
+/// state before the list is unknown, so the first one is always kept.
+fn normalize_actions(actions: &mut Vec<Action>) -> usize {
+    let before = actions.len();
+    let mut kept: Vec<Action> = Vec::with_capacity(actions.len());
+    // Modifier state set earlier in this list, if known
+    let mut state = None;
+    for mut action in actions.drain(..) {
+        match &mut action {
+            Action::SetModifiers {
+                shift,
+                ctrl,
+                alt,
+                side,
+            } => {
+                let set = (*shift, *ctrl, *alt, *side);
+                if state == Some(set) {
+                    continue;
+                }
+                if matches!(kept.last(), Some(Action::SetModifiers { .. })) {
+                    kept.pop();
+                }
+                state = Some(set);
+            }
+            Action::Repeat { actions, .. } => {
+                normalize_actions(actions);
+                // The repeated actions may have changed the modifiers
+                if actions
+                    .iter()
+                    .any(|a| matches!(a, Action::SetModifiers { .. } | Action::Repeat { .. }))
+                {
+                    state = None;
+                }
+            }
+            _ => {}
+        }
+        kept.push(action);
+    }
+    *actions = kept;
+    before - actions.len()
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+fn is_enabled(enabled: &bool) -> bool {
+    *enabled
+}
+
+/// Shortest tremolo interval we allow, so the game can still tell taps apart.
+pub const MIN_TREMOLO_INTERVAL_MS: u64 = 10;
+
+/// Longest tremolo interval we allow.
+pub const MAX_TREMOLO_INTERVAL_MS: u64 = 1000;
+
+/// Velocity-driven tremolo: notes hit at or above `threshold` repeatedly tap
+/// their key while held; softer notes are a single sustained press.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VelocityTremolo {
+    /// Minimum velocity that starts a tremolo
+    pub threshold: u8,
+    /// Repeat interval at `threshold` velocity (milliseconds)
+    pub slowest_ms: u64,
+    /// Repeat interval at velocity 127 (milliseconds)
+    pub fastest_ms: u64,
+}
+
+impl Default for VelocityTremolo {
+    fn default() -> Self {
+        Self {
+            threshold: 64,
+            slowest_ms: 200,
+            fastest_ms: 50,
+        }
+    }
+}
+
+impl VelocityTremolo {
+    /// Repeat interval for a note-on velocity, or `None` for a sustained press.
+    /// Linearly interpolates from `slowest_ms` at `threshold` to `fastest_ms`
+    /// at 127, clamped to `MIN_TREMOLO_INTERVAL_MS..=MAX_TREMOLO_INTERVAL_MS`.
+    pub fn interval(&self, velocity: u8) -> Option<Duration> {
+        let velocity = velocity.min(127);
+        if velocity == 0 || velocity < self.threshold {
+            return None;
+        }
+
+        let span = 127u64.saturating_sub(self.threshold as u64);
+        let t = if span == 0 {
+            1.0
+        } else {
+            (velocity - self.threshold) as f64 / span as f64
+        };
+        let slowest = self.slowest_ms as f64;
+        let fastest = self.fastest_ms as f64;
+        let ms = (slowest + (fastest - slowest) * t).round() as u64;
+
+        Some(Duration::from_millis(
+            ms.clamp(MIN_TREMOLO_INTERVAL_MS, MAX_TREMOLO_INTERVAL_MS),
+        ))
+    }
+}
+
+/// Mapping file format version written by this build. Bump it when adding fields
+/// an older build would silently drop on save.
+pub const MAPPING_VERSION: u32 = 2;
+
+/// Controller number of the sustain (damper) pedal
+pub const SUSTAIN_CONTROLLER: u8 = 64;
+
 /// MIDI to keyboard mapping configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MappingConfig {
+    /// File format version (`MAPPING_VERSION`); 0 for files from before versioning
+    #[serde(default)]
+    pub version: u32,
     /// Channel to listen to (0-15, None = all channels)
     pub channel: Option<u8>,
     /// Note mappings
     pub mappings: HashMap<u8, NoteMapping>,
+    /// Note tables for single channels (0-15), looked up before `mappings` for notes
+    /// on that channel. Their channels are listened to whatever `channel` says.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub channel_mappings: HashMap<u8, HashMap<u8, NoteMapping>>,
     /// Whether to transpose out-of-range notes by octaves to fit within the mapped range
     #[serde(default)]
     pub octave_transpose: bool,
+    /// Semitones added to every incoming note before lookup (and before octave
+    /// transposition). Notes shifted outside 0-127 have no mapping.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub transpose_semitones: i8,
+    /// MIDI output port to forward processed (filtered/transposed) notes to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thru_port: Option<String>,
+    /// How note-ons with velocity 0 are handled (read when connecting)
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub zero_velocity_note_on: ZeroVelocityNoteOn,
+    /// Overlay tables stacked on `mappings`, bottom to top (see `resolve_layer`).
+    /// A single object under the old `layer` key loads as one layer.
+    #[serde(
+        default,
+        alias = "layer",
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub layers: Vec<MappingLayer>,
+    /// Multiplier for every `Action::Delay` (1.0 = as written), e.g. 2.0 to play
+    /// macros at half speed. Clamped to `DELAY_SCALE_RANGE`.
+    #[serde(
+        default = "unit_delay_scale",
+        skip_serializing_if = "is_unit_delay_scale"
+    )]
+    pub delay_scale: f32,
+    /// Minimum gap between note-on keypresses (milliseconds), overriding the
+    /// engine's timing preset, e.g. for a setup where the game drops rapid repeats
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_note_gap_ms: Option<u64>,
+    /// Keys held while a controller is on (value 64 or more), by controller number,
+    /// e.g. the sustain pedal (64) holding a modifier
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub cc_keys: HashMap<u8, Key>,
+    /// Keep the last note's key down after its note-off while the sustain pedal
+    /// (`SUSTAIN_CONTROLLER`) is down, until the pedal lifts. A new note still
+    /// replaces it.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub sustain_pedal: bool,
+    /// Incoming message types to process (read when connecting). Notes only by
+    /// default; see `message_filter` for controllers the mapping itself uses.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub listen: MessageFilter,
+    /// Messages sent back to the controller while a note's key is held
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feedback: Option<Feedback>,
+    /// Keyboard backend tuning (read when connecting)
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub keyboard: KeyboardSettings,
+    /// Actions run when the pitch wheel is bent past a threshold
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pitch_bend: Option<PitchBendMapping>,
+    /// Fields in the loaded file this build doesn't know, e.g. "note 60: glide".
+    /// They are dropped on save.
+    #[serde(skip)]
+    pub unknown_fields: Vec<String>,
+}
+
+/// Control input that activates a mapping layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LayerTrigger {
+    /// A note, held between its note-on and note-off
+    Note(u8),
+    /// A controller (e.g. 64 for a sustain pedal), held while its value is 64 or more
+    ControlChange(u8),
+    /// No control input: only `Action::SwitchLayer` activates the layer
+    Switch,
+}
+
+/// How a layer's trigger activates it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LayerActivation {
+    /// Active while the trigger is held
+    #[default]
+    Hold,
+    /// Each press of the trigger switches the layer on or off; releasing it does nothing
+    Toggle,
+}
+
+/// Overlay mappings that take precedence over the tables below while active.
+/// Notes the layer doesn't map fall through.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MappingLayer {
+    pub trigger: LayerTrigger,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub activation: LayerActivation,
+    pub mappings: HashMap<u8, NoteMapping>,
+}
+
+/// Accepts either a single layer (the old `layer` field) or a list of them.
+fn one_or_many<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<MappingLayer>, D::Error> {
+    // Buffered as a `Value` rather than an untagged enum, which can't parse the
+    // numeric note keys
+    let layers = match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Null => Ok(Vec::new()),
+        value @ serde_json::Value::Array(_) => serde_json::from_value(value),
+        value => serde_json::from_value(value).map(|layer| vec![layer]),
+    };
+    layers.map_err(serde::de::Error::custom)
+}
+
+/// MIDI messages sent back to the controller while a note's key is held, e.g. to
+/// light its pad's LED
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Feedback {
+    /// Output port to send to, usually the controller's own MIDI out (read when connecting)
+    pub port: String,
+    /// Channel of the feedback messages
+    #[serde(default)]
+    pub channel: u8,
+    /// Velocity of the note-on sent when the key is pressed (often picks the LED colour)
+    #[serde(default = "full_velocity")]
+    pub on_velocity: u8,
+    /// Velocity of the note-on sent when the key is released (0 turns most LEDs off)
+    #[serde(default)]
+    pub off_velocity: u8,
+    /// Feedback note per incoming note, for pads whose LED answers to a different
+    /// note than the pad sends. Unlisted notes light themselves.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub notes: HashMap<u8, u8>,
+}
+
+impl Feedback {
+    /// The (key pressed, key released) messages for incoming `note`.
+    pub fn messages(&self, note: u8) -> ([u8; 3], [u8; 3]) {
+        let status = 0x90 | (self.channel & 0x0F);
+        let note = self.notes.get(&note).copied().unwrap_or(note) & 0x7F;
+        (
+            [status, note, self.on_velocity & 0x7F],
+            [status, note, self.off_velocity & 0x7F],
+        )
+    }
+}
+
+fn full_velocity() -> u8 {
+    127
+}
+
+/// Direction the pitch wheel is bent in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BendDirection {
+    Up,
+    Down,
+}
+
+/// Actions for bending the pitch wheel up or down. Each fires once when the wheel
+/// passes its threshold, and again only after the wheel has come back to within
+/// half the threshold of center; keys the actions left pressed are released then.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PitchBendMapping {
+    /// How far above center (1-8191) the wheel must go to run `up`
+    pub up_threshold: u16,
+    pub up: Vec<Action>,
+    /// How far below center (1-8192) the wheel must go to run `down`
+    pub down_threshold: u16,
+    pub down: Vec<Action>,
+}
+
+impl Default for PitchBendMapping {
+    fn default() -> Self {
+        Self {
+            up_threshold: 4096,
+            up: Vec::new(),
+            down_threshold: 4096,
+            down: Vec::new(),
+        }
+    }
+}
+
+impl PitchBendMapping {
+    /// The direction whose threshold pitch bend `value` (relative to center, as in
+    /// `MidiEventType::PitchBend`) reaches, if that direction has actions
+    pub fn triggered(&self, value: i16) -> Option<BendDirection> {
+        let offset = i32::from(value);
+        if !self.up.is_empty() && offset >= i32::from(self.up_threshold.max(1)) {
+            Some(BendDirection::Up)
+        } else if !self.down.is_empty() && -offset >= i32::from(self.down_threshold.max(1)) {
+            Some(BendDirection::Down)
+        } else {
+            None
+        }
+    }
+
+    /// Whether a trigger that fired in `direction` re-arms at `value`: the wheel
+    /// is back within half the threshold of center (or past it)
+    pub fn rearms(&self, direction: BendDirection, value: i16) -> bool {
+        let offset = i32::from(value);
+        match direction {
+            BendDirection::Up => offset < i32::from(self.up_threshold.max(1).div_ceil(2)),
+            BendDirection::Down => -offset < i32::from(self.down_threshold.max(1).div_ceil(2)),
+        }
+    }
+
+    pub fn actions(&self, direction: BendDirection) -> &[Action] {
+        match direction {
+            BendDirection::Up => &self.up,
+            BendDirection::Down => &self.down,
+        }
+    }
+}
+
+/// Notes shown beyond the mapped range on each side by `MappingConfig::display_range`
+pub const DISPLAY_PADDING: u8 = 12;
+
+/// Top-level and per-note fields of a mapping file that `MappingConfig` and
+/// `NoteMapping` don't declare (and would drop on save).
+fn unknown_fields(value: &serde_json::Value) -> Vec<String> {
+    fn unknown<'a>(
+        object: &'a serde_json::Map<String, serde_json::Value>,
+        known: &'static [&'static str],
+    ) -> impl Iterator<Item = &'a String> {
+        object
+            .keys()
+            .filter(move |key| !known.contains(&key.as_str()))
+    }
+
+    let Some(object) = value.as_object() else {
+        return Vec::new();
+    };
+    let mut fields: Vec<String> = unknown(object, struct_fields::<MappingConfig>())
+        .cloned()
+        .collect();
+
+    let layers: Vec<&serde_json::Value> = match object.get("layers").or(object.get("layer")) {
+        Some(serde_json::Value::Array(layers)) => layers.iter().collect(),
+        Some(layer) => vec![layer],
+        None => Vec::new(),
+    };
+    let channels = object
+        .get("channel_mappings")
+        .and_then(|c| c.as_object())
+        .into_iter()
+        .flatten()
+        .map(|(channel, mappings)| (format!("channel {} ", channel), Some(mappings)));
+    let tables = std::iter::once((String::new(), object.get("mappings")))
+        .chain(
+            layers
+                .into_iter()
+                .enumerate()
+                .map(|(i, layer)| (format!("layer {} ", i), layer.get("mappings"))),
+        )
+        .chain(channels);
+    for (prefix, mappings) in tables {
+        let Some(mappings) = mappings.and_then(|m| m.as_object()) else {
+            continue;
+        };
+        for (note, mapping) in mappings {
+            if let Some(mapping) = mapping.as_object() {
+                fields.extend(
+                    unknown(mapping, struct_fields::<NoteMapping>())
+                        .map(|field| format!("{}note {}: {}", prefix, note, field)),
+                );
+            }
+        }
+    }
+    fields
+}
+
+/// Field names a derived `Deserialize` struct accepts, read by asking it to
+/// deserialize from a deserializer that only records them.
+fn struct_fields<T: serde::de::DeserializeOwned>() -> &'static [&'static str] {
+    use serde::de::{self, Deserializer, Visitor};
+
+    struct FieldProbe<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> Deserializer<'de> for FieldProbe<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("probe"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldProbe(&mut fields));
+    fields
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+fn unit_delay_scale() -> f32 {
+    1.0
+}
+
+fn is_unit_delay_scale(scale: &f32) -> bool {
+    *scale == 1.0
+}
+
+/// Accepted values of `MappingConfig::delay_scale`
+pub const DELAY_SCALE_RANGE: RangeInclusive<f32> = 0.1..=10.0;
+
+/// Look `note` up in `mappings`, shifting it by octaves to the nearest mapped note
+/// if it has no mapping and `octave_transpose` is set.
+fn lookup_transposed(
+    mappings: &HashMap<u8, NoteMapping>,
+    note: MidiNote,
+    octave_transpose: bool,
+) -> Option<(MidiNote, &NoteMapping)> {
+    // Direct lookup first
+    if let Some(m) = mappings.get(&note.value()) {
+        return Some((note, m));
+    }
+
+    if !octave_transpose {
+        return None;
+    }
+
+    // Find the range of mapped notes
+    let min_mapped = *mappings.keys().min()?;
+    let max_mapped = *mappings.keys().max()?;
+
+    // The note's other octaves in one direction, nearest first, within 0-127
+    let octaves =
+        |step: i8| std::iter::successors(note.shift_octave(step), move |n| n.shift_octave(step));
+    let mapped = |n: MidiNote| mappings.get(&n.value()).map(|m| (n, m));
+
+    // Try shifting toward the mapped range
+    if note.value() < min_mapped {
+        octaves(1).find_map(mapped)
+    } else if note.value() > max_mapped {
+        octaves(-1).find_map(mapped)
+    } else {
+        // Note is within the overall range but has no mapping at this octave.
+        // Try the nearest octave shifts (down first, then up).
+        let (mut down, mut up) = (octaves(-1), octaves(1));
+        loop {
+            let (below, above) = (down.next(), up.next());
+            if below.is_none() && above.is_none() {
+                return None;
+            }
+            if let Some(found) = below.and_then(mapped).or_else(|| above.and_then(mapped)) {
+                return Some(found);
+            }
+        }
+    }
 }
 
 impl MappingConfig {
     pub fn new() -> Self {
         Self {
+            version: MAPPING_VERSION,
             channel: Some(0),
             mappings: HashMap::new(),
+            channel_mappings: HashMap::new(),
             octave_transpose: false,
+            transpose_semitones: 0,
+            thru_port: None,
+            zero_velocity_note_on: ZeroVelocityNoteOn::default(),
+            layers: Vec::new(),
+            delay_scale: 1.0,
+            min_note_gap_ms: None,
+            cc_keys: HashMap::new(),
+            listen: MessageFilter::default(),
+            feedback: None,
+            sustain_pedal: false,
+            keyboard: KeyboardSettings::default(),
+            pitch_bend: None,
+            unknown_fields: Vec::new(),
         }
     }
 
+    /// The filter applied to incoming messages: `listen`, plus control changes if
+    /// `cc_keys`, `sustain_pedal` or a layer trigger need them and pitch bend if
+    /// `pitch_bend` is set.
+    pub fn message_filter(&self) -> MessageFilter {
+        let mut filter = self.listen;
+        let layer_cc = self
+            .layers
+            .iter()
+            .any(|layer| matches!(layer.trigger, LayerTrigger::ControlChange(_)));
+        if layer_cc || self.sustain_pedal || !self.cc_keys.is_empty() {
+            filter.control_change = true;
+        }
+        if self.pitch_bend.is_some() {
+            filter.pitch_bend = true;
+        }
+        filter
+    }
+
     /// Get mapping for a specific note
     pub fn get_mapping(&self, note: MidiNote) -> Option<&NoteMapping> {
         self.mappings.get(&note.value())
     }
 
-    /// Get mapping for a note, with octave transposition if enabled.
-    /// If the note has no direct mapping and `octave_transpose` is true,
-    /// shifts the note up/down by octaves until a mapping is found.
+    /// `note` shifted by `transpose_semitones`, or `None` if that leaves 0-127
+    pub fn shift_note(&self, note: MidiNote) -> Option<MidiNote> {
+        note.transpose(self.transpose_semitones)
+    }
+
+    /// Look `note` up in `mappings` after `transpose_semitones` and octave transposition
+    fn lookup<'a>(
+        &self,
+        mappings: &'a HashMap<u8, NoteMapping>,
+        note: MidiNote,
+    ) -> Option<(MidiNote, &'a NoteMapping)> {
+        lookup_transposed(mappings, self.shift_note(note)?, self.octave_transpose)
+    }
+
+    /// Get mapping for a note, with transposition if enabled. The note is first
+    /// shifted by `transpose_semitones`; if it then has no direct mapping and
+    /// `octave_transpose` is true, it is shifted up/down by octaves until a mapping
+    /// is found.
     pub fn get_mapping_transposed(&self, note: MidiNote) -> Option<(MidiNote, &NoteMapping)> {
-        // Direct lookup first
-        if let Some(m) = self.mappings.get(&note.value()) {
-            return Some((note, m));
+        self.lookup(&self.mappings, note)
+    }
+
+    /// The layer whose table plays `note`, given which layers are active: layers
+    /// are stacked in list order, so the last active layer that maps the note (after
+    /// octave transposition) wins. `None` means the base `mappings`.
+    pub fn resolve_layer(
+        &self,
+        note: MidiNote,
+        is_active: impl Fn(usize) -> bool,
+    ) -> Option<usize> {
+        self.layers.iter().enumerate().rev().find_map(|(i, layer)| {
+            (is_active(i) && self.lookup(&layer.mappings, note).is_some()).then_some(i)
+        })
+    }
+
+    /// Whether messages on `channel` are processed: it is the `channel` listened
+    /// to (or all are) or has its own table in `channel_mappings`.
+    pub fn listens_to(&self, channel: u8) -> bool {
+        self.channel.is_none_or(|c| c == channel) || self.channel_mappings.contains_key(&channel)
+    }
+
+    /// Like `get_mapping_transposed`, but looked up in `channel`'s table in
+    /// `channel_mappings` first.
+    pub fn get_mapping_on_channel(
+        &self,
+        note: MidiNote,
+        channel: u8,
+    ) -> Option<(MidiNote, &NoteMapping)> {
+        if let Some(mappings) = self.channel_mappings.get(&channel)
+            && let Some(found) = self.lookup(mappings, note)
+        {
+            return Some(found);
         }
+        self.get_mapping_transposed(note)
+    }
 
-        if !self.octave_transpose {
-            return None;
+    /// Like `get_mapping_on_channel`, but looked up in `layer`'s table (see
+    /// `resolve_layer`) first.
+    pub fn get_mapping_layered(
+        &self,
+        note: MidiNote,
+        channel: u8,
+        layer: Option<usize>,
+    ) -> Option<(MidiNote, &NoteMapping)> {
+        if let Some(layer) = layer.and_then(|i| self.layers.get(i))
+            && let Some(found) = self.lookup(&layer.mappings, note)
+        {
+            return Some(found);
+        }
+        self.get_mapping_on_channel(note, channel)
+    }
+
+    /// Lowest and highest mapped notes, if any notes are mapped
+    pub fn note_range(&self) -> Option<(MidiNote, MidiNote)> {
+        let min = MidiNote::new(*self.mappings.keys().min()?).ok()?;
+        let max = MidiNote::new(*self.mappings.keys().max()?).ok()?;
+        Some((min, max))
+    }
+
+    /// Notes to draw on a piano widget: the mapped range padded by `DISPLAY_PADDING`
+    /// on both sides and widened to whole octaves (C to C). C2–C7 if nothing is mapped.
+    pub fn display_range(&self) -> RangeInclusive<u8> {
+        let Some((min, max)) = self.note_range() else {
+            return 36..=96;
+        };
+        let low = min.value().saturating_sub(DISPLAY_PADDING);
+        let high = max.value().saturating_add(DISPLAY_PADDING);
+        let start = low - low % 12;
+        let end = high.div_ceil(12).saturating_mul(12).min(127);
+        start..=end
+    }
+
+    /// The mapped range split into octaves from the lowest mapped note, e.g.
+    /// C3–B3, C4–B4, C5–C6 for the default layout. A trailing partial octave
+    /// joins the one before it.
+    pub fn octave_zones(&self) -> Vec<RangeInclusive<u8>> {
+        let Some((min, max)) = self.note_range() else {
+            return Vec::new();
+        };
+        let (min, max) = (min.value(), max.value());
+
+        let mut zones = Vec::new();
+        let mut start = min;
+        while max - start >= 23 {
+            zones.push(start..=start + 11);
+            start += 12;
         }
+        zones.push(start..=max);
+        zones
+    }
+
+    /// `min_note_gap_ms` as a duration, if the mapping sets one
+    pub fn min_note_gap(&self) -> Option<Duration> {
+        self.min_note_gap_ms.map(Duration::from_millis)
+    }
+
+    /// `delay_scale` clamped to `DELAY_SCALE_RANGE` (1.0 if it isn't a number)
+    pub fn delay_scale(&self) -> f32 {
+        if self.delay_scale.is_nan() {
+            return 1.0;
+        }
+        self.delay_scale
+            .clamp(*DELAY_SCALE_RANGE.start(), *DELAY_SCALE_RANGE.end())
+    }
 
-        // Find the range of mapped notes
-        let min_mapped = *self.mappings.keys().min()?;
-        let max_mapped = *self.mappings.keys().max()?;
+    /// Short human-readable summary of channel and note range, e.g. "ch 0, C3–C6"
+    pub fn summary(&self) -> String {
+        let channel = match self.channel {
+            Some(ch) => format!("ch {}", ch),
+            None => "all ch".to_string(),
+        };
+        match self.note_range() {
+            Some((min, max)) => format!("{}, {}–{}", channel, min, max),
+            None => format!("{}, empty", channel),
+        }
+    }
 
-        let mut candidate = note.value();
+    /// Coverage statistics: notes, modifier zones and keys used
+    pub fn stats(&self) -> MappingStats {
+        let mut notes: Vec<&u8> = self.mappings.keys().collect();
+        notes.sort_unstable();
 
-        // Try shifting toward the mapped range
-        if candidate < min_mapped {
-            // Shift up by octaves
-            while candidate + 12 <= 127 {
-                candidate += 12;
-                if let Some(m) = self.mappings.get(&candidate) {
-                    return MidiNote::new(candidate).ok().map(|n| (n, m));
+        let mut zones: Vec<(ZoneModifiers, usize)> = Vec::new();
+        let mut used = HashSet::new();
+        for note in notes {
+            let mapping = &self.mappings[note];
+            let mut mods = ZoneModifiers::NONE;
+            for action in mapping
+                .on_press
+                .iter()
+                .chain(mapping.cycle.iter().flatten())
+            {
+                match action {
+                    Action::SetModifiers {
+                        shift, ctrl, alt, ..
+                    } => {
+                        mods = ZoneModifiers {
+                            shift: *shift,
+                            ctrl: *ctrl,
+                            alt: *alt,
+                        };
+                    }
+                    Action::Press(key) => {
+                        used.insert(*key);
+                    }
+                    Action::PressChord(keys) => used.extend(keys),
+                    _ => {}
                 }
             }
-        } else if candidate > max_mapped {
-            // Shift down by octaves
-            while candidate >= 12 {
-                candidate -= 12;
-                if let Some(m) = self.mappings.get(&candidate) {
-                    return MidiNote::new(candidate).ok().map(|n| (n, m));
-                }
+            match zones.iter_mut().find(|(zone, _)| *zone == mods) {
+                Some((_, count)) => *count += 1,
+                None => zones.push((mods, 1)),
             }
-        } else {
-            // Note is within the overall range but has no mapping at this octave.
-            // Try the nearest octave shifts (down first, then up).
-            let mut down = note.value();
-            let mut up = note.value();
-            loop {
-                let can_down = down >= 12;
-                let can_up = up + 12 <= 127;
-                if !can_down && !can_up {
-                    break;
+        }
+
+        MappingStats {
+            note_count: self.mappings.len(),
+            note_range: self.note_range(),
+            zones,
+            keys_used: Key::all()
+                .iter()
+                .copied()
+                .filter(|k| used.contains(k))
+                .collect(),
+        }
+    }
+
+    /// Warnings about settings the loaded file has that this build can't keep:
+    /// a newer format version, or fields it doesn't know.
+    pub fn compatibility_warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.version > MAPPING_VERSION {
+            warnings.push(format!(
+                "this mapping was made with a newer version (format {}, this build \
+                 supports {}); saving may drop fields",
+                self.version, MAPPING_VERSION
+            ));
+        }
+        for field in &self.unknown_fields {
+            warnings.push(format!("unknown field '{}' will be dropped on save", field));
+        }
+        warnings
+    }
+
+    /// Problems that won't stop the mapping from loading but are probably mistakes
+    pub fn validate(&self) -> Vec<ValidationWarning> {
+        let mut warnings: Vec<ValidationWarning> = self
+            .compatibility_warnings()
+            .into_iter()
+            .map(ValidationWarning::file)
+            .collect();
+        let mut channels: Vec<(&u8, &HashMap<u8, NoteMapping>)> =
+            self.channel_mappings.iter().collect();
+        channels.sort_unstable_by_key(|(channel, _)| **channel);
+        for (channel, _) in &channels {
+            if **channel > 15 {
+                warnings.push(ValidationWarning::file(format!(
+                    "channel_mappings: channel {} is out of range (0-15)",
+                    channel
+                )));
+            }
+        }
+        if let Some(bend) = &self.pitch_bend {
+            for (field, threshold, max) in [
+                ("up_threshold", bend.up_threshold, PITCH_BEND_CENTER - 1),
+                ("down_threshold", bend.down_threshold, PITCH_BEND_CENTER),
+            ] {
+                if !(1..=max).contains(&threshold) {
+                    warnings.push(ValidationWarning::file(format!(
+                        "pitch_bend: {} {} is out of range (1-{})",
+                        field, threshold, max
+                    )));
                 }
-                if can_down {
-                    down -= 12;
-                    if let Some(m) = self.mappings.get(&down) {
-                        return MidiNote::new(down).ok().map(|n| (n, m));
-                    }
+            }
+        }
+
+        let layers = self
+            .layers
+            .iter()
+            .enumerate()
+            .map(|(i, layer)| (Some(format!("layer {}", i)), &layer.mappings));
+        let channels = channels
+            .into_iter()
+            .map(|(channel, mappings)| (Some(format!("channel {}", channel)), mappings));
+        let tables = std::iter::once((None, &self.mappings))
+            .chain(layers)
+            .chain(channels);
+        for (table, mappings) in tables {
+            let mut notes: Vec<&u8> = mappings.keys().collect();
+            notes.sort_unstable();
+            for &note in notes {
+                let mut problems = mappings[&note].problems();
+                if note > 127 {
+                    problems.insert(0, "outside the MIDI note range (0-127)".to_string());
                 }
-                if can_up {
-                    up += 12;
-                    if let Some(m) = self.mappings.get(&up) {
-                        return MidiNote::new(up).ok().map(|n| (n, m));
+                mappings[&note].for_each_action(&mut |action| {
+                    if let Action::SwitchLayer(layer) = action
+                        && usize::from(*layer) > self.layers.len()
+                    {
+                        problems.push(format!(
+                            "switches to layer {}, past the last layer ({})",
+                            layer,
+                            self.layers.len()
+                        ));
                     }
-                }
+                });
+                warnings.extend(problems.into_iter().map(|message| ValidationWarning {
+                    table: table.clone(),
+                    note: Some(note),
+                    message,
+                }));
             }
         }
+        warnings
+    }
 
-        None
+    /// Remove redundant `SetModifiers` actions from every note (see
+    /// `NoteMapping::normalize`). Returns how many were removed.
+    pub fn normalize(&mut self) -> usize {
+        let layers = self.layers.iter_mut().flat_map(|l| l.mappings.values_mut());
+        let channels = self
+            .channel_mappings
+            .values_mut()
+            .flat_map(|m| m.values_mut());
+        self.mappings
+            .values_mut()
+            .chain(layers)
+            .chain(channels)
+            .map(NoteMapping::normalize)
+            .sum()
+    }
+
+    /// A mapping with an empty entry for each of `notes`, to fill in by hand
+    pub fn scaffold(notes: impl IntoIterator<Item = u8>) -> Self {
+        let mut config = Self::new();
+        for note in notes {
+            config.mappings.insert(note, NoteMapping::default());
+        }
+        config
     }
 
     /// Add a mapping for a note
@@ -130,19 +1223,62 @@ impl MappingConfig {
         self.mappings.insert(note.value(), mapping);
     }
 
-    /// Load from JSON file
+    /// Load from JSON file. Fields this build doesn't know are listed in
+    /// `unknown_fields`; files older than `MAPPING_VERSION` are upgraded to it.
     pub fn from_file(path: &std::path::Path) -> crate::Result<Self> {
+        if is_yaml_path(path) {
+            #[cfg(feature = "yaml")]
+            return Self::from_yaml_file(path);
+            #[cfg(not(feature = "yaml"))]
+            return Err(yaml_disabled(path));
+        }
         let content = std::fs::read_to_string(path)?;
-        let config = serde_json::from_str(&content)?;
+        Self::from_value(serde_json::from_str(&content)?)
+    }
+
+    /// Load from a YAML file
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml_file(path: &Path) -> crate::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        Self::from_value(serde_yaml::from_str(&content)?)
+    }
+
+    /// Build from a parsed file, recording fields this version doesn't know
+    fn from_value(value: serde_json::Value) -> crate::Result<Self> {
+        let unknown_fields = unknown_fields(&value);
+        let mut config: Self = serde_json::from_value(value)?;
+        config.unknown_fields = unknown_fields;
+        // Nothing to migrate yet: older files only lack fields that have defaults
+        config.version = config.version.max(MAPPING_VERSION);
         Ok(config)
     }
 
-    /// Save to JSON file
+    /// Save to a JSON file, or YAML if `path` ends in `.yaml`/`.yml`
     pub fn to_file(&self, path: &std::path::Path) -> crate::Result<()> {
+        if is_yaml_path(path) {
+            #[cfg(feature = "yaml")]
+            return self.to_yaml_file(path);
+            #[cfg(not(feature = "yaml"))]
+            return Err(yaml_disabled(path));
+        }
         let content = serde_json::to_string_pretty(self)?;
         std::fs::write(path, content)?;
         Ok(())
     }
+
+    /// Save to a YAML file. Actions are written in the same shape as in JSON
+    /// (`Press: Q`) rather than as YAML tags, so the file reads back the same way.
+    #[cfg(feature = "yaml")]
+    pub fn to_yaml_file(&self, path: &Path) -> crate::Result<()> {
+        let content = serde_yaml::to_string(&serde_json::to_value(self)?)?;
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Write unsaved editor changes for `path` to its `.autosave` sidecar
+    pub fn write_autosave(&self, path: &Path) -> crate::Result<()> {
+        self.to_file(&autosave_path(path))
+    }
 }
 
 impl Default for MappingConfig {
@@ -151,91 +1287,201 @@ impl Default for MappingConfig {
     }
 }
 
+/// Summary of what a mapping covers, from `MappingConfig::stats`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappingStats {
+    pub note_count: usize,
+    pub note_range: Option<(MidiNote, MidiNote)>,
+    /// Number of notes per modifier combination they press with, lowest note's first
+    pub zones: Vec<(ZoneModifiers, usize)>,
+    /// Keys pressed by any note, in `Key::all()` order
+    pub keys_used: Vec<Key>,
+}
+
+impl MappingStats {
+    /// Keys from `target` that no note presses
+    pub fn unused_keys(&self, target: &[Key]) -> Vec<Key> {
+        target
+            .iter()
+            .copied()
+            .filter(|k| !self.keys_used.contains(k))
+            .collect()
+    }
+}
+
+/// Modifier keys held for one octave zone of a generated mapping
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ZoneModifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+impl ZoneModifiers {
+    pub const NONE: Self = Self {
+        shift: false,
+        ctrl: false,
+        alt: false,
+    };
+    pub const SHIFT: Self = Self {
+        shift: true,
+        ctrl: false,
+        alt: false,
+    };
+    pub const CTRL: Self = Self {
+        shift: false,
+        ctrl: true,
+        alt: false,
+    };
+
+    pub fn is_none(&self) -> bool {
+        *self == Self::NONE
+    }
+}
+
+/// Parameters for generating an octave-zoned mapping like the FFXIV default.
+/// Zone `i` starts `12 * i` semitones above `start_note` and maps `keys` in order;
+/// where zones overlap (e.g. a 13th key), the higher zone wins.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappingLayout {
+    /// MIDI note of the first key in the lowest zone
+    pub start_note: u8,
+    /// Keys for one zone, lowest note first
+    pub keys: Vec<Key>,
+    /// Modifiers for each zone, lowest zone first
+    pub zones: Vec<ZoneModifiers>,
+}
+
+impl MappingLayout {
+    /// FFXIV performance keyboard layout:
+    /// Q 2 W 3 E R 5 T 6 Y 7 U I, over C3-C6 with Ctrl / none / Shift zones
+    pub fn ffxiv_default() -> Self {
+        Self {
+            start_note: 48,
+            keys: vec![
+                Key::Q,
+                Key::Num2,
+                Key::W,
+                Key::Num3,
+                Key::E,
+                Key::R,
+                Key::Num5,
+                Key::T,
+                Key::Num6,
+                Key::Y,
+                Key::Num7,
+                Key::U,
+                Key::I,
+            ],
+            zones: vec![
+                ZoneModifiers::CTRL,
+                ZoneModifiers::NONE,
+                ZoneModifiers::SHIFT,
+            ],
+        }
+    }
+
+    /// Generate the mapping. Notes above 127 are skipped.
+    pub fn build(&self) -> MappingConfig {
+        let mut config = MappingConfig::new();
+
+        for (zone_index, mods) in self.zones.iter().enumerate() {
+            for (i, key) in self.keys.iter().enumerate() {
+                let value = self.start_note as usize + zone_index * 12 + i;
+                let Some(note) = u8::try_from(value).ok().and_then(|v| MidiNote::new(v).ok())
+                else {
+                    continue;
+                };
+
+                let mapping = if mods.is_none() {
+                    NoteMapping {
+                        on_press: vec![Action::Press(*key)],
+                        on_release: vec![Action::Release(*key)],
+                        ..Default::default()
+                    }
+                } else {
+                    NoteMapping {
+                        on_press: vec![
+                            Action::SetModifiers {
+                                shift: mods.shift,
+                                ctrl: mods.ctrl,
+                                alt: mods.alt,
+                                side: None,
+                            },
+                            Action::Press(*key),
+                        ],
+                        on_release: vec![
+                            Action::Release(*key),
+                            Action::SetModifiers {
+                                shift: false,
+                                ctrl: false,
+                                alt: false,
+                                side: None,
+                            },
+                        ],
+                        ..Default::default()
+                    }
+                };
+                config.add_mapping(note, mapping);
+            }
+        }
+
+        config
+    }
+}
+
 /// Generate default FFXIV mapping
 /// Maps 3 octaves (C3-C6) to keyboard keys with modifiers:
 /// - C3-B3: Ctrl + key
 /// - C4-B4: key (no modifier)
 /// - C5-B5: Shift + key
 pub fn create_ffxiv_default_mapping() -> MappingConfig {
-    let mut config = MappingConfig::new();
-
-    // FFXIV performance keyboard layout:
-    // Q 2 W 3 E R 5 T 6 Y 7 U I
-    let keys = [
-        Key::Q,
-        Key::Num2,
-        Key::W,
-        Key::Num3,
-        Key::E,
-        Key::R,
-        Key::Num5,
-        Key::T,
-        Key::Num6,
-        Key::Y,
-        Key::Num7,
-        Key::U,
-        Key::I,
-    ];
-
-    // C3 (MIDI 48) to C4 (MIDI 60) - with Ctrl
-    for (i, key) in keys.iter().enumerate() {
-        let note = MidiNote::new(48 + i as u8).unwrap();
-        let mapping = NoteMapping {
-            on_press: vec![
-                Action::SetModifiers {
-                    shift: false,
-                    ctrl: true,
-                    alt: false,
-                },
-                Action::Press(*key),
-            ],
-            on_release: vec![
-                Action::Release(*key),
-                Action::SetModifiers {
-                    shift: false,
-                    ctrl: false,
-                    alt: false,
-                },
-            ],
-        };
-        config.add_mapping(note, mapping);
-    }
+    MappingLayout::ffxiv_default().build()
+}
 
-    // C4 (MIDI 60) to C5 (MIDI 72) - no modifier
-    for (i, key) in keys.iter().enumerate() {
-        let note = MidiNote::new(60 + i as u8).unwrap();
-        let mapping = NoteMapping {
-            on_press: vec![Action::Press(*key)],
-            on_release: vec![Action::Release(*key)],
-        };
-        config.add_mapping(note, mapping);
-    }
+/// Extensions of mapping files, JSON first.
+pub const MAPPING_EXTENSIONS: [&str; 3] = ["json", "yaml", "yml"];
 
-    // C5 (MIDI 72) to C6 (MIDI 84) - with Shift
-    for (i, key) in keys.iter().enumerate() {
-        let note = MidiNote::new(72 + i as u8).unwrap();
-        let mapping = NoteMapping {
-            on_press: vec![
-                Action::SetModifiers {
-                    shift: true,
-                    ctrl: false,
-                    alt: false,
-                },
-                Action::Press(*key),
-            ],
-            on_release: vec![
-                Action::Release(*key),
-                Action::SetModifiers {
-                    shift: false,
-                    ctrl: false,
-                    alt: false,
-                },
-            ],
-        };
-        config.add_mapping(note, mapping);
+/// Whether `path` is a YAML mapping file (`.yaml` or `.yml`); anything else is
+/// read and written as JSON.
+pub fn is_yaml_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("yaml") || ext.eq_ignore_ascii_case("yml"))
+}
+
+#[cfg(not(feature = "yaml"))]
+fn yaml_disabled(path: &Path) -> crate::Error {
+    crate::Error::Mapping(format!(
+        "{}: YAML mapping files need the `yaml` feature",
+        path.display()
+    ))
+}
+
+/// Sidecar file holding unsaved edits of the mapping at `path`, e.g. `piano.json.autosave`
+pub fn autosave_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".autosave");
+    PathBuf::from(name)
+}
+
+/// The autosave for `path`, if there is one newer than the saved file (i.e. worth recovering)
+pub fn recoverable_autosave(path: &Path) -> Option<PathBuf> {
+    let autosave = autosave_path(path);
+    let autosaved_at = std::fs::metadata(&autosave)
+        .and_then(|m| m.modified())
+        .ok()?;
+    match std::fs::metadata(path).and_then(|m| m.modified()) {
+        Ok(saved_at) if saved_at >= autosaved_at => None,
+        _ => Some(autosave),
     }
+}
 
-    config
+/// Delete the autosave for `path`, e.g. after a successful save. Missing files are fine.
+pub fn remove_autosave(path: &Path) -> crate::Result<()> {
+    match std::fs::remove_file(autosave_path(path)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
 }
 
 #[cfg(test)]
@@ -256,4 +1502,495 @@ mod tests {
         let mapping = config.get_mapping(note).unwrap();
         assert!(mapping.on_press.len() >= 2);
     }
+
+    #[test]
+    fn test_layout_build() {
+        let layout = MappingLayout {
+            start_note: 120,
+            keys: vec![Key::A, Key::B],
+            zones: vec![ZoneModifiers::NONE, ZoneModifiers::SHIFT],
+        };
+        let config = layout.build();
+
+        // Second zone starts at 132, which is out of MIDI range
+        assert_eq!(config.mappings.len(), 2);
+        assert!(matches!(
+            config.mappings[&121].on_press.as_slice(),
+            [Action::Press(Key::B)]
+        ));
+    }
+
+    #[test]
+    fn test_summary() {
+        let config = create_ffxiv_default_mapping();
+        let (min, max) = config.note_range().unwrap();
+        assert_eq!((min.value(), max.value()), (48, 84));
+        assert_eq!(config.summary(), "ch 0, C3–C6");
+        assert_eq!(config.octave_zones(), vec![48..=59, 60..=71, 72..=84]);
+
+        let mut empty = MappingConfig::new();
+        empty.channel = None;
+        assert_eq!(empty.summary(), "all ch, empty");
+    }
+
+    #[test]
+    fn test_display_range() {
+        // Default C3–C6: one octave either side
+        assert_eq!(create_ffxiv_default_mapping().display_range(), 36..=96);
+
+        // Padding is widened to whole octaves and clamped to MIDI limits
+        let mut config = MappingConfig::new();
+        for note in [5, 121] {
+            config.add_mapping(MidiNote::new(note).unwrap(), NoteMapping::default());
+        }
+        assert_eq!(config.display_range(), 0..=127);
+
+        let mut small = MappingConfig::new();
+        for note in [62, 69] {
+            small.add_mapping(MidiNote::new(note).unwrap(), NoteMapping::default());
+        }
+        let range = small.display_range();
+        assert_eq!(range, 48..=84);
+        for note in small.mappings.keys() {
+            assert!(*range.start() + DISPLAY_PADDING <= *note);
+            assert!(*note + DISPLAY_PADDING <= *range.end());
+        }
+
+        assert_eq!(MappingConfig::new().display_range(), 36..=96);
+    }
+
+    #[test]
+    fn test_stats() {
+        let layout = MappingLayout::ffxiv_default();
+        let stats = create_ffxiv_default_mapping().stats();
+
+        // Three zones of 13 keys; each zone's top C is the next zone's bottom C
+        assert_eq!(stats.note_count, 37);
+        let (min, max) = stats.note_range.unwrap();
+        assert_eq!((min.value(), max.value()), (48, 84));
+        assert_eq!(
+            stats.zones,
+            vec![
+                (ZoneModifiers::CTRL, 12),
+                (ZoneModifiers::NONE, 12),
+                (ZoneModifiers::SHIFT, 13)
+            ]
+        );
+        assert_eq!(stats.keys_used.len(), 13);
+        assert!(layout.keys.iter().all(|k| stats.keys_used.contains(k)));
+        assert_eq!(
+            stats.unused_keys(&[Key::Q, Key::O, Key::P]),
+            vec![Key::O, Key::P]
+        );
+    }
+
+    #[test]
+    fn test_validate_pure_modifier() {
+        let mut config = create_ffxiv_default_mapping();
+        assert!(config.validate().is_empty());
+
+        config.mappings.insert(
+            60,
+            NoteMapping {
+                on_press: vec![Action::Press(Key::LShift)],
+                ..Default::default()
+            },
+        );
+        let warnings = config.validate();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].to_string().starts_with("note C4 (60)"));
+    }
+
+    #[test]
+    fn test_validate_action_mistakes() {
+        let mut config = create_ffxiv_default_mapping();
+        config.mappings.insert(
+            62,
+            NoteMapping {
+                on_press: vec![Action::SetModifiers {
+                    shift: true,
+                    ctrl: false,
+                    alt: false,
+                    side: None,
+                }],
+                ..Default::default()
+            },
+        );
+        config.mappings.insert(
+            64,
+            NoteMapping {
+                on_press: vec![Action::Press(Key::E), Action::Press(Key::E)],
+                on_release: vec![Action::Release(Key::E), Action::Release(Key::R)],
+                ..Default::default()
+            },
+        );
+        let c4 = config.mappings[&60].clone();
+        config.mappings.insert(200, c4);
+        let mut drums = config.mappings.clone();
+        drums.retain(|note, _| *note == 62);
+        config.channel_mappings.insert(9, drums);
+
+        let warnings: Vec<String> = config.validate().iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            warnings,
+            vec![
+                "note D4 (62): on_press sets modifiers but presses no key",
+                "note E4 (64): presses E again without releasing it",
+                "note E4 (64): releases R, which it never presses",
+                "note 200: outside the MIDI note range (0-127)",
+                "channel 9 note D4 (62): on_press sets modifiers but presses no key",
+            ]
+        );
+        assert_eq!(config.validate()[1].note, Some(64));
+    }
+
+    #[test]
+    fn test_validate_switch_layer_target() {
+        let mut config = create_ffxiv_default_mapping();
+        config.layers = vec![MappingLayer {
+            trigger: LayerTrigger::Switch,
+            activation: LayerActivation::Hold,
+            mappings: HashMap::new(),
+        }];
+        let switch = |layers: Vec<u8>| NoteMapping {
+            on_press: vec![Action::Repeat {
+                count: 1,
+                actions: layers.into_iter().map(Action::SwitchLayer).collect(),
+            }],
+            ..Default::default()
+        };
+        config.mappings.insert(36, switch(vec![0, 1]));
+        assert!(config.validate().is_empty());
+
+        config.mappings.insert(36, switch(vec![2]));
+        let warnings: Vec<String> = config.validate().iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            warnings,
+            vec!["note C2 (36): switches to layer 2, past the last layer (1)"]
+        );
+        assert_eq!(
+            Action::SwitchLayer(0).to_string(),
+            "Switch to primary mappings"
+        );
+    }
+
+    #[test]
+    fn test_validate_type_text_with_press() {
+        let mut config = create_ffxiv_default_mapping();
+        config.mappings.insert(
+            61,
+            NoteMapping {
+                on_press: vec![Action::TypeText("/p hi".to_string())],
+                ..Default::default()
+            },
+        );
+        assert!(config.validate().is_empty());
+
+        config
+            .mappings
+            .get_mut(&61)
+            .unwrap()
+            .on_press
+            .push(Action::Press(Key::E));
+        config
+            .mappings
+            .get_mut(&61)
+            .unwrap()
+            .on_release
+            .push(Action::Release(Key::E));
+        let warnings: Vec<String> = config.validate().iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            warnings,
+            vec!["note C#4 (61): on_press types text and presses a key; only the key is pressed"]
+        );
+        assert_eq!(
+            Action::TypeText("/p hi".to_string()).to_string(),
+            "Type text: \"/p hi\""
+        );
+    }
+
+    #[test]
+    fn test_action_display_numpad() {
+        assert_eq!(Action::Press(Key::Numpad1).to_string(), "Press: Numpad1");
+        assert_eq!(Action::Press(Key::Num1).to_string(), "Press: Num1");
+        assert_eq!(
+            Action::PressChord(vec![Key::Control, Key::NumpadAdd]).to_string(),
+            "Chord: Control + NumpadAdd"
+        );
+    }
+
+    #[test]
+    fn test_pitch_bend_thresholds() {
+        let bend: PitchBendMapping = serde_json::from_str(
+            r#"{"up_threshold": 2000, "up": [{"Press": "Space"}, {"Release": "Space"}],
+                "down": [{"Press": "E"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(bend.down_threshold, 4096);
+
+        assert_eq!(bend.triggered(0), None);
+        assert_eq!(bend.triggered(1999), None);
+        assert_eq!(bend.triggered(2000), Some(BendDirection::Up));
+        assert_eq!(bend.triggered(-4095), None);
+        assert_eq!(bend.triggered(-8192), Some(BendDirection::Down));
+
+        // Re-arms within half the threshold of center
+        assert!(!bend.rearms(BendDirection::Up, 1000));
+        assert!(bend.rearms(BendDirection::Up, 999));
+        assert!(bend.rearms(BendDirection::Up, -8192));
+        assert!(!bend.rearms(BendDirection::Down, -2048));
+        assert!(bend.rearms(BendDirection::Down, 0));
+
+        let mut mapping = MappingConfig::new();
+        assert!(!mapping.message_filter().pitch_bend);
+        mapping.pitch_bend = Some(PitchBendMapping {
+            up_threshold: 0,
+            ..bend
+        });
+        assert!(mapping.message_filter().pitch_bend);
+        let warnings = mapping.validate();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0]
+            .message
+            .contains("up_threshold 0 is out of range"));
+    }
+
+    #[test]
+    fn test_set_zone_modifiers() {
+        let config = create_ffxiv_default_mapping();
+        let actions =
+            |m: &NoteMapping| serde_json::to_string(&(&m.on_press, &m.on_release)).unwrap();
+
+        // C4 (unmodified) moved to the Ctrl zone matches the generated C3 layout
+        let mut note = config.mappings[&60].clone();
+        note.set_zone_modifiers(ZoneModifiers::CTRL);
+        assert_eq!(actions(&note), actions(&config.mappings[&48]));
+
+        // And back again
+        note.set_zone_modifiers(ZoneModifiers::NONE);
+        assert_eq!(actions(&note), actions(&config.mappings[&60]));
+    }
+
+    #[test]
+    fn test_normalize() {
+        let set = |shift, ctrl| Action::SetModifiers {
+            shift,
+            ctrl,
+            alt: false,
+            side: None,
+        };
+        let mut mapping = NoteMapping {
+            on_press: vec![
+                set(false, true),
+                set(true, false),
+                Action::Press(Key::Q),
+                Action::Delay(5),
+                set(true, false),
+                Action::Release(Key::Q),
+            ],
+            on_release: vec![set(false, false)],
+            ..Default::default()
+        };
+
+        // The overridden Ctrl set and the repeated Shift set go; the final state
+        // (Shift held while Q is pressed) is unchanged
+        assert_eq!(mapping.normalize(), 2);
+        assert_eq!(
+            serde_json::to_value(&mapping.on_press).unwrap(),
+            serde_json::to_value(vec![
+                set(true, false),
+                Action::Press(Key::Q),
+                Action::Delay(5),
+                Action::Release(Key::Q),
+            ])
+            .unwrap()
+        );
+        assert_eq!(mapping.on_release.len(), 1);
+
+        // The default mapping is already minimal
+        assert_eq!(create_ffxiv_default_mapping().normalize(), 0);
+    }
+
+    #[test]
+    fn test_velocity_tremolo_interval() {
+        let tremolo = VelocityTremolo::default();
+
+        // Below the threshold: sustained press
+        assert_eq!(tremolo.interval(40), None);
+        assert_eq!(tremolo.interval(64), Some(Duration::from_millis(200)));
+        assert_eq!(tremolo.interval(127), Some(Duration::from_millis(50)));
+
+        // Intervals are clamped to a sane range
+        let extreme = VelocityTremolo {
+            threshold: 1,
+            slowest_ms: 5000,
+            fastest_ms: 0,
+        };
+        assert_eq!(
+            extreme.interval(1),
+            Some(Duration::from_millis(MAX_TREMOLO_INTERVAL_MS))
+        );
+        assert_eq!(
+            extreme.interval(127),
+            Some(Duration::from_millis(MIN_TREMOLO_INTERVAL_MS))
+        );
+    }
+
+    fn set_mtime(path: &Path, time: std::time::SystemTime) {
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(time).unwrap();
+    }
+
+    #[test]
+    fn test_autosave_recovery() {
+        let dir = std::env::temp_dir().join(format!("xiv-midi-autosave-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("piano.json");
+        let now = std::time::SystemTime::now();
+
+        MappingConfig::new().to_file(&path).unwrap();
+        assert_eq!(autosave_path(&path), dir.join("piano.json.autosave"));
+        assert_eq!(recoverable_autosave(&path), None);
+
+        // Unsaved edits newer than the file are offered for recovery
+        let edited = create_ffxiv_default_mapping();
+        edited.write_autosave(&path).unwrap();
+        set_mtime(&path, now - Duration::from_secs(60));
+        set_mtime(&autosave_path(&path), now);
+        let autosave = recoverable_autosave(&path).unwrap();
+        let recovered = MappingConfig::from_file(&autosave).unwrap();
+        assert_eq!(recovered.mappings.len(), edited.mappings.len());
+
+        // A stale autosave (file saved since) is ignored
+        set_mtime(&path, now + Duration::from_secs(60));
+        assert_eq!(recoverable_autosave(&path), None);
+
+        // Saving cleans up; cleaning up twice is fine
+        remove_autosave(&path).unwrap();
+        assert!(!autosave_path(&path).exists());
+        remove_autosave(&path).unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_yaml_round_trip() {
+        let dir = std::env::temp_dir().join(format!("xiv-midi-yaml-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let original = create_ffxiv_default_mapping();
+
+        for name in ["piano.yaml", "piano.yml"] {
+            let path = dir.join(name);
+            original.to_file(&path).unwrap();
+            let content = std::fs::read_to_string(&path).unwrap();
+            assert!(serde_json::from_str::<serde_json::Value>(&content).is_err());
+
+            let loaded = MappingConfig::from_file(&path).unwrap();
+            assert!(loaded.unknown_fields.is_empty());
+            assert_eq!(
+                serde_json::to_value(&loaded).unwrap(),
+                serde_json::to_value(&original).unwrap()
+            );
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_fields_and_newer_version() {
+        let dir = std::env::temp_dir().join(format!("xiv-midi-version-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // A file from a newer build, with fields this one doesn't know
+        let newer = dir.join("newer.json");
+        std::fs::write(
+            &newer,
+            r#"{"version": 99, "channel": 0, "glide_ms": 20,
+                "mappings": {"60": {"on_press": [{"Press": "Q"}], "on_release": [], "pitch": 2}}}"#,
+        )
+        .unwrap();
+        let config = MappingConfig::from_file(&newer).unwrap();
+        assert_eq!(config.version, 99);
+        assert_eq!(config.mappings.len(), 1);
+        assert_eq!(config.unknown_fields, vec!["glide_ms", "note 60: pitch"]);
+        let warnings = config.validate();
+        assert!(
+            warnings[0].message.contains("newer version"),
+            "{:?}",
+            warnings
+        );
+        assert!(warnings[0].message.contains("saving may drop fields"));
+        assert!(warnings.iter().any(|w| w.message.contains("'glide_ms'")));
+
+        // A file from before versioning loads cleanly and is upgraded
+        let older = dir.join("older.json");
+        std::fs::write(&older, r#"{"channel": 0, "mappings": {}}"#).unwrap();
+        let config = MappingConfig::from_file(&older).unwrap();
+        assert_eq!(config.version, MAPPING_VERSION);
+        assert!(config.compatibility_warnings().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_layers_resolve_top_down() {
+        // The old single `layer` object still loads, as one layer
+        let legacy = serde_json::json!({
+            "channel": 0,
+            "mappings": {"60": {"on_press": [{"Press": "Q"}], "on_release": []}},
+            "layer": {
+                "trigger": {"ControlChange": 64},
+                "mappings": {"60": {"on_press": [{"Press": "E"}], "on_release": []}}
+            }
+        });
+        assert!(unknown_fields(&legacy).is_empty());
+        let mut config: MappingConfig = serde_json::from_value(legacy).unwrap();
+        assert_eq!(config.layers.len(), 1);
+        assert_eq!(config.layers[0].activation, LayerActivation::Hold);
+
+        let mut top = config.layers[0].clone();
+        top.trigger = LayerTrigger::Note(36);
+        top.activation = LayerActivation::Toggle;
+        top.mappings.insert(62, NoteMapping::default());
+        config.layers.push(top);
+
+        let c4 = MidiNote::new(60).unwrap();
+        let d4 = MidiNote::new(62).unwrap();
+        assert_eq!(config.resolve_layer(c4, |_| false), None);
+        assert_eq!(config.resolve_layer(c4, |i| i == 0), Some(0));
+        assert_eq!(config.resolve_layer(c4, |_| true), Some(1));
+        assert_eq!(config.resolve_layer(d4, |i| i == 0), None);
+
+        let json = serde_json::to_value(&config).unwrap();
+        assert!(json.get("layer").is_none());
+        assert_eq!(json["layers"].as_array().unwrap().len(), 2);
+        assert_eq!(json["layers"][1]["activation"], "Toggle");
+    }
+
+    #[test]
+    fn test_transpose_semitones() {
+        let mut config = create_ffxiv_default_mapping();
+        let c3 = MidiNote::new(48).unwrap();
+        let c4 = MidiNote::new(60).unwrap();
+        assert_eq!(config.get_mapping_transposed(c3).unwrap().0, c3);
+
+        config.transpose_semitones = 12;
+        let (played, mapping) = config.get_mapping_transposed(c3).unwrap();
+        assert_eq!(played, c4);
+        assert!(std::ptr::eq(mapping, config.get_mapping(c4).unwrap()));
+
+        // Shifted out of 0-127: no mapping, even with octave transposition
+        config.octave_transpose = true;
+        config.transpose_semitones = 100;
+        assert_eq!(config.shift_note(c3), None);
+        assert!(config.get_mapping_transposed(c3).is_none());
+        config.transpose_semitones = -49;
+        assert!(config.get_mapping_transposed(c3).is_none());
+
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["transpose_semitones"], -49);
+        let json = serde_json::to_value(create_ffxiv_default_mapping()).unwrap();
+        assert!(json.get("transpose_semitones").is_none());
+    }
 }
@@ -1,4 +1,108 @@
 use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+/// Octave numbering used when displaying note names. Doesn't affect MIDI values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OctaveConvention {
+    /// MIDI 60 = C4 (scientific pitch notation)
+    #[default]
+    Scientific,
+    /// MIDI 60 = C3 (Yamaha, used by many DAWs)
+    Yamaha,
+    /// MIDI 60 = C5 (some trackers and notation software)
+    MiddleC5,
+}
+
+impl OctaveConvention {
+    /// Every convention, in the order they're offered in the GUI
+    pub const ALL: [OctaveConvention; 3] = [
+        OctaveConvention::Yamaha,
+        OctaveConvention::Scientific,
+        OctaveConvention::MiddleC5,
+    ];
+
+    /// Octave number of MIDI note 0 under this convention
+    fn lowest_octave(self) -> i8 {
+        match self {
+            OctaveConvention::Scientific => -1,
+            OctaveConvention::Yamaha => -2,
+            OctaveConvention::MiddleC5 => 0,
+        }
+    }
+}
+
+/// How a note-on (0x90) with velocity 0 is interpreted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ZeroVelocityNoteOn {
+    /// Treat it as a note-off (standard MIDI running-status behavior)
+    #[default]
+    NoteOff,
+    /// Drop it; for controllers that send velocity-0 note-ons as ghost events
+    Ignore,
+}
+
+/// Which incoming message types are parsed and passed on to the scheduler. The rest
+/// are dropped where they arrive, before parsing (so they aren't logged as errors).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessageFilter {
+    /// Note-on and note-off
+    pub notes: bool,
+    /// Control change (CC)
+    pub control_change: bool,
+    /// Pitch bend (the pitch wheel)
+    pub pitch_bend: bool,
+    /// Channel mode messages (CC 120-127, e.g. All Notes Off), even when
+    /// `control_change` is off
+    pub channel_mode: bool,
+    /// Everything else: aftertouch, program change and system messages
+    pub other: bool,
+}
+
+impl MessageFilter {
+    pub const NOTES_ONLY: Self = Self {
+        notes: true,
+        control_change: false,
+        pitch_bend: false,
+        channel_mode: true,
+        other: false,
+    };
+
+    pub const ALL: Self = Self {
+        notes: true,
+        control_change: true,
+        pitch_bend: true,
+        channel_mode: true,
+        other: true,
+    };
+
+    /// Whether `message` (starting with its status byte) is let through.
+    pub fn accepts(&self, message: &[u8]) -> bool {
+        let Some(&status) = message.first() else {
+            return false;
+        };
+        match status & 0xF0 {
+            0x80 | 0x90 => self.notes,
+            0xB0 if message
+                .get(1)
+                .is_some_and(|&c| c >= CHANNEL_MODE_CONTROLLERS) =>
+            {
+                self.control_change || self.channel_mode
+            }
+            0xB0 => self.control_change,
+            0xE0 => self.pitch_bend,
+            _ => self.other,
+        }
+    }
+}
+
+impl Default for MessageFilter {
+    fn default() -> Self {
+        Self::NOTES_ONLY
+    }
+}
 
 /// MIDI note number (0-127)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -19,9 +123,27 @@ impl MidiNote {
         self.0
     }
 
+    /// This note shifted by `semitones`, or `None` if that leaves 0-127
+    pub fn transpose(self, semitones: i8) -> Option<MidiNote> {
+        let shifted = i16::from(self.0) + i16::from(semitones);
+        u8::try_from(shifted).ok().and_then(|v| Self::new(v).ok())
+    }
+
+    /// This note shifted by whole octaves, or `None` if that leaves 0-127
+    pub fn shift_octave(self, octaves: i8) -> Option<MidiNote> {
+        // More than 10 octaves either way can't stay in range
+        let semitones = i8::try_from(i16::from(octaves) * 12).ok()?;
+        self.transpose(semitones)
+    }
+
     /// Get the octave number (-1 to 9)
     pub fn octave(&self) -> i8 {
-        (self.0 as i8 / 12) - 1
+        self.octave_with(OctaveConvention::Scientific)
+    }
+
+    /// Get the octave number under the given numbering convention
+    pub fn octave_with(&self, convention: OctaveConvention) -> i8 {
+        (self.0 as i8 / 12) + convention.lowest_octave()
     }
 
     /// Get the note name (C, C#, D, etc.)
@@ -34,7 +156,54 @@ impl MidiNote {
 
     /// Get full note name with octave (e.g., "C4", "A#3")
     pub fn full_name(&self) -> String {
-        format!("{}{}", self.name(), self.octave())
+        self.full_name_with(OctaveConvention::Scientific)
+    }
+
+    /// Get full note name with octave under the given numbering convention
+    /// (e.g., MIDI 60 is "C4" in `Scientific`, "C3" in `Yamaha`, "C5" in `MiddleC5`)
+    pub fn full_name_with(&self, convention: OctaveConvention) -> String {
+        format!("{}{}", self.name(), self.octave_with(convention))
+    }
+
+    /// Parse a note name with octave, e.g. "C4", "A#3" or "Db5" (the inverse of
+    /// `full_name`). Accepts sharps (`#`) and flats (`b`) and any letter case.
+    pub fn from_name(name: &str) -> Result<Self> {
+        Self::from_name_with(name, OctaveConvention::Scientific)
+    }
+
+    /// Parse a note name with octave under the given numbering convention
+    /// (the inverse of `full_name_with`).
+    pub fn from_name_with(name: &str, convention: OctaveConvention) -> Result<Self> {
+        let invalid = || Error::InvalidMidiMessage(format!("Invalid note name '{}'", name));
+
+        let mut chars = name.trim().chars();
+        let semitone: i32 = match chars.next().map(|c| c.to_ascii_uppercase()) {
+            Some('C') => 0,
+            Some('D') => 2,
+            Some('E') => 4,
+            Some('F') => 5,
+            Some('G') => 7,
+            Some('A') => 9,
+            Some('B') => 11,
+            _ => return Err(invalid()),
+        };
+        let rest = chars.as_str();
+        let (semitone, octave) = if let Some(octave) = rest.strip_prefix('#') {
+            (semitone + 1, octave)
+        } else if let Some(octave) = rest.strip_prefix('b') {
+            (semitone - 1, octave)
+        } else {
+            (semitone, rest)
+        };
+        let octave: i32 = octave.parse().map_err(|_| invalid())?;
+
+        let value = (octave - i32::from(convention.lowest_octave())) * 12 + semitone;
+        u8::try_from(value)
+            .ok()
+            .and_then(|value| Self::new(value).ok())
+            .ok_or_else(|| {
+                Error::InvalidMidiMessage(format!("Note '{}' out of range (0-127)", name))
+            })
     }
 }
 
@@ -49,14 +218,34 @@ impl std::fmt::Display for MidiNote {
 pub enum MidiEventType {
     NoteOn,
     NoteOff,
+    ControlChange {
+        controller: u8,
+        value: u8,
+    },
+    /// Pitch bend relative to the wheel at rest (-8192 to 8191)
+    PitchBend(i16),
 }
 
+/// Raw 14-bit pitch bend value of the wheel at rest
+pub const PITCH_BEND_CENTER: u16 = 8192;
+
+/// Controller number of the All Sound Off channel mode message
+pub const ALL_SOUND_OFF: u8 = 120;
+
+/// Controller number of the All Notes Off channel mode message
+pub const ALL_NOTES_OFF: u8 = 123;
+
+/// Controller numbers 120-127 are channel mode messages rather than controllers
+const CHANNEL_MODE_CONTROLLERS: u8 = 120;
+
 /// Parsed MIDI message
 #[derive(Debug, Clone, PartialEq)]
 pub struct MidiMessage {
     pub event_type: MidiEventType,
     pub channel: u8,
+    /// Note of a note-on or note-off; 0 for other events
     pub note: MidiNote,
+    /// Velocity of a note-on or note-off; 0 for other events
     pub velocity: u8,
 }
 
@@ -99,12 +288,257 @@ impl MidiMessage {
                     velocity,
                 })
             }
+            0xB0 => Ok(Self::control_change(
+                channel,
+                data[1] & 0x7F,
+                data[2] & 0x7F,
+            )),
+            0xE0 => {
+                let raw = u16::from(data[2] & 0x7F) << 7 | u16::from(data[1] & 0x7F);
+                Ok(Self {
+                    event_type: MidiEventType::PitchBend(raw as i16 - PITCH_BEND_CENTER as i16),
+                    channel,
+                    note: MidiNote(0),
+                    velocity: 0,
+                })
+            }
             _ => Err(Error::InvalidMidiMessage(format!(
                 "Unsupported message type: 0x{:02X}",
                 message_type
             ))),
         }
     }
+
+    /// Parse a raw MIDI message, interpreting velocity-0 note-ons per `zero_velocity`.
+    /// Returns `Ok(None)` if the message is dropped.
+    pub fn parse_with(data: &[u8], zero_velocity: ZeroVelocityNoteOn) -> Result<Option<Self>> {
+        let message = Self::parse(data)?;
+        if zero_velocity == ZeroVelocityNoteOn::Ignore && data[0] & 0xF0 == 0x90 && data[2] == 0 {
+            return Ok(None);
+        }
+        Ok(Some(message))
+    }
+
+    /// Parse a buffer that may hold several messages (some backends batch them),
+    /// in order, honouring running status. Each message is parsed as by `parse_with`;
+    /// dropped messages are left out.
+    pub fn parse_all(data: &[u8], zero_velocity: ZeroVelocityNoteOn) -> Vec<Result<Self>> {
+        Self::parse_all_filtered(data, zero_velocity, MessageFilter::ALL)
+    }
+
+    /// `parse_all`, skipping messages `listen` doesn't accept without parsing them.
+    pub fn parse_all_filtered(
+        data: &[u8],
+        zero_velocity: ZeroVelocityNoteOn,
+        listen: MessageFilter,
+    ) -> Vec<Result<Self>> {
+        MidiDecoder::new(zero_velocity, listen).decode(data)
+    }
+
+    /// A control change message
+    pub fn control_change(channel: u8, controller: u8, value: u8) -> Self {
+        Self {
+            event_type: MidiEventType::ControlChange { controller, value },
+            channel,
+            note: MidiNote(0),
+            velocity: 0,
+        }
+    }
+
+    /// Encode back to raw MIDI bytes
+    pub fn to_bytes(&self) -> [u8; 3] {
+        let (status, data1, data2) = match self.event_type {
+            MidiEventType::NoteOn => (0x90, self.note.value(), self.velocity),
+            MidiEventType::NoteOff => (0x80, self.note.value(), self.velocity),
+            MidiEventType::ControlChange { controller, value } => (0xB0, controller, value),
+            MidiEventType::PitchBend(bend) => {
+                let raw = (i32::from(bend) + i32::from(PITCH_BEND_CENTER)).clamp(0, 0x3FFF);
+                (0xE0, (raw & 0x7F) as u8, (raw >> 7) as u8)
+            }
+        };
+        [status | (self.channel & 0x0F), data1, data2]
+    }
+}
+
+/// Parser for a stream of MIDI buffers that keeps the running status between
+/// them, for devices that leave out a repeated status byte even across packets
+/// (e.g. `[0x90, 60, 100]` followed by `[62, 100]`).
+#[derive(Debug, Clone, Copy)]
+pub struct MidiDecoder {
+    zero_velocity: ZeroVelocityNoteOn,
+    listen: MessageFilter,
+    /// Status byte of the last channel message, implied by data bytes without one
+    running_status: Option<u8>,
+}
+
+impl MidiDecoder {
+    pub fn new(zero_velocity: ZeroVelocityNoteOn, listen: MessageFilter) -> Self {
+        Self {
+            zero_velocity,
+            listen,
+            running_status: None,
+        }
+    }
+
+    /// Parse the next buffer from the device, in order, as by `MidiMessage::parse_with`.
+    /// Dropped messages and ones `listen` doesn't accept are left out.
+    pub fn decode(&mut self, data: &[u8]) -> Vec<Result<MidiMessage>> {
+        let mut results = Vec::new();
+        let mut i = 0;
+
+        while i < data.len() {
+            let status = if data[i] & 0x80 != 0 {
+                i += 1;
+                data[i - 1]
+            } else if let Some(status) = self.running_status {
+                status
+            } else {
+                results.push(Err(Error::InvalidMidiMessage(format!(
+                    "Data byte 0x{:02X} without a status byte",
+                    data[i]
+                ))));
+                i += 1;
+                continue;
+            };
+
+            // Data bytes after the status byte; `None` for system exclusive, which
+            // runs up to the end-of-exclusive byte
+            let data_len = match status {
+                0xC0..=0xDF | 0xF1 | 0xF3 => Some(1),
+                0x80..=0xEF | 0xF2 => Some(2),
+                0xF0 => None,
+                _ => Some(0),
+            };
+            // Only channel messages set running status; system common messages clear it
+            self.running_status = match status {
+                0x80..=0xEF => Some(status),
+                0xF0..=0xF7 => None,
+                _ => self.running_status,
+            };
+
+            let mut message = vec![status];
+            while i < data.len()
+                && data_len.map_or(message.last() != Some(&0xF7), |len| message.len() <= len)
+            {
+                // Real-time messages (e.g. clock) can come between any two bytes of
+                // another message; they are passed on first, as they arrived first
+                if data[i] >= 0xF8 {
+                    self.push(&[data[i]], &mut results);
+                } else {
+                    message.push(data[i]);
+                }
+                i += 1;
+            }
+            self.push(&message, &mut results);
+        }
+
+        results
+    }
+
+    /// Parse one complete `message` into `results`, unless `listen` drops it.
+    fn push(&self, message: &[u8], results: &mut Vec<Result<MidiMessage>>) {
+        if !self.listen.accepts(message) {
+            return;
+        }
+        match MidiMessage::parse_with(message, self.zero_velocity) {
+            Ok(Some(parsed)) => results.push(Ok(parsed)),
+            Ok(None) => {}
+            Err(e) => results.push(Err(e)),
+        }
+    }
+}
+
+/// Distinct note numbers sounded (note-on with non-zero velocity) on any track or
+/// channel of a Standard MIDI File, lowest first
+pub fn smf_notes(data: &[u8]) -> Result<BTreeSet<u8>> {
+    let smf = midly::Smf::parse(data)?;
+    let mut notes = BTreeSet::new();
+    for event in smf.tracks.iter().flatten() {
+        if let midly::TrackEventKind::Midi {
+            message: midly::MidiMessage::NoteOn { key, vel },
+            ..
+        } = event.kind
+            && vel > 0
+        {
+            notes.insert(key.as_int());
+        }
+    }
+    Ok(notes)
+}
+
+/// Microseconds per beat of a Standard MIDI File before its first tempo event (120 BPM)
+const SMF_DEFAULT_TEMPO: u32 = 500_000;
+
+/// The note, controller and pitch bend messages of a Standard MIDI File, merged
+/// across tracks, each with the time since the start of the song it plays at
+/// (following tempo changes)
+pub fn smf_messages(
+    data: &[u8],
+    zero_velocity: ZeroVelocityNoteOn,
+) -> Result<Vec<(Duration, MidiMessage)>> {
+    let smf = midly::Smf::parse(data)?;
+
+    // The stable sort keeps events on the same tick in track order
+    let mut events = Vec::new();
+    for track in &smf.tracks {
+        let mut tick = 0u64;
+        for event in track {
+            tick += u64::from(event.delta.as_int());
+            events.push((tick, event.kind));
+        }
+    }
+    events.sort_by_key(|(tick, _)| *tick);
+
+    // Metrical ticks are a fraction of a beat, so their length follows the tempo
+    let (ticks_per_beat, mut tick_micros) = match smf.header.timing {
+        midly::Timing::Metrical(ticks) => {
+            let ticks = f64::from(ticks.as_int().max(1));
+            (Some(ticks), f64::from(SMF_DEFAULT_TEMPO) / ticks)
+        }
+        midly::Timing::Timecode(fps, subframes) => (
+            None,
+            1_000_000.0 / (f64::from(fps.as_f32()) * f64::from(subframes.max(1))),
+        ),
+    };
+
+    let mut messages = Vec::new();
+    let (mut last_tick, mut micros) = (0, 0.0);
+    let mut bytes = Vec::new();
+    for (tick, kind) in events {
+        micros += (tick - last_tick) as f64 * tick_micros;
+        last_tick = tick;
+        match kind {
+            midly::TrackEventKind::Meta(midly::MetaMessage::Tempo(tempo)) => {
+                if let Some(ticks) = ticks_per_beat {
+                    tick_micros = f64::from(tempo.as_int()) / ticks;
+                }
+            }
+            midly::TrackEventKind::Midi { .. } => {
+                bytes.clear();
+                if let Some(event) = kind.as_live_event() {
+                    event.write_std(&mut bytes)?;
+                }
+                // Program changes, aftertouch etc. don't parse and are left out
+                if let Ok(Some(message)) = MidiMessage::parse_with(&bytes, zero_velocity) {
+                    messages.push((Duration::from_secs_f64(micros / 1_000_000.0), message));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(messages)
+}
+
+/// Destination for raw MIDI messages, e.g. a MIDI thru output port
+pub trait MidiSink: Send {
+    fn send(&mut self, message: &[u8]) -> Result<()>;
+}
+
+impl MidiSink for midir::MidiOutputConnection {
+    fn send(&mut self, message: &[u8]) -> Result<()> {
+        midir::MidiOutputConnection::send(self, message)
+            .map_err(|e| Error::MidiOutput(e.to_string()))
+    }
 }
 
 #[cfg(test)]
@@ -119,6 +553,61 @@ mod tests {
         assert_eq!(c4.full_name(), "C4");
     }
 
+    #[test]
+    fn test_octave_convention() {
+        let middle_c = MidiNote::new(60).unwrap();
+        assert_eq!(middle_c.full_name_with(OctaveConvention::Scientific), "C4");
+        assert_eq!(middle_c.full_name_with(OctaveConvention::Yamaha), "C3");
+
+        let lowest = MidiNote::new(0).unwrap();
+        assert_eq!(lowest.full_name_with(OctaveConvention::Yamaha), "C-2");
+        let highest = MidiNote::new(127).unwrap();
+        assert_eq!(highest.full_name_with(OctaveConvention::Scientific), "G9");
+
+        assert_eq!(middle_c.full_name_with(OctaveConvention::MiddleC5), "C5");
+        assert_eq!(highest.full_name_with(OctaveConvention::MiddleC5), "G10");
+
+        // Octave of MIDI note 0 under each convention
+        let lowest_octaves: Vec<_> = OctaveConvention::ALL
+            .iter()
+            .map(|&convention| lowest.octave_with(convention))
+            .collect();
+        assert_eq!(lowest_octaves, vec![-2, -1, 0]);
+        assert_eq!(lowest.full_name_with(OctaveConvention::MiddleC5), "C0");
+    }
+
+    #[test]
+    fn test_note_from_name() {
+        for value in [0, 1, 21, 59, 60, 61, 70, 108, 127] {
+            let note = MidiNote::new(value).unwrap();
+            assert_eq!(MidiNote::from_name(&note.full_name()).unwrap(), note);
+            for convention in OctaveConvention::ALL {
+                assert_eq!(
+                    MidiNote::from_name_with(&note.full_name_with(convention), convention).unwrap(),
+                    note
+                );
+            }
+        }
+
+        let value = |name: &str| MidiNote::from_name(name).map(|n| n.value());
+        assert_eq!(value("C4").unwrap(), 60);
+        assert_eq!(value("A#3").unwrap(), 58);
+        assert_eq!(value("Db5").unwrap(), 73);
+        assert_eq!(value("bb2").unwrap(), 46);
+        assert_eq!(value(" c-1 ").unwrap(), 0);
+        assert_eq!(value("Cb4").unwrap(), 59);
+
+        for garbage in [
+            "", "H4", "C", "C#", "4C", "C##4", "Cx4", "C4.5", "G#9", "Cb-1", "C10",
+        ] {
+            assert!(
+                matches!(value(garbage), Err(Error::InvalidMidiMessage(_))),
+                "{:?}",
+                garbage
+            );
+        }
+    }
+
     #[test]
     fn test_midi_message_parse() {
         // Note On C4 with velocity 64
@@ -136,4 +625,315 @@ mod tests {
         let msg = MidiMessage::parse(&[0x90, 60, 0]).unwrap();
         assert_eq!(msg.event_type, MidiEventType::NoteOff);
     }
+
+    #[test]
+    fn test_control_change_parse() {
+        // Sustain pedal down on channel 1
+        let msg = MidiMessage::parse(&[0xB0, 64, 127]).unwrap();
+        assert_eq!(
+            msg.event_type,
+            MidiEventType::ControlChange {
+                controller: 64,
+                value: 127
+            }
+        );
+        assert_eq!(msg.channel, 0);
+        // The controller isn't reported as a note
+        assert_eq!((msg.note.value(), msg.velocity), (0, 0));
+
+        // A knob on another channel, at zero
+        let msg = MidiMessage::parse(&[0xB3, 21, 0]).unwrap();
+        assert_eq!(msg, MidiMessage::control_change(3, 21, 0));
+    }
+
+    #[test]
+    fn test_zero_velocity_note_on() {
+        let ghost = [0x90, 60, 0];
+
+        let msg = MidiMessage::parse_with(&ghost, ZeroVelocityNoteOn::NoteOff)
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.event_type, MidiEventType::NoteOff);
+        assert_eq!(msg.note.value(), 60);
+
+        assert_eq!(
+            MidiMessage::parse_with(&ghost, ZeroVelocityNoteOn::Ignore).unwrap(),
+            None
+        );
+
+        // Explicit note-offs and real note-ons are unaffected
+        for bytes in [[0x80, 60, 0], [0x90, 60, 1]] {
+            assert_eq!(
+                MidiMessage::parse_with(&bytes, ZeroVelocityNoteOn::Ignore).unwrap(),
+                Some(MidiMessage::parse(&bytes).unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_all() {
+        // Note-off then note-on, then a second note-on using running status
+        let buffer = [0x80, 60, 0, 0x90, 62, 100, 64, 90];
+        let messages: Vec<MidiMessage> =
+            MidiMessage::parse_all(&buffer, ZeroVelocityNoteOn::NoteOff)
+                .into_iter()
+                .collect::<Result<_>>()
+                .unwrap();
+        let summary: Vec<(MidiEventType, u8)> = messages
+            .iter()
+            .map(|m| (m.event_type, m.note.value()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (MidiEventType::NoteOff, 60),
+                (MidiEventType::NoteOn, 62),
+                (MidiEventType::NoteOn, 64)
+            ]
+        );
+
+        // Unsupported messages are reported without losing the ones after them
+        let results = MidiMessage::parse_all(&[0xC0, 5, 0x90, 60, 0], ZeroVelocityNoteOn::Ignore);
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+        let results =
+            MidiMessage::parse_all(&[0xA0, 60, 64, 0x90, 60, 1], ZeroVelocityNoteOn::Ignore);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].as_ref().unwrap().note.value(), 60);
+    }
+
+    #[test]
+    fn test_parse_pitch_bend() {
+        let msg = MidiMessage::parse(&[0xE3, 0x00, 0x40]).unwrap();
+        assert_eq!(msg.event_type, MidiEventType::PitchBend(0));
+        assert_eq!(msg.channel, 3);
+        assert_eq!((msg.note.value(), msg.velocity), (0, 0));
+        assert_eq!(msg.to_bytes(), [0xE3, 0x00, 0x40]);
+
+        for (bytes, bend) in [([0xE0, 0x7F, 0x7F], 8191), ([0xE0, 0x00, 0x00], -8192)] {
+            let msg = MidiMessage::parse(&bytes).unwrap();
+            assert_eq!(msg.event_type, MidiEventType::PitchBend(bend));
+            assert_eq!(msg.to_bytes(), bytes);
+        }
+        let msg = MidiMessage::parse(&[0xE0, 0x01, 0x00]).unwrap();
+        assert_eq!(msg.event_type, MidiEventType::PitchBend(1 - 8192));
+
+        // Only decoded when the filter lets pitch bend through
+        let data = [0xE0, 0x00, 0x60, 0x90, 60, 100];
+        let notes =
+            MidiMessage::parse_all_filtered(&data, Default::default(), MessageFilter::NOTES_ONLY);
+        assert_eq!(notes.len(), 1);
+        let listen = MessageFilter {
+            pitch_bend: true,
+            ..MessageFilter::NOTES_ONLY
+        };
+        let all = MidiMessage::parse_all_filtered(&data, Default::default(), listen);
+        assert_eq!(
+            all[0].as_ref().unwrap().event_type,
+            MidiEventType::PitchBend((0x60 << 7) - 8192)
+        );
+    }
+
+    #[test]
+    fn test_channel_mode_passes_notes_only_filter() {
+        // Modulation is dropped, All Notes Off isn't
+        let data = [0xB0, 1, 64, 0xB0, ALL_NOTES_OFF, 0];
+        let parsed: Vec<_> =
+            MidiMessage::parse_all_filtered(&data, Default::default(), MessageFilter::NOTES_ONLY)
+                .into_iter()
+                .map(|m| m.unwrap())
+                .collect();
+        assert_eq!(
+            parsed,
+            vec![MidiMessage::control_change(0, ALL_NOTES_OFF, 0)]
+        );
+
+        let listen = MessageFilter {
+            channel_mode: false,
+            ..MessageFilter::NOTES_ONLY
+        };
+        assert!(!listen.accepts(&[0xB0, ALL_SOUND_OFF, 0]));
+        assert!(listen.accepts(&[0x90, 60, 100]));
+    }
+
+    #[test]
+    fn test_transpose_and_shift_octave() {
+        let note = |n| MidiNote::new(n).unwrap();
+        assert_eq!(note(60).transpose(-12), Some(note(48)));
+        assert_eq!(note(60).transpose(0), Some(note(60)));
+        assert_eq!(note(60).shift_octave(2), Some(note(84)));
+
+        // Leaving 0-127 at either end
+        assert_eq!(note(0).transpose(-1), None);
+        assert_eq!(note(0).shift_octave(-1), None);
+        assert_eq!(note(127).transpose(1), None);
+        assert_eq!(note(127).shift_octave(1), None);
+        assert_eq!(note(120).transpose(7), Some(note(127)));
+        assert_eq!(note(11).shift_octave(-1), None);
+        assert_eq!(note(12).shift_octave(-1), Some(note(0)));
+
+        // Extreme shifts don't overflow
+        assert_eq!(note(127).transpose(i8::MIN), None);
+        assert_eq!(note(0).transpose(i8::MAX), Some(note(127)));
+        assert_eq!(note(64).shift_octave(i8::MAX), None);
+        assert_eq!(note(64).shift_octave(i8::MIN), None);
+    }
+
+    #[test]
+    fn test_decoder_real_time_between_data_bytes() {
+        // A clock tick between the note and velocity bytes, and inside a sysex
+        let data = [0x90, 60, 0xF8, 100, 0xF0, 0x7E, 0xF8, 0xF7, 0x90, 62, 90];
+        let mut notes = MidiDecoder::new(ZeroVelocityNoteOn::NoteOff, MessageFilter::NOTES_ONLY);
+        let parsed: Vec<_> = notes
+            .decode(&data)
+            .into_iter()
+            .map(|m| {
+                let m = m.unwrap();
+                (m.note.value(), m.velocity)
+            })
+            .collect();
+        assert_eq!(parsed, vec![(60, 100), (62, 90)]);
+
+        // With everything let through, each tick comes out on its own, in order
+        let mut all = MidiDecoder::new(ZeroVelocityNoteOn::NoteOff, MessageFilter::ALL);
+        let results = all.decode(&data);
+        let kinds: Vec<_> = results.iter().map(|r| r.is_ok()).collect();
+        // tick, note, tick, sysex, note; ticks and sysex aren't parsed
+        assert_eq!(kinds, vec![false, true, false, false, true]);
+    }
+
+    #[test]
+    fn test_decoder_running_status_across_buffers() {
+        let listen = MessageFilter {
+            control_change: true,
+            ..MessageFilter::NOTES_ONLY
+        };
+        let mut decoder = MidiDecoder::new(ZeroVelocityNoteOn::NoteOff, listen);
+        let mut decode = |data: &[u8]| -> Vec<MidiMessage> {
+            decoder
+                .decode(data)
+                .into_iter()
+                .collect::<Result<_>>()
+                .unwrap()
+        };
+        let message = |event_type, channel, note, velocity| MidiMessage {
+            event_type,
+            channel,
+            note: MidiNote::new(note).unwrap(),
+            velocity,
+        };
+
+        // The device sends the status byte once, then bare data pairs
+        assert_eq!(
+            decode(&[0x92, 60, 100]),
+            vec![message(MidiEventType::NoteOn, 2, 60, 100)]
+        );
+        assert_eq!(
+            decode(&[62, 90]),
+            vec![message(MidiEventType::NoteOn, 2, 62, 90)]
+        );
+        assert_eq!(
+            decode(&[60, 0, 62, 0]),
+            vec![
+                message(MidiEventType::NoteOff, 2, 60, 0),
+                message(MidiEventType::NoteOff, 2, 62, 0),
+            ]
+        );
+        // A clock pulse in between doesn't break it; a new status byte replaces it
+        assert_eq!(
+            decode(&[0xF8, 64, 80]),
+            vec![message(MidiEventType::NoteOn, 2, 64, 80)]
+        );
+        // Nor does one inside a message, with or without its status byte
+        assert_eq!(
+            decode(&[0x92, 60, 0xF8, 100, 62, 0xFE, 0xF8, 90]),
+            vec![
+                message(MidiEventType::NoteOn, 2, 60, 100),
+                message(MidiEventType::NoteOn, 2, 62, 90),
+            ]
+        );
+        assert_eq!(
+            decode(&[0xB0, 64, 127]),
+            vec![MidiMessage::control_change(0, 64, 127)]
+        );
+        assert_eq!(
+            decode(&[64, 0]),
+            vec![MidiMessage::control_change(0, 64, 0)]
+        );
+
+        // Without a status byte seen yet, data bytes are an error
+        let mut fresh = MidiDecoder::new(ZeroVelocityNoteOn::NoteOff, MessageFilter::ALL);
+        assert!(fresh.decode(&[60, 100]).iter().all(|r| r.is_err()));
+    }
+
+    #[test]
+    fn test_midi_message_to_bytes() {
+        for bytes in [
+            [0x90, 60, 64],
+            [0x83, 72, 10],
+            [0xB0, 64, 127],
+            [0xE1, 0x12, 0x34],
+        ] {
+            assert_eq!(MidiMessage::parse(&bytes).unwrap().to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn test_smf_notes() {
+        #[rustfmt::skip]
+        let smf = [
+            b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0, 96,
+            b'M', b'T', b'r', b'k', 0, 0, 0, 24,
+            0x00, 0x90, 60, 64,
+            0x00, 0x90, 64, 64,
+            0x60, 0x80, 60, 0,
+            0x00, 0x90, 60, 64, // repeated note
+            0x00, 0x90, 67, 0, // velocity 0 is a note-off
+            0x60, 0xFF, 0x2F, 0x00,
+        ];
+        assert_eq!(smf_notes(&smf).unwrap(), BTreeSet::from([60, 64]));
+        assert!(smf_notes(b"not a midi file").is_err());
+    }
+
+    #[test]
+    fn test_smf_messages_timing() {
+        // 96 ticks per beat; a tempo track at 60 BPM, doubling to 120 BPM after
+        // one beat, and a note track
+        #[rustfmt::skip]
+        let smf = [
+            b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 1, 0, 2, 0, 96,
+            b'M', b'T', b'r', b'k', 0, 0, 0, 18,
+            0x00, 0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40,
+            0x60, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+            0x00, 0xFF, 0x2F, 0x00,
+            b'M', b'T', b'r', b'k', 0, 0, 0, 19,
+            0x00, 0x91, 60, 64,
+            0x60, 0xB1, 64, 127,
+            0x00, 0xC1, 5, // program change
+            0x60, 0x81, 60, 0,
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        let messages = smf_messages(&smf, Default::default()).unwrap();
+        let timeline: Vec<_> = messages
+            .iter()
+            .map(|(at, m)| (at.as_millis(), m.event_type, m.channel, m.note.value()))
+            .collect();
+        assert_eq!(
+            timeline,
+            vec![
+                (0, MidiEventType::NoteOn, 1, 60),
+                (
+                    1000,
+                    MidiEventType::ControlChange {
+                        controller: 64,
+                        value: 127
+                    },
+                    1,
+                    0
+                ),
+                (1500, MidiEventType::NoteOff, 1, 60),
+            ]
+        );
+        assert!(smf_messages(b"not a midi file", Default::default()).is_err());
+    }
 }